@@ -0,0 +1,154 @@
+//! Compare validating a session token through the token index of
+//! [`AccountManager`] and through the request extractor with scanning
+//! accounts under their locks, while another thread keeps writing to accounts.
+//!
+//! Run with `cargo bench --bench token_validation`.
+
+use axum::extract::FromRequestParts;
+use sha256::digest;
+use sms3_backend::{
+    account::{
+        store::FileStore, verify, Account, AccountManager, UserAttributes, UserVerifyVariant,
+        INSTANCE,
+    },
+    RequirePermissionContext,
+};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const ACCOUNTS: u64 = 2000;
+const LOOKUPS: u32 = 2000;
+
+/// Account locks taken by lookups.
+static LOCKS: AtomicU64 = AtomicU64::new(0);
+
+fn account(id: u64, tokens: verify::Tokens) -> Account {
+    Account::Verified {
+        id,
+        attributes: UserAttributes {
+            email: lettre::Address::new(format!("user{id}"), "i.pkuschool.edu.cn").unwrap(),
+            name: "Jiening Yu".to_string(),
+            school_id: 2522320,
+            house: None,
+            phone: 16601550826,
+            organization: None,
+            permissions: vec![],
+            registration_time: chrono::Utc::now(),
+            password_sha: digest("password123456"),
+            token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
+        },
+        tokens,
+        verify: UserVerifyVariant::None,
+    }
+}
+
+/// Validate `token` like before the token index, taking a read lock on
+/// every account until the owner is found.
+fn scan(manager: &AccountManager, token: &str) -> Option<u64> {
+    manager.snapshot_ids().into_iter().find(|&id| {
+        LOCKS.fetch_add(1, Ordering::Relaxed);
+        manager
+            .with_account(id, |account| match account {
+                Account::Verified { tokens, .. } => tokens.token_usable(token),
+                Account::Unverified(_) => false,
+            })
+            .unwrap_or_default()
+    })
+}
+
+/// Authenticate a request of target account with `token` like handlers do,
+/// which also touches the token.
+fn extract(runtime: &tokio::runtime::Runtime, id: u64, token: &str) -> bool {
+    let (mut parts, _) = axum::http::Request::builder()
+        .header("Token", token)
+        .header("AccountId", id.to_string())
+        .body(())
+        .unwrap()
+        .into_parts();
+    runtime
+        .block_on(RequirePermissionContext::from_request_parts(
+            &mut parts,
+            &(),
+        ))
+        .is_ok()
+}
+
+fn measure(name: &str, tokens: &[(u64, String)], f: impl Fn(u64, &str) -> bool) {
+    LOCKS.store(0, Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..LOOKUPS {
+        let (id, token) = &tokens[i as usize * 7919 % tokens.len()];
+        assert!(f(*id, token));
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{name:>7}: {:>10.2?} per lookup, {:>7.1} account locks per lookup",
+        elapsed / LOOKUPS,
+        LOCKS.load(Ordering::Relaxed) as f64 / LOOKUPS as f64,
+    );
+}
+
+fn main() {
+    // Token generation reads the config from the data directory.
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("accounts")).unwrap();
+    std::fs::write(
+        dir.path().join("config.toml"),
+        "[mail_smtp]\nserver = \"\"\nport = 465\nusername = \"\"\npassword = \"\"\naddress = \"admin@i.pkuschool.edu.cn\"\n",
+    )
+    .unwrap();
+    std::env::set_var("SMS3_DATA_DIR", dir.path());
+
+    let store = FileStore::new(dir.path().join("accounts"));
+    let mut issued = Vec::new();
+    for id in 0..ACCOUNTS {
+        let mut tokens = verify::Tokens::new();
+        issued.push((id, tokens.new_token_with(0, Default::default(), 0).unwrap()));
+        store.save(&account(id, tokens)).unwrap();
+    }
+    // The extractor validates through the static instance, loaded from the store.
+    let manager: &'static AccountManager = &INSTANCE;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    // A writer contending for account locks, as handlers changing accounts do.
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            let mut id = 0;
+            while !stop.load(Ordering::Relaxed) {
+                manager.with_account_mut(id, |_| std::thread::sleep(Duration::from_micros(50)));
+                id = (id + 1) % ACCOUNTS;
+            }
+        })
+    };
+
+    measure("index", &issued, |_, token| {
+        manager.find_by_token(token).is_some()
+    });
+    measure("scan", &issued, |_, token| scan(manager, token).is_some());
+    // The first request of each token records its use in the account, later
+    // ones within the save interval only in the token index.
+    measure("first", &issued, |id, token| {
+        LOCKS.fetch_add(1, Ordering::Relaxed);
+        extract(&runtime, id, token)
+    });
+    measure("extract", &issued, |id, token| extract(&runtime, id, token));
+
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+}
@@ -1,20 +1,66 @@
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct AccountCreateDescriptor {
-    pub email: lettre::Address,
+    /// Parsed by the server, so malformed addresses get a distinct error.
+    #[schema(format = "email")]
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct AccountCreateResult {
+    /// Whether a verification was already pending for the email.
+    pub already_pending: bool,
+    /// Whether a verification code was sent by this request.
+    pub code_sent: bool,
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EmailAvailabilityQuery {
+    /// The email address to register.
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct EmailAvailabilityResult {
+    /// Whether no verified account uses the email.
+    pub available: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VerificationStatusQuery {
+    /// The email address being registered.
+    pub email: String,
+}
+
+/// The same for verified accounts and unknown emails.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, ToSchema)]
+pub struct VerificationStatusResult {
+    /// Whether an unexpired verification code is awaited for the email.
+    pub pending: bool,
+    /// Seconds before the code expires, `None` if not pending.
+    pub expires_in: Option<u64>,
+    /// Seconds before another code can be sent, `None` if not pending.
+    pub resend_in: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct AccountVerifyDescriptor {
     pub code: u32,
     pub variant: AccountVerifyVariant,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Unknown fields are rejected, as server-assigned attributes
+/// (ex. registration time) can't be supplied by clients.
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
 pub enum AccountVerifyVariant {
     /// Activate an unverified account.
     Activate {
+        #[schema(value_type = String, format = "email")]
         email: lettre::Address,
         name: String,
         id: u32,
@@ -26,57 +72,193 @@ pub enum AccountVerifyVariant {
 
     /// Verify a resetpassword session.
     ResetPassword {
+        #[schema(value_type = String, format = "email")]
         email: lettre::Address,
         password: String,
     },
+
+    /// Verify the pending alternate email of an account.
+    AlternateEmail {
+        /// The alternate email the code was sent to.
+        #[schema(value_type = String, format = "email")]
+        email: lettre::Address,
+    },
 }
 
-#[derive(Serialize, Deserialize)]
+/// Every field of a request failing validation, answered with `422 Unprocessable Entity`.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ValidationErrors {
+    pub fields: Vec<FieldError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, ToSchema)]
+pub struct FieldError {
+    /// Name of the field in the request.
+    pub field: String,
+    /// Description of the error.
+    pub error: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct AccountLoginDescriptor {
+    #[schema(value_type = String, format = "email")]
     pub email: lettre::Address,
     pub password: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct OidcCallbackDescriptor {
+    /// The authorization code the identity provider redirected back with.
+    pub code: String,
+    /// The state the identity provider redirected back with.
+    pub state: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AccountUnlockDescriptor {
+    pub account_id: u64,
+    /// The one-time token mailed on lockout.
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AccountLoginResult {
+    pub account_id: u64,
+    pub access_token: String,
+    /// Always `Bearer`.
+    pub token_type: String,
+    /// Seconds before the token expires, `None` if it never expires.
+    pub expires_in: Option<u64>,
+    pub user: super::UserMetadata,
+    /// Whether an admin requires the password to be changed, in which case
+    /// the token is only usable once for `/api/account/change-password`.
+    #[serde(default)]
+    pub must_change_password: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TokenExpiryResult {
+    /// Seconds left before the token expires, `None` if it never expires.
+    pub expires_in: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyCreateDescriptor {
+    /// Name of the key, unique in an account.
+    pub name: String,
+    /// Permissions the key is scoped to, must be owned by the account.
+    #[schema(value_type = Vec<super::Permission>)]
+    pub permissions: super::Permissions,
+    /// The expire time of the key, `None` means never expire.
+    pub expire_time: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyCreateResult {
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ApiKeyInfo {
+    pub name: String,
+    #[schema(value_type = Vec<super::Permission>)]
+    pub permissions: super::Permissions,
+    pub expire_time: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyRevokeDescriptor {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct AccountSignOutDescriptor {
     /// For double-verifying.
     pub password: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct ViewAccountResult {
     pub id: u64,
     pub metadata: super::UserMetadata,
+    #[schema(value_type = Vec<super::Permission>)]
     pub permissions: super::Permissions,
     pub registration_time: chrono::DateTime<chrono::Utc>,
+    /// An email waiting for verification, until which the account keeps
+    /// operating under its current emails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_email: Option<PendingEmail>,
+    /// Active sessions of the account, only shown to admins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sessions: Option<SessionSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, ToSchema)]
+pub struct PendingEmail {
+    /// The masked address the code was sent to, ex. `j***@example.com`.
+    pub email: String,
+    /// When the code expires, after which the pending email is dropped.
+    pub expire_time: chrono::DateTime<chrono::Utc>,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Active sessions of an account, for spotting unusual counts of concurrent logins.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, ToSchema)]
+pub struct SessionSummary {
+    /// Count of tokens not expired yet.
+    pub active_token_count: usize,
+    /// Seconds since the oldest of these tokens was created,
+    /// absent if none has a known creation time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oldest_token_age: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct AccountEditDescriptor {
     pub variants: Vec<AccountEditVariant>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub enum AccountEditVariant {
     Name(String),
     SchoolId(u32),
     Phone(u64),
     House(Option<super::House>),
     Organization(Option<String>),
-    Password { old: String, new: String },
+    Password {
+        old: String,
+        new: String,
+    },
     TokenExpireTime(u16),
+    MuteLoginNotification(bool),
+    /// Set a secondary email for password resets, verified by a code sent to it,
+    /// or remove it with `None`.
+    AlternateEmail(
+        #[schema(value_type = Option<String>, format = "email")] Option<lettre::Address>,
+    ),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ChangePasswordDescriptor {
+    /// The new password.
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct ResetPasswordDescriptor {
+    /// The email or verified alternate email of the account.
+    #[schema(value_type = String, format = "email")]
     pub email: lettre::Address,
 }
 
 pub mod manage {
     use crate::account;
     use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
 
+    /// Unknown fields are rejected, as server-assigned attributes
+    /// (ex. registration time) can't be supplied by clients.
     #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
     pub struct MakeAccountDescriptor {
         pub email: lettre::Address,
         pub name: String,
@@ -94,6 +276,7 @@ pub mod manage {
     }
 
     #[derive(Serialize, Deserialize, Debug)]
+    #[allow(clippy::large_enum_variant)]
     pub enum ViewAccountResult {
         Err { id: u64, error: String },
         Ok(super::ViewAccountResult),
@@ -105,6 +288,155 @@ pub mod manage {
         pub variants: Vec<AccountModifyVariant>,
     }
 
+    #[derive(Serialize, Deserialize)]
+    pub struct SearchAccountDescriptor {
+        /// Part of the name to search.
+        pub query: String,
+        /// Max count of results.
+        pub limit: usize,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct HouseQuery {
+        pub house: account::House,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct OrganizationQuery {
+        pub organization: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct GrantPermissionDescriptor {
+        pub accounts: Vec<u64>,
+        pub permission: account::Permission,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+    pub enum GrantPermissionResult {
+        Err { id: u64, error: String },
+        Ok(u64),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct RemoveAccountsDescriptor {
+        pub accounts: Vec<u64>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub enum RemoveAccountResult {
+        Err {
+            id: u64,
+            error: String,
+        },
+        /// Metadata of the removed account, `None` if it was unverified.
+        Ok {
+            id: u64,
+            metadata: Option<account::UserMetadata>,
+        },
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct IntrospectTokenDescriptor {
+        pub token: String,
+    }
+
+    /// Result of a token introspection, only `active` is present
+    /// if the token is unknown or expired.
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+    pub struct IntrospectTokenResult {
+        pub active: bool,
+        /// Id of the account owning the token.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub sub: Option<u64>,
+        /// Unix timestamp the token expires at, absent if it never expires.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub exp: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub permissions: Option<account::Permissions>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct AddNoteDescriptor {
+        pub account_id: u64,
+        pub text: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ForcePasswordChangeDescriptor {
+        pub account_id: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct RestoreAccountDescriptor {
+        pub account_id: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ResendVerificationDescriptor {
+        /// Id of the pending account.
+        pub account_id: u64,
+        /// Send a new code even if the last one was sent within the resend
+        /// cooldown, requires [`account::Permission::Op`].
+        #[serde(default)]
+        pub bypass_cooldown: bool,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ResendVerificationResult {
+        /// Whether a new code was sent, `false` within the resend cooldown.
+        pub code_sent: bool,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct MaintenanceDescriptor {
+        /// Whether to reject mutations, keeping reads working.
+        pub enabled: bool,
+        /// Seconds rejected clients are told to retry after,
+        /// a default is used if not set.
+        #[serde(default)]
+        pub retry_after: Option<u64>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct MaintenanceResult {
+        /// Seconds rejected clients are told to retry after,
+        /// `None` if not in maintenance.
+        pub retry_after: Option<u64>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ListNotesDescriptor {
+        pub account_id: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct HouseHistoryDescriptor {
+        pub account_id: u64,
+    }
+
+    /// Aggregates over all accounts.
+    #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+    pub struct Statistics {
+        pub total: usize,
+        pub verified: usize,
+        /// Unverified accounts.
+        pub pending: usize,
+        /// Unverified accounts whose verification hasn't expired.
+        #[serde(default)]
+        pub pending_unexpired: usize,
+        /// Verified accounts in each house.
+        pub by_house: HashMap<account::House, usize>,
+        /// Verified accounts without a house.
+        pub without_house: usize,
+        /// Verified accounts having each permission.
+        pub by_permission: HashMap<account::Permission, usize>,
+        /// Unexpired tokens of all accounts.
+        pub active_tokens: usize,
+        /// Unexpired password reset verifications.
+        pub pending_password_resets: usize,
+    }
+
     #[derive(Serialize, Deserialize, Clone)]
     pub enum AccountModifyVariant {
         Email(lettre::Address),
@@ -114,5 +446,6 @@ pub mod manage {
         House(Option<account::House>),
         Organization(Option<String>),
         Permission(account::Permissions),
+        ScopedPermission(Vec<account::ScopedPermission>),
     }
 }
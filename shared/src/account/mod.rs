@@ -1,37 +1,95 @@
 pub mod handle;
 
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-/// Represents houses of PKUSchool.
-#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[repr(u8)]
-pub enum House {
-    ChengYi,
-    GeWu,
-    HongYi,
-    MingDe,
-    XiJing,
-    XinMin,
-    ZhengXin,
-    ZhiShan,
-    ZhiZhi,
+/// Represents houses of PKUSchool by id.
+///
+/// Valid ids are configured by the server, defaulting to [`House::BUILTIN`].
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, ToSchema)]
+#[serde(transparent)]
+pub struct House(String);
+
+impl House {
+    /// Ids of houses used without configuration.
+    pub const BUILTIN: [&'static str; 9] = [
+        "ChengYi", "GeWu", "HongYi", "MingDe", "XiJing", "XinMin", "ZhengXin", "ZhiShan", "ZhiZhi",
+    ];
+
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for House {
+    fn from(id: &str) -> Self {
+        Self::new(id)
+    }
+}
+
+impl std::fmt::Display for House {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A configured house.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct HouseInfo {
+    /// Id stored for accounts in the house.
+    pub id: String,
+    /// Name shown to users.
+    pub name: String,
 }
 
 /// Represents a user's metadata.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct UserMetadata {
+    #[schema(value_type = String, format = "email")]
     pub email: lettre::Address,
     pub name: String,
     pub school_id: u32,
     pub phone: u64,
     pub house: Option<House>,
     pub organization: Option<String>,
+    /// When the user registered, serialized in RFC 3339.
+    pub registration_time: chrono::DateTime<chrono::Utc>,
+}
+
+/// An internal note attached to an account by an admin.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    /// Id of the admin writing this note.
+    pub author: u64,
+    pub time: chrono::DateTime<chrono::Utc>,
+    pub text: String,
+}
+
+/// A house an account is assigned to since a point of time.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HouseAssignment {
+    /// The assigned house, `None` if the account left its house.
+    pub house: Option<House>,
+    pub effective_from: chrono::DateTime<chrono::Utc>,
+}
+
+/// A permission only effective on accounts of an organization.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScopedPermission {
+    pub permission: Permission,
+    pub organization: String,
 }
 
 pub type Permissions = Vec<Permission>;
 
 /// Represent permissions an account has.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(
+    Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize, ToSchema,
+)]
 pub enum Permission {
     /// Approve posters or edit approvals.
     Approve,
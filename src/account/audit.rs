@@ -0,0 +1,168 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use super::Permission;
+
+/// A record of an action performed on an account.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    /// When the action was performed.
+    pub time: DateTime<Utc>,
+    /// Id of the account performing the action, [`Entry::COMMAND_LINE`]
+    /// for actions from the admin command line.
+    pub by: u64,
+    /// Id of the account the action was performed on.
+    pub target: u64,
+    pub action: Action,
+}
+
+impl Entry {
+    /// The performer of actions from the admin command line.
+    pub const COMMAND_LINE: u64 = 0;
+}
+
+/// Permissions added and removed by a change, each sorted.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PermissionDiff {
+    pub added: Vec<Permission>,
+    pub removed: Vec<Permission>,
+}
+
+impl PermissionDiff {
+    /// Compute the change from permissions `before` to `after`.
+    pub fn between(before: &[Permission], after: &[Permission]) -> Self {
+        let diff = |a: &[Permission], b: &[Permission]| {
+            let mut vec: Vec<Permission> = a.iter().filter(|p| !b.contains(p)).copied().collect();
+            vec.sort_unstable();
+            vec.dedup();
+            vec
+        };
+        Self {
+            added: diff(after, before),
+            removed: diff(before, after),
+        }
+    }
+
+    /// Whether nothing was changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Actions recorded in the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// A permission was granted, only recorded by earlier versions.
+    GrantPermission(Permission),
+    /// Permissions were granted or revoked.
+    ChangePermissions(PermissionDiff),
+    /// Account `from` was merged into the target, see
+    /// [`super::AccountManager::merge`].
+    Merge { from: u64 },
+    /// The account was locked out after `failures` failed logins.
+    Lock {
+        failures: u32,
+        locked_until: DateTime<Utc>,
+    },
+    /// The lockout of the account was lifted with an unlock token.
+    Unlock,
+    /// A new verification code was sent to the pending account.
+    ResendVerification { bypass_cooldown: bool },
+    /// The account was suspended.
+    Suspend,
+    /// The suspension of the account was lifted.
+    Unban,
+}
+
+/// An append-only audit log, optionally persisted as JSON lines.
+#[derive(Default)]
+pub struct Log {
+    entries: Mutex<Vec<Entry>>,
+    /// The file entries are appended to, `None` for in-memory logs.
+    path: Option<PathBuf>,
+}
+
+impl Log {
+    /// Read the log from target file, which will be created on first record.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut entries = Vec::new();
+
+        match fs::File::open(&path) {
+            Ok(file) => {
+                for line in io::BufReader::new(file).lines() {
+                    entries.push(
+                        serde_json::from_str(&line?)
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                    );
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+
+        Ok(Self {
+            entries: Mutex::new(entries),
+            path: Some(path),
+        })
+    }
+
+    /// Append entries to this log.
+    pub fn record(&self, entries: impl IntoIterator<Item = Entry>) {
+        let mut inner = self.entries.lock();
+        let start = inner.len();
+        inner.extend(entries);
+
+        if let Some(path) = &self.path {
+            if let Err(err) = Self::append(path, &inner[start..]) {
+                error!("failed to write audit log: {}", err);
+            }
+        }
+    }
+
+    fn append(path: &PathBuf, entries: &[Entry]) -> io::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut data = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut data, entry)?;
+            data.push(b'\n');
+        }
+
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&data)
+    }
+
+    /// Get all entries concerning target account, either performed by it or on it.
+    pub fn concerning(&self, id: u64) -> Vec<Entry> {
+        self.entries
+            .lock()
+            .iter()
+            .filter(|e| e.by == id || e.target == id)
+            .cloned()
+            .collect()
+    }
+
+    /// Get all entries.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.entries.lock().clone()
+    }
+
+    #[cfg(test)]
+    pub fn reset(&self) {
+        self.entries.lock().clear()
+    }
+}
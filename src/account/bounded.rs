@@ -0,0 +1,90 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+/// A map holding at most `capacity` entries, forgetting the least recently
+/// used one to make room for a new key.
+///
+/// Used for state keyed by values clients choose, ex. email addresses,
+/// so memory stays bounded however many distinct keys are seen.
+pub struct BoundedMap<K, V> {
+    /// Values with the tick they were last used at.
+    entries: HashMap<K, (u64, V)>,
+    /// Keys by the tick they were last used at, the least recent first.
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+    capacity: usize,
+}
+
+impl<K: Hash + Eq + Clone, V> BoundedMap<K, V> {
+    /// Create a map holding at most `capacity` entries, at least one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Get the value of target key, marking it as the most recently used.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let (used, value) = self.entries.get_mut(key)?;
+        self.tick += 1;
+        let key = self.recency.remove(used).expect("entry without recency");
+        *used = self.tick;
+        self.recency.insert(self.tick, key);
+        Some(value)
+    }
+
+    /// Get the value of target key, inserting `V::default()` if absent,
+    /// marking it as the most recently used.
+    ///
+    /// The least recently used entry is forgotten if the map is full.
+    pub fn get_or_default(&mut self, key: K) -> &mut V
+    where
+        V: Default,
+    {
+        if !self.entries.contains_key(&key) {
+            if self.entries.len() >= self.capacity {
+                if let Some((_, oldest)) = self.recency.pop_first() {
+                    self.entries.remove(&oldest);
+                }
+            }
+            self.entries.insert(key.clone(), (0, V::default()));
+        }
+
+        self.tick += 1;
+        let (used, value) = self.entries.get_mut(&key).expect("entry just inserted");
+        self.recency.remove(used);
+        self.recency.insert(self.tick, key);
+        *used = self.tick;
+        value
+    }
+
+    /// Remove target key, returning its value.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (used, value) = self.entries.remove(key)?;
+        self.recency.remove(&used);
+        Some(value)
+    }
+
+    /// Whether target key is in this map, without marking it as used.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use tokio::sync::broadcast;
+
+/// Count of events kept for slow subscribers before they lag.
+const CAPACITY: usize = 1024;
+
+static CHANNEL: Lazy<broadcast::Sender<Event>> = Lazy::new(|| broadcast::channel(CAPACITY).0);
+
+/// An internal event for observability, ex. metrics and audit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A verification code was sent through any channel.
+    VerificationSent {
+        channel: crate::config::DeliveryChannel,
+        /// Domain of the email address the code belongs to, the full address
+        /// is left out for privacy.
+        domain: String,
+        /// Count of digits in the code.
+        code_length: usize,
+        /// Whether the code was delivered, `false` if the channel failed.
+        success: bool,
+    },
+    /// An account was locked out after failed logins, once per lockout.
+    AccountLocked {
+        account_id: u64,
+        /// Count of failed logins that triggered the lockout.
+        failures: u32,
+        locked_until: DateTime<Utc>,
+    },
+    /// A login was attempted on a locked account, at most once per
+    /// [`super::verify::LoginLimiter::REPORT_INTERVAL_SECONDS`] for each account.
+    AttemptWhileLocked {
+        account_id: u64,
+        locked_until: DateTime<Utc>,
+    },
+}
+
+/// Receive events emitted from now on.
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    CHANNEL.subscribe()
+}
+
+/// Emit an event to all subscribers, dropped if there is none.
+pub fn emit(event: Event) {
+    let _ = CHANNEL.send(event);
+}
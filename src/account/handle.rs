@@ -5,172 +5,655 @@ use crate::account::verify;
 use crate::account::Account;
 use crate::account::Permission;
 use crate::RequirePermissionContext;
-use crate::ResError;
+use axum::extract::ConnectInfo;
+use axum::extract::Query;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
 use axum::Json;
-use chrono::Duration;
-use chrono::Utc;
-use parking_lot::RwLock;
-use rand::Rng;
 use serde_json::json;
-use sha256::digest;
+use std::net::SocketAddr;
 use std::ops::Deref;
 use std::ops::DerefMut;
 
 use sms3_shared::account::handle::*;
 
 /// Create an unverified account.
+#[utoipa::path(
+    post,
+    path = "/api/account/create",
+    request_body = AccountCreateDescriptor,
+    responses(
+        (status = 200, description = "Verification created or already pending", body = AccountCreateResult),
+        (status = 400, description = "Email is not a valid address", body = ErrorResponse),
+        (status = 403, description = "Email domain is not from PKUSchool, or registration is closed", body = ErrorResponse),
+        (status = 409, description = "Email already registered", body = ErrorResponse),
+        (status = 429, description = "Too many verification mails to this address", body = ErrorResponse),
+    )
+)]
 pub async fn create_account(
     Json(descriptor): Json<AccountCreateDescriptor>,
-) -> axum::response::Result<()> {
-    if super::INSTANCE
-        .inner()
-        .read()
-        .iter()
-        .any(|account| account.read().email() == &descriptor.email)
-    {
-        return Err(ResError(super::Error::Conflict).into());
+) -> axum::response::Result<Json<AccountCreateResult>> {
+    if !crate::config::INSTANCE.account.registration_open() {
+        return Err(Error::RegistrationClosed.into());
     }
+    let email = super::parse_email(&descriptor.email)?;
+    Ok(Json(match super::INSTANCE.register(email)? {
+        super::Registration::Created => AccountCreateResult {
+            already_pending: false,
+            code_sent: true,
+        },
+        super::Registration::Pending { code_sent } => AccountCreateResult {
+            already_pending: true,
+            code_sent,
+        },
+    }))
+}
 
-    let len = super::INSTANCE.inner().read().len();
-    let account = Account::new(descriptor.email).map_err(ResError)?;
+/// Check whether an email can still be registered, for instant feedback
+/// before creating the account.
+///
+/// Only school addresses are looked up, and only whether they are taken is told.
+#[utoipa::path(
+    get,
+    path = "/api/account/available",
+    params(EmailAvailabilityQuery),
+    responses(
+        (status = 200, description = "Whether the email is available", body = EmailAvailabilityResult),
+        (status = 400, description = "Email is not a valid address", body = ErrorResponse),
+        (status = 403, description = "Email domain is not from PKUSchool", body = ErrorResponse),
+        (status = 429, description = "Too many checks from this IP address", body = ErrorResponse),
+    )
+)]
+pub async fn email_availability(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Query(query): Query<EmailAvailabilityQuery>,
+) -> axum::response::Result<Json<EmailAvailabilityResult>> {
+    verify::AVAILABILITY_LIMITER
+        .acquire(device(&headers, connect_info.map(|ConnectInfo(addr)| addr)).ip)?;
+    let email = super::parse_email(&query.email)?;
+    if !super::is_school_email(&email) {
+        return Err(Error::EmailDomainNotInSchool.into());
+    }
 
-    super::INSTANCE.index().insert(account.id(), len);
-    account.save();
-    super::INSTANCE.inner().write().push(RwLock::new(account));
+    let available = super::INSTANCE
+        .find_by_email(&email)
+        .and_then(|id| super::INSTANCE.get(id))
+        .is_none_or(|account| matches!(*account, Account::Unverified(_)));
+    Ok(Json(EmailAvailabilityResult { available }))
+}
 
-    Ok(())
+/// Check whether a verification code is awaited for an email and how long
+/// it stays valid, for counting down and offering a resend in time.
+///
+/// Verified accounts are told apart from unknown emails by nothing.
+/// Checks share their limit with [`email_availability`].
+#[utoipa::path(
+    get,
+    path = "/api/account/verify-status",
+    params(VerificationStatusQuery),
+    responses(
+        (status = 200, description = "Whether a code is awaited and until when", body = VerificationStatusResult),
+        (status = 400, description = "Email is not a valid address", body = ErrorResponse),
+        (status = 429, description = "Too many checks from this IP address", body = ErrorResponse),
+    )
+)]
+pub async fn verification_status(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Query(query): Query<VerificationStatusQuery>,
+) -> axum::response::Result<Json<VerificationStatusResult>> {
+    verify::AVAILABILITY_LIMITER
+        .acquire(device(&headers, connect_info.map(|ConnectInfo(addr)| addr)).ip)?;
+    let email = super::parse_email(&query.email)?;
+
+    let now = crate::clock::now();
+    let seconds_until =
+        |time: chrono::DateTime<chrono::Utc>| Some((time - now).num_seconds().max(0) as u64);
+    let status = super::INSTANCE
+        .find_by_email(&email)
+        .and_then(|id| super::INSTANCE.get(id))
+        .and_then(|account| match &*account {
+            Account::Unverified(cxt) if !cxt.is_expired() => Some(VerificationStatusResult {
+                pending: true,
+                expires_in: seconds_until(cxt.expire_time),
+                resend_in: seconds_until(cxt.resend_time()),
+            }),
+            _ => None,
+        })
+        .unwrap_or(VerificationStatusResult {
+            pending: false,
+            expires_in: None,
+            resend_in: None,
+        });
+    Ok(Json(status))
 }
 
 /// Verify an account.
+#[utoipa::path(
+    post,
+    path = "/api/account/verify",
+    request_body = AccountVerifyDescriptor,
+    responses(
+        (status = 200, description = "Account activated (with `account_id`) or password reset", body = Object),
+        (status = 403, description = "Verification code not match, account in wrong state or password used recently", body = ErrorResponse),
+        (status = 404, description = "Account not found", body = ErrorResponse),
+        (status = 422, description = "Fields of the account to activate are invalid", body = ValidationErrors),
+    )
+)]
 pub async fn verify_account(
     Json(descriptor): Json<AccountVerifyDescriptor>,
 ) -> axum::response::Result<Json<serde_json::Value>> {
-    for account in super::INSTANCE.inner().read().iter() {
-        match &descriptor.variant {
-            AccountVerifyVariant::Activate {
-                email,
+    // The list isn't kept locked while hashing passwords, which are only
+    // hashed once the code matches.
+    let find = |f: &dyn Fn(&Account) -> bool| {
+        super::INSTANCE
+            .inner()
+            .read()
+            .iter()
+            .find(|a| f(&a.read()))
+            .cloned()
+            .ok_or(super::ManagerError::NotFound(0))
+    };
+
+    match &descriptor.variant {
+        AccountVerifyVariant::Activate {
+            email,
+            name,
+            id,
+            phone,
+            house,
+            organization,
+            password,
+        } => {
+            let account = find(&|a| a.has_email(email))?;
+            let super::Activation {
                 name,
-                id,
-                phone,
                 house,
                 organization,
+            } = super::validate_activation(
+                &crate::config::INSTANCE.account,
+                name,
+                *phone,
+                house.clone(),
+                organization.clone(),
                 password,
-            } => {
-                let res = {
-                    let a = account.read();
-
-                    if a.email() == email {
-                        let id = a.id();
-                        drop(a);
-                        super::INSTANCE.refresh(id);
-                        true
-                    } else {
-                        false
-                    }
-                };
-                if res {
-                    let mut a = account.write();
-
-                    a.verify(
-                        descriptor.code,
-                        super::AccountVerifyVariant::Activate(UserAttributes {
-                            email: email.clone(),
-                            name: name.clone(),
+            )
+            .map_err(|errors| (StatusCode::UNPROCESSABLE_ENTITY, Json(errors)))?;
+            account.read().check_activation_code(descriptor.code)?;
+
+            let password = password.clone();
+            let password_sha = tokio::task::spawn_blocking(move || super::hash_password(&password))
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+            let account_id =
+                super::INSTANCE.assign_id(crate::config::INSTANCE.account.id_strategy, email);
+            // Saved before responding, after releasing the lock.
+            let saved = {
+                let mut a = account.write();
+                let unverified_id = a.id();
+
+                a.verify(
+                    descriptor.code,
+                    super::AccountVerifyVariant::Activate(
+                        account_id,
+                        Box::new(UserAttributes {
+                            email: super::normalize_email(email.clone()),
+                            name,
                             school_id: *id,
                             phone: *phone,
-                            house: *house,
-                            organization: organization.clone(),
-                            permissions: vec![Permission::View, Permission::Post],
-                            registration_time: Utc::now(),
-                            password_sha: digest(password as &str),
+                            house,
+                            organization,
+                            permissions: vec![Permission::Post, Permission::View],
+                            registration_time: crate::clock::now(),
+                            password_sha,
                             token_expiration_time: 5,
+                            mute_login_notification: false,
+                            password_history: vec![],
+                            admin_notes: vec![],
+                            house_history: vec![],
+                            scoped_permissions: vec![],
+                            password_changed_at: None,
+                            alternate_email: None,
+                            must_change_password: false,
+                            pending_deletion: None,
+                            suspended: false,
                         }),
-                    )
-                    .map_err(ResError)?;
+                    ),
+                )?;
 
-                    a.save();
-                    return Ok(Json(json!({ "account_id": id })));
-                }
-            }
+                super::INSTANCE.reassign(unverified_id, account_id);
+                a.save_awaitable()
+            };
+            saved.await.map_err(Error::Save)?;
+            Ok(Json(json!({ "account_id": account_id })))
+        }
 
-            AccountVerifyVariant::ResetPassword { email, password } => {
-                let res = {
-                    let a = account.read();
-                    if a.email() == email {
-                        let id = a.id();
-                        drop(a);
-                        super::INSTANCE.refresh(id);
-                        true
-                    } else {
-                        false
-                    }
-                };
-                if res {
-                    let mut a = account.write();
+        AccountVerifyVariant::ResetPassword { email, password } => {
+            let account = find(&|a| a.has_recovery_email(email))?;
+            account.read().check_reset_code(descriptor.code)?;
 
-                    a.verify(
-                        descriptor.code,
-                        super::AccountVerifyVariant::ResetPassword(password.to_string()),
-                    )
-                    .map_err(ResError)?;
+            let (code, password) = (descriptor.code, password.to_string());
+            tokio::task::spawn_blocking(move || {
+                let mut a = account.write();
+                a.verify(code, super::AccountVerifyVariant::ResetPassword(password))?;
+                a.save_now()
+            })
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)??;
+            Ok(Json(json!({})))
+        }
 
-                    a.save();
-                    return Ok(Json(json!({})));
-                }
-            }
+        AccountVerifyVariant::AlternateEmail { email } => {
+            let email = super::normalize_email(email.clone());
+            let account = find(&|a| {
+                matches!(a, Account::Verified { attributes, .. }
+                    if attributes.pending_alternate_email() == Some(&email))
+            })?;
+            let mut a = account.write();
+            a.verify(descriptor.code, super::AccountVerifyVariant::AlternateEmail)?;
+            a.save();
+            Ok(Json(json!({})))
         }
     }
-
-    Err(ResError(super::ManagerError::NotFound(0)).into())
 }
 
 /// Login to a verified account.
+#[utoipa::path(
+    post,
+    path = "/api/account/login",
+    request_body = AccountLoginDescriptor,
+    responses(
+        (status = 200, description = "Logged in", body = AccountLoginResult),
+        (status = 403, description = "Password incorrect or account unverified", body = ErrorResponse),
+        (status = 404, description = "Account not found", body = ErrorResponse),
+        (status = 429, description = "Locked out after too many failed logins", body = ErrorResponse),
+    )
+)]
 pub async fn login_account(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(descriptor): Json<AccountLoginDescriptor>,
-) -> axum::response::Result<Json<serde_json::Value>> {
-    if let Some(account) = super::INSTANCE
+) -> axum::response::Result<Json<AccountLoginResult>> {
+    // The list isn't kept locked while hashing the password.
+    let account = super::INSTANCE
         .inner()
         .read()
         .iter()
-        .find(|a| a.read().email() == &descriptor.email)
-    {
+        .find(|a| a.read().has_email(&descriptor.email))
+        .cloned()
+        .ok_or(super::ManagerError::NotFound(0))?;
+    let account_id = account.read().id();
+    verify::LOGIN_LIMITER.check(account_id)?;
+    let device = device(&headers, connect_info.map(|ConnectInfo(addr)| addr));
+
+    let result = tokio::task::spawn_blocking(move || {
         let mut aw = account.write();
-        let token = aw.login(&descriptor.password);
+        let token = match aw.login(&descriptor.password, device) {
+            Err(Error::PasswordIncorrect) => {
+                if let Some(lockout) = verify::LOGIN_LIMITER.fail(account_id) {
+                    super::INSTANCE.audit().record([super::audit::Entry {
+                        time: crate::clock::now(),
+                        by: account_id,
+                        target: account_id,
+                        action: super::audit::Action::Lock {
+                            failures: lockout.failures,
+                            locked_until: lockout.locked_until,
+                        },
+                    }]);
+
+                    let config = &crate::config::INSTANCE.account;
+                    if let (true, Account::Verified { attributes, .. }) =
+                        (config.lockout_unlock_mail, aw.deref())
+                    {
+                        verify::LOGIN_LIMITER.notify(
+                            attributes.email.clone(),
+                            account_id,
+                            lockout,
+                            chrono::Duration::minutes(config.unlock_token_minutes as i64),
+                        );
+                    }
+                }
+                return Err(Error::PasswordIncorrect);
+            }
+            result => result?,
+        };
+        verify::LOGIN_LIMITER.succeed(account_id);
 
         aw.save();
+        login_result(&aw, token)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)??;
+    Ok(Json(result))
+}
 
-        token
-            .map(|value| {
-                Json(json!({
-                    "account_id": aw.id(),
-                    "token": value,
-                }))
-            })
-            .map_err(|err| ResError(err).into())
-    } else {
-        Err(ResError(super::ManagerError::NotFound(0)).into())
+/// Describe a login to target verified account with the token.
+fn login_result(account: &Account, token: String) -> Result<AccountLoginResult, Error> {
+    let Account::Verified { id, attributes, .. } = account else {
+        return Err(Error::UserUnverified);
+    };
+    Ok(AccountLoginResult {
+        account_id: *id,
+        access_token: token,
+        token_type: "Bearer".to_string(),
+        expires_in: if attributes.must_change_password {
+            Some(verify::Tokens::PASSWORD_CHANGE_MINUTES as u64 * 60)
+        } else {
+            match crate::config::INSTANCE
+                .account
+                .token_expiration(&attributes.permissions, attributes.token_expiration_time)
+            {
+                0 => None,
+                days => Some(days as u64 * 24 * 60 * 60),
+            }
+        },
+        user: account.metadata()?,
+        must_change_password: attributes.must_change_password,
+    })
+}
+
+/// Start signing in with the configured identity provider,
+/// redirecting to its sign-in page.
+///
+/// The provider redirects back to the configured `redirect_uri` with a code
+/// and state, which are to be posted to [`oidc_callback`].
+#[utoipa::path(
+    get,
+    path = "/api/account/oidc/login",
+    responses(
+        (status = 303, description = "Redirected to the identity provider"),
+        (status = 404, description = "No identity provider configured", body = ErrorResponse),
+    )
+)]
+pub async fn oidc_login() -> axum::response::Result<axum::response::Redirect> {
+    let config = crate::config::INSTANCE.oidc().ok_or(Error::SsoDisabled)?;
+    let (state, nonce) = super::oidc::PENDING.start();
+    Ok(axum::response::Redirect::to(
+        &super::oidc::authorization_url(&config, &state, &nonce)?,
+    ))
+}
+
+/// Finish signing in with the configured identity provider, logging in the
+/// account of the verified school email.
+///
+/// If enabled, an account is created for emails without one.
+#[utoipa::path(
+    post,
+    path = "/api/account/oidc/callback",
+    request_body = OidcCallbackDescriptor,
+    responses(
+        (status = 200, description = "Logged in", body = AccountLoginResult),
+        (status = 401, description = "ID token invalid or email not verified", body = ErrorResponse),
+        (status = 403, description = "State incorrect, email domain is not from PKUSchool or account pending deletion", body = ErrorResponse),
+        (status = 404, description = "No identity provider configured, or no account of the email", body = ErrorResponse),
+        (status = 502, description = "Identity provider failed", body = ErrorResponse),
+    )
+)]
+pub async fn oidc_callback(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(descriptor): Json<OidcCallbackDescriptor>,
+) -> axum::response::Result<Json<AccountLoginResult>> {
+    let config = crate::config::INSTANCE.oidc().ok_or(Error::SsoDisabled)?;
+    let nonce = super::oidc::PENDING
+        .finish(&descriptor.state)
+        .ok_or(Error::SsoStateIncorrect)?;
+    let claims = super::oidc::exchange(&config, &descriptor.code).await?;
+    let email = claims.validate(&config, &nonce)?;
+    if !super::is_school_email(&email) {
+        return Err(Error::EmailDomainNotInSchool.into());
     }
+
+    let verified = super::INSTANCE.find_by_email(&email).filter(|id| {
+        super::INSTANCE.with_account(*id, |account| matches!(account, Account::Verified { .. }))
+            == Some(true)
+    });
+    let id = match verified {
+        Some(id) => id,
+        None if config.provision => super::INSTANCE.provision(
+            email.clone(),
+            claims.name.unwrap_or_else(|| email.user().to_string()),
+        )?,
+        None => return Err(super::ManagerError::NotFound(0).into()),
+    };
+
+    let device = device(&headers, connect_info.map(|ConnectInfo(addr)| addr));
+    let result = super::INSTANCE
+        .with_account_mut(id, |account| {
+            let token = account.login_verified(device)?;
+            account.save();
+            login_result(account, token)
+        })
+        .ok_or(super::ManagerError::NotFound(id))??;
+    Ok(Json(result))
 }
 
-/// Logout from an account.
-pub async fn logout_account(ctx: RequirePermissionContext) -> axum::response::Result<()> {
-    let account_manager = &super::INSTANCE;
+/// Lift the lockout of an account with the one-time token mailed to its owner.
+#[utoipa::path(
+    post,
+    path = "/api/account/unlock",
+    request_body = AccountUnlockDescriptor,
+    responses(
+        (status = 204, description = "Unlocked"),
+        (status = 403, description = "Token incorrect, used or expired, or account not locked", body = ErrorResponse),
+    )
+)]
+pub async fn unlock_account(
+    Json(descriptor): Json<AccountUnlockDescriptor>,
+) -> axum::response::Result<StatusCode> {
+    verify::LOGIN_LIMITER.unlock(descriptor.account_id, &descriptor.token)?;
+    super::INSTANCE.audit().record([super::audit::Entry {
+        time: crate::clock::now(),
+        by: descriptor.account_id,
+        target: descriptor.account_id,
+        action: super::audit::Action::Unlock,
+    }]);
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    if let Some(index) = account_manager
-        .index()
-        .get(&ctx.account_id)
-        .map(|e| *e.value())
-    {
-        let b = account_manager.inner().read();
-        let mut aw = b.get(index).unwrap().write();
-        aw.logout(&ctx.token).map_err(ResError)?;
-        Ok(())
-    } else {
-        Err(ResError(super::ManagerError::NotFound(ctx.account_id)).into())
+/// Get the device a request comes from.
+///
+/// The IP address is the peer address, unless the peer is a trusted proxy,
+/// see [`crate::config::Config::trusted_proxies`]. Then `X-Forwarded-For` is
+/// walked from its last entry, skipping trusted proxies, as earlier entries
+/// may be forged by the client.
+pub(crate) fn device(headers: &HeaderMap, addr: Option<SocketAddr>) -> verify::Device {
+    let mut ip = addr.map(|addr| addr.ip());
+    let mut forwarded = headers
+        .get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .rsplit(',');
+    while ip.is_some_and(|ip| crate::config::INSTANCE.is_trusted_proxy(ip)) {
+        match forwarded.next().and_then(|value| value.trim().parse().ok()) {
+            Some(next) => ip = Some(next),
+            None => break,
+        }
+    }
+
+    verify::Device {
+        ip,
+        user_agent: headers
+            .get(axum::http::header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
     }
 }
 
-/// Sign out and remove an verified account.
+/// List houses accounts can be assigned to.
+#[utoipa::path(
+    get,
+    path = "/api/account/houses",
+    responses(
+        (status = 200, description = "Configured houses", body = [HouseInfo]),
+    )
+)]
+pub async fn list_houses() -> Json<Vec<super::HouseInfo>> {
+    Json(crate::config::INSTANCE.account.houses().into_owned())
+}
+
+/// Logout from an account, removing the bearer token of the request.
+#[utoipa::path(
+    post,
+    path = "/api/account/logout",
+    responses(
+        (status = 204, description = "Token removed"),
+        (status = 401, description = "Bearer token missing or incorrect", body = ErrorResponse),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn logout_account(headers: HeaderMap) -> axum::response::Result<StatusCode> {
+    let token = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .ok_or(Error::TokenIncorrect)?;
+    let id = super::INSTANCE
+        .find_by_token(token)
+        .ok_or(Error::TokenIncorrect)?;
+
+    super::INSTANCE
+        .with_account_mut(id, |account| {
+            account.logout(token)?;
+            account.save_now()
+        })
+        .ok_or(Error::TokenIncorrect)??;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get the time left before the token of the request expires.
+#[utoipa::path(
+    post,
+    path = "/api/account/token-expiry",
+    responses(
+        (status = 200, description = "Time left of the token", body = TokenExpiryResult),
+        (status = 401, description = "Token or account id missing or incorrect", body = ErrorResponse),
+    ),
+    security(("token" = [], "account_id" = []))
+)]
+pub async fn token_expiry(
+    ctx: RequirePermissionContext,
+) -> axum::response::Result<Json<TokenExpiryResult>> {
+    let expires_in = super::INSTANCE
+        .with_account(ctx.account_id, |account| match account {
+            Account::Verified { tokens, .. } => Ok(tokens
+                .time_until_expiry(&ctx.token)
+                .map(|duration| duration.num_seconds())),
+            Account::Unverified(_) => Err(Error::UserUnverified),
+        })
+        .ok_or(super::ManagerError::NotFound(ctx.account_id))??;
+
+    Ok(Json(TokenExpiryResult { expires_in }))
+}
+
+/// Create a named API key with a subset of the account's permissions.
+#[utoipa::path(
+    post,
+    path = "/api/account/api-key/create",
+    request_body = ApiKeyCreateDescriptor,
+    responses(
+        (status = 200, description = "Key created", body = ApiKeyCreateResult),
+        (status = 401, description = "Token or account id missing or incorrect", body = ErrorResponse),
+        (status = 403, description = "Permissions not owned or expire time in the past", body = ErrorResponse),
+        (status = 409, description = "Key with same name exists", body = ErrorResponse),
+    ),
+    security(("token" = [], "account_id" = []))
+)]
+pub async fn create_api_key(
+    ctx: RequirePermissionContext,
+    Json(descriptor): Json<ApiKeyCreateDescriptor>,
+) -> axum::response::Result<Json<ApiKeyCreateResult>> {
+    // an API key may only create keys within its own scope
+    ctx.valid(&descriptor.permissions)?;
+
+    let key = super::INSTANCE
+        .with_account_mut(ctx.account_id, |account| {
+            let result = account.create_api_key(
+                descriptor.name,
+                descriptor.permissions,
+                descriptor.expire_time,
+            );
+            if result.is_ok() {
+                account.save();
+            }
+            result
+        })
+        .ok_or(super::ManagerError::NotFound(ctx.account_id))??;
+
+    Ok(Json(ApiKeyCreateResult { key }))
+}
+
+/// List API keys of the account.
+#[utoipa::path(
+    post,
+    path = "/api/account/api-key/list",
+    responses(
+        (status = 200, description = "Keys of the account", body = [ApiKeyInfo]),
+        (status = 401, description = "Token or account id missing or incorrect", body = ErrorResponse),
+    ),
+    security(("token" = [], "account_id" = []))
+)]
+pub async fn list_api_keys(
+    ctx: RequirePermissionContext,
+) -> axum::response::Result<Json<Vec<ApiKeyInfo>>> {
+    let keys = super::INSTANCE
+        .with_account(ctx.account_id, |account| match account {
+            Account::Verified { tokens, .. } => Ok(tokens
+                .api_keys()
+                .iter()
+                .map(|key| ApiKeyInfo {
+                    name: key.name().to_string(),
+                    permissions: key.permissions().to_vec(),
+                    expire_time: key.expire_time(),
+                })
+                .collect()),
+            Account::Unverified(_) => Err(Error::UserUnverified),
+        })
+        .ok_or(super::ManagerError::NotFound(ctx.account_id))??;
+
+    Ok(Json(keys))
+}
+
+/// Revoke an API key of the account by name.
+#[utoipa::path(
+    post,
+    path = "/api/account/api-key/revoke",
+    request_body = ApiKeyRevokeDescriptor,
+    responses(
+        (status = 200, description = "Key revoked"),
+        (status = 401, description = "Token or account id missing or incorrect", body = ErrorResponse),
+        (status = 404, description = "Key not found", body = ErrorResponse),
+    ),
+    security(("token" = [], "account_id" = []))
+)]
+pub async fn revoke_api_key(
+    ctx: RequirePermissionContext,
+    Json(descriptor): Json<ApiKeyRevokeDescriptor>,
+) -> axum::response::Result<()> {
+    super::INSTANCE
+        .with_account_mut(ctx.account_id, |account| {
+            account.revoke_api_key(&descriptor.name)?;
+            account.save_now()
+        })
+        .ok_or(super::ManagerError::NotFound(ctx.account_id))?
+        .map_err(Into::into)
+}
+
+/// Sign out and delete a verified account after the configured grace period.
+#[utoipa::path(
+    post,
+    path = "/api/account/signout",
+    request_body = AccountSignOutDescriptor,
+    responses(
+        (status = 200, description = "Account removed or pending deletion"),
+        (status = 401, description = "Token or account id missing or incorrect", body = ErrorResponse),
+        (status = 403, description = "Password incorrect", body = ErrorResponse),
+    ),
+    security(("token" = [], "account_id" = []))
+)]
 pub async fn sign_out_account(
     ctx: RequirePermissionContext,
     Json(descriptor): Json<AccountSignOutDescriptor>,
@@ -184,27 +667,50 @@ pub async fn sign_out_account(
             if let Some(e) = super::INSTANCE.index().get(&ctx.account_id) {
                 *e.value()
             } else {
-                return Err(ResError(super::ManagerError::NotFound(ctx.account_id)).into());
+                return Err(super::ManagerError::NotFound(ctx.account_id).into());
             },
         )
         .unwrap()
         .read()
         .deref()
     {
-        digest(descriptor.password) == attributes.password_sha && tokens.token_usable(&ctx.token)
+        super::verify_password(&attributes.password_sha, &descriptor.password)
+            && tokens.token_usable(&ctx.token)
     } else {
-        return Err(ResError(super::Error::UserUnverified).into());
+        return Err(super::Error::UserUnverified.into());
     };
 
     if passwd_correct {
-        super::INSTANCE.remove(ctx.account_id);
+        let grace = crate::config::INSTANCE.account.deletion_grace_days;
+        if grace == 0 {
+            let id = ctx.account_id;
+            tokio::task::spawn_blocking(move || super::INSTANCE.remove(id))
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)??;
+        } else {
+            super::INSTANCE
+                .with_account_mut(ctx.account_id, |account| {
+                    account.delete_after(chrono::Duration::days(grace as i64))?;
+                    account.save_now()
+                })
+                .ok_or(super::ManagerError::NotFound(ctx.account_id))??;
+        }
         Ok(())
     } else {
-        Err(ResError(super::Error::PasswordIncorrect).into())
+        Err(super::Error::PasswordIncorrect.into())
     }
 }
 
 /// Get a user's account details.
+#[utoipa::path(
+    post,
+    path = "/api/account/view",
+    responses(
+        (status = 200, description = "Details of the account", body = ViewAccountResult),
+        (status = 401, description = "Token or account id missing or incorrect", body = ErrorResponse),
+    ),
+    security(("token" = [], "account_id" = []))
+)]
 pub async fn view_account(
     ctx: RequirePermissionContext,
 ) -> axum::response::Result<Json<ViewAccountResult>> {
@@ -226,13 +732,85 @@ pub async fn view_account(
             metadata: a.metadata().unwrap(),
             permissions: a.permissions().to_vec(),
             registration_time: attributes.registration_time,
+            pending_email: attributes.pending_email(),
+            sessions: None,
         }))
     } else {
         unreachable!()
     }
 }
 
+/// Export everything stored about the account, excluding password material and secrets.
+#[utoipa::path(
+    post,
+    path = "/api/account/export",
+    responses(
+        (status = 200, description = "Data of the account as a single document", body = Object),
+        (status = 401, description = "Token or account id missing or incorrect", body = ErrorResponse),
+    ),
+    security(("token" = [], "account_id" = []))
+)]
+pub async fn export_account(
+    ctx: RequirePermissionContext,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    let export = super::INSTANCE
+        .with_account(ctx.account_id, |account| match account {
+            Account::Verified {
+                id,
+                attributes,
+                tokens,
+                ..
+            } => Ok(json!({
+                "id": id,
+                "metadata": account.metadata()?,
+                "permissions": attributes.permissions,
+                "scoped_permissions": attributes.scoped_permissions,
+                "registration_time": attributes.registration_time,
+                "house_history": attributes.houses(),
+                "token_expiration_time": attributes.token_expiration_time,
+                "mute_login_notification": attributes.mute_login_notification,
+                "alternate_email": attributes.alternate_email.as_ref().map(|alternate| match alternate {
+                    super::AlternateEmail::Pending(cxt) => json!({ "email": cxt.email, "verified": false }),
+                    super::AlternateEmail::Verified(email) => json!({ "email": email, "verified": true }),
+                }),
+                "sessions": tokens
+                    .sessions()
+                    .map(|(expire_time, device)| json!({
+                        "expire_time": expire_time,
+                        "ip": device.ip,
+                        "user_agent": device.user_agent,
+                    }))
+                    .collect::<Vec<_>>(),
+                "api_keys": tokens
+                    .api_keys()
+                    .iter()
+                    .map(|key| ApiKeyInfo {
+                        name: key.name().to_string(),
+                        permissions: key.permissions().to_vec(),
+                        expire_time: key.expire_time(),
+                    })
+                    .collect::<Vec<_>>(),
+                "audit": super::INSTANCE.audit().concerning(*id),
+            })),
+            Account::Unverified(_) => Err(Error::UserUnverified),
+        })
+        .ok_or(super::ManagerError::NotFound(ctx.account_id))??;
+
+    Ok(Json(export))
+}
+
 /// Edit account metadata.
+#[utoipa::path(
+    post,
+    path = "/api/account/edit",
+    request_body = AccountEditDescriptor,
+    responses(
+        (status = 200, description = "Account edited"),
+        (status = 401, description = "Token or account id missing or incorrect", body = ErrorResponse),
+        (status = 403, description = "Old password incorrect or new password used recently", body = ErrorResponse),
+    ),
+    security(("token" = [], "account_id" = []))
+)]
 pub async fn edit_account(
     ctx: RequirePermissionContext,
     Json(descriptor): Json<AccountEditDescriptor>,
@@ -250,7 +828,7 @@ pub async fn edit_account(
         .write();
 
     for variant in descriptor.variants {
-        apply_edit_variant(variant, a.deref_mut()).map_err(ResError)?;
+        apply_edit_variant(variant, a.deref_mut())?;
     }
 
     a.save();
@@ -262,88 +840,149 @@ pub fn apply_edit_variant(mt: AccountEditVariant, account: &mut Account) -> Resu
     match account {
         Account::Unverified(_) => return Err(Error::UserUnverified),
         Account::Verified { attributes, .. } => match mt {
-            AccountEditVariant::Name(name) => attributes.name = name,
+            AccountEditVariant::Name(name) => attributes.name = super::normalize_name(&name)?,
             AccountEditVariant::SchoolId(id) => attributes.school_id = id,
-            AccountEditVariant::Phone(phone) => attributes.phone = phone,
-            AccountEditVariant::House(house) => attributes.house = house,
-            AccountEditVariant::Organization(org) => attributes.organization = org,
+            AccountEditVariant::Phone(phone) => {
+                super::check_phone(phone)?;
+                attributes.phone = phone
+            }
+            AccountEditVariant::House(house) => attributes.set_house(super::validate_house(
+                house,
+                &crate::config::INSTANCE.account.houses(),
+            )?),
+            AccountEditVariant::Organization(org) => {
+                attributes.organization = super::normalize_organization(
+                    org,
+                    &crate::config::INSTANCE.account.organizations,
+                )?
+            }
             AccountEditVariant::Password { old, new } => {
-                if attributes.password_sha == digest(old) {
-                    attributes.password_sha = digest(new)
+                if super::verify_password(&attributes.password_sha, &old) {
+                    attributes.change_password(&new)?
                 } else {
                     return Err(Error::PasswordIncorrect);
                 }
             }
             AccountEditVariant::TokenExpireTime(time) => attributes.token_expiration_time = time,
+            AccountEditVariant::MuteLoginNotification(mute) => {
+                attributes.mute_login_notification = mute
+            }
+            AccountEditVariant::AlternateEmail(email) => attributes.set_alternate_email(email)?,
         },
     }
     Ok(())
 }
 
+/// Change the password with the one-time token from a login requiring it.
+#[utoipa::path(
+    post,
+    path = "/api/account/change-password",
+    request_body = ChangePasswordDescriptor,
+    responses(
+        (status = 200, description = "Password changed, log in again with it"),
+        (status = 401, description = "Token or account id missing or incorrect", body = ErrorResponse),
+        (status = 403, description = "Password used recently", body = ErrorResponse),
+    ),
+    security(("token" = [], "account_id" = []))
+)]
+pub async fn change_password(
+    headers: HeaderMap,
+    Json(descriptor): Json<ChangePasswordDescriptor>,
+) -> axum::response::Result<()> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let token = header("Token").ok_or(Error::TokenIncorrect)?;
+    let account_id = header("AccountId")
+        .and_then(|value| value.parse().ok())
+        .ok_or(Error::TokenIncorrect)?;
+
+    super::INSTANCE
+        .with_account_mut(account_id, |account| {
+            let Account::Verified {
+                attributes, tokens, ..
+            } = account
+            else {
+                return Err(Error::UserUnverified);
+            };
+            if !attributes.must_change_password {
+                return Err(Error::NotAllowed);
+            }
+            if !tokens.password_change_token_usable(token) {
+                return Err(Error::TokenIncorrect);
+            }
+            // The token stays usable for another try if the password is rejected.
+            // Changes forced by admins aren't limited by the change interval.
+            attributes.change_password_with(&descriptor.password, chrono::Duration::zero())?;
+            tokens.take_password_change_token(token);
+            account.save_now()?;
+            Ok(())
+        })
+        .ok_or(super::ManagerError::NotFound(account_id))?
+        .map_err(Into::into)
+}
+
 /// Initialize a reset password verification.
+///
+/// Responds the same whether or not the email belongs to an account,
+/// so addresses can't be enumerated, except when requested too often. The code is only sent to verified
+/// accounts not resetting their password yet, at their email or verified alternate email,
+/// or by SMS to their phone number if configured.
+#[utoipa::path(
+    post,
+    path = "/api/account/reset-password",
+    request_body = ResetPasswordDescriptor,
+    responses(
+        (status = 200, description = "Verification code sent if the account exists"),
+        (status = 429, description = "Too many verification mails to this address", body = ErrorResponse),
+    )
+)]
 pub async fn reset_password(
     Json(descriptor): Json<ResetPasswordDescriptor>,
 ) -> axum::response::Result<()> {
-    if let Some(account) = super::INSTANCE
-        .accounts
-        .read()
-        .iter()
-        .find(|a| a.read().email() == &descriptor.email)
-    {
-        let ar = account.read();
-        if ar.email() == &descriptor.email {
-            return if let Account::Verified { verify, .. } = ar.deref() {
-                if matches!(verify, UserVerifyVariant::None) {
-                    drop(ar);
-
-                    let mut aw = account.write();
-
-                    if let Account::Verified { verify, .. } = aw.deref_mut() {
-                        *verify = UserVerifyVariant::ForgetPassword({
-                            let ctx = verify::Context {
-                                email: descriptor.email,
-                                code: {
-                                    let mut rng = rand::thread_rng();
-                                    rng.gen_range(100000..999999)
-                                },
-                                expire_time: Utc::now().naive_utc() + Duration::minutes(15),
-                            };
-
-                            ctx.send_verify();
-                            ctx
-                        });
-                    } else {
-                        unreachable!()
-                    };
+    // Requests are counted for unknown emails too, keeping the response the same.
+    verify::MAIL_LIMITER.acquire(&descriptor.email)?;
 
-                    aw.save();
-                    Ok(())
-                } else {
-                    Err(ResError(super::Error::UserUnverified).into())
-                }
-            } else {
-                Err(ResError(super::Error::UserUnverified).into())
-            };
+    // Emails of accounts take precedence over alternate emails.
+    // Without an account nothing is built or mailed.
+    let Some(id) = super::INSTANCE
+        .find_by_email(&descriptor.email)
+        .or_else(|| super::INSTANCE.find_by_recovery_email(&descriptor.email))
+    else {
+        return Ok(());
+    };
+
+    super::INSTANCE.with_account_mut(id, |account| {
+        if let Account::Verified {
+            attributes,
+            verify: verify @ UserVerifyVariant::None,
+            ..
+        } = account
+        {
+            let ctx = verify::Context::new(descriptor.email);
+            // Failures aren't answered, which would tell the account exists.
+            if let Err(err) = ctx.send_reset(attributes.phone) {
+                tracing::error!("failed to send code resetting password of account {id}: {err}");
+            }
+            *verify = UserVerifyVariant::ForgetPassword(ctx);
+            account.save();
         }
-    }
+    });
 
-    Err(ResError(super::ManagerError::NotFound(0)).into())
+    Ok(())
 }
 
 /// Manage accounts for admins.
 pub mod manage {
     use crate::account::verify::Tokens;
     use crate::account::{self, Error, Permission};
-    use crate::account::{Account, UserAttributes};
-    use crate::{RequirePermissionContext, ResError};
+    use crate::account::{Account, HouseAssignment, Note, UserAttributes};
+    use crate::RequirePermissionContext;
+    use axum::extract::Query;
+    use axum::http::StatusCode;
     use axum::Json;
-    use chrono::Utc;
     use parking_lot::RwLock;
     use serde_json::json;
-    use sha256::digest;
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    use std::ops::{Deref, DerefMut};
+    use std::ops::Deref;
+    use std::sync::Arc;
 
     use sms3_shared::account::handle::manage::*;
 
@@ -352,7 +991,17 @@ pub mod manage {
         ctx: RequirePermissionContext,
         Json(descriptor): Json<MakeAccountDescriptor>,
     ) -> axum::response::Result<Json<serde_json::Value>> {
-        ctx.valid(&[Permission::ManageAccounts]).map_err(ResError)?;
+        ctx.valid(&[Permission::ManageAccounts])?;
+
+        let name = account::normalize_name(&descriptor.name)?;
+        account::check_phone(descriptor.phone)?;
+        account::check_password(&descriptor.password)?;
+        let house =
+            account::validate_house(descriptor.house, &crate::config::INSTANCE.account.houses())?;
+        let organization = account::normalize_organization(
+            descriptor.organization,
+            &crate::config::INSTANCE.account.organizations,
+        )?;
 
         let mut b = crate::account::INSTANCE.inner().write();
         let a = b
@@ -367,29 +1016,40 @@ pub mod manage {
             .read();
 
         let account = Account::Verified {
-            id: {
-                let mut hasher = DefaultHasher::new();
-                descriptor.email.hash(&mut hasher);
-                hasher.finish()
-            },
+            id: crate::account::INSTANCE.assign_id(
+                crate::config::INSTANCE.account.id_strategy,
+                &descriptor.email,
+            ),
 
             attributes: UserAttributes {
-                email: descriptor.email,
-                name: descriptor.name,
+                email: account::normalize_email(descriptor.email),
+                name,
                 school_id: descriptor.school_id,
                 phone: descriptor.phone,
-                house: descriptor.house,
-                organization: descriptor.organization,
-                permissions: descriptor
-                    .permissions
-                    .iter()
-                    // prevent permission overflowing
-                    .filter(|e| a.has_permission(**e))
-                    .copied()
-                    .collect(),
-                registration_time: Utc::now(),
-                password_sha: digest(descriptor.password),
+                house,
+                organization,
+                permissions: account::normalize_permissions(
+                    descriptor
+                        .permissions
+                        .iter()
+                        // prevent permission overflowing
+                        .filter(|e| a.has_permission(**e))
+                        .copied()
+                        .collect(),
+                ),
+                registration_time: crate::clock::now(),
+                password_sha: account::hash_password(&descriptor.password),
                 token_expiration_time: 5,
+                mute_login_notification: false,
+                password_history: vec![],
+                admin_notes: vec![],
+                house_history: vec![],
+                scoped_permissions: vec![],
+                password_changed_at: None,
+                alternate_email: None,
+                must_change_password: false,
+                pending_deletion: None,
+                suspended: false,
             },
 
             tokens: Tokens::new(),
@@ -398,8 +1058,14 @@ pub mod manage {
 
         drop(a);
 
-        if crate::account::INSTANCE.index().contains_key(&account.id()) {
-            return Err(ResError(account::Error::Conflict).into());
+        if let Account::Verified { attributes, .. } = &account {
+            attributes.validate_dates()?;
+        }
+
+        if crate::account::INSTANCE.index().contains_key(&account.id())
+            || b.iter().any(|e| e.read().has_email(account.email()))
+        {
+            return Err(account::Error::Conflict.into());
         }
 
         crate::account::INSTANCE
@@ -409,7 +1075,7 @@ pub mod manage {
         account.save();
 
         let id = account.id();
-        b.push(RwLock::new(account));
+        b.push(Arc::new(RwLock::new(account)));
 
         Ok(Json(json!({ "account_id": id })))
     }
@@ -419,7 +1085,7 @@ pub mod manage {
         ctx: RequirePermissionContext,
         Json(descriptor): Json<ViewAccountDescriptor>,
     ) -> axum::response::Result<Json<serde_json::Value>> {
-        ctx.valid(&[Permission::ViewAccounts]).map_err(ResError)?;
+        valid_any_scope(&ctx, Permission::ViewAccounts)?;
 
         let ar = crate::account::INSTANCE.inner().read();
         let mut vec = Vec::new();
@@ -440,14 +1106,26 @@ pub mod manage {
             let account = a.read();
 
             vec.push(
-                if let Account::Verified { attributes, .. } = account.deref() {
+                if let Account::Verified {
+                    attributes, tokens, ..
+                } = account.deref()
+                {
                     let permissions = account.permissions();
-                    if ctx.try_valid(permissions).map_err(ResError)? {
+                    if ctx.try_valid_in(&[Permission::ViewAccounts], account.organization())?
+                        && ctx.try_valid(permissions)?
+                    {
                         ViewAccountResult::Ok(super::ViewAccountResult {
                             id: *aid,
                             metadata: account.metadata().unwrap(),
                             permissions: permissions.to_vec(),
                             registration_time: attributes.registration_time,
+                            pending_email: attributes.pending_email(),
+                            sessions: Some(super::SessionSummary {
+                                active_token_count: tokens.active_count(),
+                                oldest_token_age: tokens
+                                    .oldest_active_age()
+                                    .map(|age| age.num_seconds()),
+                            }),
                         })
                     } else {
                         ViewAccountResult::Err {
@@ -474,35 +1152,508 @@ pub mod manage {
         ctx: RequirePermissionContext,
         Json(descriptor): Json<AccountModifyDescriptor>,
     ) -> axum::response::Result<()> {
-        ctx.valid(&[Permission::ManageAccounts]).map_err(ResError)?;
+        valid_any_scope(&ctx, Permission::ManageAccounts)?;
+        valid_target(&ctx, descriptor.account_id, Permission::ManageAccounts)?;
 
-        let ar = crate::account::INSTANCE.inner().read();
-        let mut a = ar
-            .get(
-                if let Some(e) = crate::account::INSTANCE.index().get(&descriptor.account_id) {
-                    *e.value()
-                } else {
-                    return Err(
-                        ResError(account::ManagerError::NotFound(descriptor.account_id)).into(),
-                    );
-                },
-            )
-            .unwrap()
-            .write();
+        // Variants are authorized before locking the target, since validating
+        // the context locks its own account, which may be the target.
+        let variants = descriptor
+            .variants
+            .into_iter()
+            .map(|variant| authorize_account_modify_variant(variant, &ctx))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        ctx.valid(a.permissions()).map_err(ResError)?;
-        for variant in descriptor.variants {
-            apply_account_modify_variant(variant, a.deref_mut(), &ctx).map_err(ResError)?;
-        }
+        crate::account::INSTANCE
+            .with_account_mut(descriptor.account_id, |account| {
+                let result = variants
+                    .into_iter()
+                    .try_for_each(|variant| apply_account_modify_variant(variant, account));
+                if result.is_ok() {
+                    account.save();
+                }
+                result
+            })
+            .ok_or(account::ManagerError::NotFound(descriptor.account_id))?
+            .map_err(Into::into)
+    }
+
+    /// Get aggregates over all accounts.
+    pub async fn statistics(
+        ctx: RequirePermissionContext,
+    ) -> axum::response::Result<Json<Statistics>> {
+        ctx.valid(&[Permission::ViewAccounts])?;
+        Ok(Json(crate::account::INSTANCE.statistics()))
+    }
 
-        a.save();
+    /// Stream all verified accounts as NDJSON, one [`super::ViewAccountResult`] per line
+    /// in the order of ids.
+    ///
+    /// Accounts are read one by one, each locked only while it's serialized,
+    /// so nothing is buffered beyond the line being sent. The export isn't a
+    /// point-in-time view: accounts changed, created or removed while it's
+    /// streamed may be exported before or after the change, or not at all.
+    pub async fn export_accounts(
+        ctx: RequirePermissionContext,
+    ) -> axum::response::Result<axum::response::Response> {
+        use axum::response::IntoResponse;
+
+        ctx.valid(&[Permission::ViewAccounts])?;
+
+        let (mut sender, body) = hyper::Body::channel();
+        tokio::spawn(async move {
+            for id in crate::account::INSTANCE.snapshot_ids() {
+                let Some(Some(line)) = crate::account::INSTANCE.with_account(id, export_line)
+                else {
+                    continue;
+                };
+                // The client went away.
+                if sender.send_data(line.into()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok((
+            [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+            axum::body::boxed(body),
+        )
+            .into_response())
+    }
+
+    /// Serialize target account as a line of [`export_accounts`],
+    /// `None` if it's unverified.
+    fn export_line(account: &Account) -> Option<Vec<u8>> {
+        let Account::Verified { id, attributes, .. } = account else {
+            return None;
+        };
+        let mut line = serde_json::to_vec(&super::ViewAccountResult {
+            id: *id,
+            metadata: account.metadata().ok()?,
+            permissions: attributes.permissions.clone(),
+            registration_time: attributes.registration_time,
+            pending_email: attributes.pending_email(),
+            sessions: None,
+        })
+        .ok()?;
+        line.push(b'\n');
+        Some(line)
+    }
+
+    /// Attach an internal note to an account.
+    pub async fn add_note(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<AddNoteDescriptor>,
+    ) -> axum::response::Result<()> {
+        valid_any_scope(&ctx, Permission::ManageAccounts)?;
+        valid_target(&ctx, descriptor.account_id, Permission::ManageAccounts)?;
+
+        crate::account::INSTANCE
+            .with_account_mut(descriptor.account_id, |account| {
+                let result = account.add_note(ctx.account_id, descriptor.text);
+                if result.is_ok() {
+                    account.save();
+                }
+                result
+            })
+            .ok_or(account::ManagerError::NotFound(descriptor.account_id))?
+            .map_err(Into::into)
+    }
+
+    /// Require an account to change its password on next login,
+    /// revoking its tokens and API keys, as the account may be compromised.
+    pub async fn force_password_change(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<ForcePasswordChangeDescriptor>,
+    ) -> axum::response::Result<()> {
+        valid_any_scope(&ctx, Permission::ManageAccounts)?;
+        valid_target(&ctx, descriptor.account_id, Permission::ManageAccounts)?;
+
+        crate::account::INSTANCE
+            .with_account_mut(descriptor.account_id, |account| match account {
+                Account::Verified {
+                    attributes, tokens, ..
+                } => {
+                    attributes.must_change_password = true;
+                    tokens.revoke_all();
+                    account.save_now()
+                }
+                Account::Unverified(_) => Err(Error::UserUnverified),
+            })
+            .ok_or(account::ManagerError::NotFound(descriptor.account_id))?
+            .map_err(Into::into)
+    }
+
+    /// Cancel the pending deletion of an account.
+    pub async fn restore_account(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<RestoreAccountDescriptor>,
+    ) -> axum::response::Result<()> {
+        valid_any_scope(&ctx, Permission::ManageAccounts)?;
+        valid_target(&ctx, descriptor.account_id, Permission::ManageAccounts)?;
+
+        crate::account::INSTANCE.restore(descriptor.account_id)?;
         Ok(())
     }
 
+    /// Send a new verification code to a pending account on behalf of its user.
+    ///
+    /// Bypassing the resend cooldown requires [`Permission::Op`].
+    pub async fn resend_verification(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<ResendVerificationDescriptor>,
+    ) -> axum::response::Result<Json<ResendVerificationResult>> {
+        valid_any_scope(&ctx, Permission::ManageAccounts)?;
+        valid_target(&ctx, descriptor.account_id, Permission::ManageAccounts)?;
+        if descriptor.bypass_cooldown {
+            ctx.valid(&[Permission::Op])?;
+        }
+
+        let code_sent = crate::account::INSTANCE
+            .resend_verification(descriptor.account_id, descriptor.bypass_cooldown)?;
+        if code_sent {
+            crate::account::INSTANCE
+                .audit()
+                .record([account::audit::Entry {
+                    time: crate::clock::now(),
+                    by: ctx.account_id,
+                    target: descriptor.account_id,
+                    action: account::audit::Action::ResendVerification {
+                        bypass_cooldown: descriptor.bypass_cooldown,
+                    },
+                }]);
+        }
+        Ok(Json(ResendVerificationResult { code_sent }))
+    }
+
+    /// Enter or leave maintenance mode, in which mutations are rejected
+    /// while reads keep working. Requires [`Permission::Op`].
+    pub async fn set_maintenance(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<MaintenanceDescriptor>,
+    ) -> axum::response::Result<Json<MaintenanceResult>> {
+        ctx.valid(&[Permission::Op])?;
+
+        if descriptor.enabled {
+            crate::maintenance::enable(
+                descriptor
+                    .retry_after
+                    .unwrap_or(crate::maintenance::DEFAULT_RETRY_AFTER),
+            );
+        } else {
+            crate::maintenance::disable();
+        }
+        tracing::info!(
+            "maintenance mode {} by account {}",
+            if descriptor.enabled {
+                "entered"
+            } else {
+                "left"
+            },
+            ctx.account_id
+        );
+        Ok(Json(MaintenanceResult {
+            retry_after: crate::maintenance::retry_after(),
+        }))
+    }
+
+    /// List internal notes of an account.
+    pub async fn list_notes(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<ListNotesDescriptor>,
+    ) -> axum::response::Result<Json<Vec<Note>>> {
+        valid_any_scope(&ctx, Permission::ViewAccounts)?;
+        valid_target(&ctx, descriptor.account_id, Permission::ViewAccounts)?;
+
+        crate::account::INSTANCE
+            .with_account(descriptor.account_id, |account| {
+                Json(account.notes().to_vec())
+            })
+            .ok_or(account::ManagerError::NotFound(descriptor.account_id).into())
+    }
+
+    /// List houses an account has been assigned to, the oldest first.
+    pub async fn house_history(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<HouseHistoryDescriptor>,
+    ) -> axum::response::Result<Json<Vec<HouseAssignment>>> {
+        valid_any_scope(&ctx, Permission::ViewAccounts)?;
+        valid_target(&ctx, descriptor.account_id, Permission::ViewAccounts)?;
+
+        crate::account::INSTANCE
+            .with_account(descriptor.account_id, |account| match account {
+                Account::Verified { attributes, .. } => Ok(Json(attributes.houses())),
+                Account::Unverified(_) => Err(Error::UserUnverified),
+            })
+            .ok_or(account::ManagerError::NotFound(descriptor.account_id))?
+            .map_err(Into::into)
+    }
+
+    /// Check the context has target permission globally or in any organization,
+    /// so that it may act on some accounts.
+    fn valid_any_scope(
+        ctx: &RequirePermissionContext,
+        permission: Permission,
+    ) -> Result<(), account::ManagerError> {
+        ctx.valid(&[])?;
+        crate::account::INSTANCE
+            .with_account(ctx.account_id, |account| {
+                account.has_permission(permission)
+                    || account
+                        .scoped_permissions()
+                        .iter()
+                        .any(|e| e.permission == permission)
+            })
+            .filter(|e| *e)
+            .map(|_| ())
+            .ok_or(account::ManagerError::Account(
+                ctx.account_id,
+                Error::PermissionDenied(permission),
+            ))
+    }
+
+    /// Check the context has target permission in the organization of target account,
+    /// and owns all permissions of it.
+    ///
+    /// The target is copied out first, since validating
+    /// the context locks its own account, which may be the target.
+    fn valid_target(
+        ctx: &RequirePermissionContext,
+        id: u64,
+        permission: Permission,
+    ) -> Result<(), account::ManagerError> {
+        let (permissions, scoped_permissions, organization) = crate::account::INSTANCE
+            .with_account(id, |account| {
+                (
+                    account.permissions().to_vec(),
+                    account.scoped_permissions().to_vec(),
+                    account.organization().map(str::to_owned),
+                )
+            })
+            .ok_or(account::ManagerError::NotFound(id))?;
+
+        ctx.valid_in(&[permission], organization.as_deref())?;
+        ctx.valid(&permissions)?;
+        scoped_permissions
+            .iter()
+            .try_for_each(|e| ctx.valid_in(&[e.permission], Some(&e.organization)))
+    }
+
+    /// Search accounts by name.
+    pub async fn search_account(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<SearchAccountDescriptor>,
+    ) -> axum::response::Result<Json<serde_json::Value>> {
+        ctx.valid(&[Permission::ViewAccounts])?;
+
+        let results = crate::account::INSTANCE.search_by_name(&descriptor.query, descriptor.limit);
+
+        Ok(Json(
+            json!({ "results": serde_json::to_value(results).unwrap_or_default() }),
+        ))
+    }
+
+    /// List accounts in a house.
+    pub async fn accounts_by_house(
+        ctx: RequirePermissionContext,
+        Query(query): Query<HouseQuery>,
+    ) -> axum::response::Result<Json<serde_json::Value>> {
+        ctx.valid(&[Permission::ViewAccounts])?;
+        account::validate_house(
+            Some(query.house.clone()),
+            &crate::config::INSTANCE.account.houses(),
+        )
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        let results = crate::account::INSTANCE.by_house(query.house);
+
+        Ok(Json(
+            json!({ "results": serde_json::to_value(results).unwrap_or_default() }),
+        ))
+    }
+
+    /// List accounts in an organization.
+    pub async fn accounts_by_organization(
+        ctx: RequirePermissionContext,
+        Query(query): Query<OrganizationQuery>,
+    ) -> axum::response::Result<Json<serde_json::Value>> {
+        ctx.valid(&[Permission::ViewAccounts])?;
+
+        let results = crate::account::INSTANCE.by_organization(&query.organization);
+
+        Ok(Json(
+            json!({ "results": serde_json::to_value(results).unwrap_or_default() }),
+        ))
+    }
+
+    /// Introspect a token, telling whether it's active and which account it belongs to.
+    pub async fn introspect_token(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<IntrospectTokenDescriptor>,
+    ) -> axum::response::Result<Json<IntrospectTokenResult>> {
+        ctx.valid(&[Permission::ViewAccounts])?;
+
+        // Only the owner found through the token index is locked.
+        let result = crate::account::INSTANCE
+            .find_by_token(&descriptor.token)
+            .and_then(|owner| {
+                crate::account::INSTANCE.with_account(owner, |account| match account {
+                    Account::Verified { id, tokens, .. } => tokens
+                        .active_expire_time(&descriptor.token)
+                        .map(|expire_time| IntrospectTokenResult {
+                            active: true,
+                            sub: Some(*id),
+                            exp: expire_time.map(|e| e.timestamp()),
+                            permissions: Some(account.permissions().to_vec()),
+                        }),
+                    Account::Unverified(_) => None,
+                })
+            })
+            .flatten();
+
+        Ok(Json(result.unwrap_or_default()))
+    }
+
+    /// Grant a permission to multiple accounts.
+    pub async fn grant_permission(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<GrantPermissionDescriptor>,
+    ) -> axum::response::Result<Json<serde_json::Value>> {
+        // prevent permission overflowing
+        ctx.valid(&[Permission::ManageAccounts, descriptor.permission])?;
+
+        let (ids, results) = tokio::task::spawn_blocking(move || {
+            let results = crate::account::INSTANCE.grant_permission_bulk(
+                &descriptor.accounts,
+                descriptor.permission,
+                ctx.account_id,
+            );
+            (descriptor.accounts, results)
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let vec: Vec<GrantPermissionResult> = ids
+            .into_iter()
+            .zip(results)
+            .map(|(id, result)| match result {
+                Ok(()) => GrantPermissionResult::Ok(id),
+                Err(err) => GrantPermissionResult::Err {
+                    id,
+                    error: err.to_string(),
+                },
+            })
+            .collect();
+
+        Ok(Json(
+            json!({ "results": serde_json::to_value(vec).unwrap_or_default() }),
+        ))
+    }
+
+    /// Remove accounts with their files, reporting the result of each.
+    ///
+    /// Each account must be manageable by the context, see [`valid_target`].
+    pub async fn remove_accounts(
+        ctx: RequirePermissionContext,
+        Json(descriptor): Json<RemoveAccountsDescriptor>,
+    ) -> axum::response::Result<Json<serde_json::Value>> {
+        valid_any_scope(&ctx, Permission::ManageAccounts)?;
+
+        // Accounts the context can't manage are reported without being removed.
+        let checks: Vec<_> = descriptor
+            .accounts
+            .into_iter()
+            .map(|id| (id, valid_target(&ctx, id, Permission::ManageAccounts)))
+            .collect();
+        let ids: Vec<u64> = checks
+            .iter()
+            .filter(|(_, check)| check.is_ok())
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut removed = tokio::task::spawn_blocking(move || {
+            crate::account::INSTANCE.remove_many(&ids).into_iter()
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let vec: Vec<RemoveAccountResult> = checks
+            .into_iter()
+            .map(|(id, check)| {
+                match check.and_then(|()| removed.next().expect("a result per removed id")) {
+                    Ok(metadata) => RemoveAccountResult::Ok { id, metadata },
+                    Err(err) => RemoveAccountResult::Err {
+                        id,
+                        error: err.to_string(),
+                    },
+                }
+            })
+            .collect();
+
+        Ok(Json(
+            json!({ "results": serde_json::to_value(vec).unwrap_or_default() }),
+        ))
+    }
+
+    /// Normalize a variant and narrow it to what the context may do.
+    ///
+    /// Moving an account into an organization requires managing accounts there,
+    /// and only permissions the context has can be granted.
+    fn authorize_account_modify_variant(
+        mt: AccountModifyVariant,
+        ctx: &RequirePermissionContext,
+    ) -> Result<AccountModifyVariant, account::ManagerError> {
+        let organizations = &crate::config::INSTANCE.account.organizations;
+        let invalid = |err| account::ManagerError::Account(ctx.account_id, err);
+
+        Ok(match mt {
+            AccountModifyVariant::Name(name) => {
+                AccountModifyVariant::Name(account::normalize_name(&name).map_err(invalid)?)
+            }
+            AccountModifyVariant::Phone(phone) => {
+                account::check_phone(phone).map_err(invalid)?;
+                AccountModifyVariant::Phone(phone)
+            }
+            AccountModifyVariant::House(house) => AccountModifyVariant::House(
+                account::validate_house(house, &crate::config::INSTANCE.account.houses())
+                    .map_err(invalid)?,
+            ),
+            AccountModifyVariant::Organization(org) => {
+                let org = account::normalize_organization(org, organizations).map_err(invalid)?;
+                ctx.valid_in(&[Permission::ManageAccounts], org.as_deref())?;
+                AccountModifyVariant::Organization(org)
+            }
+            AccountModifyVariant::Permission(permissions) => {
+                let mut granted = Vec::new();
+                for permission in permissions {
+                    if ctx.try_valid(&[permission])? {
+                        granted.push(permission);
+                    }
+                }
+                AccountModifyVariant::Permission(granted)
+            }
+            AccountModifyVariant::ScopedPermission(permissions) => {
+                let mut granted = Vec::new();
+                for mut permission in permissions {
+                    permission.organization = account::normalize_organization(
+                        Some(permission.organization),
+                        organizations,
+                    )
+                    .map_err(invalid)?
+                    .ok_or(invalid(Error::InvalidOrganization))?;
+                    if ctx.try_valid_in(&[permission.permission], Some(&permission.organization))? {
+                        granted.push(permission);
+                    }
+                }
+                AccountModifyVariant::ScopedPermission(granted)
+            }
+            variant => variant,
+        })
+    }
+
+    /// Apply an authorized variant, see [`authorize_account_modify_variant`].
     fn apply_account_modify_variant(
         mt: AccountModifyVariant,
         account: &mut Account,
-        context: &RequirePermissionContext,
     ) -> Result<(), Error> {
         match account {
             Account::Unverified(_) => return Err(Error::UserUnverified),
@@ -510,27 +1661,16 @@ pub mod manage {
                 AccountModifyVariant::Name(name) => attributes.name = name,
                 AccountModifyVariant::SchoolId(id) => attributes.school_id = id,
                 AccountModifyVariant::Phone(phone) => attributes.phone = phone,
-                AccountModifyVariant::House(house) => attributes.house = house,
+                AccountModifyVariant::House(house) => attributes.set_house(house),
                 AccountModifyVariant::Organization(org) => attributes.organization = org,
-                AccountModifyVariant::Email(email) => attributes.email = email,
+                AccountModifyVariant::Email(email) => {
+                    attributes.email = account::normalize_email(email)
+                }
                 AccountModifyVariant::Permission(permissions) => {
-                    let am = crate::account::INSTANCE.inner().read();
-                    let a = am
-                        .get(
-                            *crate::account::INSTANCE
-                                .index()
-                                .get(&context.account_id)
-                                .unwrap()
-                                .value(),
-                        )
-                        .unwrap()
-                        .read();
-                    attributes.permissions = a
-                        .permissions()
-                        .iter()
-                        .filter(|e| permissions.contains(e))
-                        .copied()
-                        .collect();
+                    attributes.set_permissions(permissions)
+                }
+                AccountModifyVariant::ScopedPermission(permissions) => {
+                    attributes.scoped_permissions = permissions
                 }
             },
         }
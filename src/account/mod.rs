@@ -1,22 +1,38 @@
+pub mod audit;
+pub mod bounded;
+pub mod event;
 pub mod handle;
+pub mod oidc;
+pub mod redact;
+pub mod store;
 pub mod verify;
 
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Datelike, Duration, Utc};
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
-use parking_lot::RwLock;
-use rand::Rng;
+use parking_lot::{
+    lock_api::{ArcRwLockReadGuard, ArcRwLockWriteGuard},
+    RawRwLock, RwLock, RwLockUpgradableReadGuard,
+};
 use serde::{Deserialize, Serialize};
 use sha256::digest;
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{BTreeMap, HashMap},
+    future::Future,
     hash::{Hash, Hasher},
     ops::{Deref, DerefMut},
+    sync::Arc,
 };
-use tracing::debug;
+use tracing::{debug, error, warn};
 
+use sms3_shared::account::handle::{
+    manage::Statistics, FieldError, PendingEmail, ValidationErrors,
+};
 pub use sms3_shared::account::*;
 
+pub use redact::mask_email;
+use redact::REDACTED;
+
 /// The static instance of accounts.
 pub static INSTANCE: Lazy<AccountManager> = Lazy::new(AccountManager::new);
 
@@ -24,6 +40,8 @@ pub static INSTANCE: Lazy<AccountManager> = Lazy::new(AccountManager::new);
 pub enum Error {
     #[error("verification code not match")]
     VerificationCode,
+    #[error("verification code expired")]
+    VerificationExpired,
     #[error("user has not been verified")]
     UserUnverified,
     #[error("user already registered")]
@@ -38,23 +56,105 @@ pub enum Error {
     DateOutOfRange,
     #[error("smtp error while sending verification mail: {0}")]
     MailSend(lettre::transport::smtp::Error),
-    #[error("permission denied")]
-    PermissionDenied,
+    #[error("verification code not delivered: {0}")]
+    CodeDelivery(&'static str),
+    #[error("permission denied, {0:?} required")]
+    PermissionDenied(Permission),
+    #[error("operation not allowed")]
+    NotAllowed,
+    #[error("registration is closed")]
+    RegistrationClosed,
     #[error("user with same id already exists")]
     Conflict,
+    #[error("password was used recently")]
+    PasswordReused,
+    #[error("password was changed too recently")]
+    PasswordChangeTooSoon,
+    #[error("api key not found")]
+    ApiKeyNotFound,
+    #[error("api key with same name already exists")]
+    ApiKeyConflict,
+    #[error("organization is not registered")]
+    InvalidOrganization,
+    #[error("name is empty")]
+    InvalidName,
+    #[error("note is empty")]
+    InvalidNote,
+    #[error("{0} contains control characters")]
+    ControlCharacter(&'static str),
+    #[error("{0} is required")]
+    Required(&'static str),
+    #[error("{field} is longer than {max} characters")]
+    TooLong { field: &'static str, max: usize },
+    #[error("too many verification mails sent to this address, try again later")]
+    RateLimited,
+    #[error("too many sessions, log out of some devices first")]
+    TooManySessions,
+    #[error("password must be changed before continuing")]
+    PasswordChangeRequired,
+    #[error("not a valid email address")]
+    InvalidEmailFormat,
+    #[error("house is not configured")]
+    InvalidHouse,
+    #[error("too many failed logins, try again later")]
+    AccountLocked,
+    #[error("password is shorter than {} characters", PASSWORD_MIN_LEN)]
+    WeakPassword,
+    #[error("not a valid mobile phone number")]
+    InvalidPhone,
+    #[error("failed to save account: {0}")]
+    Save(std::io::Error),
+    #[error("account is pending deletion")]
+    PendingDeletion,
+    #[error("account is not pending deletion")]
+    NotPendingDeletion,
+    #[error("account is suspended")]
+    AccountSuspended,
+    #[error("unlock token incorrect, used or expired")]
+    UnlockTokenIncorrect,
+    #[error("signing in with an identity provider is not configured")]
+    SsoDisabled,
+    #[error("sign-in state incorrect, used or expired")]
+    SsoStateIncorrect,
+    #[error("identity provider failed: {0}")]
+    SsoProvider(String),
+    #[error("id token is invalid: {0}")]
+    SsoTokenInvalid(&'static str),
 }
 
+/// Result of account operations, failing with [`Error`] by default.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
 impl crate::AsResCode for Error {
+    fn missing_permission(&self) -> Option<Permission> {
+        match self {
+            Error::PermissionDenied(permission) => Some(*permission),
+            _ => None,
+        }
+    }
+
     fn response_code(&self) -> hyper::StatusCode {
         match self {
-            Error::MailSend(_) => hyper::StatusCode::INTERNAL_SERVER_ERROR,
-            Error::Conflict => hyper::StatusCode::CONFLICT,
+            Error::MailSend(_) | Error::Save(_) | Error::CodeDelivery(_) => {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Error::Conflict | Error::UserRegistered | Error::ApiKeyConflict => {
+                hyper::StatusCode::CONFLICT
+            }
+            Error::ApiKeyNotFound | Error::SsoDisabled => hyper::StatusCode::NOT_FOUND,
+            Error::TokenIncorrect | Error::SsoTokenInvalid(_) => hyper::StatusCode::UNAUTHORIZED,
+            Error::SsoProvider(_) => hyper::StatusCode::BAD_GATEWAY,
+            Error::RateLimited | Error::AccountLocked => hyper::StatusCode::TOO_MANY_REQUESTS,
+            Error::InvalidEmailFormat => hyper::StatusCode::BAD_REQUEST,
             _ => hyper::StatusCode::FORBIDDEN,
         }
     }
 }
 
 /// Represent an account, including unverified and verified.
+// Accounts live behind their own locks, so the unverified variant
+// being smaller doesn't waste much.
+#[allow(clippy::large_enum_variant)]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Account {
     /// An unverified account.
@@ -63,6 +163,7 @@ pub enum Account {
     /// A normal user.
     Verified {
         /// Identifier of this user.
+        #[serde(with = "store::u64_string")]
         id: u64,
         /// Attributes of this user.
         attributes: UserAttributes,
@@ -73,74 +174,102 @@ pub enum Account {
     },
 }
 
-impl Account {
-    /// Create a new unverified account.
-    pub fn new(email: lettre::Address) -> Result<Self, Error> {
-        static DOMAINS: once_cell::sync::Lazy<std::collections::HashSet<String>> =
-            once_cell::sync::Lazy::new(|| {
-                let mut set = std::collections::HashSet::new();
+/// Accounts are equal if they have the same id, regardless of their contents.
+impl PartialEq for Account {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
 
-                set.insert("i.pkuschool.edu.cn".to_string());
-                set.insert("pkuschool.edu.cn".to_string());
+impl Eq for Account {}
 
-                set
-            });
+/// Consistent with [`PartialEq`], only the id is hashed.
+impl Hash for Account {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state)
+    }
+}
 
-        if !DOMAINS.contains(email.domain()) {
+impl Account {
+    /// Create a new unverified account, whose code is yet to be sent
+    /// with [`verify::Context::send_verify`].
+    pub fn new(email: lettre::Address) -> Result<Self, Error> {
+        if !is_school_email(&email) {
             return Err(Error::EmailDomainNotInSchool);
         }
+        verify::MAIL_LIMITER.acquire(&email)?;
 
-        Ok(Self::Unverified({
-            let ctx = verify::Context {
-                email,
-                code: {
-                    let mut rng = rand::thread_rng();
-                    rng.gen_range(100000..999999)
-                },
-                expire_time: Utc::now().naive_utc() + Duration::minutes(15),
-            };
+        Ok(Self::Unverified(verify::Context::new(email)))
+    }
 
-            ctx.send_verify();
+    /// Check `verify_code` against the pending registration of this account
+    /// without activating it, so passwords are only hashed for matching codes.
+    pub(crate) fn check_activation_code(&self, verify_code: u32) -> Result<(), Error> {
+        match self {
+            Self::Unverified(cxt) if cxt.is_expired() => Err(Error::VerificationExpired),
+            Self::Unverified(cxt) if !cxt.matches(verify_code) => Err(Error::VerificationCode),
+            Self::Unverified(_) => Ok(()),
+            Self::Verified { .. } => Err(Error::UserRegistered),
+        }
+    }
 
-            ctx
-        }))
+    /// Check `verify_code` against the pending password reset of this account
+    /// without resetting it, like [`Self::check_activation_code`].
+    ///
+    /// Expired codes pass, as [`Self::verify`] forgets them before hashing.
+    pub(crate) fn check_reset_code(&self, verify_code: u32) -> Result<(), Error> {
+        match self {
+            Self::Unverified(_) => Err(Error::UserUnverified),
+            Self::Verified { verify, .. } => match verify {
+                UserVerifyVariant::None => Err(Error::NotAllowed),
+                UserVerifyVariant::ForgetPassword(cxt)
+                    if !cxt.is_expired() && !cxt.matches(verify_code) =>
+                {
+                    Err(Error::VerificationCode)
+                }
+                UserVerifyVariant::ForgetPassword(_) => Ok(()),
+            },
+        }
     }
 
     /// Verify this account based on the variant.
-    fn verify(&mut self, verify_code: u32, variant: AccountVerifyVariant) -> Result<(), Error> {
+    pub(crate) fn verify(
+        &mut self,
+        verify_code: u32,
+        variant: AccountVerifyVariant,
+    ) -> Result<(), Error> {
         match variant {
-            AccountVerifyVariant::Activate(attributes) => {
-                if let Self::Unverified(cxt) = self {
-                    if cxt.code != verify_code {
-                        return Err(Error::VerificationCode);
-                    }
-                    *self = Self::Verified {
-                        id: {
-                            let mut hasher = DefaultHasher::new();
-                            attributes.email.hash(&mut hasher);
-                            hasher.finish()
-                        },
-                        attributes,
-                        tokens: verify::Tokens::new(),
-                        verify: UserVerifyVariant::None,
-                    };
-                    Ok(())
-                } else {
-                    Err(Error::UserRegistered)
-                }
+            AccountVerifyVariant::Activate(id, attributes) => {
+                self.check_activation_code(verify_code)?;
+                attributes.validate_dates()?;
+                *self = Self::Verified {
+                    id,
+                    attributes: *attributes,
+                    tokens: verify::Tokens::new(),
+                    verify: UserVerifyVariant::None,
+                };
+                Ok(())
             }
             AccountVerifyVariant::ResetPassword(password) => {
                 if let Self::Verified {
-                    attributes, verify, ..
+                    attributes,
+                    tokens,
+                    verify,
+                    ..
                 } = self
                 {
                     match verify {
-                        UserVerifyVariant::None => Err(Error::PermissionDenied),
+                        UserVerifyVariant::None => Err(Error::NotAllowed),
                         UserVerifyVariant::ForgetPassword(cxt) => {
-                            if cxt.code != verify_code {
+                            if cxt.is_expired() {
+                                *verify = UserVerifyVariant::None;
+                                return Err(Error::VerificationExpired);
+                            }
+                            if !cxt.matches(verify_code) {
                                 return Err(Error::VerificationCode);
                             }
-                            attributes.password_sha = digest(password);
+                            attributes.change_password(&password)?;
+                            tokens.revoke_all();
                             *verify = UserVerifyVariant::None;
                             Ok(())
                         }
@@ -149,21 +278,93 @@ impl Account {
                     Err(Error::UserUnverified)
                 }
             }
+            AccountVerifyVariant::AlternateEmail => {
+                if let Self::Verified { attributes, .. } = self {
+                    match &attributes.alternate_email {
+                        Some(AlternateEmail::Pending(cxt)) => {
+                            if cxt.is_expired() {
+                                attributes.alternate_email = None;
+                                return Err(Error::VerificationExpired);
+                            }
+                            if !cxt.matches(verify_code) {
+                                return Err(Error::VerificationCode);
+                            }
+                            attributes.alternate_email =
+                                Some(AlternateEmail::Verified(cxt.email.clone()));
+                            Ok(())
+                        }
+                        _ => Err(Error::NotAllowed),
+                    }
+                } else {
+                    Err(Error::UserUnverified)
+                }
+            }
         }
     }
 
     /// Get the only id of this user.
     pub fn id(&self) -> u64 {
         match self {
-            Account::Unverified(cxt) => {
-                let mut hasher = DefaultHasher::new();
-                cxt.email.hash(&mut hasher);
-                hasher.finish()
-            }
+            Account::Unverified(cxt) => email_id(&cxt.email),
             Account::Verified { id, .. } => *id,
         }
     }
 
+    /// Whether this is an unverified account whose code expired.
+    pub fn is_expired(&self) -> bool {
+        matches!(self, Account::Unverified(cxt) if cxt.is_expired())
+    }
+
+    /// Whether this account is removed on refresh, either expired or
+    /// pending deletion past its grace period.
+    pub fn is_removable(&self) -> bool {
+        match self {
+            Account::Unverified(cxt) => cxt.is_expired(),
+            Account::Verified { attributes, .. } => attributes
+                .pending_deletion
+                .is_some_and(|deletion| deletion.purge_at <= crate::clock::now()),
+        }
+    }
+
+    /// Make every expire time of this account no later than target time,
+    /// returning whether any was changed.
+    pub fn clamp_expiry(&mut self, horizon: DateTime<Utc>) -> bool {
+        match self {
+            Account::Unverified(cxt) => cxt.clamp_expiry(horizon),
+            Account::Verified {
+                attributes,
+                tokens,
+                verify,
+                ..
+            } => {
+                let mut clamped = tokens.clamp_expiry(horizon);
+                if let UserVerifyVariant::ForgetPassword(cxt) = verify {
+                    clamped |= cxt.clamp_expiry(horizon);
+                }
+                if let Some(AlternateEmail::Pending(cxt)) = &mut attributes.alternate_email {
+                    clamped |= cxt.clamp_expiry(horizon);
+                }
+                clamped
+            }
+        }
+    }
+
+    /// Delete this account after `grace`, logging out all of its sessions.
+    pub fn delete_after(&mut self, grace: Duration) -> Result<(), Error> {
+        match self {
+            Account::Unverified(_) => Err(Error::UserUnverified),
+            Account::Verified {
+                attributes, tokens, ..
+            } => {
+                attributes.pending_deletion = Some(PendingDeletion {
+                    purge_at: crate::clock::now() + grace,
+                });
+                *tokens = verify::Tokens::new();
+                Ok(())
+            }
+        }
+    }
+
     /// Get email of this user.
     pub fn email(&self) -> &lettre::Address {
         match self {
@@ -172,6 +373,21 @@ impl Account {
         }
     }
 
+    /// Whether this user has target email, ignoring case.
+    pub fn has_email(&self, email: &lettre::Address) -> bool {
+        normalize_email(self.email().clone()) == normalize_email(email.clone())
+    }
+
+    /// Whether password resets of this user can be sent to target email,
+    /// which is the email or the verified alternate email, ignoring case.
+    pub fn has_recovery_email(&self, email: &lettre::Address) -> bool {
+        self.has_email(email)
+            || matches!(self, Account::Verified { attributes, .. }
+            if attributes.verified_alternate_email().is_some_and(|alternate| {
+                normalize_email(alternate.clone()) == normalize_email(email.clone())
+            }))
+    }
+
     /// Get metadata of this user.
     pub fn metadata(&self) -> Result<UserMetadata, Error> {
         if let Self::Verified { attributes, .. } = self {
@@ -180,8 +396,9 @@ impl Account {
                 name: attributes.name.clone(),
                 school_id: attributes.school_id,
                 phone: attributes.phone,
-                house: attributes.house,
+                house: attributes.house.clone(),
                 organization: attributes.organization.clone(),
+                registration_time: attributes.registration_time,
             })
         } else {
             Err(Error::UserUnverified)
@@ -203,18 +420,58 @@ impl Account {
         self.permissions().contains(&permission)
     }
 
-    /// Login into the account and return back a token in a `Result`.
-    pub fn login(&mut self, password: &str) -> Result<String, Error> {
+    /// Get permissions of this user scoped to organizations.
+    pub fn scoped_permissions(&self) -> &[ScopedPermission] {
+        match self {
+            Account::Unverified(_) => &[],
+            Account::Verified { attributes, .. } => &attributes.scoped_permissions,
+        }
+    }
+
+    /// Indicates whether this user has the target permission on accounts of
+    /// target organization, either globally or scoped to the organization.
+    pub fn has_permission_in(&self, permission: Permission, organization: Option<&str>) -> bool {
+        self.has_permission(permission)
+            || organization.is_some_and(|organization| {
+                self.scoped_permissions()
+                    .iter()
+                    .any(|e| e.permission == permission && e.organization == organization)
+            })
+    }
+
+    /// Get the organization of this user.
+    pub fn organization(&self) -> Option<&str> {
+        match self {
+            Account::Unverified(_) => None,
+            Account::Verified { attributes, .. } => attributes.organization.as_deref(),
+        }
+    }
+
+    /// Login into the account from target device and return back a token in a `Result`.
+    ///
+    /// A notification mail is sent if the device was not seen before,
+    /// unless the user muted it. If the user must change the password,
+    /// the token is only usable for that, see [`verify::Tokens::new_password_change_token`].
+    pub fn login(&mut self, password: &str, device: verify::Device) -> Result<String, Error> {
         match self {
             Account::Unverified(_) => Err(Error::UserUnverified),
             Account::Verified {
-                id,
-                attributes,
-                tokens,
-                ..
+                attributes, tokens, ..
             } => {
-                if digest(password) == attributes.password_sha {
-                    Ok(tokens.new_token(*id, attributes.token_expiration_time))
+                if verify_password(&attributes.password_sha, password) {
+                    // Checked once authenticated, so only the user learns them.
+                    if attributes.pending_deletion.is_some() {
+                        return Err(Error::PendingDeletion);
+                    }
+                    if attributes.suspended {
+                        return Err(Error::AccountSuspended);
+                    }
+                    let target = &crate::config::INSTANCE.account.password_hash;
+                    if needs_rehash(&attributes.password_sha, target) {
+                        attributes.password_sha = hash_password_with(password, target);
+                    }
+
+                    start_session(attributes, tokens, device)
                 } else {
                     Err(Error::PasswordIncorrect)
                 }
@@ -222,6 +479,78 @@ impl Account {
         }
     }
 
+    /// Login to this account without a password, as its email was verified
+    /// elsewhere, ex. by an identity provider, returning the token.
+    pub fn login_verified(&mut self, device: verify::Device) -> Result<String, Error> {
+        match self {
+            Account::Unverified(_) => Err(Error::UserUnverified),
+            Account::Verified {
+                attributes, tokens, ..
+            } => {
+                if attributes.pending_deletion.is_some() {
+                    return Err(Error::PendingDeletion);
+                }
+                if attributes.suspended {
+                    return Err(Error::AccountSuspended);
+                }
+                start_session(attributes, tokens, device)
+            }
+        }
+    }
+
+    /// Attach an admin note to this account.
+    pub fn add_note(&mut self, author: u64, text: String) -> Result<(), Error> {
+        if text.trim().is_empty() {
+            return Err(Error::InvalidNote);
+        }
+        check_text_multiline("note", &text, NOTE_MAX_LEN)?;
+
+        match self {
+            Account::Unverified(_) => Err(Error::UserUnverified),
+            Account::Verified { attributes, .. } => {
+                attributes.admin_notes.push(Note {
+                    author,
+                    time: crate::clock::now(),
+                    text,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Admin notes of this account.
+    pub fn notes(&self) -> &[Note] {
+        match self {
+            Account::Unverified(_) => &[],
+            Account::Verified { attributes, .. } => &attributes.admin_notes,
+        }
+    }
+
+    /// Record target token as used now, extending it by this account's token
+    /// expiration time if sliding expiration is configured, capped at
+    /// `token_max_lifetime` days after login.
+    ///
+    /// The account is saved at most once per [`verify::Tokens::TOUCH_SAVE_MINUTES`]
+    /// for each token, so the last use and expire time on disk may lag behind.
+    pub fn touch_token(&mut self, token: &str) -> Result<(), Error> {
+        let save = match self {
+            Account::Unverified(_) => return Err(Error::UserUnverified),
+            Account::Verified {
+                attributes, tokens, ..
+            } => {
+                let config = &crate::config::INSTANCE.account;
+                let sliding = config
+                    .sliding_token_expiration
+                    .then_some((attributes.token_expiration_time, config.token_max_lifetime));
+                tokens.touch(token, sliding).ok_or(Error::TokenIncorrect)?
+            }
+        };
+        if save {
+            self.save();
+        }
+        Ok(())
+    }
+
     /// Logout this account with the target token.
     pub fn logout(&mut self, token: &str) -> Result<(), Error> {
         match self {
@@ -236,44 +565,138 @@ impl Account {
         }
     }
 
-    /// Save this account and return whether if this account was saved successfully.
+    /// Create a named API key scoped to target permissions and return back the key.
+    ///
+    /// The permissions must be a subset of this account's permissions.
+    pub fn create_api_key(
+        &mut self,
+        name: String,
+        permissions: Permissions,
+        expire_time: Option<DateTime<Utc>>,
+    ) -> Result<String, Error> {
+        if let Some(p) = permissions.iter().find(|p| !self.has_permission(**p)) {
+            return Err(Error::PermissionDenied(*p));
+        }
+        if expire_time.is_some_and(|e| e <= crate::clock::now()) {
+            return Err(Error::DateOutOfRange);
+        }
+
+        match self {
+            Account::Unverified(_) => Err(Error::UserUnverified),
+            Account::Verified { tokens, .. } => tokens
+                .new_api_key(name, permissions, expire_time)
+                .ok_or(Error::ApiKeyConflict),
+        }
+    }
+
+    /// Revoke the API key with target name.
+    pub fn revoke_api_key(&mut self, name: &str) -> Result<(), Error> {
+        match self {
+            Account::Unverified(_) => Err(Error::UserUnverified),
+            Account::Verified { tokens, .. } => {
+                if tokens.revoke_api_key(name) {
+                    Ok(())
+                } else {
+                    Err(Error::ApiKeyNotFound)
+                }
+            }
+        }
+    }
+
+    /// Save this account to the store of the static instance in background.
     pub fn save(&self) {
-        #[cfg(not(test))]
-        {
+        INSTANCE.track(self);
+        if let Some(store) = INSTANCE.store() {
             let id = self.id();
-            let data = toml::to_string(&self).unwrap_or_default();
+            let generation = store.next_generation();
+            let data = store.serialize(self).unwrap_or_default();
 
-            tokio::spawn(async move {
-                use tokio::io::AsyncWriteExt;
-
-                let mut file = tokio::fs::File::create(format!("./data/accounts/{}.toml", id))
-                    .await
-                    .unwrap();
-                file.write_all(data.as_bytes()).await.unwrap();
+            tokio::task::spawn_blocking(move || {
+                if let Some(store) = INSTANCE.store() {
+                    store.write_generation(id, generation, data).unwrap();
+                }
             });
         }
     }
 
-    /// Remove this account from filesystem and return whether this account was removed successfully.
+    /// Save this account to the store of the static instance in background,
+    /// returning a future completing once the file is written, ex. for
+    /// handlers responding only after data is durable.
+    ///
+    /// See [`AccountManager::save_awaitable`].
+    pub fn save_awaitable(&self) -> impl Future<Output = std::io::Result<()>> + Send + 'static {
+        INSTANCE.save_awaitable(self)
+    }
+
+    /// Save this account to the store of the static instance before returning,
+    /// ex. after revoking tokens, which must not survive a restart.
+    ///
+    /// Pending saves from before are skipped if they finish later.
+    pub fn save_now(&self) -> Result<(), Error> {
+        INSTANCE.track(self);
+        if let Some(store) = INSTANCE.store() {
+            let data = store.serialize(self).map_err(Error::Save)?;
+            store
+                .write_generation(self.id(), store.next_generation(), data)
+                .map_err(Error::Save)?;
+        }
+        Ok(())
+    }
+
+    /// Remove this account from the store of the static instance in background.
     pub fn remove(&self) {
-        #[cfg(not(test))]
-        {
+        if let Some(store) = INSTANCE.store() {
             let id = self.id();
+            let generation = store.next_generation();
 
-            tokio::spawn(async move {
-                tokio::fs::remove_file(format!("./data/accounts/{}.json", id))
-                    .await
-                    .unwrap()
+            tokio::task::spawn_blocking(move || {
+                if let Some(store) = INSTANCE.store() {
+                    store.remove_generation(id, generation).unwrap();
+                }
             });
         }
     }
+
+    /// Remove this account from the store of the static instance before returning.
+    pub fn remove_now(&self) -> Result<(), Error> {
+        if let Some(store) = INSTANCE.store() {
+            store
+                .remove_generation(self.id(), store.next_generation())
+                .map_err(Error::Save)?;
+        }
+        Ok(())
+    }
+
+    /// Set password of this account, bypassing history and the change interval,
+    /// ex. when forced by an admin.
+    pub fn set_password(&mut self, password: &str) -> Result<(), Error> {
+        if let Account::Verified { attributes, .. } = self {
+            attributes.password_sha = hash_password(password);
+            Ok(())
+        } else {
+            Err(Error::UserUnverified)
+        }
+    }
+
+    /// Suspend or unban this account, see [`UserAttributes::suspended`].
+    pub fn set_suspended(&mut self, suspended: bool) -> Result<(), Error> {
+        if let Account::Verified { attributes, .. } = self {
+            attributes.suspended = suspended;
+            Ok(())
+        } else {
+            Err(Error::UserUnverified)
+        }
+    }
 }
 
-enum AccountVerifyVariant {
-    /// Activate an unverified account.
-    Activate(UserAttributes),
-    /// Reset a forgotten password.
+pub(crate) enum AccountVerifyVariant {
+    /// Activate an unverified account with the id,
+    /// see [`AccountManager::assign_id`].
+    Activate(u64, Box<UserAttributes>),
+    /// Reset a forgotten password, revoking all tokens of the account.
     ResetPassword(String),
+    /// Verify the pending alternate email.
+    AlternateEmail,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -283,9 +706,10 @@ pub enum UserVerifyVariant {
 }
 
 // Attributes of a registered user.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize)]
 pub struct UserAttributes {
     /// Email address of this user.
+    #[serde(with = "store::address_string")]
     pub email: lettre::Address,
     /// Name of this user.
     pub name: String,
@@ -297,209 +721,2078 @@ pub struct UserAttributes {
     pub house: Option<House>,
     /// Organization this user belongs to. Can be `None`.
     pub organization: Option<String>,
-    /// Permissions this user has.
+    /// Permissions this user has, sorted without duplicates.
+    /// Use [`Self::set_permissions`] and [`Self::grant_permission`] to keep it so.
+    #[serde(deserialize_with = "deserialize_permissions")]
     pub permissions: Permissions,
     /// The registration time of this user.
     pub registration_time: DateTime<Utc>,
-    /// Hash of this user's password.
+    /// Hash of this user's password, a PHC string, or a SHA-256 hex digest
+    /// if not upgraded since Argon2id was used.
     pub password_sha: String,
     /// The expiration time of a token in days.
     /// `0` means never expire.
     pub token_expiration_time: u16,
+    /// Whether to stop mailing this user when signed in from a new device.
+    #[serde(default)]
+    pub mute_login_notification: bool,
+    /// Hashes of previous passwords, the most recent last.
+    #[serde(default)]
+    pub password_history: Vec<String>,
+    /// Internal notes from admins, never shown to the user.
+    #[serde(default)]
+    pub admin_notes: Vec<Note>,
+    /// Changes of `house` after registration, the oldest first.
+    /// Use [`Self::set_house`] to keep it along with `house`.
+    #[serde(default)]
+    pub house_history: Vec<HouseAssignment>,
+    /// Permissions only effective on accounts of an organization.
+    #[serde(default)]
+    pub scoped_permissions: Vec<ScopedPermission>,
+    /// When the user last changed the password, `None` if never.
+    #[serde(default)]
+    pub password_changed_at: Option<DateTime<Utc>>,
+    /// A secondary email password resets can be sent to once verified,
+    /// not restricted to school domains.
+    #[serde(default)]
+    pub alternate_email: Option<AlternateEmail>,
+    /// Whether an admin requires the password to be changed before other use,
+    /// cleared once it is changed.
+    #[serde(default)]
+    pub must_change_password: bool,
+    /// Set once the user deleted the account, which is purged after the grace
+    /// period unless an admin restores it.
+    #[serde(default)]
+    pub pending_deletion: Option<PendingDeletion>,
+    /// Whether an admin suspended the account, which can't log in or
+    /// use its tokens until unbanned.
+    #[serde(default)]
+    pub suspended: bool,
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum ManagerError {
-    #[error("account {0} errored: {1}")]
-    Account(u64, Error),
-    #[error("account {0} not found")]
-    NotFound(u64),
+/// A deletion of an account waiting for its grace period to pass.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingDeletion {
+    /// When the account is purged by [`AccountManager::refresh_all`].
+    pub purge_at: DateTime<Utc>,
 }
 
-impl crate::AsResCode for ManagerError {
-    fn response_code(&self) -> hyper::StatusCode {
-        match self {
-            ManagerError::Account(_, value) => value.response_code(),
-            ManagerError::NotFound(_) => hyper::StatusCode::NOT_FOUND,
-        }
-    }
+/// A secondary contact email of a user.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AlternateEmail {
+    /// Waiting for the code sent to the address.
+    Pending(verify::Context),
+    /// Verified and usable for recovery.
+    Verified(#[serde(with = "store::address_string")] lettre::Address),
 }
 
-/// A simple account manager.
-pub struct AccountManager {
-    accounts: RwLock<Vec<RwLock<Account>>>,
-    /// An index cache for getting index from an id.
-    index: DashMap<u64, usize>,
+/// Password hashes are redacted, so accounts can be logged.
+impl std::fmt::Debug for UserAttributes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserAttributes")
+            .field("email", &self.email)
+            .field("name", &self.name)
+            .field("school_id", &self.school_id)
+            .field("phone", &self.phone)
+            .field("house", &self.house)
+            .field("organization", &self.organization)
+            .field("permissions", &self.permissions)
+            .field("registration_time", &self.registration_time)
+            .field("password_sha", &REDACTED)
+            .field("token_expiration_time", &self.token_expiration_time)
+            .field("mute_login_notification", &self.mute_login_notification)
+            .field(
+                "password_history",
+                &vec![REDACTED; self.password_history.len()],
+            )
+            .field("admin_notes", &self.admin_notes)
+            .field("house_history", &self.house_history)
+            .field("scoped_permissions", &self.scoped_permissions)
+            .field("password_changed_at", &self.password_changed_at)
+            .field("alternate_email", &self.alternate_email)
+            .field("must_change_password", &self.must_change_password)
+            .field("pending_deletion", &self.pending_deletion)
+            .field("suspended", &self.suspended)
+            .finish()
+    }
 }
 
-impl AccountManager {
-    /// Read and create an account manager from `./data/accounts`.
-    pub fn new() -> Self {
-        #[cfg(not(test))]
-        {
-            use std::fs::{self, File};
-            use std::io::Read;
-
-            let mut vec = Vec::new();
-            let index = DashMap::new();
-            let mut i = 0;
-            for dir in fs::read_dir("./data/accounts").unwrap() {
-                if let Ok(e) = dir.map(|e| {
-                    toml::from_str::<Account>(&{
-                        let mut string = String::new();
-                        File::open(e.path())
-                            .unwrap()
-                            .read_to_string(&mut string)
-                            .unwrap();
-                        string
-                    })
-                    .unwrap()
-                }) {
-                    index.insert(e.id(), i);
-                    vec.push(RwLock::new(e));
-                    i += 1;
-                } else {
-                    continue;
-                }
-            }
-            Self {
-                accounts: RwLock::new(vec),
-                index,
+impl UserAttributes {
+    /// Earliest registration time considered sane.
+    const EARLIEST_REGISTRATION_YEAR: i32 = 2000;
+
+    /// Set permissions of this user, sorting and deduplicating them.
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = normalize_permissions(permissions);
+    }
+
+    /// Grant target permission to this user and return whether it was newly granted.
+    pub fn grant_permission(&mut self, permission: Permission) -> bool {
+        match self.permissions.binary_search(&permission) {
+            Ok(_) => false,
+            Err(index) => {
+                self.permissions.insert(index, permission);
+                true
             }
         }
+    }
 
-        #[cfg(test)]
-        Self {
-            accounts: RwLock::new(Vec::new()),
-            index: DashMap::new(),
+    /// Revoke target permission from this user and return whether it was revoked.
+    pub fn revoke_permission(&mut self, permission: Permission) -> bool {
+        let l = self.permissions.len();
+        self.permissions.retain(|p| *p != permission);
+        l > self.permissions.len()
+    }
+
+    /// Assign this user to target house, recording the change in house history.
+    pub fn set_house(&mut self, house: Option<House>) {
+        if self.house == house {
+            return;
         }
+        self.house_history = self.houses();
+        self.house = house.clone();
+        self.house_history.push(HouseAssignment {
+            house,
+            effective_from: crate::clock::now(),
+        });
     }
 
-    /// Get inner accounts.
-    pub fn inner(&self) -> &RwLock<Vec<RwLock<Account>>> {
-        &self.accounts
+    /// Get all houses this user has been assigned to, the oldest first.
+    /// The house assigned on registration is effective from the registration time.
+    pub fn houses(&self) -> Vec<HouseAssignment> {
+        match (self.house_history.is_empty(), &self.house) {
+            (true, Some(house)) => vec![HouseAssignment {
+                house: Some(house.clone()),
+                effective_from: self.registration_time,
+            }],
+            _ => self.house_history.clone(),
+        }
     }
 
-    /// Get inner indexe cache.
-    pub fn index(&self) -> &DashMap<u64, usize> {
-        &self.index
+    /// Get the alternate email if verified.
+    pub fn verified_alternate_email(&self) -> Option<&lettre::Address> {
+        match &self.alternate_email {
+            Some(AlternateEmail::Verified(email)) => Some(email),
+            _ => None,
+        }
     }
 
-    /// Update index cache of this instance.
-    pub fn update_index(&self) {
-        self.index.clear();
-        for account in self.accounts.read().iter().enumerate() {
-            self.index.insert(account.1.read().id(), account.0);
+    /// Get the alternate email if waiting for verification.
+    pub fn pending_alternate_email(&self) -> Option<&lettre::Address> {
+        match &self.alternate_email {
+            Some(AlternateEmail::Pending(cxt)) => Some(&cxt.email),
+            _ => None,
         }
     }
 
-    /// Refresh this instance.
-    ///
-    /// - Remove expired unverified accounts
-    /// - Remove expired tokens
-    pub fn refresh_all(&self) {
-        {
-            let mut rm_list: Vec<usize> = Vec::new();
+    /// Get the masked alternate email waiting for verification and when its code expires,
+    /// `None` if there is none or its code expired.
+    pub fn pending_email(&self) -> Option<PendingEmail> {
+        match &self.alternate_email {
+            Some(AlternateEmail::Pending(cxt)) if !cxt.is_expired() => Some(PendingEmail {
+                email: mask_email(&cxt.email),
+                expire_time: cxt.expire_time,
+            }),
+            _ => None,
+        }
+    }
 
-            for account in self.accounts.read().iter().enumerate() {
-                {
-                    let r_binding = account.1.read();
-                    if if let Account::Unverified(cx) = r_binding.deref() {
-                        cx.is_expired()
-                    } else {
-                        false
-                    } {
-                        rm_list.push(account.0);
-                    }
-                }
+    /// Set the alternate email and send a code to verify it, or remove it with `None`.
+    pub fn set_alternate_email(&mut self, email: Option<lettre::Address>) -> Result<(), Error> {
+        self.alternate_email = match email {
+            Some(email) => {
+                verify::MAIL_LIMITER.acquire(&email)?;
+                let ctx = verify::Context::new(normalize_email(email));
+                ctx.send_verify();
+                Some(AlternateEmail::Pending(ctx))
             }
+            None => None,
+        };
+        Ok(())
+    }
 
-            {
-                let mut w = self.accounts.write();
+    /// Clamp expire times of tokens if this account holds sensitive permissions.
+    pub fn clamp_tokens(&self, tokens: &mut verify::Tokens) {
+        if let Some(max) = crate::config::INSTANCE
+            .account
+            .max_token_expiration(&self.permissions)
+        {
+            tokens.clamp(max);
+        }
+    }
 
-                for i in rm_list.iter().enumerate() {
-                    w.remove(*i.1 - i.0);
-                }
-            }
+    /// Check dates of this user are in a sane range, ex. not registered in the future.
+    pub fn validate_dates(&self) -> Result<(), Error> {
+        if self.registration_time > crate::clock::now()
+            || self.registration_time.year() < Self::EARLIEST_REGISTRATION_YEAR
+        {
+            Err(Error::DateOutOfRange)
+        } else {
+            Ok(())
+        }
+    }
 
-            if !rm_list.is_empty() {
-                self.update_index();
-            }
+    /// Change password of this user, rejecting the current password and
+    /// the ones in history, or a change too soon after the last one.
+    pub fn change_password(&mut self, password: &str) -> Result<(), Error> {
+        self.change_password_with(
+            password,
+            Duration::minutes(
+                crate::config::INSTANCE
+                    .account
+                    .password_change_interval
+                    .into(),
+            ),
+        )
+    }
+
+    /// Change password of this user, requiring at least `min_interval`
+    /// since the last change and [`PASSWORD_MIN_LEN`] characters.
+    pub fn change_password_with(
+        &mut self,
+        password: &str,
+        min_interval: Duration,
+    ) -> Result<(), Error> {
+        check_password(password)?;
+        let now = crate::clock::now();
+        if matches!(self.password_changed_at, Some(time) if now < time + min_interval) {
+            return Err(Error::PasswordChangeTooSoon);
         }
 
+        if verify_password(&self.password_sha, password)
+            || self
+                .password_history
+                .iter()
+                .any(|hash| verify_password(hash, password))
         {
-            for account in self.accounts.read().iter() {
-                let mut w = account.write();
-                if let Account::Verified { tokens, verify, .. } = w.deref_mut() {
-                    tokens.refresh();
-                    if match verify {
-                        UserVerifyVariant::None => false,
-                        UserVerifyVariant::ForgetPassword(e) => e.is_expired(),
-                    } {
-                        *verify = UserVerifyVariant::None;
-                    }
-                }
-            }
+            return Err(Error::PasswordReused);
         }
 
-        debug!("accounts refreshed");
+        let previous = std::mem::replace(&mut self.password_sha, hash_password(password));
+        self.password_history.push(previous);
+
+        let limit = crate::config::INSTANCE.account.password_history;
+        if self.password_history.len() > limit {
+            self.password_history
+                .drain(..self.password_history.len() - limit);
+        }
+
+        self.password_changed_at = Some(now);
+        self.must_change_password = false;
+        Ok(())
     }
+}
 
-    /// Refresh target account.
+/// Parse an email address, telling malformed addresses apart from ones
+/// rejected later for their domain.
+pub fn parse_email(email: &str) -> Result<lettre::Address, Error> {
+    email.parse().map_err(|_| Error::InvalidEmailFormat)
+}
+
+/// Whether target email is from a domain of PKUSchool, which accounts can be registered with.
+pub fn is_school_email(email: &lettre::Address) -> bool {
+    static DOMAINS: once_cell::sync::Lazy<std::collections::HashSet<String>> =
+        once_cell::sync::Lazy::new(|| {
+            let mut set = std::collections::HashSet::new();
+
+            set.insert("i.pkuschool.edu.cn".to_string());
+            set.insert("pkuschool.edu.cn".to_string());
+
+            set
+        });
+
+    DOMAINS.contains(email.domain())
+}
+
+/// Lowercase an email, so that it matches however the user types it.
+pub fn normalize_email(email: lettre::Address) -> lettre::Address {
+    lettre::Address::new(email.user().to_lowercase(), email.domain().to_lowercase())
+        .unwrap_or(email)
+}
+
+/// Create a token of a user logging in on target device, notifying the user
+/// of new devices, or a password change token if the password must be changed.
+fn start_session(
+    attributes: &UserAttributes,
+    tokens: &mut verify::Tokens,
+    device: verify::Device,
+) -> Result<String, Error> {
+    if !attributes.mute_login_notification && tokens.is_new_device(&device) {
+        verify::Mail {
+            to: attributes.email.clone(),
+            subject: "New sign-in detected".to_string(),
+            body: format!(
+                "Your account was signed in at {} from a new device.\n\nIP address: {}\nUser agent: {}\n\nIf this wasn't you, please reset your password.",
+                crate::clock::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                device.ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string()),
+                device.user_agent.as_deref().unwrap_or("unknown"),
+            ),
+        }
+        .send();
+    }
+
+    if attributes.must_change_password {
+        return Ok(tokens.new_password_change_token());
+    }
+
+    tokens.new_token_on(
+        crate::config::INSTANCE
+            .account
+            .token_expiration(&attributes.permissions, attributes.token_expiration_time),
+        device,
+    )
+}
+
+/// Get the id derived from target email, ignoring case.
+///
+/// SipHash-1-3 with zero keys is used, which is what `DefaultHasher` was
+/// when ids were first derived, but unlike it, stays the same across releases.
+pub fn email_id(email: &lettre::Address) -> u64 {
+    let mut hasher = siphasher::sip::SipHasher13::new();
+    normalize_email(email.clone()).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Sort permissions and remove duplicates.
+pub fn normalize_permissions(mut permissions: Permissions) -> Permissions {
+    permissions.sort_unstable();
+    permissions.dedup();
+    permissions
+}
+
+fn deserialize_permissions<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Permissions, D::Error> {
+    Permissions::deserialize(deserializer).map(normalize_permissions)
+}
+
+/// Hash a password with Argon2id and parameters from the config into a PHC string.
+pub fn hash_password(password: &str) -> String {
+    hash_password_with(password, &crate::config::INSTANCE.account.password_hash)
+}
+
+/// Hash a password with Argon2id and target parameters into a PHC string.
+pub fn hash_password_with(password: &str, params: &crate::config::PasswordHash) -> String {
+    use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+
+    let salt = SaltString::generate(&mut OsRng);
+    argon2(params)
+        .hash_password(password.as_bytes(), &salt)
+        .expect("password hashing failed with parameters from the config")
+        .to_string()
+}
+
+/// Whether the password matches target stored hash,
+/// a PHC string or a SHA-256 hex digest stored before Argon2id was used.
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    use argon2::password_hash::{PasswordHash, PasswordVerifier};
+
+    match PasswordHash::new(hash) {
+        Ok(parsed) => argon2::Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => digest(password) == hash,
+    }
+}
+
+/// Whether target stored hash is weaker than Argon2id with target parameters,
+/// including SHA-256 hex digests.
+pub fn needs_rehash(hash: &str, params: &crate::config::PasswordHash) -> bool {
+    let Ok(parsed) = argon2::password_hash::PasswordHash::new(hash) else {
+        return true;
+    };
+    if parsed.algorithm != argon2::Algorithm::Argon2id.ident() {
+        return true;
+    }
+    match argon2::Params::try_from(&parsed) {
+        Ok(stored) => {
+            stored.m_cost() < params.memory_kib
+                || stored.t_cost() < params.iterations
+                || stored.p_cost() < params.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
+fn argon2(params: &crate::config::PasswordHash) -> argon2::Argon2<'static> {
+    argon2::Argon2::new(
+        argon2::Algorithm::Argon2id,
+        argon2::Version::V0x13,
+        argon2::Params::new(
+            params.memory_kib,
+            params.iterations,
+            params.parallelism,
+            None,
+        )
+        .expect("invalid password hash parameters in the config"),
+    )
+}
+
+/// Check target house is one of the configured houses.
+pub fn validate_house(house: Option<House>, houses: &[HouseInfo]) -> Result<Option<House>, Error> {
+    match house {
+        Some(house) if !houses.iter().any(|info| info.id == house.id()) => Err(Error::InvalidHouse),
+        house => Ok(house),
+    }
+}
+
+/// Max count of characters in a name.
+pub const NAME_MAX_LEN: usize = 64;
+/// Max count of characters in an organization name.
+pub const ORGANIZATION_MAX_LEN: usize = 64;
+/// Max count of characters in an admin note.
+pub const NOTE_MAX_LEN: usize = 1024;
+/// Min count of characters in a password.
+pub const PASSWORD_MIN_LEN: usize = 8;
+
+/// Reject free text containing control characters or longer than `max` characters.
+fn check_text(field: &'static str, text: &str, max: usize) -> Result<(), Error> {
+    if text.chars().any(char::is_control) {
+        Err(Error::ControlCharacter(field))
+    } else if text.chars().count() > max {
+        Err(Error::TooLong { field, max })
+    } else {
+        Ok(())
+    }
+}
+
+/// Like [`check_text`], but allowing line breaks.
+fn check_text_multiline(field: &'static str, text: &str, max: usize) -> Result<(), Error> {
+    check_text(field, &text.replace(['\n', '\r'], " "), max)
+}
+
+/// Trim target name and collapse its internal whitespace,
+/// rejecting it if nothing is left.
+pub fn normalize_name(name: &str) -> Result<String, Error> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if name.is_empty() {
+        return Err(Error::InvalidName);
+    }
+    check_text("name", &name, NAME_MAX_LEN)?;
+    Ok(name)
+}
+
+/// Reject passwords shorter than [`PASSWORD_MIN_LEN`].
+pub fn check_password(password: &str) -> Result<(), Error> {
+    if password.chars().count() < PASSWORD_MIN_LEN {
+        Err(Error::WeakPassword)
+    } else {
+        Ok(())
+    }
+}
+
+/// Reject phone numbers other than 11-digit mobile numbers.
+pub fn check_phone(phone: u64) -> Result<(), Error> {
+    if (10_000_000_000..20_000_000_000).contains(&phone) {
+        Ok(())
+    } else {
+        Err(Error::InvalidPhone)
+    }
+}
+
+/// Normalized fields of an account to activate, see [`validate_activation`].
+pub struct Activation {
+    pub name: String,
+    pub house: Option<House>,
+    pub organization: Option<String>,
+}
+
+/// Validate fields of an account to activate against target policies,
+/// collecting the error of every invalid field instead of stopping at the first one.
+pub fn validate_activation(
+    config: &crate::config::Account,
+    name: &str,
+    phone: u64,
+    house: Option<House>,
+    organization: Option<String>,
+    password: &str,
+) -> Result<Activation, ValidationErrors> {
+    use crate::config::RequiredField;
+
+    let mut fields = Vec::new();
+
+    let name = collect_field(&mut fields, "name", normalize_name(name));
+    collect_field(&mut fields, "phone", check_phone(phone));
+    let house = collect_field(
+        &mut fields,
+        "house",
+        validate_house(house, &config.houses()).and_then(|house| {
+            require(
+                house,
+                "house",
+                &config.required_fields,
+                RequiredField::House,
+            )
+        }),
+    );
+    let organization = collect_field(
+        &mut fields,
+        "organization",
+        normalize_organization(organization, &config.organizations).and_then(|organization| {
+            require(
+                organization,
+                "organization",
+                &config.required_fields,
+                RequiredField::Organization,
+            )
+        }),
+    );
+    collect_field(&mut fields, "password", check_password(password));
+
+    match (name, house, organization) {
+        (Some(name), Some(house), Some(organization)) if fields.is_empty() => Ok(Activation {
+            name,
+            house,
+            organization,
+        }),
+        _ => Err(ValidationErrors { fields }),
+    }
+}
+
+/// Fail with [`Error::Required`] if `value` of target field is missing
+/// while the field is in `required`.
+fn require<T>(
+    value: Option<T>,
+    name: &'static str,
+    required: &[crate::config::RequiredField],
+    field: crate::config::RequiredField,
+) -> Result<Option<T>, Error> {
+    if value.is_none() && required.contains(&field) {
+        Err(Error::Required(name))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Get the value of `result`, or push its error as one of target field to `fields`.
+fn collect_field<T>(
+    fields: &mut Vec<FieldError>,
+    field: &str,
+    result: Result<T, Error>,
+) -> Option<T> {
+    result
+        .map_err(|err| {
+            fields.push(FieldError {
+                field: field.to_string(),
+                error: err.to_string(),
+            })
+        })
+        .ok()
+}
+
+/// Validate an organization name against target registry of valid names,
+/// returning back the registered spelling.
+///
+/// Names are matched ignoring case and surrounding or repeated whitespace.
+/// Any name is accepted unchanged if the registry is empty.
+pub fn normalize_organization(
+    organization: Option<String>,
+    registry: &[String],
+) -> Result<Option<String>, Error> {
+    let Some(organization) = organization else {
+        return Ok(None);
+    };
+    check_text("organization", &organization, ORGANIZATION_MAX_LEN)?;
+    if registry.is_empty() {
+        return Ok(Some(organization));
+    }
+
+    let key = |name: &str| {
+        name.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    let target = key(&organization);
+    registry
+        .iter()
+        .find(|e| key(e) == target)
+        .map(|e| Some(e.clone()))
+        .ok_or(Error::InvalidOrganization)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ManagerError {
+    #[error("account {0} errored: {1}")]
+    Account(u64, Error),
+    #[error("account {0} not found")]
+    NotFound(u64),
+}
+
+impl From<(u64, Error)> for ManagerError {
+    /// Wrap an error of the account with given id.
+    fn from((id, err): (u64, Error)) -> Self {
+        ManagerError::Account(id, err)
+    }
+}
+
+impl crate::AsResCode for ManagerError {
+    fn missing_permission(&self) -> Option<Permission> {
+        match self {
+            ManagerError::Account(_, value) => value.missing_permission(),
+            ManagerError::NotFound(_) => None,
+        }
+    }
+
+    fn response_code(&self) -> hyper::StatusCode {
+        match self {
+            ManagerError::Account(_, value) => value.response_code(),
+            ManagerError::NotFound(_) => hyper::StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+/// Discrepancies found by [`AccountManager::verify_integrity`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Ids shared by multiple accounts.
+    pub duplicate_ids: Vec<u64>,
+    /// Ids whose index entry is missing or points at another account.
+    pub index_mismatches: Vec<u64>,
+    /// Index entries of ids no account has.
+    pub dangling_index: Vec<u64>,
+    /// Emails shared by multiple accounts.
+    pub duplicate_emails: Vec<lettre::Address>,
+    /// School ids shared by multiple verified accounts.
+    pub duplicate_school_ids: Vec<u32>,
+    /// Ids of verified accounts not derived from their email.
+    pub id_mismatches: Vec<u64>,
+}
+
+impl IntegrityReport {
+    /// Whether no discrepancy was found.
+    pub fn is_ok(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// What [`AccountManager::refresh_all`] would change, from
+/// [`AccountManager::refresh_dry_run`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RefreshPlan {
+    /// Ids of expired unverified accounts to remove.
+    pub removed_accounts: Vec<u64>,
+    /// Count of tokens and API keys to prune of each account having any.
+    pub pruned_tokens: BTreeMap<u64, usize>,
+    /// Ids of accounts whose expired password reset would be cleared.
+    pub cleared_resets: Vec<u64>,
+}
+
+/// Outcome of [`AccountManager::register`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Registration {
+    /// A new unverified account was created.
+    Created,
+    /// A verification was already pending for the address.
+    Pending {
+        /// Whether a new code was sent.
+        code_sent: bool,
+    },
+}
+
+/// A simple account manager.
+///
+/// Accounts are locked after the outer lock of the list, never before it,
+/// so guards of accounts must be dropped before calling into the manager again.
+pub struct AccountManager {
+    accounts: RwLock<Vec<Arc<RwLock<Account>>>>,
+    /// An index cache for getting index from an id.
+    index: DashMap<u64, usize>,
+    /// The persistent storage, `None` for in-memory instances.
+    store: Option<store::FileStore>,
+    audit: audit::Log,
+    memberships: parking_lot::Mutex<Memberships>,
+    /// Owners and expire times of session tokens by their hashes, for
+    /// validating tokens without locking accounts.
+    token_owners: DashMap<u64, (u64, Option<DateTime<Utc>>)>,
+    /// Hashes of the session tokens of each account in `token_owners`.
+    token_hashes: DashMap<u64, Vec<u64>>,
+    /// Why session tokens of each account can't be used for now,
+    /// absent if they can.
+    token_blocks: DashMap<u64, TokenBlock>,
+    /// Last uses of session tokens in `token_owners` by their hashes, which
+    /// reach the accounts only once per [`verify::Tokens::TOUCH_SAVE_MINUTES`].
+    token_touches: DashMap<u64, TokenTouch>,
+}
+
+/// Use of a session token recorded by [`AccountManager::touch_token`].
+#[derive(Clone, Copy)]
+struct TokenTouch {
+    last_used: DateTime<Utc>,
+    /// When the use was last recorded in the account.
+    synced: DateTime<Utc>,
+}
+
+/// Why session tokens of a verified account can't be used for now,
+/// although they are valid.
+#[derive(Clone, Copy)]
+enum TokenBlock {
+    PasswordChange,
+    Suspended,
+}
+
+impl TokenBlock {
+    fn of(account: &Account) -> Option<Self> {
+        match account {
+            Account::Verified { attributes, .. } if attributes.must_change_password => {
+                Some(Self::PasswordChange)
+            }
+            Account::Verified { attributes, .. } if attributes.suspended => Some(Self::Suspended),
+            _ => None,
+        }
+    }
+
+    fn error(self) -> Error {
+        match self {
+            Self::PasswordChange => Error::PasswordChangeRequired,
+            Self::Suspended => Error::AccountSuspended,
+        }
+    }
+}
+
+/// House and organization of a verified account.
+#[derive(Clone, PartialEq, Eq)]
+struct Group {
+    house: Option<House>,
+    organization: Option<String>,
+}
+
+impl Group {
+    fn of(account: &Account) -> Option<Self> {
+        match account {
+            Account::Unverified(_) => None,
+            Account::Verified { attributes, .. } => Some(Self {
+                house: attributes.house.clone(),
+                organization: attributes.organization.clone(),
+            }),
+        }
+    }
+}
+
+/// Member counts of houses and organizations, updated as accounts change
+/// instead of by scanning all of them.
+#[derive(Default)]
+struct Memberships {
+    groups: HashMap<u64, Group>,
+    houses: HashMap<House, usize>,
+    organizations: HashMap<String, usize>,
+}
+
+impl Memberships {
+    /// Set the group of target account, `None` if it's removed or unverified.
+    fn set(&mut self, id: u64, group: Option<Group>) {
+        let old = match &group {
+            Some(group) => self.groups.insert(id, group.clone()),
+            None => self.groups.remove(&id),
+        };
+        if old == group {
+            return;
+        }
+
+        if let Some(old) = old {
+            if let Some(house) = old.house {
+                Self::decrement(&mut self.houses, house);
+            }
+            if let Some(organization) = old.organization {
+                Self::decrement(&mut self.organizations, organization);
+            }
+        }
+        if let Some(group) = group {
+            if let Some(house) = group.house {
+                *self.houses.entry(house).or_default() += 1;
+            }
+            if let Some(organization) = group.organization {
+                *self.organizations.entry(organization).or_default() += 1;
+            }
+        }
+    }
+
+    fn decrement<K: Hash + Eq>(counts: &mut HashMap<K, usize>, key: K) {
+        if let Some(count) = counts.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&key);
+            }
+        }
+    }
+}
+
+impl Default for AccountManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccountManager {
+    /// Read and create an account manager from `accounts` inside the data directory.
+    pub fn new() -> Self {
+        #[cfg(not(test))]
+        {
+            Self::load(
+                store::FileStore::from_env(crate::config::data_dir().join("accounts")).unwrap(),
+            )
+            .unwrap()
+        }
+
+        #[cfg(test)]
+        Self {
+            accounts: RwLock::new(Vec::new()),
+            index: DashMap::new(),
+            store: None,
+            audit: audit::Log::default(),
+            memberships: Default::default(),
+            token_owners: DashMap::new(),
+            token_hashes: DashMap::new(),
+            token_blocks: DashMap::new(),
+            token_touches: DashMap::new(),
+        }
+    }
+
+    /// Create an account manager from all accounts inside target store,
+    /// creating the directory of the store if it doesn't exist.
     ///
-    /// - Remove expired unverified account;
-    /// - Remove expired tokens.
-    pub fn refresh(&self, id: u64) {
-        if let Some(index) = self.index.get(&id) {
-            if let Some(account) = self.accounts.read().get(*index) {
+    /// Accounts are sorted by id, whatever order the directory is read in.
+    pub fn load(store: store::FileStore) -> std::io::Result<Self> {
+        Self::load_with_capacity(store, crate::config::INSTANCE.account.expected_accounts)
+    }
+
+    /// Create an account manager like [`Self::load`], preallocating room for
+    /// at least `capacity` accounts.
+    pub fn load_with_capacity(store: store::FileStore, capacity: usize) -> std::io::Result<Self> {
+        let horizon = verify::expiry_horizon();
+        let mut accounts = store.load_all()?;
+        let capacity = capacity.max(accounts.len());
+        let mut vec = Vec::with_capacity(capacity);
+        let index = DashMap::with_capacity(capacity);
+        accounts.sort_unstable_by_key(Account::id);
+        for (i, mut account) in accounts.into_iter().enumerate() {
+            if horizon.is_some_and(|horizon| account.clamp_expiry(horizon)) {
+                warn!(
+                    "account {} has expire times beyond the horizon of {} days, clamped",
+                    account.id(),
+                    crate::config::INSTANCE.account.max_expiry_days
+                );
+            }
+            index.insert(account.id(), i);
+            vec.push(Arc::new(RwLock::new(account)));
+        }
+        let manager = Self {
+            accounts: RwLock::new(vec),
+            index,
+            audit: audit::Log::load(store.audit_path())?,
+            store: Some(store),
+            memberships: Default::default(),
+            token_owners: DashMap::new(),
+            token_hashes: DashMap::with_capacity(capacity),
+            token_blocks: DashMap::new(),
+            token_touches: DashMap::new(),
+        };
+        for account in manager.accounts.read().iter() {
+            manager.track(&account.read());
+        }
+        Ok(manager)
+    }
+
+    /// Get the count of accounts this instance can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.accounts.read().capacity().min(self.index.capacity())
+    }
+
+    /// Get the persistent storage of this instance.
+    pub fn store(&self) -> Option<&store::FileStore> {
+        self.store.as_ref()
+    }
+
+    /// Save target account to the store of this instance in background,
+    /// returning a future completing once the file is written.
+    ///
+    /// The account is serialized before returning, so locks on it can be
+    /// released before awaiting. Pending saves from before are skipped if
+    /// they finish later. Completes immediately without a store.
+    pub fn save_awaitable(
+        &'static self,
+        account: &Account,
+    ) -> impl Future<Output = std::io::Result<()>> + Send + 'static {
+        self.track(account);
+        let id = account.id();
+        let write = self
+            .store()
+            .map(|store| (store.next_generation(), store.serialize(account)));
+
+        async move {
+            let Some((generation, data)) = write else {
+                return Ok(());
+            };
+            let data = data?;
+            tokio::task::spawn_blocking(move || match self.store() {
+                Some(store) => store.write_generation(id, generation, data),
+                None => Ok(()),
+            })
+            .await
+            .map_err(std::io::Error::other)?
+        }
+    }
+
+    /// Get the audit log of this instance.
+    pub fn audit(&self) -> &audit::Log {
+        &self.audit
+    }
+
+    /// Get inner accounts.
+    ///
+    /// Their order is unspecified: accounts are sorted by id when loaded, but
+    /// new ones are appended and removals shift the rest. Use
+    /// [`Self::snapshot_ids`] or [`Self::list_after`] for a stable order.
+    pub fn inner(&self) -> &RwLock<Vec<Arc<RwLock<Account>>>> {
+        &self.accounts
+    }
+
+    /// Get inner indexe cache.
+    pub fn index(&self) -> &DashMap<u64, usize> {
+        &self.index
+    }
+
+    /// Get ids of all accounts at the moment of calling, in ascending order.
+    ///
+    /// Only the index is read here, so iterating the ids and accessing each
+    /// account through [`Self::with_account`] releases the outer lock between
+    /// items and never blocks structural writes (create, remove) for the whole
+    /// traversal.
+    ///
+    /// The result is not a point-in-time view: accounts removed after the
+    /// snapshot are skipped by `with_account`, accounts created after it are
+    /// not included, and each account reflects its state when it is visited.
+    pub fn snapshot_ids(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.index.iter().map(|e| *e.key()).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Read target account with the outer lock held only during `f`,
+    /// returning `None` if the account doesn't exist.
+    pub fn with_account<R>(&self, id: u64, f: impl FnOnce(&Account) -> R) -> Option<R> {
+        let accounts = self.accounts.read();
+        let index = *self.index.get(&id)?;
+        let account = accounts.get(index)?.read();
+        Some(f(account.deref()))
+    }
+
+    /// Get a read guard of target account.
+    ///
+    /// The outer lock is released once the account is found, while the
+    /// guard keeps the account locked for reading until dropped.
+    pub fn get(&self, id: u64) -> Option<AccountRef> {
+        let accounts = self.accounts.read();
+        let index = *self.index.get(&id)?;
+        Some(AccountRef(accounts.get(index)?.read_arc()))
+    }
+
+    /// Get a write guard of target account, which saves the account to
+    /// the store when dropped if it was mutated through the guard.
+    ///
+    /// Dropping the guard blocks on writing the file, so async code must
+    /// call this in `spawn_blocking`, or use [`Self::with_account_mut`]
+    /// and [`Account::save`] instead.
+    pub fn get_mut(&self, id: u64) -> Option<AccountRefMut<'_>> {
+        let accounts = self.accounts.read();
+        let index = *self.index.get(&id)?;
+        Some(AccountRefMut {
+            guard: accounts.get(index)?.write_arc(),
+            manager: self,
+            dirty: false,
+        })
+    }
+
+    /// Write target account with the outer lock held only during `f`,
+    /// returning `None` if the account doesn't exist.
+    pub fn with_account_mut<R>(&self, id: u64, f: impl FnOnce(&mut Account) -> R) -> Option<R> {
+        let accounts = self.accounts.read();
+        let index = *self.index.get(&id)?;
+        let mut account = accounts.get(index)?.write();
+        let result = f(account.deref_mut());
+        self.track(&account);
+        Some(result)
+    }
+
+    /// Update member counts of houses and organizations and the token index
+    /// with the current state of target account of this instance.
+    ///
+    /// Accounts are tracked when changed through the manager and when saved.
+    pub fn track(&self, account: &Account) {
+        let id = account.id();
+        if !self.index.contains_key(&id) {
+            return;
+        }
+        self.memberships.lock().set(id, Group::of(account));
+        match TokenBlock::of(account) {
+            Some(block) => self.token_blocks.insert(id, block),
+            None => self.token_blocks.remove(&id).map(|(_, block)| block),
+        };
+
+        let hashes = match account {
+            Account::Verified { tokens, .. } => tokens
+                .hashes()
+                .map(|(hash, expire_time)| {
+                    self.token_owners.insert(hash, (id, expire_time));
+                    hash
+                })
+                .collect(),
+            Account::Unverified(_) => Vec::new(),
+        };
+        self.untrack_tokens(id, &hashes);
+        if !hashes.is_empty() {
+            self.token_hashes.insert(id, hashes);
+        }
+    }
+
+    /// Remove tokens of target account from the token index, except `keep`.
+    fn untrack_tokens(&self, id: u64, keep: &[u64]) {
+        if let Some((_, old)) = self.token_hashes.remove(&id) {
+            for hash in old.into_iter().filter(|hash| !keep.contains(hash)) {
+                if self
+                    .token_owners
+                    .remove_if(&hash, |_, (owner, _)| *owner == id)
+                    .is_some()
                 {
-                    if match account.read().deref() {
-                        Account::Unverified(cxt) => cxt.is_expired(),
-                        _ => false,
-                    } {
-                        self.remove(id);
+                    self.token_touches.remove(&hash);
+                }
+            }
+        }
+    }
+
+    /// Count verified accounts in each house.
+    pub fn house_counts(&self) -> HashMap<House, usize> {
+        self.memberships.lock().houses.clone()
+    }
+
+    /// Count verified accounts in each organization.
+    pub fn organization_counts(&self) -> HashMap<String, usize> {
+        self.memberships.lock().organizations.clone()
+    }
+
+    /// Register target email address as an unverified account.
+    ///
+    /// A pending unverified account of the address is reused instead of
+    /// creating another one, and gets a new code once the resend cooldown passed.
+    pub fn register(&self, email: lettre::Address) -> Result<Registration, Error> {
+        let email = normalize_email(email);
+        let id = email_id(&email);
+
+        // Codes are sent once no lock is held. Pending registrations have the
+        // id of their email, while verified accounts may not.
+        let pending = {
+            let accounts = self.accounts.read();
+            self.index
+                .get(&id)
+                .and_then(|index| accounts.get(*index).cloned())
+        };
+        if let Some(account) = pending.filter(|a| a.read().has_email(&email)) {
+            let renewed = {
+                let mut account = account.write();
+                let renewed = match account.deref_mut() {
+                    Account::Verified { .. } => return Err(Error::UserRegistered),
+                    Account::Unverified(cxt) if cxt.is_expired() || cxt.can_resend() => {
+                        verify::MAIL_LIMITER.acquire(&cxt.email)?;
+                        cxt.renew();
+                        Some(cxt.clone())
+                    }
+                    Account::Unverified(_) => None,
+                };
+                if renewed.is_some() {
+                    account.save();
+                }
+                renewed
+            };
+
+            let code_sent = renewed.is_some();
+            if let Some(cxt) = renewed {
+                cxt.send_verify();
+            }
+            return Ok(Registration::Pending { code_sent });
+        }
+        if self.find_by_email(&email).is_some() {
+            return Err(Error::UserRegistered);
+        }
+
+        let account = Account::new(email.clone())?;
+        let Account::Unverified(cxt) = &account else {
+            unreachable!("new accounts are unverified")
+        };
+        let cxt = cxt.clone();
+        let account = Arc::new(RwLock::new(account));
+        {
+            let mut accounts = self.accounts.write();
+            // Another registration of the email may have been created since.
+            if let Some(index) = self.index.get(&id) {
+                return if accounts[*index].read().has_email(&email) {
+                    Ok(Registration::Pending { code_sent: false })
+                } else {
+                    Err(Error::Conflict)
+                };
+            }
+            self.index.insert(id, accounts.len());
+            accounts.push(account.clone());
+        }
+
+        account.read().save();
+        cxt.send_verify();
+        Ok(Registration::Created)
+    }
+
+    /// Assign an id for a new verified account with target email, which
+    /// isn't used by accounts of this instance yet if random.
+    ///
+    /// Unverified accounts always use [`email_id`], so their id may change
+    /// on verification, see [`Self::reassign`].
+    pub fn assign_id(&self, strategy: crate::config::IdStrategy, email: &lettre::Address) -> u64 {
+        match strategy {
+            crate::config::IdStrategy::EmailHash => email_id(email),
+            crate::config::IdStrategy::Random => loop {
+                let id = rand::random();
+                if !self.index.contains_key(&id) {
+                    break id;
+                }
+            },
+        }
+    }
+
+    /// Get the id of the verified account of target email, which was verified
+    /// elsewhere, ex. by an identity provider, creating the account named `name`
+    /// with a random password if there is none.
+    ///
+    /// A pending registration of the email is replaced.
+    pub fn provision(&self, email: lettre::Address, name: String) -> Result<u64, Error> {
+        use rand::RngCore;
+
+        let email = normalize_email(email);
+        let mut accounts = self.accounts.write();
+
+        if let Some(position) = accounts.iter().position(|a| a.read().has_email(&email)) {
+            {
+                let account = accounts[position].read();
+                if let Account::Verified { id, .. } = account.deref() {
+                    return Ok(*id);
+                }
+                account.remove();
+            }
+            self.remove_positions(&mut accounts, &[position]);
+        }
+
+        let mut password = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut password);
+        let attributes = UserAttributes {
+            name: normalize_name(&name)?,
+            school_id: 0,
+            phone: 0,
+            house: None,
+            organization: None,
+            permissions: vec![Permission::Post, Permission::View],
+            registration_time: crate::clock::now(),
+            password_sha: hash_password(&sha256::digest(&password[..])),
+            token_expiration_time: 5,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
+            email,
+        };
+        let account = Account::Verified {
+            id: self.assign_id(
+                crate::config::INSTANCE.account.id_strategy,
+                &attributes.email,
+            ),
+            attributes,
+            tokens: verify::Tokens::new(),
+            verify: UserVerifyVariant::None,
+        };
+        account.save();
+        let id = account.id();
+        self.index.insert(id, accounts.len());
+        self.track(&account);
+        accounts.push(Arc::new(RwLock::new(account)));
+        Ok(id)
+    }
+
+    /// Push an account to this instance.
+    ///
+    /// The account and its index entry are inserted under the write lock,
+    /// so lookups never observe one without the other.
+    pub fn insert(&self, account: Account) {
+        let mut accounts = self.accounts.write();
+        self.index.insert(account.id(), accounts.len());
+        self.track(&account);
+        accounts.push(Arc::new(RwLock::new(account)));
+    }
+
+    /// Remove accounts at target positions in ascending order from `accounts`,
+    /// which is the locked inner accounts of this instance.
+    ///
+    /// Only entries of the removed accounts and of those after them are
+    /// updated, and ids of the remaining accounts are never absent from
+    /// the index.
+    fn remove_positions(&self, accounts: &mut Vec<Arc<RwLock<Account>>>, positions: &[usize]) {
+        let Some(&first) = positions.first() else {
+            return;
+        };
+
+        let mut memberships = self.memberships.lock();
+        for (i, position) in positions.iter().enumerate() {
+            let account = accounts.remove(position - i);
+            let id = account.read().id();
+            self.index.remove(&id);
+            memberships.set(id, None);
+            self.untrack_tokens(id, &[]);
+            self.token_blocks.remove(&id);
+        }
+
+        for mut entry in self.index.iter_mut() {
+            if *entry > first {
+                *entry -= positions.partition_point(|p| p < &entry);
+            }
+        }
+    }
+
+    /// Find the account with target email, ignoring case.
+    pub fn find_by_email(&self, email: &lettre::Address) -> Option<u64> {
+        let email = normalize_email(email.clone());
+        self.accounts
+            .read()
+            .iter()
+            .map(|a| a.read())
+            .find(|a| a.has_email(&email))
+            .map(|a| a.id())
+    }
+
+    /// Find the account with target verified alternate email, ignoring case.
+    pub fn find_by_recovery_email(&self, email: &lettre::Address) -> Option<u64> {
+        let email = normalize_email(email.clone());
+        self.accounts
+            .read()
+            .iter()
+            .map(|a| a.read())
+            .find(|a| a.has_recovery_email(&email))
+            .map(|a| a.id())
+    }
+
+    /// Find the verified account owning a usable `token`.
+    ///
+    /// Only the token index is read, so no account is locked. Permissions
+    /// and other account state are left to the caller.
+    pub fn find_by_token(&self, token: &str) -> Option<u64> {
+        let (id, expire_time) = *self.token_owners.get(&verify::hash_token(token))?;
+        expire_time
+            .is_none_or(|time| time > crate::clock::now())
+            .then_some(id)
+    }
+
+    /// Check that `token` is a usable session token of target account, which
+    /// may still fail if the user must change the password or is suspended.
+    ///
+    /// Like [`Self::find_by_token`], no account is locked. API keys aren't
+    /// indexed, so they fail with [`Error::TokenIncorrect`].
+    pub fn check_token(&self, id: u64, token: &str) -> Result<(), Error> {
+        if self.find_by_token(token) != Some(id) {
+            return Err(Error::TokenIncorrect);
+        }
+        match self.token_blocks.get(&id) {
+            Some(block) => Err(block.error()),
+            None => Ok(()),
+        }
+    }
+
+    /// Record target session token of target account as used now.
+    ///
+    /// The use is kept in the token index, and the account is only locked
+    /// to record it by [`Account::touch_token`] once per
+    /// [`verify::Tokens::TOUCH_SAVE_MINUTES`], which also slides the expiry
+    /// and saves. API keys and tokens of other accounts are ignored.
+    pub fn touch_token(&self, id: u64, token: &str) {
+        let hash = verify::hash_token(token);
+        if self
+            .token_owners
+            .get(&hash)
+            .is_none_or(|owner| owner.0 != id)
+        {
+            return;
+        }
+        let now = crate::clock::now();
+        let interval = Duration::minutes(verify::Tokens::TOUCH_SAVE_MINUTES);
+        let sync = match self.token_touches.entry(hash) {
+            dashmap::mapref::entry::Entry::Occupied(mut entry) => {
+                let touch = entry.get_mut();
+                touch.last_used = now;
+                let sync = now - touch.synced >= interval;
+                if sync {
+                    touch.synced = now;
+                }
+                sync
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(TokenTouch {
+                    last_used: now,
+                    synced: now,
+                });
+                true
+            }
+        };
+        if sync {
+            self.with_account_mut(id, |account| {
+                let _ = account.touch_token(token);
+            });
+        }
+    }
+
+    /// Get when target session token was last used, including uses not
+    /// recorded in its account yet, see [`Self::touch_token`].
+    pub fn last_used(&self, token: &str) -> Option<DateTime<Utc>> {
+        let hash = verify::hash_token(token);
+        if let Some(touch) = self.token_touches.get(&hash) {
+            return Some(touch.last_used);
+        }
+        let (id, _) = *self.token_owners.get(&hash)?;
+        self.with_account(id, |account| match account {
+            Account::Verified { tokens, .. } => tokens.last_used(token),
+            Account::Unverified(_) => None,
+        })
+        .flatten()
+    }
+
+    /// Search verified accounts whose name contains `query` case-insensitively,
+    /// returning at most `limit` results with earlier matches first.
+    pub fn search_by_name(&self, query: &str, limit: usize) -> Vec<UserMetadata> {
+        let query = query.to_lowercase();
+
+        let mut matches: Vec<(usize, UserMetadata)> = self
+            .snapshot_ids()
+            .into_iter()
+            .filter_map(|id| {
+                self.with_account(id, |account| {
+                    let metadata = account.metadata().ok()?;
+                    let position = metadata.name.to_lowercase().find(&query)?;
+                    Some((position, metadata))
+                })
+                .flatten()
+            })
+            .collect();
+
+        matches.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.name.cmp(&b.1.name)));
+        matches.truncate(limit);
+        matches.into_iter().map(|(_, metadata)| metadata).collect()
+    }
+
+    /// Get at most `limit` verified accounts with ids after `cursor`,
+    /// ordered by id, and the cursor of the next page, `None` if this is the last.
+    ///
+    /// The cursor is the id of the last account returned, so accounts added
+    /// or removed between pages never shift others into a skipped or repeated page.
+    pub fn list_after(
+        &self,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> (Vec<UserMetadata>, Option<u64>) {
+        let ids: Vec<u64> = self
+            .snapshot_ids()
+            .into_iter()
+            .filter(|&id| cursor.is_none_or(|cursor| id > cursor))
+            .collect();
+
+        let mut page = Vec::new();
+        let mut ids = ids.into_iter();
+        while page.len() < limit {
+            let Some(id) = ids.next() else {
+                break;
+            };
+            if let Some(Ok(metadata)) = self.with_account(id, Account::metadata) {
+                page.push((id, metadata));
+            }
+        }
+
+        // Only a full page may be followed by another one.
+        let next = if ids.len() > 0 {
+            page.last().map(|(id, _)| *id)
+        } else {
+            None
+        };
+        (
+            page.into_iter().map(|(_, metadata)| metadata).collect(),
+            next,
+        )
+    }
+
+    /// Get verified accounts in target house, sorted by name.
+    pub fn by_house(&self, house: House) -> Vec<UserMetadata> {
+        self.filter_verified(|attributes| attributes.house.as_ref() == Some(&house))
+    }
+
+    /// Get verified accounts in target organization, sorted by name.
+    pub fn by_organization(&self, organization: &str) -> Vec<UserMetadata> {
+        self.filter_verified(|attributes| attributes.organization.as_deref() == Some(organization))
+    }
+
+    /// Get metadata of verified accounts matching `f` from a snapshot, sorted by name.
+    fn filter_verified(&self, f: impl Fn(&UserAttributes) -> bool) -> Vec<UserMetadata> {
+        let mut vec: Vec<UserMetadata> = self
+            .snapshot_ids()
+            .into_iter()
+            .filter_map(|id| {
+                self.with_account(id, |account| match account {
+                    Account::Verified { attributes, .. } if f(attributes) => {
+                        account.metadata().ok()
                     }
+                    _ => None,
+                })
+                .flatten()
+            })
+            .collect();
+
+        vec.sort_by(|a, b| a.name.cmp(&b.name));
+        vec
+    }
+
+    /// Check accounts and the index for discrepancies without changing anything.
+    pub fn verify_integrity(&self) -> IntegrityReport {
+        use std::collections::HashSet;
+
+        let accounts = self.accounts.read();
+        let mut report = IntegrityReport::default();
+        let mut ids = HashSet::new();
+        let mut emails = HashSet::new();
+        let mut school_ids = HashSet::new();
+
+        for (position, account) in accounts.iter().enumerate() {
+            let account = account.read();
+            let id = account.id();
+
+            if !ids.insert(id) {
+                report.duplicate_ids.push(id);
+            } else if self.index.get(&id).map(|e| *e) != Some(position) {
+                report.index_mismatches.push(id);
+            }
+            if !emails.insert(account.email().clone()) {
+                report.duplicate_emails.push(account.email().clone());
+            }
+            if let Account::Verified { attributes, .. } = account.deref() {
+                if !school_ids.insert(attributes.school_id) {
+                    report.duplicate_school_ids.push(attributes.school_id);
                 }
+                if crate::config::INSTANCE.account.id_strategy
+                    == crate::config::IdStrategy::EmailHash
+                    && email_id(&attributes.email) != id
                 {
-                    if let Account::Verified { tokens, verify, .. } = account.write().deref_mut() {
-                        tokens.refresh();
-                        if match verify {
-                            UserVerifyVariant::None => false,
-                            UserVerifyVariant::ForgetPassword(e) => e.is_expired(),
-                        } {
-                            *verify = UserVerifyVariant::None;
+                    report.id_mismatches.push(id);
+                }
+            }
+        }
+
+        report.dangling_index = self
+            .index
+            .iter()
+            .map(|e| *e.key())
+            .filter(|id| !ids.contains(id))
+            .collect();
+        report
+    }
+
+    /// Move the index entry and the stored file of an account whose id changed
+    /// from `old` to `new`, ex. verified with random ids.
+    pub fn reassign(&self, old: u64, new: u64) {
+        if old == new {
+            return;
+        }
+        if let Some((_, position)) = self.index.remove(&old) {
+            self.index.insert(new, position);
+        }
+        let mut memberships = self.memberships.lock();
+        if let Some(group) = memberships.groups.get(&old).cloned() {
+            memberships.set(old, None);
+            memberships.set(new, Some(group));
+        }
+        self.untrack_tokens(old, &[]);
+        self.token_blocks.remove(&old);
+        if let Some(store) = &self.store {
+            if let Err(err) = store.remove_generation(old, store.next_generation()) {
+                error!("failed to remove file of account {}: {}", old, err);
+            }
+        }
+    }
+
+    /// Rebuild the index, member counts and the token index from scratch,
+    /// the first account of duplicate ids wins.
+    pub fn repair(&self) {
+        let accounts = self.accounts.write();
+        self.index.clear();
+        *self.memberships.lock() = Memberships::default();
+        self.token_owners.clear();
+        self.token_hashes.clear();
+        self.token_blocks.clear();
+        self.token_touches.clear();
+        for (position, account) in accounts.iter().enumerate() {
+            let account = account.read();
+            // The entry is released before tracking, which reads the index.
+            let kept = match self.index.entry(account.id()) {
+                dashmap::mapref::entry::Entry::Vacant(entry) => {
+                    entry.insert(position);
+                    true
+                }
+                dashmap::mapref::entry::Entry::Occupied(_) => false,
+            };
+            if kept {
+                self.track(&account);
+            }
+        }
+    }
+
+    /// Count unverified accounts whose verification hasn't expired.
+    ///
+    /// A sudden spike hints at registration abuse or email bombing.
+    pub fn pending_count(&self) -> usize {
+        self.accounts
+            .read()
+            .iter()
+            .filter(|account| {
+                matches!(account.read().deref(), Account::Unverified(cxt) if !cxt.is_expired())
+            })
+            .count()
+    }
+
+    /// Compute aggregates over all accounts in a single pass.
+    pub fn statistics(&self) -> Statistics {
+        let mut stats = Statistics::default();
+
+        for account in self.accounts.read().iter() {
+            stats.total += 1;
+            match account.read().deref() {
+                Account::Unverified(cxt) => {
+                    stats.pending += 1;
+                    if !cxt.is_expired() {
+                        stats.pending_unexpired += 1;
+                    }
+                }
+                Account::Verified {
+                    attributes,
+                    tokens,
+                    verify,
+                    ..
+                } => {
+                    stats.verified += 1;
+                    match &attributes.house {
+                        Some(house) => *stats.by_house.entry(house.clone()).or_default() += 1,
+                        None => stats.without_house += 1,
+                    }
+                    for permission in &attributes.permissions {
+                        *stats.by_permission.entry(*permission).or_default() += 1;
+                    }
+                    stats.active_tokens += tokens.active_count();
+                    if matches!(verify, UserVerifyVariant::ForgetPassword(cxt) if !cxt.is_expired())
+                    {
+                        stats.pending_password_resets += 1;
+                    }
+                }
+            }
+        }
+
+        stats
+    }
+
+    /// Grant a permission to accounts on behalf of account `by`,
+    /// returning results in the order of `ids`.
+    ///
+    /// Changed accounts are saved together synchronously after all grants.
+    pub fn grant_permission_bulk(
+        &self,
+        ids: &[u64],
+        permission: Permission,
+        by: u64,
+    ) -> Vec<Result<(), ManagerError>> {
+        let mut saves = Vec::new();
+        let mut entries = Vec::new();
+
+        let results = ids
+            .iter()
+            .map(|&id| {
+                self.with_account_mut(id, |account| {
+                    if let Account::Verified { attributes, .. } = account {
+                        let before = attributes.permissions.clone();
+                        if attributes.grant_permission(permission) {
+                            entries.push(audit::Entry {
+                                time: crate::clock::now(),
+                                by,
+                                target: id,
+                                action: audit::Action::ChangePermissions(
+                                    audit::PermissionDiff::between(
+                                        &before,
+                                        &attributes.permissions,
+                                    ),
+                                ),
+                            });
+                            // Taken while the account is locked, so a later
+                            // save of it isn't replaced by this one.
+                            if let Some(store) = &self.store {
+                                saves.push((
+                                    id,
+                                    store.next_generation(),
+                                    store.serialize(account).unwrap_or_default(),
+                                ));
+                            }
                         }
+                        Ok(())
+                    } else {
+                        Err(ManagerError::Account(id, Error::UserUnverified))
+                    }
+                })
+                .unwrap_or(Err(ManagerError::NotFound(id)))
+            })
+            .collect();
+
+        self.audit.record(entries);
+        self.flush(&saves);
+        results
+    }
+
+    /// Write serialized accounts with their generations to the store in one batch,
+    /// see [`store::FileStore::write_generation`].
+    fn flush(&self, accounts: &[(u64, u64, Vec<u8>)]) {
+        let Some(store) = &self.store else {
+            return;
+        };
+
+        for (id, generation, data) in accounts {
+            if let Err(err) = store.write_generation(*id, *generation, data) {
+                error!("failed to save account {}: {}", id, err);
+            }
+        }
+    }
+
+    /// Refresh this instance, returning ids of the removed accounts.
+    ///
+    /// - Remove expired unverified accounts
+    /// - Purge accounts pending deletion past their grace period
+    /// - Remove expired tokens
+    ///
+    /// Files of removed accounts are left to the caller, see [`Self::sweep`].
+    pub fn refresh_all(&self) -> Vec<u64> {
+        let mut removed = Vec::new();
+        {
+            // Expired accounts are found and removed without releasing the lock,
+            // so a registration either renews one before it's checked or
+            // creates a new one after it's gone.
+            let accounts = self.accounts.upgradable_read();
+            let rm_list: Vec<usize> = accounts
+                .iter()
+                .enumerate()
+                .filter(|(_, account)| account.read().is_removable())
+                .map(|(position, _)| position)
+                .collect();
+
+            if !rm_list.is_empty() {
+                let mut accounts = RwLockUpgradableReadGuard::upgrade(accounts);
+                removed.extend(rm_list.iter().map(|&p| accounts[p].read().id()));
+                self.remove_positions(&mut accounts, &rm_list);
+            }
+        }
+
+        {
+            for account in self.accounts.read().iter() {
+                let mut w = account.write();
+                if let Account::Verified {
+                    attributes,
+                    tokens,
+                    verify,
+                    ..
+                } = w.deref_mut()
+                {
+                    attributes.clamp_tokens(tokens);
+                    tokens.refresh();
+                    if match verify {
+                        UserVerifyVariant::None => false,
+                        UserVerifyVariant::ForgetPassword(e) => e.is_expired(),
+                    } {
+                        *verify = UserVerifyVariant::None;
                     }
                 }
+                self.track(&w);
+            }
+        }
+
+        debug!("accounts refreshed");
+        removed
+    }
+
+    /// Refresh this instance like [`Self::refresh_all`] and remove files of
+    /// the removed accounts from the store.
+    pub fn sweep(&self) {
+        let removed = self.refresh_all();
+        if let Some(store) = &self.store {
+            for id in removed {
+                if let Err(err) = store.remove_generation(id, store.next_generation()) {
+                    error!("failed to remove file of account {}: {}", id, err);
+                }
             }
         }
     }
 
-    /// Remove target account.
-    pub fn remove(&self, id: u64) {
-        if let Some(index) = self.index.get(&id) {
+    /// Send a new verification code to target pending account,
+    /// returning whether it was sent.
+    ///
+    /// Nothing is sent within the resend cooldown of the last code
+    /// unless `bypass_cooldown`.
+    pub fn resend_verification(
+        &self,
+        id: u64,
+        bypass_cooldown: bool,
+    ) -> Result<bool, ManagerError> {
+        self.with_account_mut(id, |account| {
+            let Account::Unverified(cxt) = account else {
+                return Err(Error::UserRegistered);
+            };
+            if !(bypass_cooldown || cxt.is_expired() || cxt.can_resend()) {
+                return Ok(false);
+            }
+            verify::MAIL_LIMITER.acquire(&cxt.email)?;
+            cxt.renew();
+            cxt.send_verify();
+            account.save();
+            Ok(true)
+        })
+        .ok_or(ManagerError::NotFound(id))?
+        .map_err(|err| ManagerError::Account(id, err))
+    }
+
+    /// Cancel the pending deletion of target account.
+    pub fn restore(&self, id: u64) -> Result<(), ManagerError> {
+        self.with_account_mut(id, |account| match account {
+            Account::Verified { attributes, .. } if attributes.pending_deletion.is_some() => {
+                attributes.pending_deletion = None;
+                account.save_now()
+            }
+            Account::Verified { .. } => Err(Error::NotPendingDeletion),
+            Account::Unverified(_) => Err(Error::UserUnverified),
+        })
+        .ok_or(ManagerError::NotFound(id))?
+        .map_err(|err| ManagerError::Account(id, err))
+    }
+
+    /// Compute what [`Self::refresh_all`] would change, without changing anything.
+    pub fn refresh_dry_run(&self) -> RefreshPlan {
+        let mut plan = RefreshPlan::default();
+
+        for account in self.accounts.read().iter() {
+            let account = account.read();
+            match account.deref() {
+                Account::Unverified(cxt) => {
+                    if cxt.is_expired() {
+                        plan.removed_accounts.push(account.id());
+                    }
+                }
+                Account::Verified { id, .. } if account.is_removable() => {
+                    plan.removed_accounts.push(*id);
+                }
+                Account::Verified {
+                    id,
+                    attributes,
+                    tokens,
+                    verify,
+                } => {
+                    let mut refreshed = tokens.clone();
+                    attributes.clamp_tokens(&mut refreshed);
+                    refreshed.refresh();
+                    let pruned = tokens.len() - refreshed.len();
+                    if pruned > 0 {
+                        plan.pruned_tokens.insert(*id, pruned);
+                    }
+                    if matches!(verify, UserVerifyVariant::ForgetPassword(e) if e.is_expired()) {
+                        plan.cleared_resets.push(*id);
+                    }
+                }
+            }
+        }
+
+        plan
+    }
+
+    /// Refresh target account.
+    ///
+    /// - Remove expired unverified account;
+    /// - Purge account pending deletion past its grace period;
+    /// - Remove expired tokens.
+    pub fn refresh(&self, id: u64) {
+        if self.with_account(id, Account::is_removable) == Some(true) {
+            let mut accounts = self.accounts.write();
+            let Some(index) = self.index.get(&id).map(|e| *e) else {
+                return;
+            };
+            // The account isn't waited for with the outer lock held, which would
+            // stall every other account; a later refresh removes it instead.
+            let removable = match accounts[index].try_read() {
+                // A registration may have renewed the account before the lock was taken.
+                Some(account) if account.is_removable() => {
+                    account.remove();
+                    true
+                }
+                _ => false,
+            };
+            if removable {
+                self.remove_positions(&mut accounts, &[index]);
+            }
+            return;
+        }
+
+        self.with_account_mut(id, |account| {
+            if let Account::Verified {
+                attributes,
+                tokens,
+                verify,
+                ..
+            } = account
             {
-                let b = self.accounts.read();
-                b.get(*index).unwrap().read().remove();
+                attributes.clamp_tokens(tokens);
+                tokens.refresh();
+                if match verify {
+                    UserVerifyVariant::None => false,
+                    UserVerifyVariant::ForgetPassword(e) => e.is_expired(),
+                } {
+                    *verify = UserVerifyVariant::None;
+                }
+            }
+        });
+    }
+
+    /// Remove target account and return back its metadata,
+    /// which is `None` for unverified accounts.
+    ///
+    /// The file is deleted synchronously with all accounts locked, so call it
+    /// through [`tokio::task::spawn_blocking`] from async code.
+    pub fn remove(&self, id: u64) -> Result<Option<UserMetadata>, ManagerError> {
+        let mut accounts = self.accounts.write();
+        let index = self
+            .index
+            .get(&id)
+            .map(|e| *e)
+            .ok_or(ManagerError::NotFound(id))?;
+
+        let metadata = {
+            let account = accounts[index].read();
+            account
+                .remove_now()
+                .map_err(|err| ManagerError::from((id, err)))?;
+            account.metadata().ok()
+        };
+        self.remove_positions(&mut accounts, &[index]);
+        Ok(metadata)
+    }
+
+    /// Remove target accounts, returning the metadata of each like [`Self::remove`]
+    /// in the order of `ids`.
+    ///
+    /// Files are deleted one by one, but the index is updated once for all
+    /// removed accounts. Accounts whose files can't be deleted are kept.
+    /// Like [`Self::remove`], call it through [`tokio::task::spawn_blocking`]
+    /// from async code.
+    pub fn remove_many(&self, ids: &[u64]) -> Vec<Result<Option<UserMetadata>, ManagerError>> {
+        let mut accounts = self.accounts.write();
+        let mut positions = Vec::new();
+
+        let results = ids
+            .iter()
+            .map(|&id| {
+                let index = self
+                    .index
+                    .get(&id)
+                    .map(|e| *e)
+                    .filter(|index| !positions.contains(index))
+                    .ok_or(ManagerError::NotFound(id))?;
+
+                let account = accounts[index].read();
+                if let Some(store) = &self.store {
+                    store
+                        .remove_generation(id, store.next_generation())
+                        .map_err(|err| ManagerError::Account(id, Error::Save(err)))?;
+                }
+                positions.push(index);
+                Ok(account.metadata().ok())
+            })
+            .collect();
+
+        positions.sort_unstable();
+        self.remove_positions(&mut accounts, &positions);
+        results
+    }
+
+    /// Merge duplicate account `drop` into `keep`, removing `drop` with its
+    /// tokens and file.
+    ///
+    /// `keep` wins on conflicts: permissions are united, but school id, house
+    /// and organization of `drop` are only taken where `keep` has none. Admin
+    /// notes and house history are combined, and audit entries concerning
+    /// `drop` are copied over to `keep`.
+    pub fn merge(&self, keep: u64, drop: u64) -> Result<(), ManagerError> {
+        if keep == drop {
+            return Err(ManagerError::Account(drop, Error::Conflict));
+        }
+
+        let mut accounts = self.accounts.write();
+        let position = |id| {
+            self.index
+                .get(&id)
+                .map(|e| *e)
+                .ok_or(ManagerError::NotFound(id))
+        };
+        let (keep_position, drop_position) = (position(keep)?, position(drop)?);
+
+        {
+            let dropped = accounts[drop_position].read();
+            let Account::Verified {
+                attributes: from, ..
+            } = dropped.deref()
+            else {
+                return Err(ManagerError::Account(drop, Error::UserUnverified));
+            };
+            let mut kept = accounts[keep_position].write();
+            let Account::Verified {
+                attributes: into, ..
+            } = kept.deref_mut()
+            else {
+                return Err(ManagerError::Account(keep, Error::UserUnverified));
+            };
+
+            into.permissions = normalize_permissions(
+                into.permissions
+                    .iter()
+                    .chain(&from.permissions)
+                    .copied()
+                    .collect(),
+            );
+            for permission in &from.scoped_permissions {
+                if !into.scoped_permissions.contains(permission) {
+                    into.scoped_permissions.push(permission.clone());
+                }
+            }
+            if into.school_id == 0 {
+                into.school_id = from.school_id;
+            }
+            // Houses of both accounts, including the ones only implied by
+            // their registrations, ending with the kept one.
+            let mut houses = into.houses();
+            houses.extend(from.houses());
+            houses.sort_by_key(|assignment| assignment.effective_from);
+            if into.house.is_none() {
+                into.house = from.house.clone();
+            }
+            if houses.last().is_some_and(|last| last.house != into.house) {
+                houses.push(HouseAssignment {
+                    house: into.house.clone(),
+                    effective_from: crate::clock::now(),
+                });
+            }
+            into.house_history = houses;
+            if into.organization.is_none() {
+                into.organization = from.organization.clone();
+            }
+            into.admin_notes.extend(from.admin_notes.iter().cloned());
+            into.admin_notes.sort_by_key(|note| note.time);
+            self.track(&kept);
+
+            if let Some(store) = &self.store {
+                let save = |id, err| ManagerError::Account(id, Error::Save(err));
+                let data = store.serialize(&kept).map_err(|err| save(keep, err))?;
+                store
+                    .write_generation(keep, store.next_generation(), data)
+                    .map_err(|err| save(keep, err))?;
+                store
+                    .remove_generation(drop, store.next_generation())
+                    .map_err(|err| save(drop, err))?;
             }
-            self.accounts.write().remove(*index);
         }
-        self.update_index();
+        self.remove_positions(&mut accounts, &[drop_position]);
+
+        let now = crate::clock::now();
+        let moved = self.audit.concerning(drop).into_iter().map(|mut entry| {
+            if entry.by == drop {
+                entry.by = keep;
+            }
+            if entry.target == drop {
+                entry.target = keep;
+            }
+            entry
+        });
+        self.audit.record(moved.chain([audit::Entry {
+            time: now,
+            by: keep,
+            target: keep,
+            action: audit::Action::Merge { from: drop },
+        }]));
+        Ok(())
     }
 
     /// Push an account to this instance, only for testing.
     #[cfg(test)]
     pub fn push(&self, account: Account) {
-        assert!(self
-            .index
-            .insert(account.id(), self.accounts.read().len())
-            .is_none());
-        self.accounts.write().push(RwLock::new(account));
+        assert!(!self.index.contains_key(&account.id()));
+        self.insert(account);
     }
 
     #[cfg(test)]
     pub fn reset(&self) {
         *self.accounts.write().deref_mut() = Vec::new();
-        self.index.clear()
+        self.index.clear();
+        *self.memberships.lock() = Memberships::default();
+        self.token_owners.clear();
+        self.token_hashes.clear();
+        self.token_blocks.clear();
+        self.token_touches.clear();
+        self.audit.reset()
+    }
+}
+
+/// A read guard of an account, see [`AccountManager::get`].
+pub struct AccountRef(ArcRwLockReadGuard<RawRwLock, Account>);
+
+impl Deref for AccountRef {
+    type Target = Account;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A write guard of an account, see [`AccountManager::get_mut`].
+pub struct AccountRefMut<'a> {
+    guard: ArcRwLockWriteGuard<RawRwLock, Account>,
+    manager: &'a AccountManager,
+    /// Whether the account was mutably accessed through this guard.
+    dirty: bool,
+}
+
+impl Deref for AccountRefMut<'_> {
+    type Target = Account;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl DerefMut for AccountRefMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.dirty = true;
+        &mut self.guard
+    }
+}
+
+impl Drop for AccountRefMut<'_> {
+    fn drop(&mut self) {
+        if self.dirty {
+            self.manager.track(&self.guard);
+            if let Some(store) = self.manager.store() {
+                if let Err(err) = store.save(&self.guard) {
+                    error!("failed to save account {}: {}", self.guard.id(), err);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,201 @@
+//! Signing in with an OpenID Connect provider through the authorization code flow.
+//!
+//! ID tokens are fetched from the token endpoint directly over TLS, which
+//! validates their issuer in place of their signatures, as allowed by
+//! [OpenID Connect Core 1.0, 3.1.3.7](https://openid.net/specs/openid-connect-core-1_0.html#IDTokenValidation).
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use serde::Deserialize;
+
+use super::bounded::BoundedMap;
+use super::Error;
+use crate::config::Oidc;
+
+/// Minutes a sign-in can be finished in after it's started.
+pub const STATE_MINUTES: i64 = 10;
+
+/// Sign-ins started and not finished yet.
+pub static PENDING: Lazy<PendingSignIns> = Lazy::new(PendingSignIns::default);
+
+/// Remembers nonces of started sign-ins by their states, so each callback
+/// is matched to a sign-in started here and finishes it at most once.
+pub struct PendingSignIns {
+    inner: parking_lot::Mutex<BoundedMap<String, PendingSignIn>>,
+}
+
+#[derive(Default)]
+struct PendingSignIn {
+    nonce: String,
+    expire_time: DateTime<Utc>,
+}
+
+/// Tracks as many sign-ins as configured by `rate_limit_capacity`.
+impl Default for PendingSignIns {
+    fn default() -> Self {
+        Self::new(crate::config::INSTANCE.account.rate_limit_capacity)
+    }
+}
+
+impl PendingSignIns {
+    /// Create a tracker of at most `capacity` sign-ins, the least recently
+    /// started one is forgotten to make room for a new one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: parking_lot::Mutex::new(BoundedMap::new(capacity)),
+        }
+    }
+
+    /// Start a sign-in, returning its random state and nonce.
+    pub fn start(&self) -> (String, String) {
+        let state = random_string();
+        let nonce = random_string();
+        *self.inner.lock().get_or_default(state.clone()) = PendingSignIn {
+            nonce: nonce.clone(),
+            expire_time: crate::clock::now() + Duration::minutes(STATE_MINUTES),
+        };
+        (state, nonce)
+    }
+
+    /// Finish the sign-in of target state, returning its nonce,
+    /// or `None` if it's unknown, finished or expired.
+    pub fn finish(&self, state: &str) -> Option<String> {
+        self.inner
+            .lock()
+            .remove(&state.to_string())
+            .filter(|pending| pending.expire_time > crate::clock::now())
+            .map(|pending| pending.nonce)
+    }
+
+    #[cfg(test)]
+    pub fn reset(&self) {
+        self.inner.lock().clear();
+    }
+}
+
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE64.encode(bytes)
+}
+
+/// Get the URL of target provider to redirect users to for signing in.
+pub fn authorization_url(config: &Oidc, state: &str, nonce: &str) -> Result<String, Error> {
+    reqwest::Url::parse_with_params(
+        &config.authorization_endpoint,
+        [
+            ("response_type", "code"),
+            ("client_id", &config.client_id),
+            ("redirect_uri", &config.redirect_uri),
+            ("scope", &config.scopes),
+            ("state", state),
+            ("nonce", nonce),
+        ],
+    )
+    .map(String::from)
+    .map_err(|err| Error::SsoProvider(format!("invalid authorization endpoint: {err}")))
+}
+
+/// Claims of an ID token used for signing in.
+#[derive(Deserialize, Debug)]
+pub struct Claims {
+    pub iss: String,
+    pub aud: Audience,
+    /// Expire time in seconds since the Unix epoch.
+    pub exp: i64,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+/// Audience of an ID token, a single client id or several.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+impl Claims {
+    /// Decode the claims of an ID token without checking its signature.
+    pub fn decode(id_token: &str) -> Result<Self, Error> {
+        let payload = id_token
+            .split('.')
+            .nth(1)
+            .ok_or(Error::SsoTokenInvalid("malformed"))?;
+        let payload = BASE64
+            .decode(payload.trim_end_matches('='))
+            .map_err(|_| Error::SsoTokenInvalid("malformed"))?;
+        serde_json::from_slice(&payload).map_err(|_| Error::SsoTokenInvalid("malformed claims"))
+    }
+
+    /// Check these claims were issued by target provider for its client
+    /// and the sign-in of `nonce`, returning the verified email.
+    pub fn validate(&self, config: &Oidc, nonce: &str) -> Result<lettre::Address, Error> {
+        if self.iss != config.issuer {
+            return Err(Error::SsoTokenInvalid("issuer not match"));
+        }
+        if !self.aud.contains(&config.client_id) {
+            return Err(Error::SsoTokenInvalid("audience not match"));
+        }
+        if self.exp <= crate::clock::now().timestamp() {
+            return Err(Error::SsoTokenInvalid("expired"));
+        }
+        if self.nonce.as_deref() != Some(nonce) {
+            return Err(Error::SsoTokenInvalid("nonce not match"));
+        }
+        match &self.email {
+            Some(email) if self.email_verified => email
+                .parse()
+                .map(super::normalize_email)
+                .map_err(|_| Error::InvalidEmailFormat),
+            _ => Err(Error::SsoTokenInvalid("email not verified")),
+        }
+    }
+}
+
+/// Exchange an authorization code at the token endpoint of target provider
+/// for the claims of its ID token.
+pub async fn exchange(config: &Oidc, code: &str) -> Result<Claims, Error> {
+    #[derive(Deserialize)]
+    struct TokenResponse {
+        id_token: String,
+    }
+
+    let response = reqwest::Client::new()
+        .post(&config.token_endpoint)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &config.redirect_uri),
+            ("client_id", &config.client_id),
+            ("client_secret", &config.client_secret),
+        ])
+        .send()
+        .await
+        .map_err(|err| Error::SsoProvider(err.to_string()))?;
+    if !response.status().is_success() {
+        return Err(Error::SsoProvider(format!(
+            "token endpoint answered {}",
+            response.status()
+        )));
+    }
+    let response: TokenResponse = response
+        .json()
+        .await
+        .map_err(|err| Error::SsoProvider(err.to_string()))?;
+    Claims::decode(&response.id_token)
+}
@@ -0,0 +1,46 @@
+//! What of accounts may appear in logs.
+//!
+//! Emails are logged masked through [`Email`], tokens only by their
+//! [`TOKEN_PREFIX`] through [`Token`], and verification codes, passwords and
+//! their hashes never. `Debug` implementations print [`REDACTED`] in place
+//! of secrets.
+
+use std::fmt;
+
+use super::verify::TOKEN_PREFIX;
+
+/// Printed by `Debug` implementations in place of secrets.
+pub const REDACTED: &str = "<redacted>";
+
+/// Mask the user part of an email except its first character, ex. `j***@example.com`.
+pub fn mask_email(email: &lettre::Address) -> String {
+    Email(email).to_string()
+}
+
+/// Displays an email masked for logging, see [`mask_email`].
+///
+/// The domain is kept whole, so logs still tell which school or
+/// provider a mail went to.
+pub struct Email<'a>(pub &'a lettre::Address);
+
+impl fmt::Display for Email<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let first = self.0.user().chars().next().unwrap_or_default();
+        write!(f, "{first}***@{}", self.0.domain())
+    }
+}
+
+/// Displays only the non-secret prefix of a token for logging,
+/// ex. `sms3_<redacted>`.
+pub struct Token<'a>(pub &'a str);
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = if self.0.starts_with(TOKEN_PREFIX) {
+            TOKEN_PREFIX
+        } else {
+            ""
+        };
+        write!(f, "{prefix}{REDACTED}")
+    }
+}
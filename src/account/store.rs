@@ -0,0 +1,639 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+
+use tracing::warn;
+
+use super::Account;
+
+/// The environment variable holding the base64 encoded 32 bytes key for
+/// encrypting account files.
+pub const KEY_VAR: &str = "SMS3_ACCOUNT_KEY";
+
+/// The environment variable choosing compression of account files,
+/// either `zstd` or `none` (the default).
+pub const COMPRESSION_VAR: &str = "SMS3_ACCOUNT_COMPRESSION";
+
+/// The environment variable choosing the [`Format`] of account files,
+/// either `toml` (the default), `json` or `bincode`.
+pub const FORMAT_VAR: &str = "SMS3_ACCOUNT_FORMAT";
+
+/// Serialization format of account files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Human-editable, the default.
+    #[default]
+    Toml,
+    Json,
+    /// Compact and fast, but not human-editable.
+    Bincode,
+}
+
+impl Format {
+    const ALL: [Self; 3] = [Self::Toml, Self::Json, Self::Bincode];
+
+    /// Extension of files in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Toml => "toml",
+            Format::Json => "json",
+            Format::Bincode => "bin",
+        }
+    }
+
+    /// Serialize target account in this format.
+    pub fn serialize(self, account: &Account) -> io::Result<Vec<u8>> {
+        match self {
+            Format::Toml => toml::to_string(account)
+                .map(String::into_bytes)
+                .map_err(invalid_data),
+            Format::Json => serde_json::to_vec(account).map_err(invalid_data),
+            Format::Bincode => bincode::serialize(account).map_err(invalid_data),
+        }
+    }
+
+    /// Deserialize an account in this format.
+    pub fn deserialize(self, data: &[u8]) -> io::Result<Account> {
+        match self {
+            Format::Toml => toml::from_str(std::str::from_utf8(data).map_err(invalid_data)?)
+                .map_err(invalid_data),
+            Format::Json => serde_json::from_slice(data).map_err(invalid_data),
+            Format::Bincode => bincode::deserialize(data).map_err(invalid_data),
+        }
+    }
+}
+
+/// Storage of accounts in a directory, one file per account.
+///
+/// Files are named `{id}.{extension}` by their [`Format`], TOML by default.
+/// With compression, files are compressed with zstd and get a `.zst`
+/// suffix. Files in every format and compression are read by extension,
+/// and the other variants of an account are removed when it's written,
+/// so directories can be migrated gradually.
+///
+/// With a key, files are encrypted (after compression) with
+/// XChaCha20-Poly1305 and stored as an [`Encrypted`] TOML document.
+/// Plaintext files are still readable so existing data gets encrypted
+/// on its next save.
+///
+/// Processes sharing a directory (ex. the server and `sms3-admin`) are
+/// coordinated with advisory locks: each account file is locked while
+/// read or written, and `.lock` inside the directory is held shared by
+/// single operations and exclusively by [`Self::lock_all`]. A second
+/// writer blocks until the first one finishes. The locks are only
+/// advisory and may be ignored or emulated on network filesystems
+/// (ex. NFS without a lock manager), so don't share a directory over them.
+pub struct FileStore {
+    path: PathBuf,
+    cipher: Option<XChaCha20Poly1305>,
+    compress: bool,
+    format: Format,
+    /// The generation given to the next write.
+    next_generation: AtomicU64,
+    /// The generation of the latest write or removal of each account,
+    /// locked during the write or removal.
+    generations: parking_lot::Mutex<HashMap<u64, Arc<parking_lot::Mutex<u64>>>>,
+}
+
+/// Content of an encrypted account file.
+#[derive(Serialize, Deserialize)]
+struct Encrypted {
+    /// Base64 encoded nonce.
+    nonce: String,
+    /// Base64 encoded ciphertext with the authentication tag.
+    ciphertext: String,
+}
+
+impl FileStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            cipher: None,
+            compress: false,
+            format: Format::default(),
+            next_generation: AtomicU64::new(1),
+            generations: parking_lot::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create a store with the key from env var `SMS3_ACCOUNT_KEY` if it's set,
+    /// compression from env var `SMS3_ACCOUNT_COMPRESSION` and format from
+    /// env var `SMS3_ACCOUNT_FORMAT`.
+    pub fn from_env(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let mut store = Self::new(path);
+        match std::env::var(FORMAT_VAR).as_deref() {
+            Ok("toml") | Err(_) => (),
+            Ok("json") => store = store.with_format(Format::Json),
+            Ok("bincode") => store = store.with_format(Format::Bincode),
+            Ok(value) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown {FORMAT_VAR} {value:?}, expected toml, json or bincode"),
+                ))
+            }
+        }
+
+        match std::env::var(COMPRESSION_VAR).as_deref() {
+            Ok("zstd") => store = store.compressed(),
+            Ok("none") | Err(_) => (),
+            Ok(value) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown {COMPRESSION_VAR} {value:?}, expected zstd or none"),
+                ))
+            }
+        }
+
+        match std::env::var(KEY_VAR) {
+            Ok(key) => {
+                let key: [u8; 32] = BASE64
+                    .decode(key.trim())
+                    .ok()
+                    .and_then(|key| key.try_into().ok())
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("{KEY_VAR} is not a base64 encoded 32 bytes key"),
+                        )
+                    })?;
+                Ok(store.with_key(&key))
+            }
+            Err(_) => Ok(store),
+        }
+    }
+
+    /// Compress files written by this store.
+    pub fn compressed(mut self) -> Self {
+        self.compress = true;
+        self
+    }
+
+    /// Write files of this store in target format.
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Encrypt files of this store with target key.
+    pub fn with_key(mut self, key: &[u8; 32]) -> Self {
+        self.cipher = Some(XChaCha20Poly1305::new(key.into()));
+        self
+    }
+
+    /// The directory this store reads and writes.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of the file storing target account.
+    pub fn file_path(&self, id: u64) -> PathBuf {
+        self.file_path_of(id, self.format, self.compress)
+    }
+
+    fn file_path_of(&self, id: u64, format: Format, compressed: bool) -> PathBuf {
+        if compressed {
+            self.path.join(format!("{}.{}.zst", id, format.extension()))
+        } else {
+            self.path.join(format!("{}.{}", id, format.extension()))
+        }
+    }
+
+    /// Paths of target account in every format and compression except the current ones.
+    fn other_file_paths(&self, id: u64) -> impl Iterator<Item = PathBuf> + '_ {
+        Format::ALL
+            .into_iter()
+            .flat_map(|format| [(format, false), (format, true)])
+            .filter(|&(format, compressed)| (format, compressed) != (self.format, self.compress))
+            .map(move |(format, compressed)| self.file_path_of(id, format, compressed))
+    }
+
+    /// Serialize target account in the format of this store.
+    pub fn serialize(&self, account: &Account) -> io::Result<Vec<u8>> {
+        self.format.serialize(account)
+    }
+
+    /// Path of the audit log stored along with accounts.
+    pub fn audit_path(&self) -> PathBuf {
+        self.path.join("audit.jsonl")
+    }
+
+    /// Lock the whole store exclusively for a bulk operation, blocking
+    /// single operations from other handles until the lock is dropped.
+    pub fn lock_all(&self) -> io::Result<StoreLock<'_>> {
+        let file = self.open_lock_file()?;
+        file.lock()?;
+        Ok(StoreLock {
+            store: self,
+            _file: file,
+        })
+    }
+
+    fn open_lock_file(&self) -> io::Result<fs::File> {
+        fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.join(".lock"))
+    }
+
+    /// Hold the store lock shared during a single operation.
+    fn lock_shared(&self) -> io::Result<fs::File> {
+        let file = self.open_lock_file()?;
+        file.lock_shared()?;
+        Ok(file)
+    }
+
+    /// Directory files that failed to parse are moved into.
+    pub fn quarantine_path(&self) -> PathBuf {
+        self.path.join("corrupt")
+    }
+
+    /// Read all accounts inside the directory, creating the directory if it doesn't exist.
+    ///
+    /// Files that can't be parsed are moved to [`Self::quarantine_path`] with
+    /// a timestamp suffix instead of failing the whole load, while failing to
+    /// decrypt is still an error as it's likely a wrong key.
+    pub fn load_all(&self) -> io::Result<Vec<Account>> {
+        fs::create_dir_all(&self.path)?;
+        let _lock = self.lock_shared()?;
+
+        let mut vec = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name();
+            let Some((format, compressed)) = name.to_str().and_then(Self::parse_file_name) else {
+                continue;
+            };
+
+            let mut data = self.decrypt(Self::read_file(&path)?)?;
+            let parsed = (|| {
+                if compressed {
+                    data = zstd::decode_all(data.as_slice())?;
+                }
+                format.deserialize(&data)
+            })();
+
+            match parsed {
+                Ok(account) => vec.push(account),
+                Err(err) => {
+                    let quarantine = self.quarantine(&path)?;
+                    warn!(
+                        "failed to parse account file {}, moved to {}: {}",
+                        path.display(),
+                        quarantine.display(),
+                        err
+                    );
+                }
+            }
+        }
+        Ok(vec)
+    }
+
+    /// Get format and compression of an account file from its name,
+    /// `None` if it's not an account file.
+    fn parse_file_name(name: &str) -> Option<(Format, bool)> {
+        let (name, compressed) = match name.strip_suffix(".zst") {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let (_, extension) = name.rsplit_once('.')?;
+        Format::ALL
+            .into_iter()
+            .find(|format| format.extension() == extension)
+            .map(|format| (format, compressed))
+    }
+
+    /// Move target file into the quarantine directory.
+    fn quarantine(&self, path: &Path) -> io::Result<PathBuf> {
+        let dir = self.quarantine_path();
+        fs::create_dir_all(&dir)?;
+
+        let target = dir.join(format!(
+            "{}.{}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            crate::clock::now().format("%Y%m%d%H%M%S%.3f")
+        ));
+        fs::rename(path, &target)?;
+        Ok(target)
+    }
+
+    /// Write serialized data of target account, compressing and encrypting
+    /// it if the store is configured to.
+    ///
+    /// The write takes the next generation, see [`Self::write_generation`]
+    /// to take it earlier.
+    pub fn write(&self, id: u64, data: impl AsRef<[u8]>) -> io::Result<()> {
+        self.write_generation(id, self.next_generation(), data)
+    }
+
+    /// Read a file with a shared lock on it.
+    fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+        let mut file = fs::File::open(path)?;
+        file.lock_shared()?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Replace content of the file of target account with an exclusive lock on it.
+    fn write_file(&self, id: u64, data: impl AsRef<[u8]>) -> io::Result<()> {
+        let data = if self.compress {
+            zstd::encode_all(data.as_ref(), 0)?
+        } else {
+            data.as_ref().to_vec()
+        };
+        let data = self.encrypt(data)?;
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.file_path(id))?;
+        file.lock()?;
+        file.set_len(0)?;
+        file.write_all(&data)?;
+
+        // Remove files in other formats left from before a migration.
+        for path in self.other_file_paths(id) {
+            ignore_not_found(fs::remove_file(path))?;
+        }
+        Ok(())
+    }
+
+    fn encrypt(&self, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(data);
+        };
+
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, data.as_slice())
+            .map_err(|_| io::Error::other("failed to encrypt account"))?;
+
+        toml::to_string(&Encrypted {
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        })
+        .map(String::into_bytes)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn decrypt(&self, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let Some(encrypted) = std::str::from_utf8(&data)
+            .ok()
+            .and_then(|data| toml::from_str::<Encrypted>(data).ok())
+        else {
+            return Ok(data);
+        };
+        let Some(cipher) = &self.cipher else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("account file is encrypted but {KEY_VAR} is not set"),
+            ));
+        };
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "failed to decrypt account");
+        let nonce = BASE64.decode(encrypted.nonce).map_err(|_| invalid())?;
+        if nonce.len() != 24 {
+            return Err(invalid());
+        }
+
+        cipher
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                BASE64
+                    .decode(encrypted.ciphertext)
+                    .map_err(|_| invalid())?
+                    .as_slice(),
+            )
+            .map_err(|_| invalid())
+    }
+
+    /// Take a generation for a write or removal, later ones are greater.
+    ///
+    /// Take it while the account is locked, so generations follow the order
+    /// of changes even if writes finish in another order.
+    pub fn next_generation(&self) -> u64 {
+        self.next_generation.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Like [`Self::write`] with a generation taken earlier, ex. while the account
+    /// was locked, skipped if a later generation of the account was written or
+    /// removed, so a stale write never replaces newer data.
+    pub fn write_generation(
+        &self,
+        id: u64,
+        generation: u64,
+        data: impl AsRef<[u8]>,
+    ) -> io::Result<()> {
+        let _lock = self.lock_shared()?;
+        self.with_generation(id, generation, || self.write_file(id, data))
+    }
+
+    /// Like [`Self::remove`] with a generation taken earlier, see
+    /// [`Self::write_generation`], succeeding if there was no file.
+    pub fn remove_generation(&self, id: u64, generation: u64) -> io::Result<()> {
+        let _lock = self.lock_shared()?;
+        self.with_generation(id, generation, || ignore_not_found(self.remove_file(id)))
+    }
+
+    /// Run `op` writing or removing the file of target account unless a later
+    /// generation of it was written or removed, recording `generation` once it
+    /// succeeds.
+    ///
+    /// Only the generation of this account is held during `op`, so accounts
+    /// are written concurrently while writes of one account stay in order.
+    /// Take the store lock before, as [`StoreLock`] holds it while calling this.
+    fn with_generation(
+        &self,
+        id: u64,
+        generation: u64,
+        op: impl FnOnce() -> io::Result<()>,
+    ) -> io::Result<()> {
+        let latest = self.generations.lock().entry(id).or_default().clone();
+        let mut latest = latest.lock();
+        if *latest > generation {
+            return Ok(());
+        }
+        op()?;
+        *latest = generation;
+        Ok(())
+    }
+
+    /// Serialize and write target account synchronously, see [`Self::write`].
+    pub fn save(&self, account: &Account) -> io::Result<()> {
+        self.write(account.id(), self.serialize(account)?)
+    }
+
+    /// Remove file of target account, taking the next generation.
+    pub fn remove(&self, id: u64) -> io::Result<()> {
+        let _lock = self.lock_shared()?;
+        self.with_generation(id, self.next_generation(), || self.remove_file(id))
+    }
+
+    /// Remove file of target account in all formats, failing with
+    /// [`io::ErrorKind::NotFound`] if there's none.
+    fn remove_file(&self, id: u64) -> io::Result<()> {
+        let mut result = fs::remove_file(self.file_path(id));
+        for path in self.other_file_paths(id) {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => return Err(err),
+                Ok(()) => result = Ok(()),
+            }
+        }
+        result
+    }
+}
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn ignore_not_found(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// An exclusive lock of a whole [`FileStore`], see [`FileStore::lock_all`].
+///
+/// Operations must go through this lock while it's held, as operations
+/// of the store itself would wait for it.
+pub struct StoreLock<'a> {
+    store: &'a FileStore,
+    _file: fs::File,
+}
+
+impl StoreLock<'_> {
+    /// See [`FileStore::write`].
+    pub fn write(&self, id: u64, data: impl AsRef<[u8]>) -> io::Result<()> {
+        self.store
+            .with_generation(id, self.store.next_generation(), || {
+                self.store.write_file(id, data)
+            })
+    }
+
+    /// See [`FileStore::save`].
+    pub fn save(&self, account: &Account) -> io::Result<()> {
+        self.write(account.id(), self.store.serialize(account)?)
+    }
+
+    /// See [`FileStore::remove`].
+    pub fn remove(&self, id: u64) -> io::Result<()> {
+        self.store
+            .with_generation(id, self.store.next_generation(), || {
+                self.store.remove_file(id)
+            })
+    }
+}
+
+/// Serialize `u64` as a string in human-readable formats, as TOML
+/// integers are limited to the range of `i64`.
+pub(super) mod u64_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(value)
+        } else {
+            serializer.serialize_u64(*value)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        if deserializer.is_human_readable() {
+            String::deserialize(deserializer)?
+                .parse()
+                .map_err(D::Error::custom)
+        } else {
+            u64::deserialize(deserializer)
+        }
+    }
+}
+
+/// Deserialize UTC times also from naive ones without an offset, which
+/// earlier versions stored, always in UTC.
+pub(super) mod utc_time {
+    use chrono::{DateTime, NaiveDateTime, Utc};
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        parse(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+
+    /// Parse a time with an offset, or a naive one as UTC.
+    pub fn parse(value: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+        value
+            .parse()
+            .or_else(|_| value.parse::<NaiveDateTime>().map(|time| time.and_utc()))
+    }
+}
+
+/// Optional [`utc_time`].
+pub(super) mod utc_time_option {
+    use chrono::{DateTime, Utc};
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| super::utc_time::parse(&value))
+            .transpose()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Deserialize email addresses from strings, as the implementation of
+/// `lettre::Address` relies on `deserialize_any`, which bincode doesn't support.
+pub(super) mod address_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &lettre::Address,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<lettre::Address, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
@@ -1,139 +1,1299 @@
-use std::hash::{Hash, Hasher};
+use std::{
+    collections::VecDeque,
+    hash::{Hash, Hasher},
+    net::IpAddr,
+};
 
-use chrono::{Days, NaiveDateTime, Utc};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use chrono::{DateTime, Days, Duration, Utc};
+use rand::{Rng, RngCore};
 use serde::{Deserialize, Serialize};
 use sha256::digest;
+use sms3_shared::account::{Permission, Permissions};
 
-#[cfg(not(test))]
 use once_cell::sync::Lazy;
 
 use tracing::info;
 
+use super::bounded::BoundedMap;
+use super::redact::{Email, REDACTED};
+
 #[cfg(not(test))]
 pub(super) static SENDER_INSTANCE: Lazy<VerificationSender> = Lazy::new(VerificationSender::new);
 
 #[cfg(test)]
 pub static VERIFICATION_CODE: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
 
+/// Mails sent during tests.
+#[cfg(test)]
+pub static SENT_MAILS: parking_lot::Mutex<Vec<Mail>> = parking_lot::Mutex::new(Vec::new());
+
+/// Limits verification mails sent to each address.
+pub static MAIL_LIMITER: Lazy<MailLimiter> = Lazy::new(MailLimiter::default);
+
+/// Locks accounts out after failed logins.
+pub static LOGIN_LIMITER: Lazy<LoginLimiter> = Lazy::new(LoginLimiter::default);
+
+/// Limits email availability checks from each IP address.
+pub static AVAILABILITY_LIMITER: Lazy<IpLimiter> = Lazy::new(IpLimiter::default);
+
+/// Text messages sent during tests, with the phone numbers they were sent to.
+#[cfg(test)]
+pub static SENT_SMS: parking_lot::Mutex<Vec<(u64, String)>> = parking_lot::Mutex::new(Vec::new());
+
+/// Whether sending mails fails during tests.
+#[cfg(test)]
+pub static FAIL_MAILS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Count of attempts sending a mail, retrying transient failures.
+pub const MAIL_ATTEMPTS: u32 = 3;
+
+/// The non-secret prefix of tokens, identifying them for secret scanners.
+/// Only this prefix of a token may appear in logs.
+pub const TOKEN_PREFIX: &str = "sms3_";
+
+/// Generate a token from `len` random bytes of a CSPRNG,
+/// encoded in URL-safe base64 after [`TOKEN_PREFIX`].
+pub fn generate_token(len: usize) -> String {
+    let mut bytes = vec![0; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{TOKEN_PREFIX}{}", BASE64.encode(bytes))
+}
+
+/// Hash a token the way [`Tokens`] stores it.
+pub fn hash_token(token: &str) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    token.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A plain text mail to a user.
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: lettre::Address,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Mail {
+    /// Build the message of this mail, sent from the configured address.
+    pub fn message(&self, config: &crate::config::MailSmtp) -> lettre::Message {
+        use lettre::message::{header::ContentType, Mailbox};
+
+        let mut builder = lettre::Message::builder()
+            .from(Mailbox::new(
+                Some(config.name.clone()).filter(|name| !name.is_empty()),
+                config.address.clone(),
+            ))
+            .to(Mailbox::new(None, self.to.clone()))
+            .subject(&self.subject)
+            .header(ContentType::TEXT_PLAIN);
+        if let Some(reply_to) = &config.reply_to {
+            builder = builder.reply_to(Mailbox::new(None, reply_to.clone()));
+        }
+        builder
+            .body(self.body.clone())
+            .expect("sender and recipient are set")
+    }
+
+    /// Send this mail in background.
+    pub fn send(self) {
+        self.send_then(|_| ())
+    }
+
+    /// Send this mail in background, then call `then` with whether it was sent.
+    pub fn send_then(self, then: impl FnOnce(bool) + Send + 'static) {
+        #[cfg(not(test))]
+        tokio::spawn(async move {
+            match SENDER_INSTANCE.send(&self).await {
+                Ok(()) => {
+                    info!("mail \"{}\" to {} sent", self.subject, Email(&self.to));
+                    then(true)
+                }
+                Err(err) => {
+                    tracing::error!(
+                        "failed to send mail to {}: {}",
+                        Email(&self.to),
+                        super::Error::MailSend(err)
+                    );
+                    then(false)
+                }
+            }
+        });
+
+        #[cfg(test)]
+        if FAIL_MAILS.load(std::sync::atomic::Ordering::Relaxed) {
+            then(false)
+        } else {
+            SENT_MAILS.lock().push(self);
+            then(true)
+        }
+    }
+}
+
+/// Whether a failure sending a mail would recur on retry, ex. a recipient rejected
+/// with a `5xx` reply, as opposed to `4xx` replies and connection errors.
+pub fn is_permanent(err: &lettre::transport::smtp::Error) -> bool {
+    err.is_permanent()
+}
+
+/// Send a message with target transport, making at most `attempts` attempts
+/// and waiting `delay` longer before each retry.
+/// Permanent failures are returned at once, see [`is_permanent`].
+pub async fn send_retrying<T>(
+    transport: &T,
+    message: &lettre::Message,
+    attempts: u32,
+    delay: std::time::Duration,
+) -> Result<(), lettre::transport::smtp::Error>
+where
+    T: lettre::AsyncTransport<Error = lettre::transport::smtp::Error> + Sync,
+{
+    let mut attempt = 1;
+    loop {
+        match transport.send(message.clone()).await {
+            Ok(_) => return Ok(()),
+            Err(err) if attempt < attempts && !is_permanent(&err) => {
+                tracing::warn!("attempt {attempt} to send mail failed, retrying: {err}");
+                tokio::time::sleep(delay * attempt).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Where a verification code is delivered to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recipient {
+    Email(lettre::Address),
+    Phone(u64),
+}
+
+/// Called with whether a code was delivered, once known.
+pub type Delivered = Box<dyn FnOnce(bool) + Send>;
+
+/// A channel delivering verification codes, see [`Context::send_verify_through`].
+pub trait CodeDelivery: Send + Sync {
+    /// The channel this delivers through.
+    fn channel(&self) -> crate::config::DeliveryChannel;
+
+    /// Deliver `code` to target recipient, then call `then` with whether it was
+    /// delivered. Failing if this channel can't reach the recipient, without
+    /// calling `then`.
+    fn send(&self, to: &Recipient, code: &str, then: Delivered) -> Result<(), super::Error>;
+}
+
+/// Get the delivery of target channel.
+pub fn delivery(channel: crate::config::DeliveryChannel) -> &'static dyn CodeDelivery {
+    match channel {
+        crate::config::DeliveryChannel::Email => &EmailDelivery,
+        crate::config::DeliveryChannel::Sms => &SmsDelivery,
+    }
+}
+
+/// Delivers codes by mail in background, see [`Mail::send_then`].
+pub struct EmailDelivery;
+
+impl CodeDelivery for EmailDelivery {
+    fn channel(&self) -> crate::config::DeliveryChannel {
+        crate::config::DeliveryChannel::Email
+    }
+
+    fn send(&self, to: &Recipient, code: &str, then: Delivered) -> Result<(), super::Error> {
+        let Recipient::Email(email) = to else {
+            return Err(super::Error::CodeDelivery("no email to mail"));
+        };
+        Mail {
+            to: email.clone(),
+            subject: "Your verification code".to_string(),
+            body: format!("Your verification code is {code}"),
+        }
+        .send_then(then);
+        Ok(())
+    }
+}
+
+/// Delivers codes by text messages in background through the gateway
+/// configured under `[sms]`.
+pub struct SmsDelivery;
+
+impl CodeDelivery for SmsDelivery {
+    fn channel(&self) -> crate::config::DeliveryChannel {
+        crate::config::DeliveryChannel::Sms
+    }
+
+    fn send(&self, to: &Recipient, code: &str, then: Delivered) -> Result<(), super::Error> {
+        let Recipient::Phone(phone) = *to else {
+            return Err(super::Error::CodeDelivery("no phone number to text"));
+        };
+        let message = format!("Your SMS3 verification code is {code}");
+
+        #[cfg(not(test))]
+        {
+            let Some(gateway) = crate::config::INSTANCE.sms.clone() else {
+                return Err(super::Error::CodeDelivery("no SMS gateway configured"));
+            };
+            tokio::spawn(async move {
+                let result = reqwest::Client::new()
+                    .post(&gateway.endpoint)
+                    .bearer_auth(&gateway.token)
+                    .json(&serde_json::json!({ "to": phone, "message": message }))
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status);
+                match result {
+                    Ok(_) => then(true),
+                    Err(err) => {
+                        tracing::error!("failed to send text message: {}", err.without_url());
+                        then(false)
+                    }
+                }
+            });
+        }
+
+        #[cfg(test)]
+        {
+            SENT_SMS.lock().push((phone, message));
+            then(true)
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts verification mails sent to each address within a time window,
+/// across all flows sending them.
+pub struct MailLimiter {
+    sent: parking_lot::Mutex<BoundedMap<lettre::Address, VecDeque<chrono::DateTime<chrono::Utc>>>>,
+}
+
+/// Tracks as many addresses as configured by `rate_limit_capacity`.
+impl Default for MailLimiter {
+    fn default() -> Self {
+        Self::new(crate::config::INSTANCE.account.rate_limit_capacity)
+    }
+}
+
+impl MailLimiter {
+    /// Create a limiter tracking at most `capacity` addresses,
+    /// the least recently mailed one is forgotten beyond it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sent: parking_lot::Mutex::new(BoundedMap::new(capacity)),
+        }
+    }
+
+    /// Count a mail to target address, or fail with [`super::Error::RateLimited`]
+    /// if the configured limit was reached.
+    pub fn acquire(&self, email: &lettre::Address) -> Result<(), super::Error> {
+        let config = &crate::config::INSTANCE.account;
+        self.acquire_with(
+            email,
+            config.verification_mail_limit,
+            Duration::minutes(config.verification_mail_window as i64),
+        )
+    }
+
+    /// Count a mail to target address, allowing `limit` mails within `window`.
+    pub fn acquire_with(
+        &self,
+        email: &lettre::Address,
+        limit: usize,
+        window: Duration,
+    ) -> Result<(), super::Error> {
+        if limit == 0 {
+            return Ok(());
+        }
+
+        let now = crate::clock::now();
+        let email = super::normalize_email(email.clone());
+        let mut sent = self.sent.lock();
+
+        let times = sent.get_or_default(email);
+        while times.front().is_some_and(|t| *t <= now - window) {
+            times.pop_front();
+        }
+        if times.len() >= limit {
+            return Err(super::Error::RateLimited);
+        }
+        times.push_back(now);
+        Ok(())
+    }
+
+    /// Count of addresses tracked.
+    pub fn len(&self) -> usize {
+        self.sent.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sent.lock().is_empty()
+    }
+
+    #[cfg(test)]
+    pub fn reset(&self) {
+        self.sent.lock().clear();
+    }
+}
+
+/// Counts requests from each IP address within a time window,
+/// requests without a known address share one count.
+pub struct IpLimiter {
+    requests:
+        parking_lot::Mutex<BoundedMap<Option<IpAddr>, VecDeque<chrono::DateTime<chrono::Utc>>>>,
+}
+
+/// Tracks as many addresses as configured by `rate_limit_capacity`.
+impl Default for IpLimiter {
+    fn default() -> Self {
+        Self::new(crate::config::INSTANCE.account.rate_limit_capacity)
+    }
+}
+
+impl IpLimiter {
+    /// Create a limiter tracking at most `capacity` addresses,
+    /// the least recently seen one is forgotten beyond it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            requests: parking_lot::Mutex::new(BoundedMap::new(capacity)),
+        }
+    }
+
+    /// Count an availability check from target address, or fail with
+    /// [`super::Error::RateLimited`] if the configured limit was reached.
+    pub fn acquire(&self, ip: Option<IpAddr>) -> Result<(), super::Error> {
+        let config = &crate::config::INSTANCE.account;
+        self.acquire_with(
+            ip,
+            config.availability_check_limit,
+            Duration::minutes(config.availability_check_window as i64),
+        )
+    }
+
+    /// Count a request from target address, allowing `limit` requests within `window`.
+    pub fn acquire_with(
+        &self,
+        ip: Option<IpAddr>,
+        limit: usize,
+        window: Duration,
+    ) -> Result<(), super::Error> {
+        if limit == 0 {
+            return Ok(());
+        }
+
+        let now = crate::clock::now();
+        let mut requests = self.requests.lock();
+
+        let times = requests.get_or_default(ip);
+        while times.front().is_some_and(|t| *t <= now - window) {
+            times.pop_front();
+        }
+        if times.len() >= limit {
+            return Err(super::Error::RateLimited);
+        }
+        times.push_back(now);
+        Ok(())
+    }
+
+    /// Count of addresses tracked.
+    pub fn len(&self) -> usize {
+        self.requests.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.lock().is_empty()
+    }
+
+    #[cfg(test)]
+    pub fn reset(&self) {
+        self.requests.lock().clear();
+    }
+}
+
+/// Failed logins of an account since its last successful one.
+#[derive(Default)]
+struct LoginAttempts {
+    failures: u32,
+    locked_until: Option<chrono::DateTime<chrono::Utc>>,
+    /// When an attempt while locked was last reported.
+    reported_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Hash and expire time of the one-time token unlocking the account.
+    unlock: Option<(String, chrono::DateTime<chrono::Utc>)>,
+}
+
+/// A lockout of an account, started by its latest failed login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lockout {
+    /// Count of failed logins that triggered the lockout.
+    pub failures: u32,
+    pub locked_until: chrono::DateTime<chrono::Utc>,
+}
+
+/// Counts consecutive failed logins of each account, locking it out
+/// once the configured threshold is reached.
+pub struct LoginLimiter {
+    attempts: parking_lot::Mutex<BoundedMap<u64, LoginAttempts>>,
+}
+
+/// Tracks as many accounts as configured by `rate_limit_capacity`.
+impl Default for LoginLimiter {
+    fn default() -> Self {
+        Self::new(crate::config::INSTANCE.account.rate_limit_capacity)
+    }
+}
+
+impl LoginLimiter {
+    /// Create a limiter tracking at most `capacity` accounts, the one
+    /// least recently attempted is forgotten beyond it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            attempts: parking_lot::Mutex::new(BoundedMap::new(capacity)),
+        }
+    }
+
+    /// Min seconds between events of attempts on the same locked account.
+    pub const REPORT_INTERVAL_SECONDS: i64 = 60;
+
+    /// Check target account isn't locked out, or fail with [`super::Error::AccountLocked`].
+    ///
+    /// Attempts while locked are reported as events, rate limited for each account.
+    pub fn check(&self, id: u64) -> Result<(), super::Error> {
+        let now = crate::clock::now();
+        let mut attempts = self.attempts.lock();
+        let Some(entry) = attempts.get_mut(&id) else {
+            return Ok(());
+        };
+        let Some(locked_until) = entry.locked_until else {
+            return Ok(());
+        };
+
+        if locked_until <= now {
+            attempts.remove(&id);
+            return Ok(());
+        }
+        if entry
+            .reported_at
+            .is_none_or(|t| t + Duration::seconds(Self::REPORT_INTERVAL_SECONDS) <= now)
+        {
+            entry.reported_at = Some(now);
+            super::event::emit(super::event::Event::AttemptWhileLocked {
+                account_id: id,
+                locked_until,
+            });
+        }
+        Err(super::Error::AccountLocked)
+    }
+
+    /// Count a failed login of target account, returning the lockout
+    /// if this failure started one.
+    pub fn fail(&self, id: u64) -> Option<Lockout> {
+        let config = &crate::config::INSTANCE.account;
+        self.fail_with(
+            id,
+            config.lockout_threshold,
+            Duration::minutes(config.lockout_minutes as i64),
+        )
+    }
+
+    /// Count a failed login of target account, locking it for `duration`
+    /// after `threshold` consecutive failures.
+    pub fn fail_with(&self, id: u64, threshold: u32, duration: Duration) -> Option<Lockout> {
+        if threshold == 0 {
+            return None;
+        }
+
+        let now = crate::clock::now();
+        let mut attempts = self.attempts.lock();
+        let entry = attempts.get_or_default(id);
+        match entry.locked_until {
+            Some(t) if t > now => return None,
+            // Failures before an ended lockout start over.
+            Some(_) => *entry = LoginAttempts::default(),
+            None => (),
+        }
+
+        entry.failures += 1;
+        if entry.failures < threshold {
+            return None;
+        }
+
+        let lockout = Lockout {
+            failures: entry.failures,
+            locked_until: now + duration,
+        };
+        entry.locked_until = Some(lockout.locked_until);
+        entry.reported_at = None;
+        super::event::emit(super::event::Event::AccountLocked {
+            account_id: id,
+            failures: lockout.failures,
+            locked_until: lockout.locked_until,
+        });
+        Some(lockout)
+    }
+
+    /// Issue a one-time token unlocking target account while it's locked out,
+    /// valid for `valid`, replacing the previous one.
+    ///
+    /// Returns `None` if the account isn't locked out.
+    pub fn issue_unlock(&self, id: u64, valid: Duration) -> Option<String> {
+        let now = crate::clock::now();
+        let mut attempts = self.attempts.lock();
+        let entry = attempts.get_mut(&id)?;
+        if entry.locked_until.is_none_or(|t| t <= now) {
+            return None;
+        }
+
+        let token = generate_token(crate::config::INSTANCE.account.token_bytes);
+        entry.unlock = Some((digest(token.as_str()), now + valid));
+        Some(token)
+    }
+
+    /// Lift the lockout of target account with a token from [`Self::issue_unlock`],
+    /// which can't be used again, or fail with [`super::Error::UnlockTokenIncorrect`].
+    pub fn unlock(&self, id: u64, token: &str) -> Result<(), super::Error> {
+        let now = crate::clock::now();
+        let mut attempts = self.attempts.lock();
+        let entry = attempts
+            .get_mut(&id)
+            .filter(|e| e.locked_until.is_some_and(|t| t > now))
+            .ok_or(super::Error::UnlockTokenIncorrect)?;
+
+        match entry.unlock.take() {
+            Some((hash, expire_time)) if hash == digest(token) && expire_time > now => {
+                attempts.remove(&id);
+                Ok(())
+            }
+            // An expired token is gone, while the valid one stays.
+            Some(unlock) if unlock.1 > now => {
+                entry.unlock = Some(unlock);
+                Err(super::Error::UnlockTokenIncorrect)
+            }
+            _ => Err(super::Error::UnlockTokenIncorrect),
+        }
+    }
+
+    /// Mail the owner of a locked out account about the lockout with a
+    /// token unlocking it, see [`Self::issue_unlock`].
+    pub fn notify(&self, to: lettre::Address, id: u64, lockout: Lockout, valid: Duration) {
+        let Some(token) = self.issue_unlock(id, valid) else {
+            return;
+        };
+        Mail {
+            to,
+            subject: "Your account was locked".to_string(),
+            body: format!(
+                "Your account was locked until {} after {} failed logins. \
+                If you just tried to log in, unlock it now with this one-time token \
+                within {} minutes: {token}",
+                lockout.locked_until,
+                lockout.failures,
+                valid.num_minutes(),
+            ),
+        }
+        .send();
+    }
+
+    /// Forget failed logins of target account after a successful one.
+    pub fn succeed(&self, id: u64) {
+        self.attempts.lock().remove(&id);
+    }
+
+    /// Count of accounts tracked.
+    pub fn len(&self) -> usize {
+        self.attempts.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attempts.lock().is_empty()
+    }
+
+    #[cfg(test)]
+    pub fn reset(&self) {
+        self.attempts.lock().clear();
+    }
+}
+
+/// Render a verification code with [`Context::CODE_LENGTH`] digits,
+/// zero-padded, ex. `012345`.
+pub fn format_code(code: u32) -> String {
+    format!("{code:0width$}", width = Context::CODE_LENGTH)
+}
+
+/// The latest expire time allowed from now, `None` if unlimited.
+///
+/// See [`crate::config::Account::max_expiry_days`].
+pub fn expiry_horizon() -> Option<DateTime<Utc>> {
+    match crate::config::INSTANCE.account.max_expiry_days {
+        0 => None,
+        days => Some(crate::clock::now() + Duration::days(days as i64)),
+    }
+}
+
+/// Clamp target expire time to [`expiry_horizon`].
+pub fn clamp_expiry(time: DateTime<Utc>) -> DateTime<Utc> {
+    expiry_horizon().map_or(time, |horizon| time.min(horizon))
+}
+
 /// Represent infos of an unverified object.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Context {
     /// The email address.
+    #[serde(with = "super::store::address_string")]
     pub email: lettre::Address,
-    /// The pending verification code with 6 digits.
+    /// The pending verification code with [`Context::CODE_LENGTH`] digits,
+    /// see [`format_code`].
     pub code: u32,
     /// The expire time of this context.
-    pub expire_time: NaiveDateTime,
+    #[serde(with = "super::store::utc_time")]
+    pub expire_time: DateTime<Utc>,
+}
+
+/// The code is redacted.
+impl std::fmt::Debug for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Context")
+            .field("email", &self.email)
+            .field("code", &REDACTED)
+            .field("expire_time", &self.expire_time)
+            .finish()
+    }
 }
 
 impl Context {
+    /// Count of digits in a verification code.
+    pub const CODE_LENGTH: usize = 6;
+    /// Minutes a verification code stays valid.
+    const VALID_MINUTES: i64 = 15;
+    /// Seconds to wait before sending another code to the same address.
+    const RESEND_COOLDOWN_SECONDS: i64 = 60;
+
+    /// Create a context with a new code for target email address.
+    pub fn new(email: lettre::Address) -> Self {
+        let mut ctx = Self {
+            email,
+            code: 0,
+            expire_time: DateTime::<Utc>::default(),
+        };
+        ctx.renew();
+        ctx
+    }
+
+    /// Replace the code of this context with a new one and restart its expiration.
+    pub fn renew(&mut self) {
+        self.code = rand::thread_rng().gen_range(0..10u32.pow(Self::CODE_LENGTH as u32));
+        self.expire_time = crate::clock::now() + Duration::minutes(Self::VALID_MINUTES);
+    }
+
+    /// Make this context expire no later than target time,
+    /// returning whether it was changed.
+    pub fn clamp_expiry(&mut self, horizon: DateTime<Utc>) -> bool {
+        if self.expire_time > horizon {
+            self.expire_time = horizon;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the resend cooldown of this context's code has passed.
+    pub fn can_resend(&self) -> bool {
+        crate::clock::now() >= self.resend_time()
+    }
+
+    /// Get when the resend cooldown of this context's code passes.
+    pub fn resend_time(&self) -> DateTime<Utc> {
+        let sent_time = self.expire_time - Duration::minutes(Self::VALID_MINUTES);
+        sent_time + Duration::seconds(Self::RESEND_COOLDOWN_SECONDS)
+    }
+
+    /// Mail the code of this context to its email.
+    ///
+    /// This doesn't follow [`crate::config::CodeDelivery`], as codes of
+    /// registrations and alternate emails verify the email itself.
     pub fn send_verify(&self) {
-        info!(
-            "sending verification code for {} (code: {})",
-            self.email, self.code
-        );
+        // Mails are sent in background, so queueing one never fails.
+        let _ = self.send_verify_through(&EmailDelivery, &Recipient::Email(self.email.clone()));
+    }
 
-        #[cfg(not(test))]
+    /// Send the code of this context resetting a forgotten password through
+    /// the configured channel, see [`crate::config::CodeDelivery::reset_password`].
+    ///
+    /// Accounts without a phone number, ex. provisioned by SSO, are mailed.
+    pub fn send_reset(&self, phone: u64) -> Result<(), super::Error> {
+        use crate::config::DeliveryChannel;
+
+        match crate::config::INSTANCE
+            .account
+            .code_delivery()
+            .reset_password
         {
-            let this = self.clone();
+            DeliveryChannel::Sms if phone != 0 => {
+                self.send_verify_through(&SmsDelivery, &Recipient::Phone(phone))
+            }
+            _ => self.send_verify_through(&EmailDelivery, &Recipient::Email(self.email.clone())),
+        }
+    }
 
-            tokio::spawn(async move {
-                SENDER_INSTANCE.send_verification(&this).await.unwrap();
-                info!("verification code for {} sent", this.email);
-            });
+    /// Send the code of this context to target recipient through a channel,
+    /// emitting [`super::event::Event::VerificationSent`] with the outcome.
+    pub fn send_verify_through(
+        &self,
+        delivery: &dyn CodeDelivery,
+        to: &Recipient,
+    ) -> Result<(), super::Error> {
+        match to {
+            Recipient::Email(email) => {
+                info!("sending verification code to {}", Email(email))
+            }
+            Recipient::Phone(_) => {
+                info!("sending verification code of {} by SMS", Email(&self.email))
+            }
         }
 
         #[cfg(test)]
         {
             VERIFICATION_CODE.store(self.code, std::sync::atomic::Ordering::Relaxed);
         }
+
+        let code = format_code(self.code);
+        let sent = {
+            let channel = delivery.channel();
+            let domain = self.email.domain().to_string();
+            let code_length = code.len();
+            move |success| {
+                super::event::emit(super::event::Event::VerificationSent {
+                    channel,
+                    domain,
+                    code_length,
+                    success,
+                })
+            }
+        };
+        let failed = sent.clone();
+        delivery
+            .send(to, &code, Box::new(sent))
+            .inspect_err(|_| failed(false))
+    }
+
+    /// Whether `code` is the code of this context, compared as rendered
+    /// by [`format_code`].
+    pub fn matches(&self, code: u32) -> bool {
+        format_code(self.code) == format_code(code)
     }
 
     /// Whether this context was expired.
     pub fn is_expired(&self) -> bool {
-        self.expire_time <= Utc::now().naive_utc()
+        self.expire_time <= crate::clock::now()
     }
 }
 
 /// A simple token manager.
-#[derive(Serialize, Deserialize, Debug)]
+///
+/// Token and API key hashes are redacted from `Debug` output.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Tokens {
-    inner: Vec<(Option<NaiveDateTime>, u64)>,
+    inner: Vec<Token>,
+    /// Devices tokens were created on, the most recently seen last.
+    #[serde(default)]
+    devices: Vec<Device>,
+    /// Named long-lived keys with narrowed permissions.
+    #[serde(default)]
+    api_keys: Vec<ApiKey>,
+    /// A one-time token only usable to change the password.
+    #[serde(default)]
+    password_change: Option<Token>,
+}
+
+/// A named API key stored as its hash.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    name: String,
+    hash: String,
+    /// Permissions this key is scoped to.
+    permissions: Permissions,
+    /// The expire time of this key, `None` means never expire.
+    #[serde(default, with = "super::store::utc_time_option")]
+    expire_time: Option<DateTime<Utc>>,
+}
+
+impl std::fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiKey")
+            .field("name", &self.name)
+            .field("hash", &REDACTED)
+            .field("permissions", &self.permissions)
+            .field("expire_time", &self.expire_time)
+            .finish()
+    }
+}
+
+impl ApiKey {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn permissions(&self) -> &[Permission] {
+        &self.permissions
+    }
+
+    pub fn expire_time(&self) -> Option<DateTime<Utc>> {
+        self.expire_time
+    }
+
+    fn expired(&self) -> bool {
+        self.expire_time.is_some_and(|e| e <= crate::clock::now())
+    }
+}
+
+/// A token stored as its hash.
+#[derive(Serialize, Deserialize, Clone)]
+struct Token {
+    /// The expire time of this token, `None` means never expire.
+    #[serde(default, with = "super::store::utc_time_option")]
+    expire_time: Option<DateTime<Utc>>,
+    #[serde(with = "super::store::u64_string")]
+    hash: u64,
+    /// The device this token was created on.
+    #[serde(default)]
+    device: Device,
+    /// When this token was created, `None` for tokens created before it was recorded.
+    #[serde(default, with = "super::store::utc_time_option")]
+    issue_time: Option<DateTime<Utc>>,
+    /// When this token was last used, `None` if never touched.
+    #[serde(default, with = "super::store::utc_time_option")]
+    last_used: Option<DateTime<Utc>>,
+    /// When touching this token last asked for saving, not persisted.
+    #[serde(skip)]
+    touch_saved: Option<DateTime<Utc>>,
+}
+
+impl Token {
+    /// Extend this token to expire `window` days from now, but no later than
+    /// `max_lifetime` days after it was created, see [`Tokens::extend`].
+    fn extend(&mut self, window: u16, max_lifetime: u16) -> bool {
+        let now = crate::clock::now();
+        let (Some(expire_time), Some(issue_time)) = (self.expire_time, self.issue_time) else {
+            return false;
+        };
+        if expire_time <= now {
+            return false;
+        }
+
+        let extended = (now + Duration::days(window as i64))
+            .min(issue_time + Duration::days(max_lifetime as i64));
+        let extended = clamp_expiry(extended);
+        if extended > expire_time {
+            self.expire_time = Some(extended);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl std::fmt::Debug for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Token")
+            .field("expire_time", &self.expire_time)
+            .field("hash", &REDACTED)
+            .field("device", &self.device)
+            .field("issue_time", &self.issue_time)
+            .field("last_used", &self.last_used)
+            .finish()
+    }
+}
+
+/// The device a login comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Device {
+    /// IP address of the client.
+    pub ip: Option<IpAddr>,
+    /// `User-Agent` header of the client.
+    pub user_agent: Option<String>,
+}
+
+impl Default for Tokens {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Tokens {
+    /// Max count of devices remembered.
+    const MAX_DEVICES: usize = 16;
+    /// Max count of tokens regardless of the configured session cap.
+    pub const MAX_TOKENS: usize = 128;
+    /// Minutes a password change token stays valid.
+    pub const PASSWORD_CHANGE_MINUTES: i64 = 15;
+    /// Minutes between saves asked for by touching a token.
+    pub const TOUCH_SAVE_MINUTES: i64 = 5;
+
     pub fn new() -> Self {
         Self {
-            inner: Vec::with_capacity(16),
+            inner: Vec::new(),
+            devices: Vec::new(),
+            api_keys: Vec::new(),
+            password_change: None,
         }
     }
 
     /// Create a new token.
-    #[must_use]
-    pub fn new_token(
+    pub fn new_token(&mut self, expire_time: u16) -> Result<String, super::Error> {
+        self.new_token_on(expire_time, Device::default())
+    }
+
+    /// Create a new token on target device and remember the device,
+    /// capping sessions as configured.
+    pub fn new_token_on(
         &mut self,
-        // The user id.
-        id: u64,
         expire_time: u16,
-    ) -> String {
-        let now = if expire_time == 0 {
+        device: Device,
+    ) -> Result<String, super::Error> {
+        self.new_token_with(
+            expire_time,
+            device,
+            crate::config::INSTANCE.account.max_sessions,
+        )
+    }
+
+    /// Create a new token on target device, evicting the oldest token
+    /// when `max_sessions` tokens exist, `0` for no cap.
+    ///
+    /// Fails with [`super::Error::TooManySessions`] rather than growing
+    /// beyond [`Self::MAX_TOKENS`].
+    pub fn new_token_with(
+        &mut self,
+        expire_time: u16,
+        device: Device,
+        max_sessions: usize,
+    ) -> Result<String, super::Error> {
+        let now = crate::clock::now();
+        let expire_time = if expire_time == 0 {
             None
         } else {
-            Some(
-                Utc::now()
-                    .naive_utc()
-                    .checked_add_days(Days::new(expire_time as u64))
-                    .unwrap_or_default(),
-            )
+            Some(clamp_expiry(
+                now.checked_add_days(Days::new(expire_time as u64))
+                    .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            ))
         };
 
-        let token = digest(format!("{}-{:?}", id, now));
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        token.hash(&mut hasher);
+        self.inner.retain(|e| e.expire_time.is_none_or(|a| a > now));
+        if max_sessions != 0 && self.inner.len() >= max_sessions {
+            let excess = self.inner.len() + 1 - max_sessions;
+            for _ in 0..excess {
+                if let Some((oldest, _)) = self
+                    .inner
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, e)| e.issue_time)
+                {
+                    self.inner.remove(oldest);
+                }
+            }
+        }
+        if self.inner.len() >= Self::MAX_TOKENS {
+            return Err(super::Error::TooManySessions);
+        }
+
+        let token = generate_token(crate::config::INSTANCE.account.token_bytes);
 
-        if self.inner.capacity() == self.inner.len() + 1 {
-            self.inner.remove(self.inner.len());
+        self.devices.retain(|e| e != &device);
+        if self.devices.len() == Self::MAX_DEVICES {
+            self.devices.remove(0);
         }
+        self.devices.push(device.clone());
 
-        self.inner.push((now, hasher.finish()));
+        self.inner.push(Token {
+            expire_time,
+            hash: hash_token(&token),
+            device,
+            issue_time: Some(now),
+            last_used: None,
+            touch_saved: None,
+        });
+        Ok(token)
+    }
+
+    /// Create a one-time token only usable to change the password,
+    /// replacing the previous one.
+    pub fn new_password_change_token(&mut self) -> String {
+        let token = generate_token(crate::config::INSTANCE.account.token_bytes);
+        let now = crate::clock::now();
+
+        self.password_change = Some(Token {
+            expire_time: Some(now + Duration::minutes(Self::PASSWORD_CHANGE_MINUTES)),
+            hash: hash_token(&token),
+            device: Device::default(),
+            issue_time: Some(now),
+            last_used: None,
+            touch_saved: None,
+        });
         token
     }
 
+    /// Check if target token is the password change token and not expired.
+    pub fn password_change_token_usable(&self, token: &str) -> bool {
+        let hash = hash_token(token);
+
+        self.password_change.as_ref().is_some_and(|e| {
+            e.hash == hash && e.expire_time.is_none_or(|a| a > crate::clock::now())
+        })
+    }
+
+    /// Consume the password change token, returning whether it was usable.
+    pub fn take_password_change_token(&mut self, token: &str) -> bool {
+        let usable = self.password_change_token_usable(token);
+        if usable {
+            self.password_change = None;
+        }
+        usable
+    }
+
+    /// Expire times and devices of all sessions, without the tokens.
+    pub fn sessions(&self) -> impl Iterator<Item = (Option<DateTime<Utc>>, &Device)> {
+        self.inner.iter().map(|e| (e.expire_time, &e.device))
+    }
+
+    /// Count of tokens not expired yet.
+    pub fn active_count(&self) -> usize {
+        let now = crate::clock::now();
+        self.inner
+            .iter()
+            .filter(|e| e.expire_time.is_none_or(|a| a > now))
+            .count()
+    }
+
+    /// Time since the oldest token not expired yet was created, ignoring
+    /// tokens created before it was recorded.
+    pub fn oldest_active_age(&self) -> Option<Duration> {
+        let now = crate::clock::now();
+        self.inner
+            .iter()
+            .filter(|e| e.expire_time.is_none_or(|a| a > now))
+            .filter_map(|e| e.issue_time)
+            .min()
+            .map(|issue_time| now - issue_time)
+    }
+
+    /// Whether target device is different from all remembered devices.
+    /// Always `false` if no device has been remembered yet.
+    pub fn is_new_device(&self, device: &Device) -> bool {
+        !self.devices.is_empty() && !self.devices.contains(device)
+    }
+
     /// Remove a target token and return whether the token was be removed successfully.
     pub(super) fn remove(&mut self, token: &str) -> bool {
         let l = self.inner.len();
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        token.hash(&mut hasher);
-        let hash = hasher.finish();
-        self.inner.retain(|e| e.1 != hash);
+        let hash = hash_token(token);
+        self.inner.retain(|e| e.hash != hash);
         l > self.inner.len()
     }
 
+    /// Revoke all session tokens, API keys and the password change token,
+    /// ex. when the account may be compromised.
+    pub(super) fn revoke_all(&mut self) {
+        self.inner.clear();
+        self.api_keys.clear();
+        self.password_change = None;
+    }
+
+    /// Hashes of the session tokens with their expire times, `None` if never expiring.
+    pub fn hashes(&self) -> impl Iterator<Item = (u64, Option<DateTime<Utc>>)> + '_ {
+        self.inner.iter().map(|e| (e.hash, e.expire_time))
+    }
+
     /// Check if a token is usable.
     #[inline]
     pub fn token_usable(&self, token: &str) -> bool {
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        token.hash(&mut hasher);
-        let hash = hasher.finish();
-        self.inner.iter().any(|e| e.1 == hash)
+        let hash = hash_token(token);
+        self.inner.iter().any(|e| e.hash == hash)
+    }
+
+    /// Get the time left before target token expires, `None` if the token
+    /// never expires or doesn't exist.
+    pub fn time_until_expiry(&self, token: &str) -> Option<Duration> {
+        let hash = hash_token(token);
+        let expire_time = self.inner.iter().find(|e| e.hash == hash)?.expire_time?;
+        Some((expire_time - crate::clock::now()).max(Duration::zero()))
+    }
+
+    /// Extend target token to expire `window` days from now, but no later than
+    /// `max_lifetime` days after it was created.
+    /// Returns whether the expire time was changed.
+    ///
+    /// Tokens never expiring, expired or without a known creation time are left as is.
+    pub fn extend(&mut self, token: &str, window: u16, max_lifetime: u16) -> bool {
+        let hash = hash_token(token);
+
+        self.inner
+            .iter_mut()
+            .find(|e| e.hash == hash)
+            .is_some_and(|token| token.extend(window, max_lifetime))
     }
 
-    /// Remove expired tokens.
+    /// Record target token as used now, extending it like [`Self::extend`]
+    /// with `sliding` window and max lifetime if set.
+    ///
+    /// Returns `None` if the token doesn't exist or expired, otherwise whether
+    /// the change should be saved, which is at most once per
+    /// [`Self::TOUCH_SAVE_MINUTES`] for each token, so validating tokens on
+    /// every request doesn't write to disk every time.
+    pub fn touch(&mut self, token: &str, sliding: Option<(u16, u16)>) -> Option<bool> {
+        let hash = hash_token(token);
+        let now = crate::clock::now();
+
+        let token = self.inner.iter_mut().find(|e| e.hash == hash)?;
+        if token
+            .expire_time
+            .is_some_and(|expire_time| expire_time <= now)
+        {
+            return None;
+        }
+        token.last_used = Some(now);
+        if let Some((window, max_lifetime)) = sliding {
+            token.extend(window, max_lifetime);
+        }
+
+        let save = token
+            .touch_saved
+            .is_none_or(|saved| now - saved >= Duration::minutes(Self::TOUCH_SAVE_MINUTES));
+        if save {
+            token.touch_saved = Some(now);
+        }
+        Some(save)
+    }
+
+    /// Get when target token was last used, `None` if it was never touched
+    /// or doesn't exist.
+    pub fn last_used(&self, token: &str) -> Option<DateTime<Utc>> {
+        let hash = hash_token(token);
+        self.inner.iter().find(|e| e.hash == hash)?.last_used
+    }
+
+    /// Get the expire time of target token if it exists and hasn't expired yet.
+    /// The inner `None` means the token never expires.
+    pub fn active_expire_time(&self, token: &str) -> Option<Option<DateTime<Utc>>> {
+        let hash = hash_token(token);
+        let expire_time = self.inner.iter().find(|e| e.hash == hash)?.expire_time;
+        if expire_time.is_some_and(|e| e <= crate::clock::now()) {
+            None
+        } else {
+            Some(expire_time)
+        }
+    }
+
+    /// Create a new named API key and return back the key,
+    /// or `None` if a key with the same name exists.
+    #[must_use]
+    pub(super) fn new_api_key(
+        &mut self,
+        name: String,
+        permissions: Permissions,
+        expire_time: Option<DateTime<Utc>>,
+    ) -> Option<String> {
+        if self.api_keys.iter().any(|e| e.name == name) {
+            return None;
+        }
+
+        let key = generate_token(crate::config::INSTANCE.account.token_bytes);
+        self.api_keys.push(ApiKey {
+            name,
+            hash: digest(key.as_str()),
+            permissions,
+            expire_time: expire_time.map(clamp_expiry),
+        });
+        Some(key)
+    }
+
+    /// All API keys of this account.
+    pub fn api_keys(&self) -> &[ApiKey] {
+        &self.api_keys
+    }
+
+    /// Revoke the API key with target name and return whether it was revoked.
+    pub(super) fn revoke_api_key(&mut self, name: &str) -> bool {
+        let l = self.api_keys.len();
+        self.api_keys.retain(|e| e.name != name);
+        l > self.api_keys.len()
+    }
+
+    /// Get the permissions target API key is scoped to,
+    /// `None` if the key doesn't exist or has expired.
+    pub fn api_key_scope(&self, key: &str) -> Option<&[Permission]> {
+        let hash = digest(key);
+        self.api_keys
+            .iter()
+            .find(|e| e.hash == hash && !e.expired())
+            .map(|e| e.permissions.as_slice())
+    }
+
+    /// Make tokens expire no later than `max_days` days after they were created,
+    /// or from now if the creation time is unknown.
+    pub fn clamp(&mut self, max_days: u16) {
+        let now = crate::clock::now();
+        for token in self.inner.iter_mut() {
+            let max = token.issue_time.unwrap_or(now) + Duration::days(max_days as i64);
+            if token.expire_time.is_none_or(|e| e > max) {
+                token.expire_time = Some(max);
+            }
+        }
+    }
+
+    /// Make tokens and API keys expire no later than target time,
+    /// returning whether any was changed.
+    ///
+    /// Ones never expiring are left as is.
+    pub fn clamp_expiry(&mut self, horizon: DateTime<Utc>) -> bool {
+        let mut clamped = false;
+        let expire_times = self
+            .inner
+            .iter_mut()
+            .chain(self.password_change.iter_mut())
+            .map(|e| &mut e.expire_time)
+            .chain(self.api_keys.iter_mut().map(|e| &mut e.expire_time));
+        for expire_time in expire_times.flatten() {
+            if *expire_time > horizon {
+                *expire_time = horizon;
+                clamped = true;
+            }
+        }
+        clamped
+    }
+
+    /// Count of tokens and API keys, including expired ones.
+    pub fn len(&self) -> usize {
+        self.inner.len() + self.api_keys.len()
+    }
+
+    /// Whether there is no token or API key.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove expired tokens and API keys.
     pub fn refresh(&mut self) {
+        self.api_keys.retain(|e| !e.expired());
+        if self
+            .password_change
+            .as_ref()
+            .is_some_and(|e| e.expire_time.is_some_and(|a| a <= crate::clock::now()))
+        {
+            self.password_change = None;
+        }
         self.inner
-            .retain(|e| e.0.map_or(true, |a| a > Utc::now().naive_utc()));
-        self.inner.sort_by(|a, b| b.0.cmp(&a.0));
+            .retain(|e| e.expire_time.is_none_or(|a| a > crate::clock::now()));
+        self.inner.sort_by_key(|e| std::cmp::Reverse(e.expire_time));
     }
 }
 
 #[cfg(not(test))]
-pub struct VerificationSender {
+pub(super) struct VerificationSender {
     config: &'static crate::config::MailSmtp,
 }
 
 #[cfg(not(test))]
 impl VerificationSender {
-    pub fn new() -> Self {
+    fn new() -> Self {
         Self {
             config: &crate::config::INSTANCE.mail_smtp,
         }
@@ -154,31 +1314,13 @@ impl VerificationSender {
             .build()
     }
 
-    pub async fn send_verification(
-        &self,
-        cxt: &Context,
-    ) -> Result<(), lettre::transport::smtp::Error> {
-        use lettre::{
-            message::{header::ContentType, Mailbox},
-            AsyncTransport, Message,
-        };
-
-        let mailer = self.mailer();
-        mailer
-            .send(
-                Message::builder()
-                    .from(Mailbox::new(
-                        Some("SubIT".to_string()),
-                        self.config.address.clone(),
-                    ))
-                    .to(Mailbox::new(None, cxt.email.clone()))
-                    .subject("Your verification code")
-                    .header(ContentType::TEXT_PLAIN)
-                    .body(format!("Your verification code is {}", cxt.code))
-                    .unwrap(),
-            )
-            .await
-            .map(|_| ())?;
-        Ok(())
+    pub async fn send(&self, mail: &Mail) -> Result<(), lettre::transport::smtp::Error> {
+        send_retrying(
+            &self.mailer(),
+            &mail.message(self.config),
+            MAIL_ATTEMPTS,
+            std::time::Duration::from_secs(5),
+        )
+        .await
     }
 }
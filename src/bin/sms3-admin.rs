@@ -0,0 +1,279 @@
+//! Command line tool for managing accounts inside the data directory,
+//! sharing storage with the server.
+
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use sms3_backend::account::{
+    self,
+    audit::{self, PermissionDiff},
+    Account, ManagerError, Permission, UserAttributes,
+};
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(
+    name = "sms3-admin",
+    about = "Manage accounts of a sms3 data directory"
+)]
+struct Cli {
+    /// Print results as JSON for scripting.
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// The data directory, env var `SMS3_DATA_DIR` or `./data` by default.
+    #[arg(long, global = true)]
+    data_dir: Option<std::path::PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List all accounts.
+    List,
+    /// Grant a permission to an account.
+    Grant {
+        id: u64,
+        #[arg(value_parser = parse_permission)]
+        permission: Permission,
+    },
+    /// Revoke a permission from an account.
+    Revoke {
+        id: u64,
+        #[arg(value_parser = parse_permission)]
+        permission: Permission,
+    },
+    /// Reset password of an account.
+    ResetPassword { id: u64, password: String },
+    /// Suspend an account, rejecting its logins and tokens.
+    Suspend { id: u64 },
+    /// Lift the suspension of an account.
+    Unban { id: u64 },
+    /// Remove expired unverified accounts and tokens.
+    Refresh {
+        /// Only show what would be removed.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+fn parse_permission(value: &str) -> std::result::Result<Permission, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| format!("unknown permission: {}", value))
+}
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    if let Some(dir) = &cli.data_dir {
+        std::env::set_var(sms3_backend::config::DATA_DIR_VAR, dir);
+    }
+
+    match run(&cli) {
+        Ok(value) => {
+            if cli.json {
+                println!("{}", value);
+            } else {
+                print_human(&value);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            if cli.json {
+                println!("{}", json!({ "error": err.to_string() }));
+            } else {
+                eprintln!("error: {}", err);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<serde_json::Value> {
+    let manager = &account::INSTANCE;
+    let store = manager.store().ok_or("account storage unavailable")?;
+
+    match &cli.command {
+        Command::List => {
+            let mut accounts = Vec::new();
+            for id in manager.snapshot_ids() {
+                if let Some(value) = manager.with_account(id, describe) {
+                    accounts.push(value);
+                }
+            }
+            Ok(serde_json::Value::Array(accounts))
+        }
+
+        Command::Grant { id, permission } => change_permissions(*id, |attributes| {
+            attributes.grant_permission(*permission);
+        }),
+
+        Command::Revoke { id, permission } => change_permissions(*id, |attributes| {
+            attributes.revoke_permission(*permission);
+        }),
+
+        Command::ResetPassword { id, password } => {
+            modify(*id, |account| account.set_password(password))
+        }
+
+        Command::Suspend { id } => set_suspended(*id, true),
+
+        Command::Unban { id } => set_suspended(*id, false),
+
+        Command::Refresh { dry_run: true } => {
+            let plan = manager.refresh_dry_run();
+            Ok(json!({
+                "would_remove": plan.removed_accounts,
+                "pruned_tokens": plan.pruned_tokens,
+                "cleared_resets": plan.cleared_resets,
+            }))
+        }
+
+        Command::Refresh { dry_run: false } => {
+            let lock = store.lock_all()?;
+            let before = manager.snapshot_ids();
+            manager.refresh_all();
+
+            let mut removed = 0;
+            for id in before {
+                if manager
+                    .with_account(id, |account| lock.save(account))
+                    .transpose()?
+                    .is_none()
+                {
+                    lock.remove(id)?;
+                    removed += 1;
+                }
+            }
+            Ok(json!({ "removed": removed }))
+        }
+    }
+}
+
+/// Apply a modification to target account and save it before returning.
+fn modify(
+    id: u64,
+    f: impl FnOnce(&mut Account) -> std::result::Result<(), account::Error>,
+) -> Result<serde_json::Value> {
+    let manager = &account::INSTANCE;
+    let store = manager.store().ok_or("account storage unavailable")?;
+
+    let lock = store.lock_all()?;
+
+    manager
+        .with_account_mut(id, |account| -> Result<serde_json::Value> {
+            f(account).map_err(|err| ManagerError::from((id, err)))?;
+            lock.save(account)?;
+            Ok(describe(account))
+        })
+        .ok_or(ManagerError::NotFound(id))?
+}
+
+/// Change permissions of a verified account, recording the change in the audit log.
+fn change_permissions(id: u64, f: impl FnOnce(&mut UserAttributes)) -> Result<serde_json::Value> {
+    let mut diff = PermissionDiff::default();
+    let value = modify(id, |account| {
+        if let Account::Verified { attributes, .. } = account {
+            let before = attributes.permissions.clone();
+            f(attributes);
+            diff = PermissionDiff::between(&before, &attributes.permissions);
+            Ok(())
+        } else {
+            Err(account::Error::UserUnverified)
+        }
+    })?;
+
+    if !diff.is_empty() {
+        account::INSTANCE.audit().record([audit::Entry {
+            time: sms3_backend::clock::now(),
+            by: audit::Entry::COMMAND_LINE,
+            target: id,
+            action: audit::Action::ChangePermissions(diff),
+        }]);
+    }
+    Ok(value)
+}
+
+/// Suspend or unban a verified account, recording the change in the audit log.
+fn set_suspended(id: u64, suspended: bool) -> Result<serde_json::Value> {
+    let mut changed = false;
+    let value = modify(id, |account| {
+        changed = matches!(account, Account::Verified { attributes, .. } if attributes.suspended != suspended);
+        account.set_suspended(suspended)
+    })?;
+
+    if changed {
+        account::INSTANCE.audit().record([audit::Entry {
+            time: sms3_backend::clock::now(),
+            by: audit::Entry::COMMAND_LINE,
+            target: id,
+            action: if suspended {
+                audit::Action::Suspend
+            } else {
+                audit::Action::Unban
+            },
+        }]);
+    }
+    Ok(value)
+}
+
+fn describe(account: &Account) -> serde_json::Value {
+    match account {
+        Account::Unverified(_) => json!({
+            "id": account.id(),
+            "verified": false,
+            "email": account.email(),
+        }),
+        Account::Verified { attributes, .. } => json!({
+            "id": account.id(),
+            "verified": true,
+            "email": account.email(),
+            "name": attributes.name,
+            "permissions": attributes.permissions,
+            "suspended": attributes.suspended,
+        }),
+    }
+}
+
+fn print_human(value: &serde_json::Value) {
+    let print_account = |account: &serde_json::Value| {
+        println!(
+            "{}\t{}\t{}\t{}{}",
+            account["id"],
+            account["email"].as_str().unwrap_or_default(),
+            account["name"].as_str().unwrap_or("(unverified)"),
+            account["permissions"]
+                .as_array()
+                .map(|p| p
+                    .iter()
+                    .filter_map(|e| e.as_str())
+                    .collect::<Vec<_>>()
+                    .join(","))
+                .unwrap_or_default(),
+            if account["suspended"] == true {
+                "\tsuspended"
+            } else {
+                ""
+            },
+        )
+    };
+
+    match value {
+        serde_json::Value::Array(accounts) => accounts.iter().for_each(print_account),
+        serde_json::Value::Object(object) if object.contains_key("removed") => {
+            println!("{} expired accounts removed", value["removed"])
+        }
+        serde_json::Value::Object(object) if object.contains_key("would_remove") => {
+            println!(
+                "{} expired accounts, tokens of {} accounts and {} password resets would be removed",
+                value["would_remove"].as_array().map_or(0, Vec::len),
+                value["pruned_tokens"].as_object().map_or(0, |e| e.len()),
+                value["cleared_resets"].as_array().map_or(0, Vec::len),
+            )
+        }
+        _ => print_account(value),
+    }
+}
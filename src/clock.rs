@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+
+#[cfg(test)]
+static FROZEN: parking_lot::Mutex<Option<DateTime<Utc>>> = parking_lot::Mutex::new(None);
+
+/// Get the current time.
+///
+/// In tests the time can be frozen with [`set`] and moved with [`advance`].
+pub fn now() -> DateTime<Utc> {
+    #[cfg(test)]
+    if let Some(time) = *FROZEN.lock() {
+        return time;
+    }
+
+    Utc::now()
+}
+
+/// Freeze the clock at target time, only for testing.
+#[cfg(test)]
+pub fn set(time: DateTime<Utc>) {
+    *FROZEN.lock() = Some(time);
+}
+
+/// Move the clock forward, freezing it if it wasn't, only for testing.
+#[cfg(test)]
+pub fn advance(duration: chrono::Duration) {
+    let time = now() + duration;
+    set(time);
+}
+
+/// Unfreeze the clock, only for testing.
+#[cfg(test)]
+pub fn reset() {
+    *FROZEN.lock() = None;
+}
@@ -1,5 +1,8 @@
+use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
 use serde::Deserialize;
+use sms3_shared::account::{House, HouseInfo, Permission};
+use std::{borrow::Cow, collections::BTreeMap};
 
 /// The static config instance.
 #[allow(dead_code)]
@@ -10,7 +13,7 @@ pub static INSTANCE: Lazy<Config> = Lazy::new(|| {
 
         toml::from_str(&{
             let mut string = String::new();
-            File::open("./data/config.toml")
+            File::open(data_dir().join("config.toml"))
                 .unwrap()
                 .read_to_string(&mut string)
                 .unwrap();
@@ -23,20 +26,530 @@ pub static INSTANCE: Lazy<Config> = Lazy::new(|| {
     Config::default()
 });
 
+/// The environment variable for overriding the data directory.
+pub const DATA_DIR_VAR: &str = "SMS3_DATA_DIR";
+
+/// The directory storing config and data of the server,
+/// from env var `SMS3_DATA_DIR` and `./data` by default.
+pub fn data_dir() -> std::path::PathBuf {
+    std::env::var_os(DATA_DIR_VAR)
+        .map(Into::into)
+        .unwrap_or_else(|| "./data".into())
+}
+
 /// Describing the server configuration.
+#[cfg_attr(test, allow(dead_code))]
 #[derive(Deserialize, Default)]
 pub struct Config {
     pub mail_smtp: MailSmtp,
+    #[serde(default)]
+    pub account: Account,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub cors: Cors,
+    /// Signing in with an OpenID Connect provider, disabled if not set.
+    #[serde(default)]
+    pub oidc: Option<Oidc>,
+    /// The gateway delivering verification codes by SMS, disabled if not set.
+    #[serde(default)]
+    pub sms: Option<Sms>,
+    /// Addresses of reverse proxies whose `X-Forwarded-For` headers are trusted,
+    /// none by default.
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+}
+
+impl Config {
+    /// Get the OpenID Connect provider to sign in with, if any.
+    pub fn oidc(&self) -> Option<Oidc> {
+        #[cfg(test)]
+        if let Some(oidc) = &*OIDC.lock() {
+            return Some(oidc.clone());
+        }
+
+        self.oidc.clone()
+    }
+
+    /// Whether target peer is a trusted reverse proxy.
+    pub fn is_trusted_proxy(&self, ip: std::net::IpAddr) -> bool {
+        #[cfg(test)]
+        if let Some(proxies) = &*TRUSTED_PROXIES.lock() {
+            return proxies.contains(&ip);
+        }
+
+        self.trusted_proxies.contains(&ip)
+    }
+}
+
+/// Describing an OpenID Connect provider, ex. the school's SSO,
+/// whose verified emails sign in to accounts.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Oidc {
+    /// Issuer identifier ID tokens must be issued by.
+    pub issuer: String,
+    /// URL users are redirected to for signing in.
+    pub authorization_endpoint: String,
+    /// URL authorization codes are exchanged for ID tokens at,
+    /// which must be HTTPS as ID tokens are trusted by its TLS certificate.
+    pub token_endpoint: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// URL the provider redirects users back to, ex. a page of the frontend
+    /// posting the code and state to `/api/account/oidc/callback`.
+    pub redirect_uri: String,
+    /// Scopes requested, which must include `openid` and `email`.
+    #[serde(default = "Oidc::default_scopes")]
+    pub scopes: String,
+    /// Whether accounts are created for school emails without one.
+    #[serde(default)]
+    pub provision: bool,
+}
+
+impl Oidc {
+    fn default_scopes() -> String {
+        "openid email profile".to_string()
+    }
+
+    /// Check this configuration, failing with a description of the first problem.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.token_endpoint.starts_with("https://") {
+            return Err(format!(
+                "token endpoint {} isn't HTTPS",
+                self.token_endpoint
+            ));
+        }
+        let scopes: Vec<_> = self.scopes.split_whitespace().collect();
+        for scope in ["openid", "email"] {
+            if !scopes.contains(&scope) {
+                return Err(format!("scopes don't include {scope}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Describing an HTTP gateway sending text messages.
+///
+/// Messages are posted to `endpoint` as JSON `{"to": <phone>, "message": <text>}`
+/// with `token` as a bearer token.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Sms {
+    pub endpoint: String,
+    pub token: String,
+}
+
+impl Sms {
+    /// Check this configuration, failing with a description of the first problem.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.endpoint.starts_with("https://") {
+            return Err(format!("endpoint {} isn't HTTPS", self.endpoint));
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the configured OpenID Connect provider, only for testing.
+#[cfg(test)]
+pub static OIDC: parking_lot::Mutex<Option<Oidc>> = parking_lot::Mutex::new(None);
+
+/// Replaces the configured trusted proxies, only for testing.
+#[cfg(test)]
+pub static TRUSTED_PROXIES: parking_lot::Mutex<Option<Vec<std::net::IpAddr>>> =
+    parking_lot::Mutex::new(None);
+
+/// Describing which cross-origin requests browsers are allowed to make,
+/// none by default.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Cors {
+    /// Origins allowed to make requests, ex. `https://sms.pkuschool.edu.cn`,
+    /// or only `*` for any origin, which can't be combined with credentials.
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed in cross-origin requests.
+    pub allowed_methods: Vec<String>,
+    /// Whether cross-origin requests may include credentials.
+    pub allow_credentials: bool,
+}
+
+impl Cors {
+    /// Whether any origin is allowed.
+    pub fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    /// Check this configuration, failing with a description of the first problem.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allows_any_origin() {
+            if self.allowed_origins.len() > 1 {
+                return Err("origin * can't be listed with other origins".to_string());
+            }
+            if self.allow_credentials {
+                return Err("origin * can't be allowed with credentials".to_string());
+            }
+        }
+        for origin in &self.allowed_origins {
+            if origin != "*" && axum::http::HeaderValue::from_str(origin).is_err() {
+                return Err(format!("origin {origin:?} isn't a valid header value"));
+            }
+        }
+        for method in &self.allowed_methods {
+            if axum::http::Method::from_bytes(method.as_bytes()).is_err() {
+                return Err(format!("method {method:?} isn't a valid method"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allow_credentials: false,
+        }
+    }
+}
+
+/// Describing request body size limits in bytes,
+/// requests beyond them are rejected with `413 Payload Too Large`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Limit of most endpoints.
+    pub body: usize,
+    /// Limit of bulk endpoints, ex. granting a permission to many accounts.
+    pub bulk_body: usize,
+    /// Limit of image uploads.
+    pub image_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            body: 64 * 1024,
+            bulk_body: 1024 * 1024,
+            image_body: 2 * 1024 * 1024,
+        }
+    }
+}
+
+/// Describing account policies.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Account {
+    /// Count of previous passwords a user can't change back to.
+    pub password_history: usize,
+    /// Minimum minutes between password changes by the user, `0` disables it.
+    pub password_change_interval: u32,
+    /// Registry of valid organization names, any name is accepted if empty.
+    pub organizations: Vec<String>,
+    /// Houses accounts can be assigned to, [`House::BUILTIN`] if empty.
+    pub houses: Vec<HouseInfo>,
+    /// Optional fields accounts must have to be activated.
+    pub required_fields: Vec<RequiredField>,
+    /// Channels verification codes of each flow are delivered through.
+    pub code_delivery: CodeDelivery,
+    /// Whether tokens are extended by the account's token expiration time on each use.
+    pub sliding_token_expiration: bool,
+    /// Days after login a sliding token expires regardless of use.
+    pub token_max_lifetime: u16,
+    /// Permissions whose holders can't have tokens never expiring.
+    pub sensitive_permissions: Vec<Permission>,
+    /// Max days tokens of accounts holding sensitive permissions last,
+    /// `0` disables the limit.
+    pub sensitive_token_expiration: u16,
+    /// Token expiration days of accounts holding each permission, replacing
+    /// their own token expiration time with the least of their held ones,
+    /// `0` for never expiring.
+    pub token_expiration_overrides: BTreeMap<Permission, u16>,
+    /// Count of random bytes in a token.
+    pub token_bytes: usize,
+    /// Max count of sessions of an account, the oldest is logged out
+    /// to make room for a new login, `0` disables the cap.
+    pub max_sessions: usize,
+    /// Max count of verification mails sent to an address within
+    /// `verification_mail_window`, `0` disables the limit.
+    pub verification_mail_limit: usize,
+    /// Minutes verification mails are counted in.
+    pub verification_mail_window: u16,
+    /// Max count of email availability checks from an IP address within
+    /// `availability_check_window`, `0` disables the limit.
+    pub availability_check_limit: usize,
+    /// Minutes availability checks are counted in.
+    pub availability_check_window: u16,
+    /// Max count of addresses or accounts each rate limiter tracks,
+    /// the least recently seen one is forgotten beyond it.
+    pub rate_limit_capacity: usize,
+    /// Count of consecutive failed logins locking an account out, `0` disables lockouts.
+    pub lockout_threshold: u32,
+    /// Minutes an account stays locked out.
+    pub lockout_minutes: u16,
+    /// Whether to mail the owner of a locked out account a one-time token
+    /// unlocking it immediately.
+    pub lockout_unlock_mail: bool,
+    /// Minutes an unlock token stays valid, while the account is still locked out.
+    pub unlock_token_minutes: u16,
+    /// Days an account deleted by its user can be restored by an admin
+    /// before it's purged, `0` deletes accounts immediately.
+    pub deletion_grace_days: u16,
+    /// Max days from now an expire time may be, later ones are clamped
+    /// when created or loaded, so a skewed clock can't mint near-permanent
+    /// credentials. `0` disables the limit.
+    pub max_expiry_days: u16,
+    /// Count of accounts to preallocate room for when loading, avoiding
+    /// reallocations while the accounts grow to it, `0` for only the loaded ones.
+    pub expected_accounts: usize,
+    /// When users can register themselves, always by default.
+    pub registration: RegistrationWindow,
+    /// How ids of new accounts are assigned.
+    pub id_strategy: IdStrategy,
+    /// Argon2id parameters of new password hashes,
+    /// weaker hashes are upgraded on login.
+    pub password_hash: PasswordHash,
+}
+
+/// Argon2id parameters of password hashes.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct PasswordHash {
+    /// Memory cost in KiB.
+    pub memory_kib: u32,
+    /// Count of passes.
+    pub iterations: u32,
+    /// Degree of parallelism.
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHash {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// When users can register themselves.
+/// Accounts created by admins aren't limited by it.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct RegistrationWindow {
+    /// Whether registration is open, within the schedule below.
+    pub open: bool,
+    /// When registration opens, always opened if not set.
+    pub opens_at: Option<DateTime<Utc>>,
+    /// When registration closes, never if not set.
+    pub closes_at: Option<DateTime<Utc>>,
+}
+
+impl Default for RegistrationWindow {
+    fn default() -> Self {
+        Self {
+            open: true,
+            opens_at: None,
+            closes_at: None,
+        }
+    }
+}
+
+impl RegistrationWindow {
+    /// Whether registration is open at target time.
+    pub fn is_open_at(&self, time: DateTime<Utc>) -> bool {
+        self.open
+            && self.opens_at.is_none_or(|opens_at| opens_at <= time)
+            && self.closes_at.is_none_or(|closes_at| time < closes_at)
+    }
+}
+
+/// Replaces the configured registration window, only for testing.
+#[cfg(test)]
+pub static REGISTRATION_WINDOW: parking_lot::Mutex<Option<RegistrationWindow>> =
+    parking_lot::Mutex::new(None);
+
+/// Replaces the configured delivery channels, only for testing.
+#[cfg(test)]
+pub static CODE_DELIVERY: parking_lot::Mutex<Option<CodeDelivery>> = parking_lot::Mutex::new(None);
+
+/// A channel verification codes are delivered through.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryChannel {
+    #[default]
+    Email,
+    /// To the phone number of the account through the gateway under `[sms]`.
+    Sms,
+}
+
+/// Channels verification codes are delivered through, by email if not set.
+///
+/// Codes of registrations and alternate emails always go by email,
+/// as they verify the email itself.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct CodeDelivery {
+    /// Codes resetting forgotten passwords.
+    pub reset_password: DeliveryChannel,
+}
+
+/// An optional field of accounts which can be required on activation.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RequiredField {
+    House,
+    Organization,
+}
+
+/// How ids of new accounts are assigned.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdStrategy {
+    /// Derived from the email with a stable hash, always the same for the same email.
+    #[default]
+    EmailHash,
+    /// Randomly assigned on verification and persisted, unrelated to the email.
+    Random,
+}
+
+impl Account {
+    /// Whether users can register themselves now.
+    pub fn registration_open(&self) -> bool {
+        #[cfg(test)]
+        if let Some(window) = &*REGISTRATION_WINDOW.lock() {
+            return window.is_open_at(crate::clock::now());
+        }
+
+        self.registration.is_open_at(crate::clock::now())
+    }
+
+    /// Get the channels verification codes are delivered through.
+    pub fn code_delivery(&self) -> CodeDelivery {
+        #[cfg(test)]
+        if let Some(delivery) = *CODE_DELIVERY.lock() {
+            return delivery;
+        }
+
+        self.code_delivery
+    }
+
+    /// Get the configured houses, or the built-in ones named by their ids.
+    pub fn houses(&self) -> Cow<'_, [HouseInfo]> {
+        if self.houses.is_empty() {
+            Cow::Owned(
+                House::BUILTIN
+                    .iter()
+                    .map(|id| HouseInfo {
+                        id: id.to_string(),
+                        name: id.to_string(),
+                    })
+                    .collect(),
+            )
+        } else {
+            Cow::Borrowed(&self.houses)
+        }
+    }
+
+    /// Get the effective token expiration days of an account with given permissions,
+    /// overriding `requested` as configured and clamping it for accounts holding
+    /// sensitive permissions.
+    pub fn token_expiration(&self, permissions: &[Permission], requested: u16) -> u16 {
+        let requested = self
+            .token_expiration_override(permissions)
+            .unwrap_or(requested);
+        match self.max_token_expiration(permissions) {
+            Some(max) if requested == 0 || requested > max => max,
+            _ => requested,
+        }
+    }
+
+    /// Get the least token expiration override of given permissions,
+    /// `None` if none of them is overridden.
+    pub fn token_expiration_override(&self, permissions: &[Permission]) -> Option<u16> {
+        permissions
+            .iter()
+            .filter_map(|p| self.token_expiration_overrides.get(p).copied())
+            .min_by_key(|&days| if days == 0 { u16::MAX } else { days })
+    }
+
+    /// Get the max token expiration days of an account with given permissions,
+    /// `None` if unlimited.
+    pub fn max_token_expiration(&self, permissions: &[Permission]) -> Option<u16> {
+        (self.sensitive_token_expiration != 0
+            && permissions
+                .iter()
+                .any(|p| self.sensitive_permissions.contains(p)))
+        .then_some(self.sensitive_token_expiration)
+    }
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Self {
+            password_history: 5,
+            password_change_interval: 0,
+            organizations: Vec::new(),
+            houses: Vec::new(),
+            required_fields: Vec::new(),
+            code_delivery: CodeDelivery::default(),
+            sliding_token_expiration: false,
+            token_max_lifetime: 30,
+            sensitive_permissions: vec![Permission::ManageAccounts, Permission::Op],
+            sensitive_token_expiration: 7,
+            token_expiration_overrides: BTreeMap::new(),
+            token_bytes: 32,
+            max_sessions: 16,
+            verification_mail_limit: 5,
+            verification_mail_window: 60,
+            availability_check_limit: 20,
+            availability_check_window: 1,
+            rate_limit_capacity: 4096,
+            lockout_threshold: 5,
+            lockout_minutes: 15,
+            lockout_unlock_mail: false,
+            unlock_token_minutes: 15,
+            deletion_grace_days: 7,
+            max_expiry_days: 400,
+            expected_accounts: 0,
+            registration: RegistrationWindow::default(),
+            id_strategy: IdStrategy::default(),
+            password_hash: PasswordHash::default(),
+        }
+    }
 }
 
 /// Describing mailing configuration.
+#[cfg_attr(test, allow(dead_code))]
 #[derive(Deserialize, Clone)]
 pub struct MailSmtp {
     pub server: String,
     pub port: u16,
     pub username: String,
     pub password: String,
+    /// Address mails are sent from, which should be aligned with the
+    /// SPF and DKIM records of its domain.
     pub address: lettre::Address,
+    /// Display name mails are sent from, none if empty.
+    #[serde(default = "MailSmtp::default_name")]
+    pub name: String,
+    /// Address replies to mails are sent to, `address` if not set.
+    #[serde(default)]
+    pub reply_to: Option<lettre::Address>,
+}
+
+impl MailSmtp {
+    fn default_name() -> String {
+        "SubIT".to_string()
+    }
+
+    /// Check this configuration, failing with a description of the first problem.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.chars().any(char::is_control) {
+            return Err(format!("name {:?} contains control characters", self.name));
+        }
+        Ok(())
+    }
 }
 
 impl Default for MailSmtp {
@@ -47,6 +560,8 @@ impl Default for MailSmtp {
             username: String::default(),
             password: String::default(),
             address: lettre::Address::new("user", "email.com").unwrap(),
+            name: Self::default_name(),
+            reply_to: None,
         }
     }
 }
@@ -0,0 +1,460 @@
+pub mod account;
+pub mod clock;
+pub mod config;
+pub mod maintenance;
+pub mod openapi;
+pub mod post;
+
+/// The module for unit testing, will only be availabled in dev env.
+#[cfg(test)]
+mod tests;
+
+use axum::{
+    async_trait,
+    extract::DefaultBodyLimit,
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post, MethodRouter},
+};
+use serde::Serialize;
+use sms3_shared::account::Permission;
+
+/// Construct a router.
+///
+/// Request bodies are limited as configured, with higher limits
+/// on bulk endpoints and image uploads.
+pub fn router() -> axum::Router {
+    router_with(&config::INSTANCE.cors)
+}
+
+/// Construct a router like [`router`], answering cross-origin requests
+/// with the given policy.
+pub fn router_with(cors: &config::Cors) -> axum::Router {
+    let limits = &config::INSTANCE.limits;
+
+    axum::Router::new()
+        // account
+        .route(
+            "/api/account/create",
+            mutating(post(account::handle::create_account)),
+        )
+        .route(
+            "/api/account/available",
+            get(account::handle::email_availability),
+        )
+        .route(
+            "/api/account/verify-status",
+            get(account::handle::verification_status),
+        )
+        .route(
+            "/api/account/verify",
+            mutating(post(account::handle::verify_account)),
+        )
+        .route("/api/account/login", post(account::handle::login_account))
+        .route("/api/account/oidc/login", get(account::handle::oidc_login))
+        .route(
+            "/api/account/oidc/callback",
+            mutating(post(account::handle::oidc_callback)),
+        )
+        .route("/api/account/logout", post(account::handle::logout_account))
+        .route("/api/account/unlock", post(account::handle::unlock_account))
+        .route("/api/account/houses", get(account::handle::list_houses))
+        .route(
+            "/api/account/token-expiry",
+            post(account::handle::token_expiry),
+        )
+        .route(
+            "/api/account/signout",
+            mutating(post(account::handle::sign_out_account)),
+        )
+        .route(
+            "/api/account/api-key/create",
+            mutating(post(account::handle::create_api_key)),
+        )
+        .route(
+            "/api/account/api-key/list",
+            post(account::handle::list_api_keys),
+        )
+        .route(
+            "/api/account/api-key/revoke",
+            mutating(post(account::handle::revoke_api_key)),
+        )
+        .route("/api/account/view", post(account::handle::view_account))
+        .route("/api/account/export", post(account::handle::export_account))
+        .route(
+            "/api/account/edit",
+            mutating(post(account::handle::edit_account)),
+        )
+        .route(
+            "/api/account/change-password",
+            mutating(post(account::handle::change_password)),
+        )
+        .route(
+            "/api/account/reset-password",
+            mutating(post(account::handle::reset_password)),
+        )
+        // account management
+        .route(
+            "/api/account/manage/create",
+            mutating(post(account::handle::manage::make_account)),
+        )
+        .route(
+            "/api/account/manage/view",
+            post(account::handle::manage::view_account),
+        )
+        .route(
+            "/api/account/manage/modify",
+            mutating(post(account::handle::manage::modify_account)),
+        )
+        .route(
+            "/api/account/manage/search",
+            post(account::handle::manage::search_account),
+        )
+        .route(
+            "/api/account/manage/by-house",
+            get(account::handle::manage::accounts_by_house),
+        )
+        .route(
+            "/api/account/manage/by-organization",
+            get(account::handle::manage::accounts_by_organization),
+        )
+        .route(
+            "/api/account/manage/statistics",
+            get(account::handle::manage::statistics),
+        )
+        .route(
+            "/api/account/manage/export",
+            get(account::handle::manage::export_accounts),
+        )
+        .route(
+            "/api/account/manage/force-password-change",
+            mutating(post(account::handle::manage::force_password_change)),
+        )
+        .route(
+            "/api/account/manage/restore",
+            mutating(post(account::handle::manage::restore_account)),
+        )
+        .route(
+            "/api/account/manage/resend-verification",
+            mutating(post(account::handle::manage::resend_verification)),
+        )
+        .route(
+            "/api/account/manage/note/add",
+            mutating(post(account::handle::manage::add_note)),
+        )
+        .route(
+            "/api/account/manage/note/list",
+            post(account::handle::manage::list_notes),
+        )
+        .route(
+            "/api/account/manage/house-history",
+            post(account::handle::manage::house_history),
+        )
+        .route(
+            "/api/account/manage/introspect",
+            post(account::handle::manage::introspect_token),
+        )
+        .route(
+            "/api/account/manage/maintenance",
+            post(account::handle::manage::set_maintenance),
+        )
+        .route(
+            "/api/account/manage/remove",
+            mutating(
+                post(account::handle::manage::remove_accounts)
+                    .layer(DefaultBodyLimit::max(limits.bulk_body)),
+            ),
+        )
+        .route(
+            "/api/account/manage/grant",
+            mutating(
+                post(account::handle::manage::grant_permission)
+                    .layer(DefaultBodyLimit::max(limits.bulk_body)),
+            ),
+        )
+        // posting
+        .route(
+            "/api/post/upload-image",
+            mutating(
+                post(post::handle::cache_image).layer(DefaultBodyLimit::max(limits.image_body)),
+            ),
+        )
+        .route("/api/post/get-image", post(post::handle::get_image))
+        .route("/api/post/create", mutating(post(post::handle::new_post)))
+        .route("/api/post/get", post(post::handle::get_posts))
+        .route("/api/post/edit", mutating(post(post::handle::edit_post)))
+        .route("/api/post/get-info", post(post::handle::get_posts_info))
+        .route(
+            "/api/post/approve",
+            mutating(post(post::handle::approve_post)),
+        )
+        // documentation
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/swagger-ui", get(openapi::swagger_ui))
+        .layer(DefaultBodyLimit::max(limits.body))
+        .layer(cors_layer(cors))
+}
+
+/// Reject requests to target route with `503 Service Unavailable` in maintenance,
+/// for routes mutating accounts or posts.
+fn mutating(route: MethodRouter) -> MethodRouter {
+    route.route_layer(axum::middleware::from_fn(maintenance::reject))
+}
+
+/// Construct the layer answering preflight requests and setting CORS headers.
+///
+/// The config must have passed [`config::Cors::validate`].
+fn cors_layer(cors: &config::Cors) -> tower_http::cors::CorsLayer {
+    use axum::http::{header, HeaderName, HeaderValue, Method};
+    use tower_http::cors::AllowOrigin;
+
+    let origins = if cors.allows_any_origin() {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(cors.allowed_origins.iter().map(|origin| {
+            HeaderValue::from_str(origin).expect("origins are validated with the config")
+        }))
+    };
+    tower_http::cors::CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(
+            cors.allowed_methods
+                .iter()
+                .map(|method| {
+                    Method::from_bytes(method.as_bytes())
+                        .expect("methods are validated with the config")
+                })
+                .collect::<Vec<_>>(),
+        )
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            HeaderName::from_static("token"),
+            HeaderName::from_static("accountid"),
+        ])
+        .allow_credentials(cors.allow_credentials)
+}
+
+/// A context for checking the validation of action an account
+/// performs with permission requirements.
+pub struct RequirePermissionContext {
+    /// The access token of this account.
+    pub token: String,
+    /// The only id of this account.
+    pub account_id: u64,
+}
+
+impl RequirePermissionContext {
+    /// Check this context's token and permissions.
+    ///
+    /// The token may also be an API key, whose scope narrows the permissions.
+    /// An unusable token results in [`account::Error::TokenIncorrect`], and
+    /// missing permissions in [`account::Error::PermissionDenied`]. Nothing is
+    /// valid while the user must change the password or is suspended.
+    pub fn valid(&self, permissions: &[Permission]) -> Result<(), account::ManagerError> {
+        self.valid_in(permissions, None)
+    }
+
+    /// Check this context's token and permissions on accounts of target organization,
+    /// where permissions scoped to the organization also count.
+    pub fn valid_in(
+        &self,
+        permissions: &[Permission],
+        organization: Option<&str>,
+    ) -> Result<(), account::ManagerError> {
+        // Session tokens are checked through the token index, which also knows
+        // their expiry, so no account is locked unless permissions are required.
+        let session = match account::INSTANCE.check_token(self.account_id, &self.token) {
+            Err(account::Error::TokenIncorrect) => false,
+            Ok(()) if permissions.is_empty() => return Ok(()),
+            Ok(()) => true,
+            Err(err) => return Err(account::ManagerError::Account(self.account_id, err)),
+        };
+
+        account::INSTANCE
+            .with_account(self.account_id, |account| {
+                let err = |err| Err(account::ManagerError::Account(self.account_id, err));
+
+                if let account::Account::Verified {
+                    attributes, tokens, ..
+                } = account
+                {
+                    // API keys are limited to their own scope, session tokens aren't.
+                    let scope = if session {
+                        None
+                    } else if let Some(scope) = tokens.api_key_scope(&self.token) {
+                        Some(scope)
+                    } else {
+                        return err(account::Error::TokenIncorrect);
+                    };
+
+                    // Checked after the token, so only the user learns them.
+                    if attributes.must_change_password {
+                        return err(account::Error::PasswordChangeRequired);
+                    }
+                    if attributes.suspended {
+                        return err(account::Error::AccountSuspended);
+                    }
+
+                    // The token was checked first, so only authenticated
+                    // callers learn which permission they lack.
+                    match permissions.iter().find(|p| {
+                        !account.has_permission_in(**p, organization)
+                            || scope.is_some_and(|s| !s.contains(p))
+                    }) {
+                        Some(p) => err(account::Error::PermissionDenied(*p)),
+                        None => Ok(()),
+                    }
+                } else {
+                    err(account::Error::UserUnverified)
+                }
+            })
+            .unwrap_or(Err(account::ManagerError::NotFound(self.account_id)))
+    }
+
+    /// Indicates whether this context's token and permissions is valid.
+    pub fn try_valid(&self, permissions: &[Permission]) -> Result<bool, account::ManagerError> {
+        self.try_valid_in(permissions, None)
+    }
+
+    /// Indicates whether this context's token and permissions is valid on
+    /// accounts of target organization, see [`Self::valid_in`].
+    pub fn try_valid_in(
+        &self,
+        permissions: &[Permission],
+        organization: Option<&str>,
+    ) -> Result<bool, account::ManagerError> {
+        match self.valid_in(permissions, organization) {
+            Ok(()) => Ok(true),
+            Err(account::ManagerError::Account(
+                _,
+                account::Error::TokenIncorrect | account::Error::PermissionDenied(_),
+            )) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> axum::extract::FromRequestParts<S> for RequirePermissionContext {
+    type Rejection = (StatusCode, axum::Json<ErrorResponse>);
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let this = Self {
+            token: if let Some(value) = parts.headers.get("Token") {
+                value.to_str().unwrap_or_default().to_string()
+            } else {
+                return Err((
+                    StatusCode::UNAUTHORIZED,
+                    axum::Json(ErrorResponse {
+                        error: "no valid token field found in headers".to_string(),
+                        permission: None,
+                    }),
+                ));
+            },
+
+            account_id: if let Some(value) = parts.headers.get("AccountId") {
+                value
+                    .to_str()
+                    .unwrap_or_default()
+                    .to_string()
+                    .parse()
+                    .unwrap_or_default()
+            } else {
+                return Err((
+                    StatusCode::UNAUTHORIZED,
+                    axum::Json(ErrorResponse {
+                        error: "no valid account id field found in headers".to_string(),
+                        permission: None,
+                    }),
+                ));
+            },
+        };
+
+        // Unknown accounts are rejected like wrong tokens, not telling which
+        // accounts exist, while a blocked but valid token is forbidden.
+        if let Err(err) = this.valid(&[]) {
+            return Err((
+                match err {
+                    account::ManagerError::NotFound(_) => StatusCode::UNAUTHORIZED,
+                    ref err => err.response_code(),
+                },
+                axum::Json(ErrorResponse {
+                    error: err.to_string(),
+                    permission: None,
+                }),
+            ));
+        }
+
+        // API keys aren't touched, as their use isn't tracked.
+        account::INSTANCE.touch_token(this.account_id, &this.token);
+
+        Ok(this)
+    }
+}
+
+trait AsResCode: std::error::Error {
+    fn response_code(&self) -> StatusCode;
+
+    /// The permission whose absence caused this error, if any.
+    fn missing_permission(&self) -> Option<Permission> {
+        None
+    }
+}
+
+/// The body of all error responses.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ErrorResponse {
+    /// Description of the error.
+    pub error: String,
+    /// The permission the request was denied for lacking, only told to
+    /// authenticated callers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permission: Option<Permission>,
+}
+
+struct ResError<T>(pub T)
+where
+    T: AsResCode;
+
+impl<T> IntoResponse for ResError<T>
+where
+    T: AsResCode,
+{
+    fn into_response(self) -> axum::response::Response {
+        (
+            self.0.response_code(),
+            axum::Json(ErrorResponse {
+                error: self.0.to_string(),
+                permission: self.0.missing_permission(),
+            }),
+        )
+            .into_response()
+    }
+}
+
+// Account errors convert into `axum::response::ErrorResponse` directly,
+// so account handlers can use `?` without wrapping them in `ResError`.
+impl IntoResponse for account::Error {
+    fn into_response(self) -> axum::response::Response {
+        ResError(self).into_response()
+    }
+}
+
+impl IntoResponse for account::ManagerError {
+    fn into_response(self) -> axum::response::Response {
+        ResError(self).into_response()
+    }
+}
+
+impl AsResCode for std::io::Error {
+    fn response_code(&self) -> StatusCode {
+        match self.kind() {
+            std::io::ErrorKind::NotFound => hyper::StatusCode::NOT_FOUND,
+            _ => hyper::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
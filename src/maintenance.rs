@@ -0,0 +1,52 @@
+//! A read-only mode freezing mutations, ex. during backups or migrations.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Seconds clients are told to retry after, `0` when not in maintenance.
+static RETRY_AFTER: AtomicU64 = AtomicU64::new(0);
+
+/// Seconds clients are told to retry after if not specified.
+pub const DEFAULT_RETRY_AFTER: u64 = 300;
+
+/// Enter maintenance mode, telling rejected clients to retry after
+/// `retry_after` seconds, at least one.
+pub fn enable(retry_after: u64) {
+    RETRY_AFTER.store(retry_after.max(1), Ordering::Relaxed);
+}
+
+/// Leave maintenance mode, accepting mutations again.
+pub fn disable() {
+    RETRY_AFTER.store(0, Ordering::Relaxed);
+}
+
+/// Seconds rejected clients are told to retry after,
+/// `None` if not in maintenance.
+pub fn retry_after() -> Option<u64> {
+    match RETRY_AFTER.load(Ordering::Relaxed) {
+        0 => None,
+        seconds => Some(seconds),
+    }
+}
+
+/// Middleware rejecting requests with `503 Service Unavailable` and a
+/// `Retry-After` header while in maintenance.
+pub async fn reject<B>(request: Request<B>, next: Next<B>) -> Response {
+    match retry_after() {
+        Some(seconds) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, seconds.to_string())],
+            axum::Json(crate::ErrorResponse {
+                error: "the server is in maintenance, try again later".to_string(),
+                permission: None,
+            }),
+        )
+            .into_response(),
+        None => next.run(request).await,
+    }
+}
@@ -0,0 +1,116 @@
+use axum::{response::Html, Json};
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::account::handle as account;
+use sms3_shared::account::{handle::*, House, HouseInfo, Permission, UserMetadata};
+
+/// The OpenAPI specification of the account endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        account::create_account,
+        account::email_availability,
+        account::verification_status,
+        account::verify_account,
+        account::login_account,
+        account::oidc_login,
+        account::oidc_callback,
+        account::logout_account,
+        account::unlock_account,
+        account::list_houses,
+        account::token_expiry,
+        account::create_api_key,
+        account::list_api_keys,
+        account::revoke_api_key,
+        account::sign_out_account,
+        account::view_account,
+        account::export_account,
+        account::edit_account,
+        account::change_password,
+        account::reset_password,
+    ),
+    components(schemas(
+        AccountCreateDescriptor,
+        AccountCreateResult,
+        EmailAvailabilityResult,
+        VerificationStatusResult,
+        AccountVerifyDescriptor,
+        AccountVerifyVariant,
+        ValidationErrors,
+        FieldError,
+        AccountLoginDescriptor,
+        AccountLoginResult,
+        OidcCallbackDescriptor,
+        AccountUnlockDescriptor,
+        TokenExpiryResult,
+        ApiKeyCreateDescriptor,
+        ApiKeyCreateResult,
+        ApiKeyInfo,
+        ApiKeyRevokeDescriptor,
+        AccountSignOutDescriptor,
+        ViewAccountResult,
+        PendingEmail,
+        SessionSummary,
+        AccountEditDescriptor,
+        AccountEditVariant,
+        ChangePasswordDescriptor,
+        ResetPasswordDescriptor,
+        UserMetadata,
+        House,
+        HouseInfo,
+        Permission,
+        crate::ErrorResponse,
+    )),
+    modifiers(&Authentication)
+)]
+pub struct ApiDoc;
+
+/// Add header authentication required by `RequirePermissionContext` and
+/// bearer authentication used by logout.
+struct Authentication;
+
+impl Modify for Authentication {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("Token"))),
+        );
+        components.add_security_scheme(
+            "account_id",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("AccountId"))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Serve the generated OpenAPI specification.
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Serve a Swagger UI page rendering the specification.
+pub async fn swagger_ui() -> Html<&'static str> {
+    Html(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>sms3 API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>"##,
+    )
+}
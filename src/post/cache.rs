@@ -83,7 +83,7 @@ impl PostImageCache {
             tokio::spawn(async move {
                 if let Some(img) = &this.img.read().as_ref() {
                     img.save_with_format(
-                        format!("./data/images/{}.png", this.hash),
+                        crate::config::data_dir().join(format!("images/{}.png", this.hash)),
                         image::ImageFormat::Png,
                     )
                     .unwrap();
@@ -92,8 +92,10 @@ impl PostImageCache {
 
                 use tokio::io::AsyncWriteExt;
 
-                if let Ok(mut file) =
-                    tokio::fs::File::create(format!("./data/images/{}.toml", this.hash)).await
+                if let Ok(mut file) = tokio::fs::File::create(
+                    crate::config::data_dir().join(format!("images/{}.toml", this.hash)),
+                )
+                .await
                 {
                     file.write_all(toml::to_string(&this).unwrap().as_bytes())
                         .await
@@ -123,7 +125,10 @@ impl CacheManager {
             use std::io::Read;
 
             let mut vec = Vec::new();
-            for dir in std::fs::read_dir("./data/images").unwrap().flatten() {
+            for dir in std::fs::read_dir(crate::config::data_dir().join("images"))
+                .unwrap()
+                .flatten()
+            {
                 if let Ok(cache) = {
                     toml::from_str::<PostImageCache>(&{
                         let mut string = String::new();
@@ -171,7 +176,9 @@ impl CacheManager {
             let mut i = 0;
             for e in cr.iter().enumerate() {
                 if !e.1.blocked.load(Ordering::Acquire) {
-                    let _ = std::fs::remove_file(format!("./data/images/{}.png", e.1.hash));
+                    let _ = std::fs::remove_file(
+                        crate::config::data_dir().join(format!("images/{}.png", e.1.hash)),
+                    );
                     i = e.0;
                     break;
                 }
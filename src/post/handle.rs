@@ -46,14 +46,16 @@ pub async fn get_image(
         .find(|e| e.hash == descriptor.hash)
     {
         #[cfg(not(test))]
-        return std::fs::File::open(format!("./data/images/{}.png", _img.hash))
-            .map(|mut file| {
-                let mut vec = Vec::new();
-                let _ = std::io::Read::read_to_end(&mut file, &mut vec);
+        return std::fs::File::open(
+            crate::config::data_dir().join(format!("images/{}.png", _img.hash)),
+        )
+        .map(|mut file| {
+            let mut vec = Vec::new();
+            let _ = std::io::Read::read_to_end(&mut file, &mut vec);
 
-                vec
-            })
-            .map_err(|err| ResError(err).into());
+            vec
+        })
+        .map_err(|err| ResError(err).into());
 
         #[cfg(test)]
         unreachable!("test not covered");
@@ -174,7 +176,7 @@ fn matches_get_post_filter(filter: &GetPostsFilter, post: &Post, user: &Account)
         GetPostsFilter::Acceptation(status) => post
             .status
             .last()
-            .map_or(false, |s| status.matches(&s.status)),
+            .is_some_and(|s| status.matches(&s.status)),
 
         GetPostsFilter::Account(account) => &post.publisher == account,
         GetPostsFilter::Before(d) => &post.metadata.time_range.0 <= d,
@@ -211,7 +213,7 @@ pub async fn edit_post(
                 .map(|e| matches!(e.status, PostAcceptationStatus::Submitted(_)))
                 .unwrap_or_default()
         {
-            return Err(ResError(crate::account::Error::PermissionDenied).into());
+            return Err(ResError(crate::account::Error::NotAllowed).into());
         }
 
         drop(pr);
@@ -289,7 +291,7 @@ fn apply_edit_post_variant(
         EditPostVariant::TimeRange(start, end) => {
             if start
                 .checked_add_days(Days::new(7))
-                .map_or(false, |e| &e < end)
+                .is_some_and(|e| &e < end)
             {
                 return Err(super::Error::DateOutOfRange);
             }
@@ -300,7 +302,7 @@ fn apply_edit_post_variant(
             if post
                 .status
                 .last()
-                .map_or(true, |e| matches!(e.status, PostAcceptationStatus::Pending))
+                .is_none_or(|e| matches!(e.status, PostAcceptationStatus::Pending))
             {
                 return Err(super::Error::Already(PostAcceptationStatus::Pending));
             }
@@ -42,8 +42,10 @@ pub fn save_post(_post: &Post) {
         tokio::spawn(async move {
             use tokio::io::AsyncWriteExt;
 
-            if let Ok(mut file) =
-                tokio::fs::File::create(format!("./data/posts/{}.toml", this.id)).await
+            if let Ok(mut file) = tokio::fs::File::create(
+                crate::config::data_dir().join(format!("posts/{}.toml", this.id)),
+            )
+            .await
             {
                 file.write_all(toml::to_string(&this).unwrap().as_bytes())
                     .await
@@ -59,7 +61,7 @@ pub fn remove_post(_post: &Post) {
         let id = _post.id;
 
         tokio::spawn(async move {
-            tokio::fs::remove_file(format!("./data/posts/{}.toml", id))
+            tokio::fs::remove_file(crate::config::data_dir().join(format!("posts/{}.toml", id)))
                 .await
                 .unwrap()
         });
@@ -79,7 +81,10 @@ impl PostManager {
 
             let mut vec = Vec::new();
 
-            for dir in fs::read_dir("./data/posts").unwrap().flatten() {
+            for dir in fs::read_dir(crate::config::data_dir().join("posts"))
+                .unwrap()
+                .flatten()
+            {
                 if let Ok(cache) = {
                     toml::from_str::<Post>(&{
                         let mut string = String::new();
@@ -19,7 +19,7 @@ async fn registry() {
         use sms3_shared::account::handle::AccountCreateDescriptor;
 
         let descriptor = AccountCreateDescriptor {
-            email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
+            email: "yujiening2025@i.pkuschool.edu.cn".to_string(),
         };
 
         assert_eq!(
@@ -54,7 +54,7 @@ async fn registry() {
                 name: "Jiening Yu".to_string(),
                 id: 2522320,
                 phone: 16601550826,
-                house: Some(sms3_shared::account::House::ZhiZhi),
+                house: Some(sms3_shared::account::House::from("ZhiZhi")),
                 organization: None,
                 password: "password123456".to_string(),
             },
@@ -77,6 +77,51 @@ async fn registry() {
         );
     }
 
+    // Every invalid field is reported at once
+    {
+        use sms3_shared::account::handle::{AccountVerifyDescriptor, ValidationErrors};
+
+        let descriptor = AccountVerifyDescriptor {
+            code: crate::account::verify::VERIFICATION_CODE
+                .load(std::sync::atomic::Ordering::Relaxed),
+            variant: sms3_shared::account::handle::AccountVerifyVariant::Activate {
+                email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
+                name: "Jiening Yu".to_string(),
+                id: 2522320,
+                phone: 114514,
+                house: Some(sms3_shared::account::House::from("ZhiZhi")),
+                organization: Some("Sub\u{7}IT".to_string()),
+                password: "pass".to_string(),
+            },
+        };
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/account/verify")
+                    .method("POST")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(serde_json::to_vec(&descriptor).unwrap().into())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let errors: ValidationErrors =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(
+            errors
+                .fields
+                .iter()
+                .map(|e| e.field.as_str())
+                .collect::<Vec<_>>(),
+            ["phone", "organization", "password"]
+        );
+    }
+
     {
         use sms3_shared::account::handle::AccountVerifyDescriptor;
 
@@ -90,7 +135,7 @@ async fn registry() {
                 name: "Jiening Yu".to_string(),
                 id: 2522320,
                 phone: 16601550826,
-                house: Some(sms3_shared::account::House::ZhiZhi),
+                house: Some(sms3_shared::account::House::from("ZhiZhi")),
                 organization: None,
                 password: "password123456".to_string(),
             },
@@ -131,13 +176,23 @@ async fn login() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Jiening Yu".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: crate::account::verify::Tokens::new(),
         verify: crate::account::UserVerifyVariant::None,
@@ -182,7 +237,7 @@ async fn login() {
     let token = response_json
         .as_object()
         .unwrap()
-        .get("token")
+        .get("access_token")
         .unwrap()
         .as_str()
         .unwrap()
@@ -196,6 +251,86 @@ async fn login() {
     assert!(cxt.try_valid(&[]).unwrap());
 }
 
+/// Test: signing in from a new device mails the user unless muted.
+#[serial]
+#[tokio::test]
+async fn login_notification() {
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let account_id = 123456;
+    let password = "password123456";
+
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(
+        account_id,
+        "yujiening2025",
+        &[],
+    ));
+
+    use sms3_shared::account::handle::AccountLoginDescriptor;
+
+    let login = |ip: &'static str| {
+        let descriptor = AccountLoginDescriptor {
+            email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
+            password: password.to_string(),
+        };
+
+        async move {
+            let response = crate::router()
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/account/login")
+                        .method("POST")
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .header(http::header::USER_AGENT, "Mozilla/5.0")
+                        .extension(axum::extract::ConnectInfo(std::net::SocketAddr::new(
+                            ip.parse().unwrap(),
+                            0,
+                        )))
+                        .body(serde_json::to_vec(&descriptor).unwrap().into())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    };
+
+    // The first device ever seen is not new.
+    login("10.0.0.1").await;
+    assert!(crate::account::verify::SENT_MAILS.lock().is_empty());
+
+    login("10.0.0.2").await;
+    {
+        let mails = crate::account::verify::SENT_MAILS.lock();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].to.user(), "yujiening2025");
+        assert_eq!(mails[0].subject, "New sign-in detected");
+        assert!(mails[0].body.contains("10.0.0.2"));
+        assert!(mails[0].body.contains("Mozilla/5.0"));
+        assert!(mails[0]
+            .body
+            .contains(&crate::clock::now().format("%Y-%m-%d %H:%M:%S").to_string()));
+    }
+
+    login("10.0.0.2").await;
+    login("10.0.0.1").await;
+    assert_eq!(crate::account::verify::SENT_MAILS.lock().len(), 1);
+
+    crate::account::INSTANCE
+        .with_account_mut(account_id, |account| {
+            crate::account::handle::apply_edit_variant(
+                sms3_shared::account::handle::AccountEditVariant::MuteLoginNotification(true),
+                account,
+            )
+        })
+        .unwrap()
+        .unwrap();
+
+    login("10.0.0.3").await;
+    assert_eq!(crate::account::verify::SENT_MAILS.lock().len(), 1);
+}
+
 /// Test for usage of `RequirePermissionContext`.
 #[serial]
 #[test]
@@ -214,17 +349,27 @@ fn require_permission_context() {
                 email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
                 name: "Yu Jiening".to_string(),
                 school_id: 2522320,
-                house: Some(sms3_shared::account::House::ZhiZhi),
+                house: Some(sms3_shared::account::House::from("ZhiZhi")),
                 phone: 16601550826,
                 organization: None,
                 permissions: vec![],
                 registration_time: chrono::Utc::now(),
                 password_sha: digest(password.to_string()),
                 token_expiration_time: 0,
+                mute_login_notification: false,
+                password_history: vec![],
+                admin_notes: vec![],
+                house_history: vec![],
+                scoped_permissions: vec![],
+                password_changed_at: None,
+                alternate_email: None,
+                must_change_password: false,
+                pending_deletion: None,
+                suspended: false,
             },
             tokens: {
                 let mut t = crate::account::verify::Tokens::new();
-                token = t.new_token(account_id, 0);
+                token = t.new_token(0).unwrap();
                 t
             },
             verify: crate::account::UserVerifyVariant::None,
@@ -253,7 +398,7 @@ fn require_permission_context() {
         crate::account::INSTANCE.push(crate::account::Account::Unverified(
             crate::account::verify::Context {
                 email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
-                expire_time: (chrono::Utc::now() + chrono::Days::new(1)).naive_utc(),
+                expire_time: chrono::Utc::now() + chrono::Days::new(1),
                 code: 6,
             },
         ));
@@ -286,36 +431,46 @@ async fn logout() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Yu Jiening".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: {
             let mut t = crate::account::verify::Tokens::new();
-            token = t.new_token(account_id, 0);
+            token = t.new_token(0).unwrap();
             t
         },
         verify: crate::account::UserVerifyVariant::None,
     });
 
-    assert_eq!(
-        app.oneshot(
+    let logout = |authorization: String| {
+        app.clone().oneshot(
             Request::builder()
                 .uri("/api/account/logout")
                 .method("POST")
-                .header("Token", &token)
-                .header("AccountId", account_id)
+                .header(http::header::AUTHORIZATION, authorization)
                 .body(hyper::Body::empty())
-                .unwrap()
+                .unwrap(),
         )
-        .await
-        .unwrap()
-        .status(),
-        StatusCode::OK
+    };
+
+    assert_eq!(
+        logout(format!("Bearer {token}")).await.unwrap().status(),
+        StatusCode::NO_CONTENT
     );
 
     let ctx = crate::RequirePermissionContext {
@@ -324,6 +479,23 @@ async fn logout() {
     };
 
     assert!(!ctx.try_valid(&[]).unwrap());
+
+    // The removed token can't be used again.
+    assert_eq!(
+        logout(format!("Bearer {token}")).await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        logout("Bearer sms3_invalid".to_string())
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        logout(token.clone()).await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
 }
 
 #[serial]
@@ -344,17 +516,27 @@ async fn signout() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Jiening Yu".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: {
             let mut t = crate::account::verify::Tokens::new();
-            token = t.new_token(account_id, 0);
+            token = t.new_token(0).unwrap();
             t
         },
         verify: crate::account::UserVerifyVariant::None,
@@ -410,6 +592,37 @@ async fn signout() {
         );
     }
 
+    // The account is kept through the grace period, but can't be used.
+    crate::clock::set(chrono::Utc::now());
+    assert_eq!(crate::account::INSTANCE.find_by_token(&token), None);
+    {
+        use sms3_shared::account::handle::AccountLoginDescriptor;
+
+        let descriptor = AccountLoginDescriptor {
+            email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
+            password: password.to_string(),
+        };
+        assert_eq!(
+            app.oneshot(
+                Request::builder()
+                    .uri("/api/account/login")
+                    .method("POST")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(serde_json::to_vec(&descriptor).unwrap().into())
+                    .unwrap()
+            )
+            .await
+            .unwrap()
+            .status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    crate::clock::advance(chrono::Duration::days(6));
+    assert!(crate::account::INSTANCE.refresh_all().is_empty());
+    crate::clock::advance(chrono::Duration::days(1));
+    assert_eq!(crate::account::INSTANCE.refresh_all(), vec![account_id]);
+
     assert!(crate::account::INSTANCE.inner().read().is_empty());
     assert!(crate::account::INSTANCE.index().is_empty());
 }
@@ -432,17 +645,27 @@ async fn view() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Jiening Yu".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: {
             let mut t = crate::account::verify::Tokens::new();
-            token = t.new_token(account_id, 0);
+            token = t.new_token(0).unwrap();
             t
         },
         verify: crate::account::UserVerifyVariant::None,
@@ -491,17 +714,27 @@ async fn edit() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Jiening Yu".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: {
             let mut t = crate::account::verify::Tokens::new();
-            token = t.new_token(account_id, 0);
+            token = t.new_token(0).unwrap();
             t
         },
         verify: crate::account::UserVerifyVariant::None,
@@ -536,12 +769,72 @@ async fn edit() {
         );
     }
 
+    {
+        let descriptor_unknown_house = AccountEditDescriptor {
+            variants: vec![AccountEditVariant::House(Some(
+                sms3_shared::account::House::from("Hogwarts"),
+            ))],
+        };
+
+        assert_eq!(
+            app.clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/account/edit")
+                        .method("POST")
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .header("Token", &token)
+                        .header("AccountId", account_id)
+                        .body(
+                            serde_json::to_vec(&descriptor_unknown_house)
+                                .unwrap()
+                                .into()
+                        )
+                        .unwrap()
+                )
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    // Invalid phones and weak passwords are rejected like on registration.
+    for variant in [
+        AccountEditVariant::Phone(114514),
+        AccountEditVariant::Password {
+            old: password.to_string(),
+            new: "short".to_string(),
+        },
+    ] {
+        let descriptor = AccountEditDescriptor {
+            variants: vec![variant],
+        };
+        assert_eq!(
+            app.clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/account/edit")
+                        .method("POST")
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .header("Token", &token)
+                        .header("AccountId", account_id)
+                        .body(serde_json::to_vec(&descriptor).unwrap().into())
+                        .unwrap()
+                )
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
     let descriptor = AccountEditDescriptor {
         variants: vec![
             AccountEditVariant::Name("Tianyang He".to_string()),
             AccountEditVariant::SchoolId(2100000),
-            AccountEditVariant::Phone(114514),
-            AccountEditVariant::House(Some(sms3_shared::account::House::ZhengXin)),
+            AccountEditVariant::Phone(13800138000),
+            AccountEditVariant::House(Some(sms3_shared::account::House::from("ZhengXin"))),
             AccountEditVariant::Organization(Some("SubIT".to_string())),
             AccountEditVariant::Password {
                 old: password.to_string(),
@@ -597,13 +890,16 @@ async fn edit() {
 
             assert_eq!(&attributes.name, "Tianyang He");
             assert_eq!(attributes.school_id, 2100000);
-            assert_eq!(attributes.phone, 114514);
+            assert_eq!(attributes.phone, 13800138000);
             assert_eq!(
                 attributes.house,
-                Some(sms3_shared::account::House::ZhengXin)
+                Some(sms3_shared::account::House::from("ZhengXin"))
             );
             assert_eq!(attributes.organization, Some("SubIT".to_string()));
-            assert_eq!(attributes.password_sha, digest("newpassword").to_string());
+            assert!(crate::account::verify_password(
+                &attributes.password_sha,
+                "newpassword"
+            ));
             assert_eq!(attributes.token_expiration_time, 9);
         }
         _ => unreachable!(),
@@ -627,13 +923,23 @@ async fn reset_password() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Jiening Yu".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: crate::account::verify::Tokens::new(),
         verify: crate::account::UserVerifyVariant::None,
@@ -744,7 +1050,3385 @@ async fn reset_password() {
         ));
 
         if let crate::account::Account::Verified { attributes, .. } = ar.deref() {
-            assert_eq!(attributes.password_sha, digest(new_password.to_string()))
+            assert!(crate::account::verify_password(
+                &attributes.password_sha,
+                new_password
+            ))
         }
     }
 }
+
+/// Test: recently used passwords are rejected in both reset and edit paths.
+#[serial]
+#[tokio::test]
+async fn password_reuse() {
+    reset_all();
+
+    let app = crate::router();
+
+    let account_id = 123456;
+
+    crate::account::INSTANCE.push(fixtures::with_attributes(
+        fixtures::verified_account_with_id(account_id, "yujiening2025", &[]),
+        |attributes| {
+            attributes.password_sha = digest("password0");
+        },
+    ));
+
+    let change = |old: &str, new: &str| {
+        crate::account::INSTANCE
+            .with_account_mut(account_id, |account| {
+                crate::account::handle::apply_edit_variant(
+                    sms3_shared::account::handle::AccountEditVariant::Password {
+                        old: old.to_string(),
+                        new: new.to_string(),
+                    },
+                    account,
+                )
+            })
+            .unwrap()
+    };
+
+    change("password0", "password1").unwrap();
+
+    {
+        use sms3_shared::account::handle::{
+            AccountVerifyDescriptor, AccountVerifyVariant, ResetPasswordDescriptor,
+        };
+
+        let descriptor = ResetPasswordDescriptor {
+            email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
+        };
+
+        assert_eq!(
+            app.clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/account/reset-password")
+                        .method("POST")
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .body(serde_json::to_vec(&descriptor).unwrap().into())
+                        .unwrap()
+                )
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::OK
+        );
+
+        let reset = |password: &str| {
+            let descriptor = AccountVerifyDescriptor {
+                code: crate::account::verify::VERIFICATION_CODE
+                    .load(std::sync::atomic::Ordering::Relaxed),
+                variant: AccountVerifyVariant::ResetPassword {
+                    email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
+                    password: password.to_string(),
+                },
+            };
+
+            app.clone().oneshot(
+                Request::builder()
+                    .uri("/api/account/verify")
+                    .method("POST")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(serde_json::to_vec(&descriptor).unwrap().into())
+                    .unwrap(),
+            )
+        };
+
+        // The immediately previous password.
+        let response = reset("password0").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response_json: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        assert!(response_json["error"]
+            .as_str()
+            .unwrap()
+            .contains("password was used recently"));
+
+        // The reset session stays usable.
+        assert_eq!(reset("password2").await.unwrap().status(), StatusCode::OK);
+    }
+
+    assert!(matches!(
+        change("password2", "password1"),
+        Err(crate::account::Error::PasswordReused)
+    ));
+    assert!(matches!(
+        change("password2", "password2"),
+        Err(crate::account::Error::PasswordReused)
+    ));
+
+    for i in 3..=7 {
+        change(&format!("password{}", i - 1), &format!("password{i}")).unwrap();
+    }
+
+    // Only the latest 5 previous passwords are kept.
+    if let crate::account::Account::Verified { attributes, .. } =
+        crate::account::INSTANCE.get(account_id).unwrap().deref()
+    {
+        assert_eq!(
+            attributes.password_history.len(),
+            crate::config::INSTANCE.account.password_history
+        );
+    }
+    change("password7", "password1").unwrap();
+}
+
+/// Test: authentication failures respond `401`, authorization failures `403`.
+#[serial]
+#[tokio::test]
+async fn status_codes() {
+    use crate::account::{Error, ManagerError};
+    use crate::AsResCode;
+
+    assert_eq!(
+        Error::TokenIncorrect.response_code(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        ManagerError::Account(0, Error::TokenIncorrect).response_code(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        Error::PermissionDenied(crate::account::Permission::Op).response_code(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        Error::PasswordIncorrect.response_code(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(Error::UserUnverified.response_code(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        Error::PasswordChangeRequired.response_code(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        Error::AccountSuspended.response_code(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(Error::Conflict.response_code(), StatusCode::CONFLICT);
+    assert_eq!(Error::UserRegistered.response_code(), StatusCode::CONFLICT);
+    assert_eq!(
+        ManagerError::NotFound(0).response_code(),
+        StatusCode::NOT_FOUND
+    );
+
+    reset_all();
+
+    let app = crate::router();
+
+    let account_id = 123456;
+
+    let mut account = fixtures::verified_account_with_id(account_id, "yujiening2025", &[]);
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    let request = |uri: &str, token: &str, account_id: u64, body: hyper::Body| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", token)
+                .header("AccountId", account_id)
+                .body(body)
+                .unwrap(),
+        )
+    };
+
+    // Missing headers.
+    assert_eq!(
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/account/view")
+                    .method("POST")
+                    .body(hyper::Body::empty())
+                    .unwrap()
+            )
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    // Incorrect token or unknown account.
+    for (token, account_id) in [("wrongtoken", account_id), (token.as_str(), 42)] {
+        let response = request("/api/account/view", token, account_id, hyper::Body::empty())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response_json: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        assert!(response_json["error"].is_string());
+        // Permissions required aren't told before authentication.
+        assert!(response_json.get("permission").is_none());
+    }
+
+    // Authenticated but lacking permission, which is named.
+    let descriptor = sms3_shared::account::handle::manage::ViewAccountDescriptor {
+        accounts: vec![account_id],
+    };
+    let response = request(
+        "/api/account/manage/view",
+        &token,
+        account_id,
+        serde_json::to_vec(&descriptor).unwrap().into(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response_json: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert_eq!(response_json["permission"], "ViewAccounts");
+    assert!(response_json["error"]
+        .as_str()
+        .unwrap()
+        .ends_with(&Error::PermissionDenied(crate::account::Permission::ViewAccounts).to_string()));
+
+    assert_eq!(
+        request(
+            "/api/account/view",
+            &token,
+            account_id,
+            hyper::Body::empty()
+        )
+        .await
+        .unwrap()
+        .status(),
+        StatusCode::OK
+    );
+
+    // A valid token of a user who must change the password is forbidden.
+    crate::account::INSTANCE.with_account_mut(account_id, |account| {
+        if let crate::account::Account::Verified { attributes, .. } = account {
+            attributes.must_change_password = true;
+        }
+    });
+    let response = request(
+        "/api/account/view",
+        &token,
+        account_id,
+        hyper::Body::empty(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response_json: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert!(response_json["error"]
+        .as_str()
+        .unwrap()
+        .ends_with(&Error::PasswordChangeRequired.to_string()));
+}
+
+/// Test: time left before tokens expire.
+#[serial]
+#[tokio::test]
+async fn token_expiry() {
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let account_id = 123456;
+    let mut account = fixtures::verified_account_with_id(account_id, "yujiening2025", &[]);
+    let crate::account::Account::Verified { tokens, .. } = &mut account else {
+        unreachable!()
+    };
+    let expiring = tokens.new_token(1).unwrap();
+    let never = tokens.new_token(0).unwrap();
+
+    // A fresh token.
+    assert_eq!(
+        tokens.time_until_expiry(&expiring),
+        Some(chrono::Duration::days(1))
+    );
+    assert_eq!(tokens.time_until_expiry(&never), None);
+    assert_eq!(tokens.time_until_expiry("unknown"), None);
+
+    // A token about to expire.
+    crate::clock::advance(chrono::Duration::days(1) - chrono::Duration::seconds(30));
+    assert_eq!(
+        tokens.time_until_expiry(&expiring),
+        Some(chrono::Duration::seconds(30))
+    );
+
+    crate::account::INSTANCE.push(account);
+
+    let app = crate::router();
+    let request = |token: &str| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri("/api/account/token-expiry")
+                .method("POST")
+                .header("Token", token)
+                .header("AccountId", account_id)
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    for (token, expires_in) in [(&expiring, Some(30)), (&never, None)] {
+        let response = request(token).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let result: sms3_shared::account::handle::TokenExpiryResult =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(result.expires_in, expires_in);
+    }
+
+    // Expired tokens are rejected.
+    crate::clock::advance(chrono::Duration::seconds(30));
+    assert_eq!(
+        request(&expiring).await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+/// Test: API keys are scoped within the account's permissions and can be revoked.
+#[serial]
+#[tokio::test]
+async fn api_key() {
+    reset_all();
+
+    let account_id = 123456;
+
+    let mut account = fixtures::verified_account_with_id(
+        account_id,
+        "yujiening2025",
+        &[
+            sms3_shared::account::Permission::ViewAccounts,
+            sms3_shared::account::Permission::Post,
+        ],
+    );
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    use sms3_shared::account::handle::{
+        ApiKeyCreateDescriptor, ApiKeyCreateResult, ApiKeyInfo, ApiKeyRevokeDescriptor,
+    };
+    use sms3_shared::account::Permission;
+
+    let app = crate::router();
+    let request = |uri: &str, token: &str, body: serde_json::Value| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", token)
+                .header("AccountId", account_id)
+                .body(serde_json::to_vec(&body).unwrap().into())
+                .unwrap(),
+        )
+    };
+    let create = |token: String, name: &str, permissions: Vec<Permission>| {
+        request(
+            "/api/account/api-key/create",
+            &token,
+            serde_json::to_value(ApiKeyCreateDescriptor {
+                name: name.to_string(),
+                permissions,
+                expire_time: None,
+            })
+            .unwrap(),
+        )
+    };
+    let search = |token: &str| {
+        request(
+            "/api/account/manage/search",
+            token,
+            serde_json::json!({ "query": "jien", "limit": 10 }),
+        )
+    };
+
+    // A key can't exceed the account's permissions.
+    assert_eq!(
+        create(token.clone(), "op", vec![Permission::Op])
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let response = create(token.clone(), "poster", vec![Permission::Post])
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let poster: ApiKeyCreateResult =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    // Keys look like tokens, so secret scanners recognize them.
+    assert!(poster.key.starts_with(crate::account::verify::TOKEN_PREFIX));
+
+    assert_eq!(
+        create(token.clone(), "poster", vec![Permission::Post])
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::CONFLICT
+    );
+
+    // Authorization uses the key's scope, not the account's permissions.
+    assert_eq!(search(&token).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(
+        search(&poster.key).await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+
+    // Nor can a key create a key broader than itself.
+    assert_eq!(
+        create(poster.key.clone(), "viewer", vec![Permission::ViewAccounts])
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let response = create(token.clone(), "viewer", vec![Permission::ViewAccounts])
+        .await
+        .unwrap();
+    let viewer: ApiKeyCreateResult =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert_eq!(search(&viewer.key).await.unwrap().status(), StatusCode::OK);
+
+    let response = request("/api/account/api-key/list", &token, serde_json::Value::Null)
+        .await
+        .unwrap();
+    let keys: Vec<ApiKeyInfo> =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert_eq!(
+        keys.iter().map(|k| k.name.as_str()).collect::<Vec<_>>(),
+        vec!["poster", "viewer"]
+    );
+
+    // Revocation takes effect immediately.
+    let revoke = |name: &str| {
+        request(
+            "/api/account/api-key/revoke",
+            &token,
+            serde_json::to_value(ApiKeyRevokeDescriptor {
+                name: name.to_string(),
+            })
+            .unwrap(),
+        )
+    };
+    assert_eq!(revoke("viewer").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(
+        search(&viewer.key).await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        revoke("viewer").await.unwrap().status(),
+        StatusCode::NOT_FOUND
+    );
+}
+
+/// Test: organizations are validated and normalized against the registry.
+#[test]
+fn organization_registry() {
+    use crate::account::{normalize_organization, Error};
+
+    let registry = vec!["Robotics Club".to_string(), "SubIT".to_string()];
+
+    assert_eq!(
+        normalize_organization(Some("SubIT".to_string()), &registry).unwrap(),
+        Some("SubIT".to_string())
+    );
+    assert!(matches!(
+        normalize_organization(Some("Robotics".to_string()), &registry),
+        Err(Error::InvalidOrganization)
+    ));
+    assert_eq!(
+        normalize_organization(Some("  robotics   club ".to_string()), &registry).unwrap(),
+        Some("Robotics Club".to_string())
+    );
+    assert_eq!(normalize_organization(None, &registry).unwrap(), None);
+
+    // Any value is accepted without a registry.
+    assert_eq!(
+        normalize_organization(Some("Robotics".to_string()), &[]).unwrap(),
+        Some("Robotics".to_string())
+    );
+}
+
+/// Test: houses are validated against the configured ones, falling back to
+/// the built-in houses without configuration.
+#[test]
+fn house_registry() {
+    use crate::account::{validate_house, Error};
+    use sms3_shared::account::{House, HouseInfo};
+
+    let houses = vec![HouseInfo {
+        id: "Hogwarts".to_string(),
+        name: "Hogwarts School".to_string(),
+    }];
+
+    assert_eq!(
+        validate_house(Some(House::from("Hogwarts")), &houses).unwrap(),
+        Some(House::from("Hogwarts"))
+    );
+    assert!(matches!(
+        validate_house(Some(House::from("ZhiZhi")), &houses),
+        Err(Error::InvalidHouse)
+    ));
+    assert_eq!(validate_house(None, &houses).unwrap(), None);
+
+    let builtin = crate::config::Account::default().houses().into_owned();
+    assert_eq!(builtin.len(), House::BUILTIN.len());
+    assert!(validate_house(Some(House::from("ZhiZhi")), &builtin).is_ok());
+    assert!(matches!(
+        validate_house(Some(House::from("Hogwarts")), &builtin),
+        Err(Error::InvalidHouse)
+    ));
+}
+
+/// Test: a user can export their own data, without password material.
+#[serial]
+#[tokio::test]
+async fn export() {
+    reset_all();
+
+    let account_id = 123456;
+    let admin_id = 654321;
+
+    let mut account = fixtures::with_attributes(
+        fixtures::verified_account_with_id(
+            account_id,
+            "yujiening2025",
+            &[sms3_shared::account::Permission::View],
+        ),
+        |attributes| {
+            attributes.password_history = vec![digest("oldpassword")];
+        },
+    );
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    crate::account::INSTANCE.grant_permission_bulk(
+        &[account_id],
+        sms3_shared::account::Permission::Post,
+        admin_id,
+    );
+
+    let response = crate::router()
+        .oneshot(
+            Request::builder()
+                .uri("/api/account/export")
+                .method("POST")
+                .header("Token", &token)
+                .header("AccountId", account_id)
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let export: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(
+        export["metadata"]["email"],
+        "yujiening2025@i.pkuschool.edu.cn"
+    );
+    assert_eq!(export["sessions"].as_array().unwrap().len(), 1);
+    let audit = export["audit"].as_array().unwrap();
+    assert_eq!(audit.len(), 1);
+    assert_eq!(audit[0]["by"], admin_id);
+
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(!body.contains(&digest("password123456")));
+    assert!(!body.contains(&digest("oldpassword")));
+    assert!(!body.contains(&token));
+}
+
+/// Test: names are trimmed, and empty names rejected.
+#[test]
+fn name_normalization() {
+    use crate::account::{normalize_name, Error};
+
+    assert!(matches!(normalize_name(""), Err(Error::InvalidName)));
+    assert!(matches!(normalize_name("  \t "), Err(Error::InvalidName)));
+    assert_eq!(normalize_name("  Jiening Yu ").unwrap(), "Jiening Yu");
+    assert_eq!(normalize_name("Jiening   Yu").unwrap(), "Jiening Yu");
+}
+
+/// Test: metadata exposes the registration time in RFC 3339.
+#[serial]
+#[tokio::test]
+async fn registration_time() {
+    use chrono::TimeZone;
+
+    reset_all();
+
+    let account_id = 123456;
+
+    let mut account = fixtures::with_attributes(
+        fixtures::verified_account_with_id(account_id, "yujiening2025", &[]),
+        |attributes| {
+            attributes.registration_time =
+                chrono::Utc.with_ymd_and_hms(2023, 9, 1, 8, 0, 0).unwrap();
+        },
+    );
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    let response = crate::router()
+        .oneshot(
+            Request::builder()
+                .uri("/api/account/view")
+                .method("POST")
+                .header("Token", &token)
+                .header("AccountId", account_id)
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let result: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert_eq!(
+        result["metadata"]["registration_time"],
+        "2023-09-01T08:00:00Z"
+    );
+}
+
+/// Test: registering a pending email again reuses its verification.
+#[serial]
+#[tokio::test]
+async fn idempotent_registration() {
+    use sms3_shared::account::handle::{
+        AccountCreateDescriptor, AccountCreateResult, AccountVerifyDescriptor, AccountVerifyVariant,
+    };
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let app = crate::router();
+    let email = lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap();
+    let create = || {
+        app.clone().oneshot(
+            Request::builder()
+                .uri("/api/account/create")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(
+                    serde_json::to_vec(&AccountCreateDescriptor {
+                        email: email.to_string(),
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+    };
+    let create_result = || async {
+        let response = create().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        serde_json::from_slice::<AccountCreateResult>(
+            &hyper::body::to_bytes(response.into_body()).await.unwrap(),
+        )
+        .unwrap()
+    };
+
+    let result = create_result().await;
+    assert!(!result.already_pending && result.code_sent);
+    assert_eq!(crate::account::verify::SENT_MAILS.lock().len(), 1);
+
+    // Within the cooldown, nothing is sent.
+    let result = create_result().await;
+    assert!(result.already_pending && !result.code_sent);
+    assert_eq!(crate::account::verify::SENT_MAILS.lock().len(), 1);
+    assert_eq!(crate::account::INSTANCE.snapshot_ids().len(), 1);
+
+    // After the cooldown, the code is refreshed.
+    crate::clock::advance(chrono::Duration::minutes(2));
+    let result = create_result().await;
+    assert!(result.already_pending && result.code_sent);
+    assert_eq!(crate::account::verify::SENT_MAILS.lock().len(), 2);
+    assert_eq!(crate::account::INSTANCE.snapshot_ids().len(), 1);
+
+    let descriptor = AccountVerifyDescriptor {
+        code: crate::account::verify::VERIFICATION_CODE.load(std::sync::atomic::Ordering::Relaxed),
+        variant: AccountVerifyVariant::Activate {
+            email: email.clone(),
+            name: "Jiening Yu".to_string(),
+            id: 2522320,
+            phone: 16601550826,
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
+            organization: None,
+            password: "password123456".to_string(),
+        },
+    };
+    assert_eq!(
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/account/verify")
+                    .method("POST")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(serde_json::to_vec(&descriptor).unwrap().into())
+                    .unwrap()
+            )
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::OK
+    );
+
+    // Registered emails are rejected.
+    assert!(matches!(
+        crate::account::INSTANCE.register(email.clone()),
+        Err(crate::account::Error::UserRegistered)
+    ));
+    assert_eq!(create().await.unwrap().status(), StatusCode::CONFLICT);
+}
+
+/// Test: sliding expiration extends tokens on use, up to the max lifetime.
+#[serial]
+#[test]
+fn sliding_expiration() {
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let mut tokens = crate::account::verify::Tokens::new();
+    let token = tokens.new_token(1).unwrap();
+
+    // Extended on use.
+    crate::clock::advance(chrono::Duration::hours(12));
+    assert!(tokens.extend(&token, 1, 3));
+    assert_eq!(
+        tokens.time_until_expiry(&token),
+        Some(chrono::Duration::days(1))
+    );
+
+    // Capped by the max lifetime.
+    for _ in 0..3 {
+        crate::clock::advance(chrono::Duration::hours(12));
+        tokens.extend(&token, 1, 3);
+    }
+    assert_eq!(
+        tokens.time_until_expiry(&token),
+        Some(chrono::Duration::days(1))
+    );
+    crate::clock::advance(chrono::Duration::hours(12));
+    assert!(!tokens.extend(&token, 1, 3));
+    assert_eq!(
+        tokens.time_until_expiry(&token),
+        Some(chrono::Duration::hours(12))
+    );
+    crate::clock::advance(chrono::Duration::hours(12));
+    tokens.refresh();
+    assert!(!tokens.token_usable(&token));
+
+    // Expired when idle.
+    let token = tokens.new_token(1).unwrap();
+    crate::clock::advance(chrono::Duration::days(1));
+    assert!(!tokens.extend(&token, 1, 3));
+    tokens.refresh();
+    assert!(!tokens.token_usable(&token));
+}
+
+/// Test: touching a token records its last use, extends it under sliding
+/// expiration and asks for saving at most once per interval.
+#[serial]
+#[tokio::test]
+async fn touch_token() {
+    use crate::account::verify::Tokens;
+
+    let mut client = harness::TestClient::new();
+    let start = crate::clock::now();
+
+    let mut tokens = Tokens::new();
+    let token = tokens.new_token(1).unwrap();
+    assert_eq!(tokens.last_used(&token), None);
+
+    assert_eq!(tokens.touch(&token, None), Some(true));
+    assert_eq!(tokens.last_used(&token), Some(start));
+    // Later touches update the last use, but only ask for saving once the interval passed.
+    client.advance(chrono::Duration::minutes(1));
+    assert_eq!(tokens.touch(&token, None), Some(false));
+    assert_eq!(
+        tokens.last_used(&token),
+        Some(start + chrono::Duration::minutes(1))
+    );
+    client.advance(chrono::Duration::minutes(Tokens::TOUCH_SAVE_MINUTES));
+    assert_eq!(tokens.touch(&token, None), Some(true));
+    assert_eq!(tokens.touch(&token, None), Some(false));
+
+    // Expiry isn't extended without sliding expiration, and is with it.
+    client.advance(chrono::Duration::hours(12));
+    tokens.touch(&token, None);
+    let left = chrono::Duration::days(1)
+        - chrono::Duration::hours(12)
+        - chrono::Duration::minutes(1 + Tokens::TOUCH_SAVE_MINUTES);
+    assert_eq!(tokens.time_until_expiry(&token), Some(left));
+    tokens.touch(&token, Some((1, 3)));
+    assert_eq!(
+        tokens.time_until_expiry(&token),
+        Some(chrono::Duration::days(1))
+    );
+
+    // Unknown and expired tokens are rejected.
+    assert_eq!(tokens.touch("sms3_unknown", None), None);
+    client.advance(chrono::Duration::days(1));
+    assert_eq!(tokens.touch(&token, Some((1, 3))), None);
+
+    // Authenticated requests touch their tokens.
+    let mut account = fixtures::verified_account("user", &[]);
+    let token = fixtures::new_token(&mut account);
+    let id = account.id();
+    crate::account::INSTANCE.push(account);
+    client.authorize(id, &token);
+    client.advance(chrono::Duration::minutes(1));
+    assert_eq!(
+        client.post("/api/account/view", &()).await.status,
+        StatusCode::OK
+    );
+    let last_used = crate::account::INSTANCE.with_account(id, |account| match account {
+        crate::account::Account::Verified { tokens, .. } => tokens.last_used(&token),
+        crate::account::Account::Unverified(_) => None,
+    });
+    assert_eq!(last_used, Some(Some(crate::clock::now())));
+
+    // Later requests are only recorded in the index until the interval passed.
+    let synced = crate::clock::now();
+    client.advance(chrono::Duration::minutes(1));
+    assert_eq!(
+        client.post("/api/account/view", &()).await.status,
+        StatusCode::OK
+    );
+    assert_eq!(
+        crate::account::INSTANCE.last_used(&token),
+        Some(crate::clock::now())
+    );
+    let last_used = || {
+        crate::account::INSTANCE.with_account(id, |account| match account {
+            crate::account::Account::Verified { tokens, .. } => tokens.last_used(&token),
+            crate::account::Account::Unverified(_) => None,
+        })
+    };
+    assert_eq!(last_used(), Some(Some(synced)));
+    client.advance(chrono::Duration::minutes(Tokens::TOUCH_SAVE_MINUTES));
+    assert_eq!(
+        client.post("/api/account/view", &()).await.status,
+        StatusCode::OK
+    );
+    assert_eq!(last_used(), Some(Some(crate::clock::now())));
+
+    let mut account = crate::account::INSTANCE.get_mut(id).unwrap();
+    assert!(matches!(
+        account.touch_token("sms3_unknown"),
+        Err(crate::account::Error::TokenIncorrect)
+    ));
+}
+
+/// Test: the login response describes the token and the user.
+#[serial]
+#[tokio::test]
+async fn login_response() {
+    reset_all();
+
+    let account_id = 123456;
+
+    crate::account::INSTANCE.push(fixtures::with_attributes(
+        fixtures::verified_account_with_id(account_id, "yujiening2025", &[]),
+        |attributes| {
+            attributes.token_expiration_time = 7;
+        },
+    ));
+
+    let descriptor = sms3_shared::account::handle::AccountLoginDescriptor {
+        email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
+        password: "password123456".to_string(),
+    };
+
+    let response = crate::router()
+        .oneshot(
+            Request::builder()
+                .uri("/api/account/login")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(serde_json::to_vec(&descriptor).unwrap().into())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let result: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+
+    assert_eq!(result["account_id"], account_id);
+    assert!(result["access_token"].is_string());
+    assert_eq!(result["token_type"], "Bearer");
+    assert_eq!(result["expires_in"], 7 * 24 * 60 * 60);
+    assert_eq!(result["user"]["email"], "yujiening2025@i.pkuschool.edu.cn");
+    assert_eq!(result["user"]["name"], "Jiening Yu");
+}
+
+/// Test: free text is rejected if overlong or containing control characters.
+#[test]
+fn text_hygiene() {
+    use crate::account::{normalize_name, normalize_organization, Error, NAME_MAX_LEN};
+
+    assert!(matches!(
+        normalize_name(&"a".repeat(NAME_MAX_LEN + 1)),
+        Err(Error::TooLong { field: "name", .. })
+    ));
+    assert!(matches!(
+        normalize_name("Jiening\0Yu"),
+        Err(Error::ControlCharacter("name"))
+    ));
+    assert!(matches!(
+        normalize_organization(Some("Sub\0IT".to_string()), &[]),
+        Err(Error::ControlCharacter("organization"))
+    ));
+
+    assert_eq!(normalize_name("余嘉宁").unwrap(), "余嘉宁");
+    assert_eq!(
+        normalize_name(&"a".repeat(NAME_MAX_LEN)).unwrap(),
+        "a".repeat(NAME_MAX_LEN)
+    );
+    assert_eq!(
+        normalize_organization(Some("SubIT".to_string()), &[]).unwrap(),
+        Some("SubIT".to_string())
+    );
+}
+
+/// Test: registration times must be in a sane range and can't be supplied by clients.
+#[serial]
+#[tokio::test]
+async fn registration_time_range() {
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let attributes = |registration_time| crate::account::UserAttributes {
+        registration_time,
+        ..fixtures::attributes("yujiening2025", &[])
+    };
+
+    assert!(matches!(
+        attributes(crate::clock::now() + chrono::Duration::days(1)).validate_dates(),
+        Err(crate::account::Error::DateOutOfRange)
+    ));
+    assert!(matches!(
+        attributes(chrono::DateTime::UNIX_EPOCH).validate_dates(),
+        Err(crate::account::Error::DateOutOfRange)
+    ));
+    assert!(attributes(crate::clock::now()).validate_dates().is_ok());
+    assert!(
+        attributes(crate::clock::now() - chrono::Duration::days(365))
+            .validate_dates()
+            .is_ok()
+    );
+
+    let app = crate::router();
+    let email = lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap();
+    crate::account::INSTANCE.register(email.clone()).unwrap();
+
+    let mut descriptor = serde_json::json!({
+        "code": crate::account::verify::VERIFICATION_CODE.load(std::sync::atomic::Ordering::Relaxed),
+        "variant": { "Activate": {
+            "email": email,
+            "name": "Jiening Yu",
+            "id": 2522320,
+            "phone": 16601550826u64,
+            "house": "ZhiZhi",
+            "organization": null,
+            "password": "password123456",
+            "registration_time": "2000-01-01T00:00:00Z",
+        } },
+    });
+    let verify = |descriptor: &serde_json::Value| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri("/api/account/verify")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(serde_json::to_vec(descriptor).unwrap().into())
+                .unwrap(),
+        )
+    };
+
+    assert_eq!(
+        verify(&descriptor).await.unwrap().status(),
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+
+    descriptor["variant"]["Activate"]
+        .as_object_mut()
+        .unwrap()
+        .remove("registration_time");
+    assert_eq!(verify(&descriptor).await.unwrap().status(), StatusCode::OK);
+}
+
+/// Test: never-expire tokens are clamped for accounts holding sensitive permissions only.
+#[serial]
+#[test]
+fn never_expire_guardrail() {
+    use sms3_shared::account::Permission;
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let account = |id: u64, permissions: Vec<Permission>| {
+        fixtures::verified_account_with_id(id, &format!("user{id}"), &permissions)
+    };
+    let max =
+        chrono::Duration::days(crate::config::INSTANCE.account.sensitive_token_expiration as i64);
+
+    let mut privileged = account(1, vec![Permission::View, Permission::ManageAccounts]);
+    let token = privileged
+        .login("password123456", Default::default())
+        .unwrap();
+    let crate::account::Account::Verified { tokens, .. } = &privileged else {
+        unreachable!()
+    };
+    assert_eq!(tokens.time_until_expiry(&token), Some(max));
+
+    let mut regular = account(2, vec![Permission::View]);
+    let regular_token = regular.login("password123456", Default::default()).unwrap();
+    let crate::account::Account::Verified { tokens, .. } = &regular else {
+        unreachable!()
+    };
+    assert!(tokens.token_usable(&regular_token));
+    assert_eq!(tokens.time_until_expiry(&regular_token), None);
+
+    // A never-expire token issued before the permission was granted is clamped on refresh.
+    if let crate::account::Account::Verified { attributes, .. } = &mut regular {
+        attributes.permissions.push(Permission::ManageAccounts);
+    }
+    crate::account::INSTANCE.push(regular);
+    crate::account::INSTANCE.refresh(2);
+    assert_eq!(
+        crate::account::INSTANCE.with_account(2, |account| match account {
+            crate::account::Account::Verified { tokens, .. } => {
+                tokens.time_until_expiry(&regular_token)
+            }
+            _ => unreachable!(),
+        }),
+        Some(Some(max))
+    );
+
+    crate::clock::advance(max);
+    crate::account::INSTANCE.refresh(2);
+    assert_eq!(
+        crate::account::INSTANCE.with_account(2, |account| match account {
+            crate::account::Account::Verified { tokens, .. } => tokens.token_usable(&regular_token),
+            _ => unreachable!(),
+        }),
+        Some(false)
+    );
+}
+
+/// Test: token expiration overrides shorten sessions of accounts holding
+/// overridden permissions, taking the least of them.
+#[serial]
+#[test]
+fn token_expiration_overrides() {
+    use sms3_shared::account::Permission;
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let config: crate::config::Account = toml::from_str(
+        "sensitive_token_expiration = 0\n\
+         [token_expiration_overrides]\n\
+         ManageAccounts = 1\n\
+         Op = 3\n\
+         View = 0\n",
+    )
+    .unwrap();
+
+    let expiry = |permissions: &[Permission], requested: u16| {
+        let days = config.token_expiration(permissions, requested);
+        let mut tokens = crate::account::verify::Tokens::new();
+        let token = tokens.new_token(days).unwrap();
+        tokens.time_until_expiry(&token)
+    };
+
+    // Admins get the shortest override of their permissions.
+    assert_eq!(
+        expiry(&[Permission::ManageAccounts, Permission::Op], 30),
+        Some(chrono::Duration::days(1))
+    );
+    assert_eq!(
+        expiry(&[Permission::Op], 0),
+        Some(chrono::Duration::days(3))
+    );
+    // Plain accounts keep their own expiration time.
+    assert_eq!(expiry(&[], 30), Some(chrono::Duration::days(30)));
+    assert_eq!(expiry(&[Permission::Post], 0), None);
+    // Overrides of never expiring are the longest.
+    assert_eq!(expiry(&[Permission::View], 30), None);
+    assert_eq!(
+        expiry(&[Permission::View, Permission::Op], 30),
+        Some(chrono::Duration::days(3))
+    );
+
+    // Sensitive permissions still clamp overridden expiration times.
+    let config = crate::config::Account {
+        sensitive_token_expiration: 2,
+        ..config
+    };
+    assert_eq!(config.token_expiration(&[Permission::Op], 30), 2);
+    assert_eq!(config.token_expiration(&[Permission::View], 30), 0);
+    assert!(crate::config::INSTANCE
+        .account
+        .token_expiration_overrides
+        .is_empty());
+}
+
+/// Test: requesting a password reset responds the same for known and unknown emails.
+#[serial]
+#[tokio::test]
+async fn reset_password_enumeration() {
+    reset_all();
+
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(
+        123456,
+        "yujiening2025",
+        &[],
+    ));
+
+    let request = |user: &str| {
+        crate::router().oneshot(
+            Request::builder()
+                .uri("/api/account/reset-password")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(
+                    serde_json::to_vec(&sms3_shared::account::handle::ResetPasswordDescriptor {
+                        email: lettre::Address::new(user, "i.pkuschool.edu.cn").unwrap(),
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+    };
+
+    let mut responses = Vec::new();
+    for user in ["yujiening2025", "nobody", "yujiening2025"] {
+        let response = request(user).await.unwrap();
+        responses.push((
+            response.status(),
+            hyper::body::to_bytes(response.into_body()).await.unwrap(),
+        ));
+    }
+    assert_eq!(responses[0].0, StatusCode::OK);
+    assert!(responses.iter().all(|r| r == &responses[0]));
+
+    // Only the known account got a code, once.
+    let mails = crate::account::verify::SENT_MAILS.lock();
+    assert_eq!(mails.len(), 1);
+    assert_eq!(mails[0].to.user(), "yujiening2025");
+}
+
+/// Test: password resets for unknown or unverified emails succeed without mailing
+/// or changing any account.
+#[serial]
+#[tokio::test]
+async fn reset_password_unknown_email() {
+    use crate::account::{Account, UserVerifyVariant};
+
+    reset_all();
+
+    let pending = fixtures::pending_account("pending");
+    let pending_id = pending.id();
+    crate::account::INSTANCE.push(pending);
+    crate::account::verify::SENT_MAILS.lock().clear();
+
+    let request = |user: &str| {
+        crate::router().oneshot(
+            Request::builder()
+                .uri("/api/account/reset-password")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(
+                    serde_json::to_vec(&sms3_shared::account::handle::ResetPasswordDescriptor {
+                        email: fixtures::email(user),
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+    };
+
+    for user in ["nobody", "pending"] {
+        assert_eq!(request(user).await.unwrap().status(), StatusCode::OK);
+    }
+    assert!(crate::account::verify::SENT_MAILS.lock().is_empty());
+    assert_eq!(crate::account::INSTANCE.snapshot_ids(), [pending_id]);
+
+    // A verified account is found by its alternate email too.
+    let mut account = fixtures::verified_account("user", &[]);
+    if let Account::Verified { attributes, .. } = &mut account {
+        attributes.alternate_email = Some(crate::account::AlternateEmail::Verified(
+            lettre::Address::new("user", "example.com").unwrap(),
+        ));
+    }
+    let id = account.id();
+    crate::account::INSTANCE.push(account);
+    let response = crate::router()
+        .oneshot(
+            Request::builder()
+                .uri("/api/account/reset-password")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(
+                    serde_json::to_vec(&sms3_shared::account::handle::ResetPasswordDescriptor {
+                        email: lettre::Address::new("User", "example.com").unwrap(),
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(crate::account::verify::SENT_MAILS.lock().len(), 1);
+    assert!(crate::account::INSTANCE
+        .with_account(id, |account| matches!(
+            account,
+            Account::Verified {
+                verify: UserVerifyVariant::ForgetPassword(_),
+                ..
+            }
+        ))
+        .unwrap());
+}
+
+/// Test: tokens are prefixed random strings of the configured length.
+#[test]
+fn token_format() {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    let mut tokens = crate::account::verify::Tokens::new();
+    let first = tokens.new_token(0).unwrap();
+    let second = tokens.new_token(0).unwrap();
+    assert_ne!(first, second);
+
+    for token in [&first, &second] {
+        let encoded = token
+            .strip_prefix(crate::account::verify::TOKEN_PREFIX)
+            .unwrap();
+        let bytes = URL_SAFE_NO_PAD.decode(encoded).unwrap();
+        assert_eq!(bytes.len(), crate::config::INSTANCE.account.token_bytes);
+        // Random bytes are hardly repeated.
+        assert!(bytes.iter().collect::<std::collections::HashSet<_>>().len() > bytes.len() / 2);
+        assert!(tokens.token_usable(token));
+    }
+
+    assert_eq!(
+        crate::account::verify::generate_token(16).len(),
+        crate::account::verify::TOKEN_PREFIX.len() + 22
+    );
+}
+
+/// Test: a password reset code is rejected once expired, without waiting for a refresh.
+#[serial]
+#[tokio::test]
+async fn reset_password_expiry() {
+    use sms3_shared::account::handle::{
+        AccountVerifyDescriptor, AccountVerifyVariant, ResetPasswordDescriptor,
+    };
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let app = crate::router();
+    let email = lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap();
+
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(
+        123456,
+        "yujiening2025",
+        &[],
+    ));
+
+    let request = |uri: &str, body: Vec<u8>| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(body.into())
+                .unwrap(),
+        )
+    };
+
+    let response = request(
+        "/api/account/reset-password",
+        serde_json::to_vec(&ResetPasswordDescriptor {
+            email: email.clone(),
+        })
+        .unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    crate::clock::advance(chrono::Duration::minutes(15) + chrono::Duration::seconds(1));
+
+    let response = request(
+        "/api/account/verify",
+        serde_json::to_vec(&AccountVerifyDescriptor {
+            code: crate::account::verify::VERIFICATION_CODE
+                .load(std::sync::atomic::Ordering::Relaxed),
+            variant: AccountVerifyVariant::ResetPassword {
+                email,
+                password: "newpassword".to_string(),
+            },
+        })
+        .unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert_eq!(body["error"], "verification code expired");
+
+    crate::account::INSTANCE.with_account(123456, |account| match account {
+        crate::account::Account::Verified {
+            attributes, verify, ..
+        } => {
+            assert!(crate::account::verify_password(
+                &attributes.password_sha,
+                "password123456"
+            ));
+            assert!(matches!(verify, crate::account::UserVerifyVariant::None));
+        }
+        _ => unreachable!(),
+    });
+}
+
+/// Test: sending a verification code emits an event with its domain and outcome.
+#[serial]
+#[test]
+fn verification_sent_event() {
+    use crate::account::event::Event;
+
+    reset_all();
+
+    let mut events = crate::account::event::subscribe();
+    let context = crate::account::verify::Context::new(
+        lettre::Address::new("yujiening2025", "events.pkuschool.edu.cn").unwrap(),
+    );
+
+    context.send_verify();
+    crate::account::verify::FAIL_MAILS.store(true, std::sync::atomic::Ordering::Relaxed);
+    context.send_verify();
+
+    let mut received = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if matches!(event, Event::VerificationSent { ref domain, .. } if domain == "events.pkuschool.edu.cn")
+        {
+            received.push(event);
+        }
+    }
+    assert_eq!(
+        received,
+        [true, false].map(|success| Event::VerificationSent {
+            channel: crate::config::DeliveryChannel::Email,
+            domain: "events.pkuschool.edu.cn".to_string(),
+            code_length: 6,
+            success,
+        })
+    );
+    assert_eq!(crate::account::verify::SENT_MAILS.lock().len(), 1);
+}
+
+/// Test: the mailed unlock token lifts a lockout once, and only before it expires.
+#[serial]
+#[tokio::test]
+async fn unlock_lockout() {
+    use crate::account::verify::{LoginLimiter, LOGIN_LIMITER};
+    use chrono::Duration;
+    use sms3_shared::account::handle::{AccountLoginDescriptor, AccountUnlockDescriptor};
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let limiter = LoginLimiter::new(16);
+    let lock = || {
+        for _ in 0..3 {
+            limiter.fail_with(1, 3, Duration::minutes(15));
+        }
+        assert!(limiter.check(1).is_err());
+    };
+
+    lock();
+    let token = limiter.issue_unlock(1, Duration::minutes(10)).unwrap();
+    assert!(limiter.unlock(1, "sms3_wrong").is_err());
+    assert!(limiter.unlock(1, &token).is_ok());
+    assert!(limiter.check(1).is_ok());
+    assert!(limiter.unlock(1, &token).is_err());
+    assert!(limiter.issue_unlock(1, Duration::minutes(10)).is_none());
+
+    // Expired tokens are rejected while the account stays locked out.
+    lock();
+    let token = limiter.issue_unlock(1, Duration::minutes(10)).unwrap();
+    crate::clock::advance(Duration::minutes(11));
+    assert!(limiter.unlock(1, &token).is_err());
+    assert!(limiter.check(1).is_err());
+
+    let app = crate::router();
+    let account_id = 123456;
+    let email = lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap();
+    crate::account::INSTANCE.push(fixtures::with_attributes(
+        fixtures::verified_account_with_id(account_id, "yujiening2025", &[]),
+        |attributes| attributes.mute_login_notification = true,
+    ));
+
+    let request = |uri: &'static str, body: Vec<u8>| {
+        let app = app.clone();
+        async move {
+            app.oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .method("POST")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(body.into())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+            .status()
+        }
+    };
+    let login = |password: &str| {
+        request(
+            "/api/account/login",
+            serde_json::to_vec(&AccountLoginDescriptor {
+                email: email.clone(),
+                password: password.to_string(),
+            })
+            .unwrap(),
+        )
+    };
+    let unlock = |token: &str| {
+        request(
+            "/api/account/unlock",
+            serde_json::to_vec(&AccountUnlockDescriptor {
+                account_id,
+                token: token.to_string(),
+            })
+            .unwrap(),
+        )
+    };
+
+    for _ in 0..5 {
+        login("wrong").await;
+    }
+    assert_eq!(login("password123456").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // The token is mailed to the owner when enabled in the config.
+    LOGIN_LIMITER.notify(
+        email.clone(),
+        account_id,
+        crate::account::verify::Lockout {
+            failures: 5,
+            locked_until: crate::clock::now() + Duration::minutes(15),
+        },
+        Duration::minutes(15),
+    );
+    let token = {
+        let mails = crate::account::verify::SENT_MAILS.lock();
+        let mail = mails.last().unwrap();
+        assert_eq!(mail.to, email);
+        mail.body.split_whitespace().last().unwrap().to_string()
+    };
+
+    assert_eq!(unlock("sms3_wrong").await, StatusCode::FORBIDDEN);
+    assert_eq!(unlock(&token).await, StatusCode::NO_CONTENT);
+    assert_eq!(login("password123456").await, StatusCode::OK);
+    assert_eq!(unlock(&token).await, StatusCode::FORBIDDEN);
+    assert!(matches!(
+        crate::account::INSTANCE
+            .audit()
+            .concerning(account_id)
+            .last(),
+        Some(crate::account::audit::Entry {
+            action: crate::account::audit::Action::Unlock,
+            ..
+        })
+    ));
+}
+
+/// Test: verification codes with leading zeros are rendered and matched zero-padded.
+#[serial]
+#[test]
+fn zero_padded_code() {
+    use crate::account::verify::{format_code, Context};
+
+    reset_all();
+
+    assert_eq!(format_code(12345), "012345");
+    assert_eq!(format_code(7), "000007");
+    assert_eq!(format_code(123456), "123456");
+
+    let mut context =
+        Context::new(lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap());
+    context.code = 12345;
+    assert!(context.matches(12345));
+    assert!(!context.matches(123450));
+    assert!(!context.matches(0));
+
+    context.send_verify();
+    let mails = crate::account::verify::SENT_MAILS.lock();
+    assert!(mails[0].body.ends_with(" 012345"), "{}", mails[0].body);
+}
+
+/// Test: password hashes, verification codes and token hashes are redacted from `Debug` output.
+#[test]
+fn debug_redaction() {
+    use crate::account::{verify, Account, UserAttributes, UserVerifyVariant};
+
+    let mut context =
+        verify::Context::new(lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap());
+    context.code = 654321;
+    // Fixed times, so the code can't appear in them by chance.
+    context.expire_time = chrono::DateTime::default();
+    let mut tokens = verify::Tokens::new();
+    tokens.new_token_with(0, Default::default(), 0).unwrap();
+    let (hash, _) = tokens.hashes().next().unwrap();
+
+    let account = Account::Verified {
+        id: 123456,
+        attributes: UserAttributes {
+            registration_time: chrono::DateTime::default(),
+            password_history: vec![digest("previous123456")],
+            ..fixtures::attributes("yujiening2025", &[])
+        },
+        tokens,
+        verify: UserVerifyVariant::ForgetPassword(context.clone()),
+    };
+
+    let output = format!("{account:?}");
+    assert!(output.contains(r#"password_sha: "<redacted>""#), "{output}");
+    assert!(output.contains("Jiening Yu"));
+    for secret in [
+        digest("password123456"),
+        digest("previous123456"),
+        "654321".to_string(),
+        hash.to_string(),
+    ] {
+        assert!(!output.contains(&secret), "{secret} leaked in {output}");
+    }
+
+    let output = format!("{:?}", Account::Unverified(context));
+    assert!(output.contains(r#"code: "<redacted>""#), "{output}");
+    assert!(!output.contains("654321"));
+}
+
+/// Test: failed logins lock an account out, emitting one lockout event and
+/// rate limited events of attempts while locked.
+#[serial]
+#[tokio::test]
+async fn lockout_events() {
+    use crate::account::{audit::Action, event::Event};
+    use sms3_shared::account::handle::AccountLoginDescriptor;
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let app = crate::router();
+    let account_id = 123456;
+
+    crate::account::INSTANCE.push(fixtures::with_attributes(
+        fixtures::verified_account_with_id(account_id, "yujiening2025", &[]),
+        |attributes| {
+            attributes.mute_login_notification = true;
+        },
+    ));
+
+    let login = |password: &str| {
+        let descriptor = AccountLoginDescriptor {
+            email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
+            password: password.to_string(),
+        };
+        let app = app.clone();
+        async move {
+            app.oneshot(
+                Request::builder()
+                    .uri("/api/account/login")
+                    .method("POST")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(serde_json::to_vec(&descriptor).unwrap().into())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+            .status()
+        }
+    };
+    let mut events = crate::account::event::subscribe();
+    let mut received = || {
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if !matches!(event, Event::VerificationSent { .. }) {
+                received.push(event);
+            }
+        }
+        received
+    };
+
+    // A successful login starts the count over.
+    for _ in 0..4 {
+        assert_eq!(login("wrong").await, StatusCode::FORBIDDEN);
+    }
+    assert_eq!(login("password123456").await, StatusCode::OK);
+    assert_eq!(received(), []);
+
+    for _ in 0..5 {
+        assert_eq!(login("wrong").await, StatusCode::FORBIDDEN);
+    }
+    let locked_until = crate::clock::now() + chrono::Duration::minutes(15);
+    for password in ["wrong", "wrong", "password123456"] {
+        assert_eq!(login(password).await, StatusCode::TOO_MANY_REQUESTS);
+    }
+    assert_eq!(
+        received(),
+        [
+            Event::AccountLocked {
+                account_id,
+                failures: 5,
+                locked_until,
+            },
+            Event::AttemptWhileLocked {
+                account_id,
+                locked_until,
+            },
+        ]
+    );
+
+    crate::clock::advance(chrono::Duration::seconds(
+        crate::account::verify::LoginLimiter::REPORT_INTERVAL_SECONDS,
+    ));
+    assert_eq!(login("wrong").await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        received(),
+        [Event::AttemptWhileLocked {
+            account_id,
+            locked_until,
+        }]
+    );
+
+    let audit = crate::account::INSTANCE.audit().concerning(account_id);
+    assert_eq!(audit.len(), 1);
+    assert_eq!(
+        audit[0].action,
+        Action::Lock {
+            failures: 5,
+            locked_until,
+        }
+    );
+
+    crate::clock::advance(chrono::Duration::minutes(15));
+    assert_eq!(login("password123456").await, StatusCode::OK);
+    assert_eq!(received(), []);
+}
+
+/// Test: emails match ignoring case, from registration to login.
+#[serial]
+#[tokio::test]
+async fn email_case_insensitive() {
+    use sms3_shared::account::handle::{
+        AccountCreateDescriptor, AccountLoginDescriptor, AccountVerifyDescriptor,
+        AccountVerifyVariant,
+    };
+
+    reset_all();
+
+    let app = crate::router();
+    let request = |uri: &str, body: Vec<u8>| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(body.into())
+                .unwrap(),
+        )
+    };
+    let email = |user: &str, domain: &str| lettre::Address::new(user, domain).unwrap();
+
+    let response = request(
+        "/api/account/create",
+        serde_json::to_vec(&AccountCreateDescriptor {
+            email: email("YuJiening2025", "i.pkuschool.edu.cn").to_string(),
+        })
+        .unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request(
+        "/api/account/verify",
+        serde_json::to_vec(&AccountVerifyDescriptor {
+            code: crate::account::verify::VERIFICATION_CODE
+                .load(std::sync::atomic::Ordering::Relaxed),
+            variant: AccountVerifyVariant::Activate {
+                email: email("yujiening2025", "I.PKUSCHOOL.EDU.CN"),
+                name: "Jiening Yu".to_string(),
+                id: 2522320,
+                phone: 16601550826,
+                house: None,
+                organization: None,
+                password: "password123456".to_string(),
+            },
+        })
+        .unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    let account_id = crate::account::email_id(&email("yujiening2025", "i.pkuschool.edu.cn"));
+    assert_eq!(result["account_id"], account_id);
+
+    for user in ["yujiening2025", "YUJIENING2025"] {
+        let response = request(
+            "/api/account/login",
+            serde_json::to_vec(&AccountLoginDescriptor {
+                email: email(user, "i.pkuschool.edu.cn"),
+                password: "password123456".to_string(),
+            })
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let result: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(result["account_id"], account_id);
+        assert_eq!(result["user"]["email"], "yujiening2025@i.pkuschool.edu.cn");
+    }
+}
+
+/// Test: requesting a password reset of an unverified account leaves it as is.
+#[serial]
+#[tokio::test]
+async fn reset_password_unverified() {
+    reset_all();
+
+    let email = lettre::Address::new("pending", "i.pkuschool.edu.cn").unwrap();
+    let context = crate::account::verify::Context::new(email.clone());
+    let (code, expire_time) = (context.code, context.expire_time);
+    crate::account::INSTANCE.push(crate::account::Account::Unverified(context));
+
+    let response = crate::router()
+        .oneshot(
+            Request::builder()
+                .uri("/api/account/reset-password")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(
+                    serde_json::to_vec(&sms3_shared::account::handle::ResetPasswordDescriptor {
+                        email: email.clone(),
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let id = crate::account::email_id(&email);
+    crate::account::INSTANCE
+        .with_account(id, |account| match account {
+            crate::account::Account::Unverified(context) => {
+                assert_eq!(context.code, code);
+                assert_eq!(context.expire_time, expire_time);
+            }
+            _ => panic!("account {id} was verified"),
+        })
+        .unwrap();
+    assert!(crate::account::verify::SENT_MAILS.lock().is_empty());
+}
+
+/// Test: verification mails to an address are limited per window across flows.
+#[serial]
+#[tokio::test]
+async fn verification_mail_limit() {
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let app = crate::router();
+    let email = lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap();
+    let request = |uri: &str| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(
+                    serde_json::to_vec(&serde_json::json!({ "email": email }))
+                        .unwrap()
+                        .into(),
+                )
+                .unwrap(),
+        )
+    };
+
+    // Registration and resends, past the resend cooldown each time.
+    for _ in 0..3 {
+        let response = request("/api/account/create").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        crate::clock::advance(chrono::Duration::minutes(2));
+    }
+    // Reset requests are counted whether or not the account is verified.
+    for _ in 0..2 {
+        let response = request("/api/account/reset-password").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = request("/api/account/create").await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = request("/api/account/reset-password").await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(crate::account::verify::SENT_MAILS.lock().len(), 3);
+
+    // The first mail leaves the window after an hour.
+    crate::clock::advance(chrono::Duration::minutes(55));
+    let response = request("/api/account/create").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(crate::account::verify::SENT_MAILS.lock().len(), 4);
+}
+
+/// Test: the mail limiter forgets addresses beyond its capacity.
+#[serial]
+#[test]
+fn mail_limiter_bounded() {
+    reset_all();
+
+    let limiter = crate::account::verify::MailLimiter::default();
+    let window = chrono::Duration::hours(1);
+    let email = |i: usize| lettre::Address::new(format!("user{i}"), "pkuschool.edu.cn").unwrap();
+
+    assert!(limiter.acquire_with(&email(0), 1, window).is_ok());
+    assert!(matches!(
+        limiter.acquire_with(&email(0), 1, window),
+        Err(crate::account::Error::RateLimited)
+    ));
+    for i in 1..=4096 {
+        assert!(limiter.acquire_with(&email(i), 1, window).is_ok());
+    }
+    // The least recently mailed address was forgotten.
+    assert!(limiter.acquire_with(&email(0), 1, window).is_ok());
+}
+
+/// Test: state of rate limiters stays within its capacity however many keys are seen,
+/// forgetting the least recently used ones.
+#[test]
+fn rate_limit_state_bounded() {
+    use crate::account::bounded::BoundedMap;
+    use crate::account::verify::{LoginLimiter, MailLimiter};
+
+    let mut map = BoundedMap::new(100);
+    for i in 0..10_000 {
+        *map.get_or_default(i) += 1;
+        // A key in use is kept.
+        *map.get_mut(&0).unwrap_or(&mut 0) += 1;
+    }
+    assert_eq!(map.len(), 100);
+    assert!(map.contains_key(&0));
+    assert!(!map.contains_key(&1));
+    assert!(!map.contains_key(&9900));
+    assert!(map.contains_key(&9901));
+    assert_eq!(map.remove(&9999), Some(1));
+    assert_eq!(map.len(), 99);
+
+    let limiter = MailLimiter::new(8);
+    let window = chrono::Duration::hours(1);
+    for i in 0..1000 {
+        let email = lettre::Address::new(format!("user{i}"), "pkuschool.edu.cn").unwrap();
+        assert!(limiter.acquire_with(&email, 1, window).is_ok());
+    }
+    assert_eq!(limiter.len(), 8);
+
+    let limiter = LoginLimiter::new(8);
+    for id in 0..1000 {
+        limiter.fail_with(id, 5, chrono::Duration::minutes(15));
+    }
+    assert_eq!(limiter.len(), 8);
+}
+
+/// Test: password changes too soon after the last one are rejected,
+/// unless forced by an admin.
+#[serial]
+#[test]
+fn password_change_interval() {
+    use crate::account::Error;
+    use chrono::Duration;
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let mut account = fixtures::verified_account_with_id(123456, "yujiening2025", &[]);
+    let interval = Duration::hours(1);
+
+    let crate::account::Account::Verified { attributes, .. } = &mut account else {
+        unreachable!()
+    };
+    // Never changed before.
+    attributes
+        .change_password_with("password1", interval)
+        .unwrap();
+    assert_eq!(attributes.password_changed_at, Some(crate::clock::now()));
+
+    crate::clock::advance(Duration::minutes(59));
+    assert!(matches!(
+        attributes.change_password_with("password2", interval),
+        Err(Error::PasswordChangeTooSoon)
+    ));
+    assert!(crate::account::verify_password(
+        &attributes.password_sha,
+        "password1"
+    ));
+
+    crate::clock::advance(Duration::minutes(1));
+    attributes
+        .change_password_with("password2", interval)
+        .unwrap();
+
+    // Admins bypass the interval.
+    crate::clock::advance(Duration::minutes(1));
+    account.set_password("password3").unwrap();
+    let crate::account::Account::Verified { attributes, .. } = &mut account else {
+        unreachable!()
+    };
+    assert!(crate::account::verify_password(
+        &attributes.password_sha,
+        "password3"
+    ));
+    assert!(matches!(
+        attributes.change_password_with("password4", interval),
+        Err(Error::PasswordChangeTooSoon)
+    ));
+
+    // Disabled by default.
+    attributes.change_password("password4").unwrap();
+}
+
+/// Test: error conversions keep the underlying variant and status code.
+#[test]
+fn error_conversions() {
+    use crate::account::{Error, ManagerError};
+    use axum::response::{ErrorResponse, IntoResponse};
+
+    let err = ManagerError::from((42, Error::Conflict));
+    assert!(matches!(err, ManagerError::Account(42, Error::Conflict)));
+
+    let status = |err: ErrorResponse| Err::<(), _>(err).into_response().status();
+    assert_eq!(status(err.into()), StatusCode::CONFLICT);
+    assert_eq!(
+        status(ManagerError::NotFound(42).into()),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        status(Error::TokenIncorrect.into()),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        status(ManagerError::from((42, Error::RateLimited)).into()),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+/// Test: concurrent logins never grow tokens beyond the session cap,
+/// and tokens never grow beyond the hard ceiling.
+#[serial]
+#[test]
+fn session_cap() {
+    use crate::account::{verify::Device, verify::Tokens, Account, Error};
+
+    reset_all();
+
+    let account_id = 123456;
+    crate::account::INSTANCE.push(fixtures::with_attributes(
+        fixtures::verified_account_with_id(account_id, "yujiening2025", &[]),
+        |attributes| {
+            attributes.mute_login_notification = true;
+        },
+    ));
+
+    let max_sessions = crate::config::INSTANCE.account.max_sessions;
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                for _ in 0..4 {
+                    let count = crate::account::INSTANCE
+                        .with_account_mut(account_id, |account| {
+                            account.login("password123456", Device::default()).unwrap();
+                            match account {
+                                Account::Verified { tokens, .. } => tokens.sessions().count(),
+                                Account::Unverified(_) => unreachable!(),
+                            }
+                        })
+                        .unwrap();
+                    assert!(count <= max_sessions);
+                }
+            });
+        }
+    });
+
+    let last = crate::account::INSTANCE
+        .with_account_mut(account_id, |account| {
+            account.login("password123456", Device::default()).unwrap()
+        })
+        .unwrap();
+    assert!(crate::RequirePermissionContext {
+        token: last,
+        account_id,
+    }
+    .try_valid(&[])
+    .unwrap());
+
+    // Without a session cap, logins fail at the ceiling instead of growing.
+    let mut tokens = Tokens::new();
+    for _ in 0..Tokens::MAX_TOKENS {
+        tokens.new_token_with(0, Device::default(), 0).unwrap();
+    }
+    assert!(matches!(
+        tokens.new_token_with(0, Device::default(), 0),
+        Err(Error::TooManySessions)
+    ));
+    assert_eq!(tokens.sessions().count(), Tokens::MAX_TOKENS);
+}
+
+/// Test: password resets can be sent to a verified alternate email only.
+#[serial]
+#[tokio::test]
+async fn alternate_email() {
+    use sms3_shared::account::handle::{
+        AccountEditDescriptor, AccountEditVariant, AccountVerifyDescriptor, AccountVerifyVariant,
+        ResetPasswordDescriptor,
+    };
+
+    reset_all();
+
+    let app = crate::router();
+    let account_id = 123456;
+
+    let mut account = fixtures::verified_account_with_id(account_id, "yujiening2025", &[]);
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    let request = |uri: &str, body: Vec<u8>| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", &token)
+                .header("AccountId", account_id)
+                .body(body.into())
+                .unwrap(),
+        )
+    };
+    let code =
+        || crate::account::verify::VERIFICATION_CODE.load(std::sync::atomic::Ordering::Relaxed);
+    let alternate = lettre::Address::new("jiening", "example.com").unwrap();
+    let reset = || {
+        request(
+            "/api/account/reset-password",
+            serde_json::to_vec(&ResetPasswordDescriptor {
+                email: alternate.clone(),
+            })
+            .unwrap(),
+        )
+    };
+    let sent_to_alternate = || {
+        crate::account::verify::SENT_MAILS
+            .lock()
+            .iter()
+            .filter(|mail| mail.to == alternate)
+            .count()
+    };
+
+    let response = request(
+        "/api/account/edit",
+        serde_json::to_vec(&AccountEditDescriptor {
+            variants: vec![AccountEditVariant::AlternateEmail(Some(alternate.clone()))],
+        })
+        .unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(sent_to_alternate(), 1);
+    let alternate_code = code();
+
+    // Not used for recovery before being verified.
+    assert_eq!(reset().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(sent_to_alternate(), 1);
+    crate::account::INSTANCE
+        .with_account(account_id, |account| {
+            assert!(matches!(
+                account,
+                crate::account::Account::Verified {
+                    verify: crate::account::UserVerifyVariant::None,
+                    ..
+                }
+            ))
+        })
+        .unwrap();
+
+    let verify = |code: u32, variant: AccountVerifyVariant| {
+        request(
+            "/api/account/verify",
+            serde_json::to_vec(&AccountVerifyDescriptor { code, variant }).unwrap(),
+        )
+    };
+    let response = verify(
+        alternate_code,
+        AccountVerifyVariant::AlternateEmail {
+            email: alternate.clone(),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_eq!(reset().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(sent_to_alternate(), 2);
+
+    let response = verify(
+        code(),
+        AccountVerifyVariant::ResetPassword {
+            email: alternate.clone(),
+            password: "password1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    crate::account::INSTANCE
+        .with_account(account_id, |account| match account {
+            crate::account::Account::Verified { attributes, .. } => {
+                assert!(crate::account::verify_password(
+                    &attributes.password_sha,
+                    "password1"
+                ));
+                // The primary email stays the identity.
+                assert!(account.has_email(
+                    &lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap()
+                ));
+            }
+            crate::account::Account::Unverified(_) => unreachable!(),
+        })
+        .unwrap();
+}
+
+/// Test: logging in upgrades hashes weaker than the configured parameters.
+#[serial]
+#[test]
+fn password_hash_upgrade() {
+    use crate::account::{hash_password_with, needs_rehash, verify_password, Account};
+    use crate::config::PasswordHash;
+
+    reset_all();
+
+    let target = crate::config::INSTANCE.account.password_hash;
+    let weak = PasswordHash {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    for (id, hash) in [
+        (1, hash_password_with("password123456", &weak)),
+        (2, digest("password123456")),
+    ] {
+        assert!(needs_rehash(&hash, &target));
+        crate::account::INSTANCE.push(fixtures::with_attributes(
+            fixtures::verified_account_with_id(id, &format!("user{id}"), &[]),
+            |attributes| {
+                attributes.password_sha = hash;
+                attributes.mute_login_notification = true;
+            },
+        ));
+
+        let hash = || {
+            crate::account::INSTANCE
+                .with_account(id, |account| match account {
+                    Account::Verified { attributes, .. } => attributes.password_sha.clone(),
+                    Account::Unverified(_) => unreachable!(),
+                })
+                .unwrap()
+        };
+
+        // A failed login keeps the hash.
+        let before = hash();
+        crate::account::INSTANCE
+            .with_account_mut(id, |account| {
+                assert!(account.login("wrong", Default::default()).is_err())
+            })
+            .unwrap();
+        assert_eq!(hash(), before);
+
+        crate::account::INSTANCE
+            .with_account_mut(id, |account| {
+                account.login("password123456", Default::default()).unwrap()
+            })
+            .unwrap();
+        let upgraded = hash();
+        assert!(upgraded.starts_with("$argon2id$"));
+        assert!(!needs_rehash(&upgraded, &target));
+        assert!(verify_password(&upgraded, "password123456"));
+
+        // Hashes meeting the target are kept as is.
+        crate::account::INSTANCE
+            .with_account_mut(id, |account| {
+                account.login("password123456", Default::default()).unwrap()
+            })
+            .unwrap();
+        assert_eq!(hash(), upgraded);
+    }
+}
+
+/// Test: request bodies beyond the configured limits are rejected with `413`.
+#[serial]
+#[tokio::test]
+async fn body_limit() {
+    use sms3_shared::account::Permission;
+
+    reset_all();
+
+    let app = crate::router();
+    let limits = &crate::config::INSTANCE.limits;
+    let account_id = 123456;
+
+    let mut account = fixtures::verified_account_with_id(
+        account_id,
+        "admin",
+        &[Permission::ManageAccounts, Permission::View],
+    );
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    // Valid JSON padded with whitespace to `len` bytes.
+    let padded = |value: serde_json::Value, len: usize| {
+        let mut body = serde_json::to_vec(&value).unwrap();
+        body.resize(len, b' ');
+        body
+    };
+    let request = |uri: &str, body: Vec<u8>| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", &token)
+                .header("AccountId", account_id)
+                .body(body.into())
+                .unwrap(),
+        )
+    };
+
+    let create = serde_json::json!({ "email": "yujiening2025@i.pkuschool.edu.cn" });
+    let response = request("/api/account/create", padded(create.clone(), limits.body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request("/api/account/create", padded(create, limits.body + 1))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Bulk endpoints accept more.
+    let grant = serde_json::json!({ "accounts": [], "permission": "View" });
+    let response = request(
+        "/api/account/manage/grant",
+        padded(grant.clone(), limits.body * 2),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request(
+        "/api/account/manage/grant",
+        padded(grant, limits.bulk_body + 1),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+/// Test: malformed emails are told apart from emails outside school domains.
+#[serial]
+#[tokio::test]
+async fn email_format() {
+    use sms3_shared::account::handle::AccountCreateDescriptor;
+
+    reset_all();
+
+    let app = crate::router();
+    let create = |email: &str| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri("/api/account/create")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(
+                    serde_json::to_vec(&AccountCreateDescriptor {
+                        email: email.to_string(),
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+    };
+
+    for (email, status, error) in [
+        (
+            "not an email",
+            StatusCode::BAD_REQUEST,
+            Some(crate::account::Error::InvalidEmailFormat),
+        ),
+        (
+            "yujiening2025@example.com",
+            StatusCode::FORBIDDEN,
+            Some(crate::account::Error::EmailDomainNotInSchool),
+        ),
+        ("yujiening2025@i.pkuschool.edu.cn", StatusCode::OK, None),
+    ] {
+        let response = create(email).await.unwrap();
+        assert_eq!(response.status(), status, "{email}");
+        if let Some(error) = error {
+            let body: serde_json::Value =
+                serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                    .unwrap();
+            assert_eq!(body["error"], error.to_string());
+        }
+    }
+}
+
+/// Test: checking whether emails are available, limited per IP address.
+#[serial]
+#[tokio::test]
+async fn email_availability() {
+    use sms3_shared::account::handle::EmailAvailabilityResult;
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    crate::account::INSTANCE.push(fixtures::verified_account("yujiening2025", &[]));
+    // A pending registration doesn't take the address.
+    crate::account::INSTANCE
+        .register(fixtures::email("pending"))
+        .unwrap();
+
+    let check = |email: &str, ip: &str| {
+        crate::router().oneshot(
+            Request::builder()
+                .uri(format!("/api/account/available?email={email}"))
+                .method("GET")
+                .extension(axum::extract::ConnectInfo(std::net::SocketAddr::new(
+                    ip.parse().unwrap(),
+                    0,
+                )))
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+    };
+    let available = |response: axum::response::Response| async move {
+        assert_eq!(response.status(), StatusCode::OK);
+        serde_json::from_slice::<EmailAvailabilityResult>(
+            &hyper::body::to_bytes(response.into_body()).await.unwrap(),
+        )
+        .unwrap()
+        .available
+    };
+
+    let ip = "10.0.0.1";
+    assert!(available(check("newcomer@i.pkuschool.edu.cn", ip).await.unwrap()).await);
+    assert!(available(check("pending@i.pkuschool.edu.cn", ip).await.unwrap()).await);
+    assert!(!available(check("yujiening2025@i.pkuschool.edu.cn", ip).await.unwrap()).await);
+    assert!(!available(check("YuJiening2025@i.pkuschool.edu.cn", ip).await.unwrap()).await);
+    assert_eq!(
+        check("yujiening2025@example.com", ip)
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        check("not-an-email", ip).await.unwrap().status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    let limit = crate::config::INSTANCE.account.availability_check_limit;
+    for _ in 6..limit {
+        check("newcomer@i.pkuschool.edu.cn", ip).await.unwrap();
+    }
+    assert_eq!(
+        check("newcomer@i.pkuschool.edu.cn", ip)
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    // Other addresses have their own count, which restarts after the window.
+    assert!(
+        available(
+            check("newcomer@i.pkuschool.edu.cn", "10.0.0.2")
+                .await
+                .unwrap()
+        )
+        .await
+    );
+    crate::clock::advance(chrono::Duration::minutes(
+        crate::config::INSTANCE.account.availability_check_window as i64,
+    ));
+    assert!(available(check("newcomer@i.pkuschool.edu.cn", ip).await.unwrap()).await);
+}
+
+/// Test: expiry and stored times don't depend on the timezone of the host,
+/// and naive times stored by earlier versions are read as UTC.
+#[serial]
+#[test]
+fn timezone_independent_expiry() {
+    use crate::account::verify::Context;
+    use chrono::TimeZone;
+
+    reset_all();
+    let start = chrono::Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+    let previous = std::env::var("TZ").ok();
+    for tz in [
+        "UTC",
+        "Asia/Shanghai",
+        "America/New_York",
+        "Pacific/Kiritimati",
+    ] {
+        std::env::set_var("TZ", tz);
+        crate::clock::set(start);
+
+        let context =
+            Context::new(lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap());
+        assert_eq!(
+            context.expire_time,
+            start + chrono::Duration::minutes(15),
+            "{tz}"
+        );
+        crate::clock::advance(chrono::Duration::minutes(14));
+        assert!(!context.is_expired(), "{tz}");
+        crate::clock::advance(chrono::Duration::minutes(1));
+        assert!(context.is_expired(), "{tz}");
+
+        for stored in ["2024-01-01T00:15:00", "2024-01-01T00:15:00Z"] {
+            let context: Context = toml::from_str(&format!(
+                "email = \"yujiening2025@i.pkuschool.edu.cn\"\ncode = 0\nexpire_time = \"{stored}\""
+            ))
+            .unwrap();
+            assert_eq!(
+                context.expire_time,
+                start + chrono::Duration::minutes(15),
+                "{tz}"
+            );
+        }
+    }
+    match previous {
+        Some(tz) => std::env::set_var("TZ", tz),
+        None => std::env::remove_var("TZ"),
+    }
+}
+
+/// Test: transient SMTP failures are retried while permanent ones aren't
+/// and map to a client error.
+#[tokio::test]
+async fn mail_send_failures() {
+    use crate::account::verify::{is_permanent, send_retrying, Mail};
+    use lettre::{AsyncSmtpTransport, Tokio1Executor};
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    /// Serve SMTP replying `rcpt_reply` to recipients, counting them.
+    async fn serve(rcpt_reply: &'static str) -> (u16, Arc<AtomicU32>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let recipients = Arc::new(AtomicU32::new(0));
+        let counter = recipients.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let counter = counter.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    write.write_all(b"220 localhost\r\n").await.unwrap();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let reply = match &line.to_ascii_uppercase()[..4] {
+                            "RCPT" => {
+                                counter.fetch_add(1, Ordering::Relaxed);
+                                rcpt_reply
+                            }
+                            "DATA" => "354 go ahead",
+                            "QUIT" => "221 bye",
+                            _ => "250 ok",
+                        };
+                        if write
+                            .write_all(format!("{reply}\r\n").as_bytes())
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        (port, recipients)
+    }
+
+    let message = Mail {
+        to: fixtures::email("user"),
+        subject: "Subject".to_string(),
+        body: "Body".to_string(),
+    }
+    .message(&crate::config::MailSmtp {
+        server: "localhost".to_string(),
+        port: 25,
+        username: String::new(),
+        password: String::new(),
+        address: lettre::Address::new("noreply", "example.com").unwrap(),
+        name: String::new(),
+        reply_to: None,
+    });
+    let send = |port: u16| {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+            .port(port)
+            .build();
+        let message = message.clone();
+        async move { send_retrying(&transport, &message, 3, std::time::Duration::from_millis(1)).await }
+    };
+
+    let (port, recipients) = serve("550 no such user").await;
+    let err = send(port).await.unwrap_err();
+    assert!(is_permanent(&err));
+    assert_eq!(recipients.load(Ordering::Relaxed), 1);
+
+    let (port, recipients) = serve("450 mailbox busy").await;
+    let err = send(port).await.unwrap_err();
+    assert!(!is_permanent(&err));
+    assert_eq!(recipients.load(Ordering::Relaxed), 3);
+
+    let (port, recipients) = serve("250 ok").await;
+    send(port).await.unwrap();
+    assert_eq!(recipients.load(Ordering::Relaxed), 1);
+
+    // Connection errors are transient too.
+    let port = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    assert!(!is_permanent(&send(port).await.unwrap_err()));
+}
+
+/// Test: mails are sent from the configured name and address, with the configured reply-to.
+#[test]
+fn mail_sender() {
+    use crate::account::verify::Mail;
+    use lettre::Transport;
+
+    let mut config = crate::config::MailSmtp {
+        address: fixtures::email("noreply"),
+        name: "SubIT Help".to_string(),
+        reply_to: Some(fixtures::email("help")),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let mail = Mail {
+        to: fixtures::email("yujiening2025"),
+        subject: "Verification code".to_string(),
+        body: "Your code is 012345".to_string(),
+    };
+
+    let transport = lettre::transport::stub::StubTransport::new_ok();
+    transport.send(&mail.message(&config)).unwrap();
+    config.name = String::new();
+    config.reply_to = None;
+    transport.send(&mail.message(&config)).unwrap();
+
+    let messages = transport.messages();
+    let headers = |i: usize| {
+        let (envelope, message) = &messages[i];
+        assert_eq!(envelope.from(), Some(&fixtures::email("noreply")));
+        message
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    let configured = headers(0);
+    assert!(configured.contains(&"From: \"SubIT Help\" <noreply@i.pkuschool.edu.cn>".to_string()));
+    assert!(configured.contains(&"Reply-To: help@i.pkuschool.edu.cn".to_string()));
+    let plain = headers(1);
+    assert!(plain.contains(&"From: noreply@i.pkuschool.edu.cn".to_string()));
+    assert!(!plain.iter().any(|line| line.starts_with("Reply-To:")));
+
+    config.name = "SubIT\r\nBcc: someone@example.com".to_string();
+    assert!(config.validate().is_err());
+}
+
+/// Test: self-registration follows the registration window and its schedule,
+/// while admins can create accounts regardless.
+#[serial]
+#[tokio::test]
+async fn registration_window() {
+    use crate::config::{RegistrationWindow, REGISTRATION_WINDOW};
+    use sms3_shared::account::handle::manage::MakeAccountDescriptor;
+    use sms3_shared::account::handle::AccountCreateDescriptor;
+    use sms3_shared::account::Permission;
+
+    reset_all();
+    let now = chrono::Utc::now();
+    crate::clock::set(now);
+
+    let window: RegistrationWindow = toml::from_str("opens_at = \"2025-09-01T00:00:00Z\"").unwrap();
+    assert!(window.open);
+    assert!(!window.is_open_at("2025-08-31T23:59:59Z".parse().unwrap()));
+    assert!(window.is_open_at("2025-09-01T00:00:00Z".parse().unwrap()));
+
+    let mut admin = fixtures::verified_account("admin", &[Permission::ManageAccounts]);
+    let admin_token = fixtures::new_token(&mut admin);
+    let admin_id = admin.id();
+    crate::account::INSTANCE.push(admin);
+
+    let app = crate::router();
+    let create = |user: &str| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri("/api/account/create")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(
+                    serde_json::to_vec(&AccountCreateDescriptor {
+                        email: fixtures::email(user).to_string(),
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+    };
+    let closed = |response: axum::response::Response| async move {
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(
+            body["error"],
+            crate::account::Error::RegistrationClosed.to_string()
+        );
+    };
+
+    assert_eq!(create("open").await.unwrap().status(), StatusCode::OK);
+
+    *REGISTRATION_WINDOW.lock() = Some(RegistrationWindow {
+        open: false,
+        ..Default::default()
+    });
+    closed(create("closed").await.unwrap()).await;
+    assert!(crate::account::INSTANCE
+        .find_by_email(&fixtures::email("closed"))
+        .is_none());
+
+    // Admins create accounts even while registration is closed.
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/account/manage/create")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", &admin_token)
+                .header("AccountId", admin_id)
+                .body(
+                    serde_json::to_vec(&MakeAccountDescriptor {
+                        email: fixtures::email("provisioned"),
+                        name: "Yuguo Ma".to_string(),
+                        school_id: 114514,
+                        phone: 13800138000,
+                        house: None,
+                        organization: None,
+                        password: "password123456".to_string(),
+                        permissions: vec![],
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(crate::account::INSTANCE
+        .find_by_email(&fixtures::email("provisioned"))
+        .is_some());
+
+    // A scheduled window opens and closes with the clock.
+    *REGISTRATION_WINDOW.lock() = Some(RegistrationWindow {
+        open: true,
+        opens_at: Some(now + chrono::Duration::hours(1)),
+        closes_at: Some(now + chrono::Duration::hours(2)),
+    });
+    closed(create("early").await.unwrap()).await;
+    crate::clock::advance(chrono::Duration::hours(1));
+    assert_eq!(create("ontime").await.unwrap().status(), StatusCode::OK);
+    crate::clock::advance(chrono::Duration::hours(1));
+    closed(create("late").await.unwrap()).await;
+}
+
+/// Test: a pending alternate email is shown masked with its expiry,
+/// until it's verified or expires.
+#[serial]
+#[tokio::test]
+async fn pending_email() {
+    use sms3_shared::account::handle::{
+        AccountEditDescriptor, AccountEditVariant, AccountVerifyDescriptor, AccountVerifyVariant,
+        PendingEmail, ViewAccountResult,
+    };
+
+    let mut client = harness::TestClient::new();
+    let mut account = fixtures::verified_account("user", &[]);
+    let token = fixtures::new_token(&mut account);
+    let id = account.id();
+    crate::account::INSTANCE.push(account);
+    client.authorize(id, token);
+
+    let alternate = lettre::Address::new("Backup", "example.com").unwrap();
+    let set_alternate = AccountEditDescriptor {
+        variants: vec![AccountEditVariant::AlternateEmail(Some(alternate.clone()))],
+    };
+    let set_alternate = || client.post("/api/account/edit", &set_alternate);
+    let pending = || async {
+        let response = client.post("/api/account/view", &()).await;
+        assert_eq!(response.status, StatusCode::OK);
+        response.json::<ViewAccountResult>().pending_email
+    };
+
+    assert_eq!(pending().await, None);
+    assert_eq!(set_alternate().await.status, StatusCode::OK);
+    assert_eq!(
+        pending().await,
+        Some(PendingEmail {
+            email: "b***@example.com".to_string(),
+            expire_time: crate::clock::now() + chrono::Duration::minutes(15),
+        })
+    );
+    // The account keeps operating under its current email.
+    let response = client.post("/api/account/view", &()).await;
+    assert_eq!(
+        response.json::<ViewAccountResult>().metadata.email,
+        fixtures::email("user")
+    );
+
+    let response = client
+        .post(
+            "/api/account/verify",
+            &AccountVerifyDescriptor {
+                code: client.verification_code(),
+                variant: AccountVerifyVariant::AlternateEmail {
+                    email: alternate.clone(),
+                },
+            },
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(pending().await, None);
+
+    assert_eq!(set_alternate().await.status, StatusCode::OK);
+    assert!(pending().await.is_some());
+    client.advance(chrono::Duration::minutes(15));
+    assert_eq!(pending().await, None);
+}
+
+/// Test: the verification status tells a pending email's remaining validity,
+/// and nothing about expired, verified or unknown emails.
+#[serial]
+#[tokio::test]
+async fn verification_status() {
+    use sms3_shared::account::handle::{AccountCreateDescriptor, VerificationStatusResult};
+
+    let client = harness::TestClient::new();
+    crate::account::INSTANCE.push(fixtures::verified_account("verified", &[]));
+    let status = |user: &str| {
+        let uri = format!("/api/account/verify-status?email={}", fixtures::email(user));
+        let client = &client;
+        async move {
+            let response = client.get(&uri).await;
+            assert_eq!(response.status, StatusCode::OK);
+            response.json::<VerificationStatusResult>()
+        }
+    };
+    let neutral = VerificationStatusResult {
+        pending: false,
+        expires_in: None,
+        resend_in: None,
+    };
+
+    let response = client
+        .post(
+            "/api/account/create",
+            &AccountCreateDescriptor {
+                email: fixtures::email("pending").to_string(),
+            },
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        status("pending").await,
+        VerificationStatusResult {
+            pending: true,
+            expires_in: Some(15 * 60),
+            resend_in: Some(60),
+        }
+    );
+
+    client.advance(chrono::Duration::minutes(5));
+    assert_eq!(
+        status("PENDING").await,
+        VerificationStatusResult {
+            pending: true,
+            expires_in: Some(10 * 60),
+            resend_in: Some(0),
+        }
+    );
+
+    assert_eq!(status("verified").await, neutral);
+    assert_eq!(status("nobody").await, neutral);
+
+    client.advance(chrono::Duration::minutes(10));
+    assert_eq!(status("pending").await, neutral);
+
+    let response = client
+        .get("/api/account/verify-status?email=not-an-email")
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+/// Test: signing in with a mock OpenID Connect provider logs in existing accounts,
+/// provisions new ones in the school domain if enabled and rejects others.
+#[serial]
+#[tokio::test]
+async fn oidc_sign_in() {
+    use axum::{extract::Form, routing::post};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+    use sms3_shared::account::handle::{AccountLoginResult, OidcCallbackDescriptor};
+    use std::{collections::HashMap, sync::Arc};
+
+    let client = harness::TestClient::new();
+    assert_eq!(
+        client.get("/api/account/oidc/login").await.status,
+        StatusCode::NOT_FOUND
+    );
+
+    // The provider issues an ID token with the claims set below for code `code`.
+    let claims = Arc::new(parking_lot::Mutex::new(serde_json::Value::Null));
+    let issued = claims.clone();
+    let provider = axum::Router::new().route(
+        "/token",
+        post(
+            move |Form(form): Form<HashMap<String, String>>| async move {
+                assert_eq!(form["grant_type"], "authorization_code");
+                assert_eq!(form["client_id"], "sms3");
+                assert_eq!(form["client_secret"], "secret");
+                if form["code"] != "code" {
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                let payload = BASE64.encode(serde_json::to_vec(&*issued.lock()).unwrap());
+                Ok(axum::Json(serde_json::json!({
+                    "access_token": "access",
+                    "token_type": "Bearer",
+                    "id_token": format!("eyJhbGciOiJub25lIn0.{payload}."),
+                })))
+            },
+        ),
+    );
+    let server =
+        axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(provider.into_make_service());
+    let token_endpoint = format!("http://{}/token", server.local_addr());
+    tokio::spawn(server);
+    let configure = |provision: bool| {
+        *crate::config::OIDC.lock() = Some(crate::config::Oidc {
+            issuer: "https://sso.example.com".to_string(),
+            authorization_endpoint: "https://sso.example.com/authorize".to_string(),
+            token_endpoint: token_endpoint.clone(),
+            client_id: "sms3".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_uri: "https://sms.example.com/sso".to_string(),
+            scopes: "openid email profile".to_string(),
+            provision,
+        })
+    };
+    configure(false);
+
+    // Start signing in, then call back with the claims patched by `patch`.
+    let sign_in = |email: String, patch: serde_json::Value| {
+        let client = &client;
+        let claims = &claims;
+        async move {
+            let response = client.get("/api/account/oidc/login").await;
+            assert_eq!(response.status, StatusCode::SEE_OTHER);
+            let location =
+                reqwest::Url::parse(response.headers[http::header::LOCATION].to_str().unwrap())
+                    .unwrap();
+            assert_eq!(location.path(), "/authorize");
+            let params: HashMap<String, String> = location.query_pairs().into_owned().collect();
+            assert_eq!(params["response_type"], "code");
+            assert_eq!(params["redirect_uri"], "https://sms.example.com/sso");
+
+            let mut issued = serde_json::json!({
+                "iss": "https://sso.example.com",
+                "aud": "sms3",
+                "exp": crate::clock::now().timestamp() + 300,
+                "nonce": params["nonce"],
+                "email": email,
+                "email_verified": true,
+                "name": "Jiening Yu",
+            });
+            for (key, value) in patch.as_object().unwrap() {
+                issued[key] = value.clone();
+            }
+            *claims.lock() = issued;
+
+            let descriptor = OidcCallbackDescriptor {
+                code: "code".to_string(),
+                state: params["state"].clone(),
+            };
+            let response = client.post("/api/account/oidc/callback", &descriptor).await;
+            (response, descriptor)
+        }
+    };
+    let no_patch = || serde_json::json!({});
+
+    // An existing account is logged in, and the state can't be used again.
+    let existing = fixtures::verified_account("existing", &[]);
+    let existing_id = existing.id();
+    crate::account::INSTANCE.push(existing);
+    let (response, descriptor) = sign_in(fixtures::email("Existing").to_string(), no_patch()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let login: AccountLoginResult = response.json();
+    assert_eq!(login.account_id, existing_id);
+    assert_eq!(
+        crate::account::INSTANCE.find_by_token(&login.access_token),
+        Some(existing_id)
+    );
+    let response = client.post("/api/account/oidc/callback", &descriptor).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    // Invalid ID tokens are rejected.
+    for patch in [
+        serde_json::json!({ "email_verified": false }),
+        serde_json::json!({ "nonce": "forged" }),
+        serde_json::json!({ "aud": ["other"] }),
+        serde_json::json!({ "iss": "https://evil.example.com" }),
+        serde_json::json!({ "exp": crate::clock::now().timestamp() }),
+    ] {
+        let (response, _) = sign_in(fixtures::email("existing").to_string(), patch).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+
+    // New school emails are only provisioned if enabled.
+    let pending = fixtures::pending_account("new");
+    crate::account::INSTANCE.push(pending);
+    let (response, _) = sign_in(fixtures::email("new").to_string(), no_patch()).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    configure(true);
+    let (response, _) = sign_in(fixtures::email("new").to_string(), no_patch()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let login: AccountLoginResult = response.json();
+    assert_eq!(login.user.email, fixtures::email("new"));
+    assert_eq!(login.user.name, "Jiening Yu");
+    assert_eq!(
+        crate::account::INSTANCE.find_by_email(&fixtures::email("new")),
+        Some(login.account_id)
+    );
+    assert_eq!(crate::account::INSTANCE.snapshot_ids().len(), 2);
+
+    // Other domains are rejected without creating accounts.
+    let (response, _) = sign_in("user@example.com".to_string(), no_patch()).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(crate::account::INSTANCE.snapshot_ids().len(), 2);
+}
+
+/// Test: activation requires the configured optional fields, reporting each missing one.
+#[test]
+fn required_fields() {
+    use crate::account::validate_activation;
+    use sms3_shared::account::House;
+
+    let config: crate::config::Account =
+        toml::from_str("required_fields = [\"house\", \"organization\"]").unwrap();
+    let errors = validate_activation(
+        &config,
+        "Jiening Yu",
+        16601550826,
+        None,
+        None,
+        fixtures::PASSWORD,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        errors
+            .fields
+            .iter()
+            .map(|e| (e.field.as_str(), e.error.as_str()))
+            .collect::<Vec<_>>(),
+        [
+            ("house", "house is required"),
+            ("organization", "organization is required")
+        ]
+    );
+
+    let config: crate::config::Account = toml::from_str("required_fields = [\"house\"]").unwrap();
+    let errors = validate_activation(&config, "", 16601550826, None, None, fixtures::PASSWORD)
+        .err()
+        .unwrap();
+    assert_eq!(
+        errors
+            .fields
+            .iter()
+            .map(|e| e.field.as_str())
+            .collect::<Vec<_>>(),
+        ["name", "house"]
+    );
+    let activation = validate_activation(
+        &config,
+        "Jiening Yu",
+        16601550826,
+        Some(House::from("ZhiZhi")),
+        None,
+        fixtures::PASSWORD,
+    )
+    .unwrap();
+    assert_eq!(activation.house, Some(House::from("ZhiZhi")));
+    assert_eq!(activation.organization, None);
+
+    // Nothing optional is required by default.
+    assert!(validate_activation(
+        &Default::default(),
+        "Jiening Yu",
+        16601550826,
+        None,
+        None,
+        fixtures::PASSWORD
+    )
+    .is_ok());
+}
+
+/// Test: logs mask the user part of emails and keep only the prefix of tokens.
+#[test]
+fn redaction() {
+    use crate::account::redact::{mask_email, Email, Token};
+
+    let email = lettre::Address::new("fengyu", "pkuschool.edu.cn").unwrap();
+    assert_eq!(mask_email(&email), "f***@pkuschool.edu.cn");
+    assert_eq!(Email(&email).to_string(), "f***@pkuschool.edu.cn");
+    let email = lettre::Address::new("俞洁宁", "i.pkuschool.edu.cn").unwrap();
+    assert_eq!(mask_email(&email), "俞***@i.pkuschool.edu.cn");
+
+    let token = crate::account::verify::generate_token(32);
+    assert_eq!(Token(&token).to_string(), "sms3_<redacted>");
+    assert_eq!(Token("legacy-token").to_string(), "<redacted>");
+}
+
+/// Test: sending a verification code logs neither the code nor the full email.
+#[serial]
+#[test]
+fn send_verify_redacted() {
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    reset_all();
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+
+    let context = crate::account::verify::Context::new(fixtures::email("fengyu"));
+    tracing::subscriber::with_default(subscriber, || context.send_verify());
+
+    let logs = String::from_utf8(captured.0.lock().clone()).unwrap();
+    assert!(logs.contains("f***@"), "{logs}");
+    assert!(!logs.contains("fengyu"), "{logs}");
+    let code = crate::account::verify::VERIFICATION_CODE.load(std::sync::atomic::Ordering::Relaxed);
+    assert!(
+        !logs.contains(&crate::account::verify::format_code(code)),
+        "{logs}"
+    );
+}
+
+/// Test: codes are sent through the given delivery, and reset codes through the configured channel.
+#[serial]
+#[tokio::test]
+async fn code_delivery() {
+    use crate::account::verify::{CodeDelivery, Context, Recipient, SENT_SMS};
+    use crate::config::DeliveryChannel;
+    use sms3_shared::account::handle::ResetPasswordDescriptor;
+
+    #[derive(Default)]
+    struct Stub(parking_lot::Mutex<Vec<(Recipient, String)>>);
+
+    impl CodeDelivery for Stub {
+        fn channel(&self) -> DeliveryChannel {
+            DeliveryChannel::Sms
+        }
+
+        fn send(
+            &self,
+            to: &Recipient,
+            code: &str,
+            then: crate::account::verify::Delivered,
+        ) -> Result<(), crate::account::Error> {
+            self.0.lock().push((to.clone(), code.to_string()));
+            then(false);
+            Ok(())
+        }
+    }
+
+    let client = harness::TestClient::new();
+    let mut events = crate::account::event::subscribe();
+    let stub = Stub::default();
+    let context = Context::new(lettre::Address::new("user", "delivery.pkuschool.edu.cn").unwrap());
+    context
+        .send_verify_through(&stub, &Recipient::Phone(16601550826))
+        .unwrap();
+    assert_eq!(
+        *stub.0.lock(),
+        [(
+            Recipient::Phone(16601550826),
+            crate::account::verify::format_code(context.code)
+        )]
+    );
+    assert!(client.take_mails().is_empty());
+
+    // Channels reject recipients they can't reach.
+    assert!(context
+        .send_verify_through(
+            &crate::account::verify::EmailDelivery,
+            &Recipient::Phone(16601550826)
+        )
+        .is_err());
+    assert!(context
+        .send_verify_through(
+            &crate::account::verify::SmsDelivery,
+            &Recipient::Email(fixtures::email("user"))
+        )
+        .is_err());
+
+    // Every attempt is reported with its channel and outcome.
+    let sent = |channel| crate::account::event::Event::VerificationSent {
+        channel,
+        domain: "delivery.pkuschool.edu.cn".to_string(),
+        code_length: 6,
+        success: false,
+    };
+    let mut received = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if matches!(event, crate::account::event::Event::VerificationSent { ref domain, .. } if domain == "delivery.pkuschool.edu.cn")
+        {
+            received.push(event);
+        }
+    }
+    assert_eq!(
+        received,
+        [
+            sent(DeliveryChannel::Sms),
+            sent(DeliveryChannel::Email),
+            sent(DeliveryChannel::Sms),
+        ]
+    );
+
+    *crate::config::CODE_DELIVERY.lock() = Some(crate::config::CodeDelivery {
+        reset_password: DeliveryChannel::Sms,
+    });
+    crate::account::INSTANCE.push(fixtures::verified_account("user", &[]));
+    let response = client
+        .post(
+            "/api/account/reset-password",
+            &ResetPasswordDescriptor {
+                email: fixtures::email("user"),
+            },
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let code = crate::account::verify::format_code(client.verification_code());
+    assert_eq!(
+        *SENT_SMS.lock(),
+        [(
+            16601550826,
+            format!("Your SMS3 verification code is {code}")
+        )]
+    );
+    assert!(client.take_mails().is_empty());
+
+    // Accounts without a phone number are mailed instead.
+    let mut account = fixtures::verified_account("nophone", &[]);
+    if let crate::account::Account::Verified { attributes, .. } = &mut account {
+        attributes.phone = 0;
+    }
+    crate::account::INSTANCE.push(account);
+    SENT_SMS.lock().clear();
+    let response = client
+        .post(
+            "/api/account/reset-password",
+            &ResetPasswordDescriptor {
+                email: fixtures::email("nophone"),
+            },
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(SENT_SMS.lock().is_empty());
+    let mails = client.take_mails();
+    assert_eq!(mails.len(), 1);
+    assert_eq!(mails[0].to, fixtures::email("nophone"));
+    assert!(mails[0].body.contains(&crate::account::verify::format_code(
+        client.verification_code()
+    )));
+}
+
+/// Test: suspended accounts can neither log in nor use their tokens until unbanned.
+#[serial]
+#[tokio::test]
+async fn suspended() {
+    use sms3_shared::account::handle::AccountLoginDescriptor;
+
+    let mut client = harness::TestClient::new();
+    let mut account =
+        fixtures::with_attributes(fixtures::verified_account("user", &[]), |attributes| {
+            attributes.mute_login_notification = true;
+            attributes.suspended = true;
+        });
+    let token = fixtures::new_token(&mut account);
+    let id = account.id();
+    crate::account::INSTANCE.push(account);
+    client.authorize(id, token.clone());
+
+    let mut login = AccountLoginDescriptor {
+        email: fixtures::email("user"),
+        password: fixtures::PASSWORD.to_string(),
+    };
+    let response = client.post("/api/account/login", &login).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(response.error(), "account is suspended");
+    let response = client.post("/api/account/view", &()).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert!(response.error().ends_with("account is suspended"));
+    login.password = "wrongpassword".to_string();
+    assert_eq!(
+        client.post("/api/account/login", &login).await.error(),
+        "password incorrect"
+    );
+    login.password = fixtures::PASSWORD.to_string();
+
+    // A wrong token is still rejected as such, not revealing the suspension.
+    let cxt = crate::RequirePermissionContext {
+        token: "wrong".to_string(),
+        account_id: id,
+    };
+    assert!(matches!(
+        cxt.valid(&[]),
+        Err(crate::account::ManagerError::Account(
+            _,
+            crate::account::Error::TokenIncorrect
+        ))
+    ));
+
+    crate::account::INSTANCE.with_account_mut(id, |account| account.set_suspended(false).unwrap());
+    assert_eq!(
+        client.post("/api/account/view", &()).await.status,
+        StatusCode::OK
+    );
+    assert_eq!(
+        client.post("/api/account/login", &login).await.status,
+        StatusCode::OK
+    );
+}
+
+/// Test: `X-Forwarded-For` is only honoured from trusted proxies, walked from
+/// its last entry.
+#[serial]
+#[test]
+fn client_ip() {
+    use crate::account::handle::device;
+
+    reset_all();
+
+    let headers = |forwarded: &str| {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("X-Forwarded-For", forwarded.parse().unwrap());
+        headers
+    };
+    let ip = |forwarded: &str, peer: &str| {
+        device(
+            &headers(forwarded),
+            Some(std::net::SocketAddr::new(peer.parse().unwrap(), 0)),
+        )
+        .ip
+        .map(|ip| ip.to_string())
+    };
+
+    // Headers from other peers are ignored, as anyone can send them.
+    assert_eq!(ip("1.1.1.1", "10.0.0.1").as_deref(), Some("10.0.0.1"));
+    assert_eq!(device(&headers("1.1.1.1"), None).ip, None);
+
+    *crate::config::TRUSTED_PROXIES.lock() = Some(vec![
+        "10.0.0.1".parse().unwrap(),
+        "10.0.0.2".parse().unwrap(),
+    ]);
+    assert_eq!(ip("1.1.1.1", "10.0.0.1").as_deref(), Some("1.1.1.1"));
+    // Entries before the first untrusted one may be forged.
+    assert_eq!(
+        ip("6.6.6.6, 1.1.1.1, 10.0.0.2", "10.0.0.1").as_deref(),
+        Some("1.1.1.1")
+    );
+    // Without a usable entry the last trusted hop is used.
+    assert_eq!(ip("10.0.0.2", "10.0.0.1").as_deref(), Some("10.0.0.2"));
+    assert_eq!(ip("garbage", "10.0.0.1").as_deref(), Some("10.0.0.1"));
+    assert_eq!(ip("1.1.1.1", "10.0.0.3").as_deref(), Some("10.0.0.3"));
+}
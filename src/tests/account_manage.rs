@@ -26,17 +26,27 @@ async fn make() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Jiening Yu".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![sms3_shared::account::Permission::ManageAccounts],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: {
             let mut t = crate::account::verify::Tokens::new();
-            token = t.new_token(account_id, 0);
+            token = t.new_token(0).unwrap();
             t
         },
         verify: crate::account::UserVerifyVariant::None,
@@ -44,20 +54,42 @@ async fn make() {
 
     use sms3_shared::account::handle::manage::MakeAccountDescriptor;
 
-    let descriptor = MakeAccountDescriptor {
+    let descriptor = |phone, password: &str| MakeAccountDescriptor {
         email: lettre::Address::new("myg", "i.pkuschool.edu.cn").unwrap(),
         name: "Yuguo Ma".to_string(),
         school_id: 114514,
-        phone: 1919810,
+        phone,
         house: None,
         organization: Some("PKU".to_string()),
-        password: "password".to_string(),
+        password: password.to_string(),
         permissions: vec![
             sms3_shared::account::Permission::ManageAccounts,
             sms3_shared::account::Permission::Op,
         ],
     };
 
+    // Invalid phones and weak passwords are rejected like on registration.
+    for invalid in [
+        descriptor(1919810, "password"),
+        descriptor(13800138000, "short"),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/account/manage/create")
+                    .method("POST")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header("Token", &token)
+                    .header("AccountId", account_id)
+                    .body(serde_json::to_vec(&invalid).unwrap().into())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
     let response = app
         .clone()
         .oneshot(
@@ -67,7 +99,11 @@ async fn make() {
                 .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
                 .header("Token", &token)
                 .header("AccountId", account_id)
-                .body(serde_json::to_vec(&descriptor).unwrap().into())
+                .body(
+                    serde_json::to_vec(&descriptor(13800138000, "password"))
+                        .unwrap()
+                        .into(),
+                )
                 .unwrap(),
         )
         .await
@@ -129,17 +165,27 @@ async fn view() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Jiening Yu".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![sms3_shared::account::Permission::ViewAccounts],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: {
             let mut t = crate::account::verify::Tokens::new();
-            token = t.new_token(account_id, 0);
+            token = t.new_token(0).unwrap();
             t
         },
         verify: crate::account::UserVerifyVariant::None,
@@ -162,6 +208,16 @@ async fn view() {
             registration_time: chrono::Utc::now(),
             password_sha: digest(test_password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: crate::account::verify::Tokens::new(),
         verify: crate::account::UserVerifyVariant::None,
@@ -180,6 +236,16 @@ async fn view() {
             registration_time: chrono::Utc::now(),
             password_sha: digest(test_password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: crate::account::verify::Tokens::new(),
         verify: crate::account::UserVerifyVariant::None,
@@ -262,17 +328,27 @@ async fn modify() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Jiening Yu".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![sms3_shared::account::Permission::ManageAccounts],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: {
             let mut t = crate::account::verify::Tokens::new();
-            token = t.new_token(account_id, 0);
+            token = t.new_token(0).unwrap();
             t
         },
         verify: crate::account::UserVerifyVariant::None,
@@ -295,6 +371,16 @@ async fn modify() {
             registration_time: chrono::Utc::now(),
             password_sha: digest(test_password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: crate::account::verify::Tokens::new(),
         verify: crate::account::UserVerifyVariant::None,
@@ -313,6 +399,16 @@ async fn modify() {
             registration_time: chrono::Utc::now(),
             password_sha: digest(test_password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: crate::account::verify::Tokens::new(),
         verify: crate::account::UserVerifyVariant::None,
@@ -348,6 +444,32 @@ async fn modify() {
         );
     }
 
+    // Invalid phones are rejected like on registration.
+    {
+        let descriptor = AccountModifyDescriptor {
+            account_id: test_account_id_1,
+            variants: vec![AccountModifyVariant::Phone(1)],
+        };
+
+        assert_eq!(
+            app.clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/account/manage/modify")
+                        .method("POST")
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .header("Token", &token)
+                        .header("AccountId", account_id)
+                        .body(serde_json::to_vec(&descriptor).unwrap().into())
+                        .unwrap()
+                )
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
     {
         let descriptor = AccountModifyDescriptor {
             account_id: test_account_id_1,
@@ -357,8 +479,8 @@ async fn modify() {
                 ),
                 AccountModifyVariant::Name("Tianyang He".to_string()),
                 AccountModifyVariant::SchoolId(2100000),
-                AccountModifyVariant::Phone(1),
-                AccountModifyVariant::House(Some(sms3_shared::account::House::ZhengXin)),
+                AccountModifyVariant::Phone(13800138001),
+                AccountModifyVariant::House(Some(sms3_shared::account::House::from("ZhengXin"))),
                 AccountModifyVariant::Organization(Some("SubIT".to_string())),
                 AccountModifyVariant::Permission(vec![Permission::ManageAccounts, Permission::Op]),
             ],
@@ -409,10 +531,10 @@ async fn modify() {
             );
             assert_eq!(attributes.name, "Tianyang He");
             assert_eq!(attributes.school_id, 2100000);
-            assert_eq!(attributes.phone, 1);
+            assert_eq!(attributes.phone, 13800138001);
             assert_eq!(
                 attributes.house,
-                Some(sms3_shared::account::House::ZhengXin)
+                Some(sms3_shared::account::House::from("ZhengXin"))
             );
             assert_eq!(attributes.organization, Some("SubIT".to_string()));
             assert_eq!(attributes.permissions, &[Permission::ManageAccounts]);
@@ -421,3 +543,1318 @@ async fn modify() {
         }
     }
 }
+
+/// Test: granting a permission to existing, unverified and missing accounts.
+#[serial]
+#[tokio::test]
+async fn grant() {
+    reset_all();
+
+    let app = crate::router();
+
+    let account_id = 123456;
+    let target_id = 654321;
+
+    let mut account = fixtures::verified_account_with_id(
+        account_id,
+        "yujiening2025",
+        &[
+            sms3_shared::account::Permission::ManageAccounts,
+            sms3_shared::account::Permission::View,
+        ],
+    );
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(target_id, "myg", &[]));
+
+    let unverified = crate::account::Account::Unverified(crate::account::verify::Context {
+        email: lettre::Address::new("hty", "i.pkuschool.edu.cn").unwrap(),
+        code: 123456,
+        expire_time: chrono::Utc::now() + chrono::Duration::minutes(15),
+    });
+    let unverified_id = unverified.id();
+    crate::account::INSTANCE.push(unverified);
+
+    use sms3_shared::account::handle::manage::{GrantPermissionDescriptor, GrantPermissionResult};
+
+    let request = |permission| {
+        let descriptor = GrantPermissionDescriptor {
+            accounts: vec![target_id, 42, unverified_id],
+            permission,
+        };
+
+        app.clone().oneshot(
+            Request::builder()
+                .uri("/api/account/manage/grant")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", &token)
+                .header("AccountId", account_id)
+                .body(serde_json::to_vec(&descriptor).unwrap().into())
+                .unwrap(),
+        )
+    };
+
+    // Granting a permission the admin doesn't have.
+    assert_eq!(
+        request(sms3_shared::account::Permission::Op)
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let response = request(sms3_shared::account::Permission::View)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response_json: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    let results: Vec<GrantPermissionResult> =
+        serde_json::from_value(response_json["results"].clone()).unwrap();
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0], GrantPermissionResult::Ok(target_id));
+    assert!(
+        matches!(&results[1], GrantPermissionResult::Err { id: 42, error } if error.contains("not found"))
+    );
+    assert!(matches!(&results[2], GrantPermissionResult::Err { id, .. } if *id == unverified_id));
+
+    assert!(crate::account::INSTANCE
+        .get(target_id)
+        .unwrap()
+        .has_permission(sms3_shared::account::Permission::View));
+
+    let entries = crate::account::INSTANCE.audit().entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].by, account_id);
+    assert_eq!(entries[0].target, target_id);
+    assert_eq!(
+        entries[0].action,
+        crate::account::audit::Action::ChangePermissions(crate::account::audit::PermissionDiff {
+            added: vec![sms3_shared::account::Permission::View],
+            removed: vec![],
+        })
+    );
+}
+
+/// Test: searching accounts requires `ViewAccounts`.
+#[serial]
+#[tokio::test]
+async fn search() {
+    reset_all();
+
+    let app = crate::router();
+
+    let account_id = 123456;
+
+    let mut account = fixtures::verified_account_with_id(
+        account_id,
+        "yujiening2025",
+        &[sms3_shared::account::Permission::ViewAccounts],
+    );
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    let descriptor = sms3_shared::account::handle::manage::SearchAccountDescriptor {
+        query: "jien".to_string(),
+        limit: 10,
+    };
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/account/manage/search")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", &token)
+                .header("AccountId", account_id)
+                .body(serde_json::to_vec(&descriptor).unwrap().into())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response_json: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    let results: Vec<sms3_shared::account::UserMetadata> =
+        serde_json::from_value(response_json["results"].clone()).unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].email.user(), "yujiening2025");
+}
+
+/// Test: listing accounts by house and organization with query parameters.
+#[serial]
+#[tokio::test]
+async fn filter() {
+    reset_all();
+
+    let app = crate::router();
+
+    let account_id = 123456;
+
+    let mut account = fixtures::with_attributes(
+        fixtures::verified_account_with_id(
+            account_id,
+            "yujiening2025",
+            &[sms3_shared::account::Permission::ViewAccounts],
+        ),
+        |attributes| {
+            attributes.house = Some(sms3_shared::account::House::from("ZhiZhi"));
+            attributes.organization = Some("SubIT".to_string());
+        },
+    );
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    let request = |uri: &str| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("GET")
+                .header("Token", &token)
+                .header("AccountId", account_id)
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    for (uri, count) in [
+        ("/api/account/manage/by-house?house=ZhiZhi", 1),
+        ("/api/account/manage/by-house?house=GeWu", 0),
+        ("/api/account/manage/by-organization?organization=SubIT", 1),
+        ("/api/account/manage/by-organization?organization=Sub", 0),
+    ] {
+        let response = request(uri).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response_json: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(response_json["results"].as_array().unwrap().len(), count);
+    }
+
+    assert_eq!(
+        request("/api/account/manage/by-house?house=Unknown")
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::BAD_REQUEST
+    );
+}
+
+/// Test: introspecting active, expired and unknown tokens.
+#[serial]
+#[tokio::test]
+async fn introspect() {
+    reset_all();
+
+    let app = crate::router();
+
+    let account_id = 123456;
+    let target_id = 654321;
+
+    let mut account = fixtures::verified_account_with_id(
+        account_id,
+        "yujiening2025",
+        &[sms3_shared::account::Permission::ViewAccounts],
+    );
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    let mut target = fixtures::with_attributes(
+        fixtures::verified_account_with_id(
+            target_id,
+            "myg",
+            &[sms3_shared::account::Permission::Post],
+        ),
+        |attributes| attributes.token_expiration_time = 1,
+    );
+    let crate::account::Account::Verified { tokens, .. } = &mut target else {
+        unreachable!()
+    };
+    let target_token = tokens.new_token(1).unwrap();
+    crate::account::INSTANCE.push(target);
+
+    use sms3_shared::account::handle::manage::{IntrospectTokenDescriptor, IntrospectTokenResult};
+
+    let introspect = |t: &str| {
+        let descriptor = IntrospectTokenDescriptor {
+            token: t.to_string(),
+        };
+        let app = app.clone();
+        let token = token.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/account/manage/introspect")
+                        .method("POST")
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .header("Token", &token)
+                        .header("AccountId", account_id)
+                        .body(serde_json::to_vec(&descriptor).unwrap().into())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_slice::<serde_json::Value>(
+                &hyper::body::to_bytes(response.into_body()).await.unwrap(),
+            )
+            .unwrap()
+        }
+    };
+
+    let result: IntrospectTokenResult =
+        serde_json::from_value(introspect(&target_token).await).unwrap();
+    assert!(result.active);
+    assert_eq!(result.sub, Some(target_id));
+    assert_eq!(
+        result.permissions,
+        Some(vec![sms3_shared::account::Permission::Post])
+    );
+    assert!(result.exp.unwrap() > crate::clock::now().timestamp());
+
+    // tokens never expiring come without `exp`
+    let result: IntrospectTokenResult = serde_json::from_value(introspect(&token).await).unwrap();
+    assert_eq!(result.sub, Some(account_id));
+    assert_eq!(result.exp, None);
+
+    assert_eq!(
+        introspect("unknown").await,
+        serde_json::json!({ "active": false })
+    );
+
+    crate::clock::advance(chrono::Duration::days(2));
+    assert_eq!(
+        introspect(&target_token).await,
+        serde_json::json!({ "active": false })
+    );
+}
+
+/// Test: admin notes are stored with author and time, and hidden from the user.
+#[serial]
+#[tokio::test]
+async fn notes() {
+    reset_all();
+    let now = chrono::Utc::now();
+    crate::clock::set(now);
+
+    let app = crate::router();
+
+    let account_id = 123456;
+    let target_id = 654321;
+
+    let mut account = fixtures::verified_account_with_id(
+        account_id,
+        "yujiening2025",
+        &[
+            sms3_shared::account::Permission::ManageAccounts,
+            sms3_shared::account::Permission::ViewAccounts,
+        ],
+    );
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    let mut account = fixtures::verified_account_with_id(target_id, "myg", &[]);
+    let target_token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    let request = |uri: &str, token: &str, account_id: u64, body: serde_json::Value| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", token)
+                .header("AccountId", account_id)
+                .body(serde_json::to_vec(&body).unwrap().into())
+                .unwrap(),
+        )
+    };
+    let text = "verified identity over phone";
+
+    let response = request(
+        "/api/account/manage/note/add",
+        &token,
+        account_id,
+        serde_json::json!({ "account_id": target_id, "text": text }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request(
+        "/api/account/manage/note/list",
+        &token,
+        account_id,
+        serde_json::json!({ "account_id": target_id }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let notes: Vec<sms3_shared::account::Note> =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert_eq!(
+        notes,
+        vec![sms3_shared::account::Note {
+            author: account_id,
+            time: now,
+            text: text.to_string(),
+        }]
+    );
+
+    // Users can neither list notes nor see them in their own data.
+    assert_eq!(
+        request(
+            "/api/account/manage/note/list",
+            &target_token,
+            target_id,
+            serde_json::json!({ "account_id": target_id }),
+        )
+        .await
+        .unwrap()
+        .status(),
+        StatusCode::FORBIDDEN
+    );
+    for uri in ["/api/account/view", "/api/account/export"] {
+        let response = request(uri, &target_token, target_id, serde_json::Value::Null)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(!String::from_utf8(body.to_vec()).unwrap().contains(text));
+    }
+}
+
+/// Test: changing house records each assignment in chronological order.
+#[serial]
+#[tokio::test]
+async fn house_history() {
+    use sms3_shared::account::{House, HouseAssignment, Permission};
+
+    reset_all();
+    let start = chrono::Utc::now();
+    crate::clock::set(start);
+
+    let app = crate::router();
+
+    let account_id = 123456;
+    let target_id = 654321;
+
+    let mut account = fixtures::with_attributes(
+        fixtures::verified_account_with_id(
+            account_id,
+            "yujiening2025",
+            &[Permission::ManageAccounts, Permission::ViewAccounts],
+        ),
+        |attributes| {
+            attributes.house = Some(House::from("ZhiZhi"));
+        },
+    );
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(target_id, "myg", &[]));
+
+    let request = |uri: &str, body: serde_json::Value| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", &token)
+                .header("AccountId", account_id)
+                .body(serde_json::to_vec(&body).unwrap().into())
+                .unwrap(),
+        )
+    };
+
+    for house in [House::from("GeWu"), House::from("ZhiZhi")] {
+        crate::clock::advance(chrono::Duration::days(1));
+        let response = request(
+            "/api/account/manage/modify",
+            serde_json::to_value(
+                sms3_shared::account::handle::manage::AccountModifyDescriptor {
+                    account_id: target_id,
+                    variants: vec![
+                        sms3_shared::account::handle::manage::AccountModifyVariant::House(Some(
+                            house,
+                        )),
+                    ],
+                },
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = request(
+        "/api/account/manage/house-history",
+        serde_json::json!({ "account_id": target_id }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let history: Vec<HouseAssignment> =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert_eq!(
+        history,
+        vec![
+            HouseAssignment {
+                house: Some(House::from("GeWu")),
+                effective_from: start + chrono::Duration::days(1),
+            },
+            HouseAssignment {
+                house: Some(House::from("ZhiZhi")),
+                effective_from: start + chrono::Duration::days(2),
+            },
+        ]
+    );
+
+    // The current house is kept in metadata.
+    assert_eq!(
+        crate::account::INSTANCE
+            .get(target_id)
+            .unwrap()
+            .metadata()
+            .unwrap()
+            .house,
+        Some(House::from("ZhiZhi"))
+    );
+}
+
+/// Test: admins scoped to an organization only manage accounts of it.
+#[serial]
+#[tokio::test]
+async fn organization_scoped_admin() {
+    use sms3_shared::account::{
+        handle::manage::{AccountModifyDescriptor, AccountModifyVariant},
+        Permission, ScopedPermission,
+    };
+
+    reset_all();
+
+    let app = crate::router();
+
+    let account_id = 123456;
+    let member_id = 1;
+    let outsider_id = 2;
+    let account = |id: u64, user: &str, organization: &str| {
+        fixtures::with_attributes(
+            fixtures::verified_account_with_id(id, user, &[Permission::View, Permission::Post]),
+            |attributes| attributes.organization = Some(organization.to_string()),
+        )
+    };
+
+    let mut admin = fixtures::with_attributes(
+        account(account_id, "yujiening2025", "Student Union"),
+        |attributes| {
+            attributes.scoped_permissions = vec![ScopedPermission {
+                permission: Permission::ManageAccounts,
+                organization: "Student Union".to_string(),
+            }]
+        },
+    );
+    let token = fixtures::new_token(&mut admin);
+    crate::account::INSTANCE.push(admin);
+    for (id, user, organization) in [
+        (member_id, "member", "Student Union"),
+        (outsider_id, "outsider", "Chess Club"),
+    ] {
+        crate::account::INSTANCE.push(account(id, user, organization));
+    }
+
+    let modify = |target: u64, variant: AccountModifyVariant| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri("/api/account/manage/modify")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", &token)
+                .header("AccountId", account_id)
+                .body(
+                    serde_json::to_vec(&AccountModifyDescriptor {
+                        account_id: target,
+                        variants: vec![variant],
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+    };
+    let name = |id: u64| {
+        crate::account::INSTANCE
+            .get(id)
+            .unwrap()
+            .metadata()
+            .unwrap()
+            .name
+    };
+
+    let response = modify(
+        member_id,
+        AccountModifyVariant::Name("Yuguo Ma".to_string()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(name(member_id), "Yuguo Ma");
+
+    let response = modify(
+        outsider_id,
+        AccountModifyVariant::Name("Yuguo Ma".to_string()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(name(outsider_id), "Jiening Yu");
+
+    // Members can't be moved out of the organization either.
+    let response = modify(
+        member_id,
+        AccountModifyVariant::Organization(Some("Chess Club".to_string())),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        crate::account::INSTANCE
+            .get(member_id)
+            .unwrap()
+            .organization(),
+        Some("Student Union")
+    );
+
+    // Scoped permissions don't count globally.
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/account/manage/statistics")
+                .method("GET")
+                .header("Token", &token)
+                .header("AccountId", account_id)
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+/// Test: a forced password change blocks other use until the password is changed.
+#[serial]
+#[tokio::test]
+async fn force_password_change() {
+    use sms3_shared::account::handle::{
+        manage::ForcePasswordChangeDescriptor, AccountLoginDescriptor, AccountLoginResult,
+        ChangePasswordDescriptor,
+    };
+    use sms3_shared::account::Permission;
+
+    reset_all();
+
+    let app = crate::router();
+
+    let admin_id = 123456;
+    let user_id = 1;
+    let mut admin = fixtures::with_attributes(
+        fixtures::verified_account_with_id(
+            admin_id,
+            "yujiening2025",
+            &[Permission::ManageAccounts, Permission::View],
+        ),
+        |attributes| attributes.mute_login_notification = true,
+    );
+    let admin_token = fixtures::new_token(&mut admin);
+    crate::account::INSTANCE.push(admin);
+    let mut user = fixtures::with_attributes(
+        fixtures::verified_account_with_id(user_id, "myg", &[Permission::View]),
+        |attributes| attributes.mute_login_notification = true,
+    );
+    let user_token = fixtures::new_token(&mut user);
+    user.create_api_key("script".to_string(), vec![Permission::View], None)
+        .unwrap();
+    crate::account::INSTANCE.push(user);
+
+    let request = |uri: &str, token: &str, account_id: u64, body: Vec<u8>| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", token)
+                .header("AccountId", account_id)
+                .body(body.into())
+                .unwrap(),
+        )
+    };
+    let view = |token: &str| request("/api/account/view", token, user_id, vec![]);
+    let login = |password: &str| {
+        request(
+            "/api/account/login",
+            "",
+            0,
+            serde_json::to_vec(&AccountLoginDescriptor {
+                email: lettre::Address::new("myg", "i.pkuschool.edu.cn").unwrap(),
+                password: password.to_string(),
+            })
+            .unwrap(),
+        )
+    };
+    let change_password = |token: &str, password: &str| {
+        request(
+            "/api/account/change-password",
+            token,
+            user_id,
+            serde_json::to_vec(&ChangePasswordDescriptor {
+                password: password.to_string(),
+            })
+            .unwrap(),
+        )
+    };
+
+    assert_eq!(view(&user_token).await.unwrap().status(), StatusCode::OK);
+
+    let response = request(
+        "/api/account/manage/force-password-change",
+        &admin_token,
+        admin_id,
+        serde_json::to_vec(&ForcePasswordChangeDescriptor {
+            account_id: user_id,
+        })
+        .unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Existing tokens and API keys are revoked.
+    assert_eq!(
+        view(&user_token).await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert!(matches!(
+        crate::RequirePermissionContext {
+            token: user_token.clone(),
+            account_id: user_id,
+        }
+        .valid(&[]),
+        Err(crate::account::ManagerError::Account(
+            _,
+            crate::account::Error::TokenIncorrect
+        ))
+    ));
+    assert_eq!(
+        crate::account::INSTANCE.with_account(user_id, |account| match account {
+            crate::account::Account::Verified { tokens, .. } => tokens.api_keys().len(),
+            crate::account::Account::Unverified(_) => unreachable!(),
+        }),
+        Some(0)
+    );
+
+    // Logging in only grants a token for changing the password.
+    let response = login("password123456").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: AccountLoginResult =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert!(result.must_change_password);
+    assert_eq!(result.expires_in, Some(15 * 60));
+    let limited = result.access_token;
+    assert_eq!(
+        view(&limited).await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    // A regular token can't be used to change the password, nor can a reused password.
+    assert_eq!(
+        change_password(&user_token, "newpassword")
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        change_password(&limited, "password123456")
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+
+    assert_eq!(
+        change_password(&limited, "newpassword")
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::OK
+    );
+    // The token is one-time.
+    assert_eq!(
+        change_password(&limited, "newpassword2")
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let response = login("newpassword").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: AccountLoginResult =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert!(!result.must_change_password);
+    assert_eq!(
+        view(&result.access_token).await.unwrap().status(),
+        StatusCode::OK
+    );
+    // Sessions from before the force stay revoked after the change.
+    assert_eq!(
+        view(&user_token).await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+/// Test: an admin can restore an account deleted by its user within the grace period.
+#[serial]
+#[tokio::test]
+async fn restore_account() {
+    use sms3_shared::account::handle::{
+        manage::RestoreAccountDescriptor, AccountLoginDescriptor, AccountSignOutDescriptor,
+    };
+    use sms3_shared::account::Permission;
+
+    reset_all();
+
+    let app = crate::router();
+
+    let admin_id = 123456;
+    let user_id = 1;
+    let mut admin = fixtures::with_attributes(
+        fixtures::verified_account_with_id(
+            admin_id,
+            "yujiening2025",
+            &[Permission::ManageAccounts, Permission::View],
+        ),
+        |attributes| attributes.mute_login_notification = true,
+    );
+    let admin_token = fixtures::new_token(&mut admin);
+    crate::account::INSTANCE.push(admin);
+    let mut user = fixtures::with_attributes(
+        fixtures::verified_account_with_id(user_id, "myg", &[Permission::View]),
+        |attributes| attributes.mute_login_notification = true,
+    );
+    let user_token = fixtures::new_token(&mut user);
+    crate::account::INSTANCE.push(user);
+
+    let request = |uri: &str, token: &str, account_id: u64, body: Vec<u8>| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", token)
+                .header("AccountId", account_id)
+                .body(body.into())
+                .unwrap(),
+        )
+    };
+    let login_with = |password: &str| {
+        request(
+            "/api/account/login",
+            "",
+            0,
+            serde_json::to_vec(&AccountLoginDescriptor {
+                email: lettre::Address::new("myg", "i.pkuschool.edu.cn").unwrap(),
+                password: password.to_string(),
+            })
+            .unwrap(),
+        )
+    };
+    let login = || login_with("password123456");
+    let error = |response: axum::response::Response| async {
+        let body: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        body["error"].as_str().unwrap().to_string()
+    };
+    let restore = || {
+        request(
+            "/api/account/manage/restore",
+            &admin_token,
+            admin_id,
+            serde_json::to_vec(&RestoreAccountDescriptor {
+                account_id: user_id,
+            })
+            .unwrap(),
+        )
+    };
+
+    // Nothing to restore yet.
+    assert_eq!(restore().await.unwrap().status(), StatusCode::FORBIDDEN);
+
+    let signout = AccountSignOutDescriptor {
+        password: "password123456".to_string(),
+    };
+    assert_eq!(
+        request(
+            "/api/account/signout",
+            &user_token,
+            user_id,
+            serde_json::to_vec(&signout).unwrap()
+        )
+        .await
+        .unwrap()
+        .status(),
+        StatusCode::OK
+    );
+    let response = login().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(error(response).await, "account is pending deletion");
+    // The deletion is only told to the user.
+    let response = login_with("wrongpassword").await.unwrap();
+    assert_eq!(error(response).await, "password incorrect");
+
+    crate::clock::advance(chrono::Duration::days(6));
+    assert_eq!(restore().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(login().await.unwrap().status(), StatusCode::OK);
+
+    // Restored accounts aren't purged once the grace period would have passed.
+    crate::clock::advance(chrono::Duration::days(2));
+    crate::account::INSTANCE.refresh_all();
+    assert!(crate::account::INSTANCE.get(user_id).is_some());
+}
+
+/// Test: admins can resend verification codes to pending accounts, only
+/// bypassing the resend cooldown with `Op`.
+#[serial]
+#[tokio::test]
+async fn resend_verification() {
+    use crate::account::audit::Action;
+    use sms3_shared::account::handle::manage::{
+        ResendVerificationDescriptor, ResendVerificationResult,
+    };
+    use sms3_shared::account::Permission;
+
+    reset_all();
+
+    let app = crate::router();
+
+    let mut tokens = Vec::new();
+    for (id, user, permissions) in [
+        (1, "admin", vec![Permission::ManageAccounts]),
+        (2, "op", vec![Permission::ManageAccounts, Permission::Op]),
+    ] {
+        let mut account = fixtures::verified_account_with_id(id, user, &permissions);
+        tokens.push(fixtures::new_token(&mut account));
+        crate::account::INSTANCE.push(account);
+    }
+
+    crate::account::INSTANCE
+        .register(lettre::Address::new("myg", "i.pkuschool.edu.cn").unwrap())
+        .unwrap();
+    let pending_id = crate::account::INSTANCE
+        .snapshot_ids()
+        .into_iter()
+        .find(|&id| id != 1 && id != 2)
+        .unwrap();
+    let mails = || crate::account::verify::SENT_MAILS.lock().len();
+    assert_eq!(mails(), 1);
+
+    let resend = |admin: u64, account_id: u64, bypass_cooldown: bool| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri("/api/account/manage/resend-verification")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", &tokens[admin as usize - 1])
+                .header("AccountId", admin)
+                .body(
+                    serde_json::to_vec(&ResendVerificationDescriptor {
+                        account_id,
+                        bypass_cooldown,
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+    };
+    let code_sent = |response: axum::response::Response| async move {
+        assert_eq!(response.status(), StatusCode::OK);
+        serde_json::from_slice::<ResendVerificationResult>(
+            &hyper::body::to_bytes(response.into_body()).await.unwrap(),
+        )
+        .unwrap()
+        .code_sent
+    };
+
+    // Within the cooldown nothing is sent, and bypassing it requires `Op`.
+    assert!(!code_sent(resend(1, pending_id, false).await.unwrap()).await);
+    assert_eq!(
+        resend(1, pending_id, true).await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(mails(), 1);
+    assert!(code_sent(resend(2, pending_id, true).await.unwrap()).await);
+    assert_eq!(mails(), 2);
+
+    crate::clock::advance(chrono::Duration::minutes(2));
+    assert!(code_sent(resend(1, pending_id, false).await.unwrap()).await);
+    assert_eq!(mails(), 3);
+
+    let actions: Vec<_> = crate::account::INSTANCE
+        .audit()
+        .concerning(pending_id)
+        .into_iter()
+        .map(|e| (e.by, e.action))
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            (
+                2,
+                Action::ResendVerification {
+                    bypass_cooldown: true
+                }
+            ),
+            (
+                1,
+                Action::ResendVerification {
+                    bypass_cooldown: false
+                }
+            ),
+        ]
+    );
+
+    // Verified accounts have nothing to resend.
+    assert_eq!(
+        resend(2, 1, true).await.unwrap().status(),
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        resend(1, 42, false).await.unwrap().status(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(mails(), 3);
+}
+
+/// Test: maintenance mode rejects mutations but not reads, until `Op` leaves it.
+#[serial]
+#[tokio::test]
+async fn maintenance() {
+    use sms3_shared::account::handle::manage::{MaintenanceDescriptor, MaintenanceResult};
+    use sms3_shared::account::handle::{AccountCreateDescriptor, AccountLoginDescriptor};
+    use sms3_shared::account::Permission;
+
+    reset_all();
+
+    let app = crate::router();
+
+    let mut admins = Vec::new();
+    for (user, permissions) in [
+        ("admin", &[Permission::ManageAccounts][..]),
+        ("op", &[Permission::ManageAccounts, Permission::Op]),
+    ] {
+        let mut account = fixtures::verified_account(user, permissions);
+        admins.push((account.id(), fixtures::new_token(&mut account)));
+        crate::account::INSTANCE.push(account);
+    }
+
+    let set = |admin: usize, enabled: bool| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri("/api/account/manage/maintenance")
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Token", &admins[admin].1)
+                .header("AccountId", admins[admin].0)
+                .body(
+                    serde_json::to_vec(&MaintenanceDescriptor {
+                        enabled,
+                        retry_after: Some(120),
+                    })
+                    .unwrap()
+                    .into(),
+                )
+                .unwrap(),
+        )
+    };
+    let post = |uri: &'static str, body: Vec<u8>| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(body.into())
+                .unwrap(),
+        )
+    };
+    let create = || {
+        post(
+            "/api/account/create",
+            serde_json::to_vec(&AccountCreateDescriptor {
+                email: "myg@i.pkuschool.edu.cn".to_string(),
+            })
+            .unwrap(),
+        )
+    };
+    let login = || {
+        post(
+            "/api/account/login",
+            serde_json::to_vec(&AccountLoginDescriptor {
+                email: fixtures::email("admin"),
+                password: fixtures::PASSWORD.to_string(),
+            })
+            .unwrap(),
+        )
+    };
+
+    assert_eq!(set(0, true).await.unwrap().status(), StatusCode::FORBIDDEN);
+    assert!(crate::maintenance::retry_after().is_none());
+
+    let response = set(1, true).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: MaintenanceResult =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert_eq!(result.retry_after, Some(120));
+
+    let response = create().await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[http::header::RETRY_AFTER], "120");
+    assert!(crate::account::verify::SENT_MAILS.lock().is_empty());
+    assert_eq!(login().await.unwrap().status(), StatusCode::OK);
+
+    assert_eq!(set(1, false).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(create().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(crate::account::verify::SENT_MAILS.lock().len(), 1);
+}
+
+/// Test: all verified accounts are streamed as one JSON object per line, ordered by id.
+#[serial]
+#[tokio::test]
+async fn export_accounts() {
+    use sms3_shared::account::handle::ViewAccountResult;
+    use sms3_shared::account::Permission;
+
+    reset_all();
+
+    let mut admin = fixtures::verified_account("admin", &[Permission::ViewAccounts]);
+    let admin_token = fixtures::new_token(&mut admin);
+    let mut user = fixtures::verified_account("user0", &[]);
+    let user_token = fixtures::new_token(&mut user);
+    let (admin_id, user_id) = (admin.id(), user.id());
+    let mut ids = vec![admin_id, user_id];
+    crate::account::INSTANCE.push(admin);
+    crate::account::INSTANCE.push(user);
+    for i in 1..50 {
+        let account = fixtures::verified_account(&format!("user{i}"), &[]);
+        ids.push(account.id());
+        crate::account::INSTANCE.push(account);
+    }
+    crate::account::INSTANCE.push(fixtures::pending_account("pending"));
+    ids.sort_unstable();
+
+    let export = |token: &str, id: u64| {
+        crate::router().oneshot(
+            Request::builder()
+                .uri("/api/account/manage/export")
+                .method("GET")
+                .header("Token", token)
+                .header("AccountId", id)
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    assert_eq!(
+        export(&user_token, user_id).await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let response = export(&admin_token, admin_id).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[http::header::CONTENT_TYPE],
+        "application/x-ndjson"
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.ends_with('\n'));
+    let exported: Vec<u64> = body
+        .lines()
+        .map(|line| serde_json::from_str::<ViewAccountResult>(line).unwrap().id)
+        .collect();
+    assert_eq!(exported, ids);
+}
+
+/// Test: admins see the count and oldest age of active sessions, expired tokens excluded.
+#[serial]
+#[tokio::test]
+async fn sessions() {
+    use sms3_shared::account::handle::manage::{ViewAccountDescriptor, ViewAccountResult};
+    use sms3_shared::account::handle::SessionSummary;
+    use sms3_shared::account::Permission;
+
+    let mut client = harness::TestClient::new();
+    let mut admin = fixtures::verified_account("admin", &[Permission::ViewAccounts]);
+    let admin_token = fixtures::new_token(&mut admin);
+    let admin_id = admin.id();
+    crate::account::INSTANCE.push(admin);
+
+    let mut user = fixtures::verified_account("user", &[]);
+    let user_id = user.id();
+    let crate::account::Account::Verified { tokens, .. } = &mut user else {
+        unreachable!()
+    };
+    // The oldest token expires first.
+    tokens.new_token(1).unwrap();
+    client.advance(chrono::Duration::hours(1));
+    tokens.new_token(0).unwrap();
+    client.advance(chrono::Duration::hours(1));
+    tokens.new_token(7).unwrap();
+    crate::account::INSTANCE.push(user);
+    crate::account::INSTANCE.push(fixtures::verified_account("idle", &[]));
+    let idle_id = crate::account::email_id(&fixtures::email("idle"));
+
+    client.authorize(admin_id, admin_token);
+    let view = || async {
+        let response = client
+            .post(
+                "/api/account/manage/view",
+                &ViewAccountDescriptor {
+                    accounts: vec![user_id, idle_id],
+                },
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        serde_json::from_value::<Vec<ViewAccountResult>>(
+            response.json::<serde_json::Value>()["results"].clone(),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|result| match result {
+            ViewAccountResult::Ok(result) => result.sessions.unwrap(),
+            ViewAccountResult::Err { error, .. } => panic!("{error}"),
+        })
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        view().await,
+        [
+            SessionSummary {
+                active_token_count: 3,
+                oldest_token_age: Some(2 * 3600),
+            },
+            SessionSummary {
+                active_token_count: 0,
+                oldest_token_age: None,
+            }
+        ]
+    );
+
+    client.advance(chrono::Duration::days(1));
+    assert_eq!(
+        view().await[0],
+        SessionSummary {
+            active_token_count: 2,
+            oldest_token_age: Some(25 * 3600),
+        }
+    );
+
+    // Sessions are only shown to admins.
+    let response = client.post("/api/account/view", &()).await;
+    assert!(response
+        .json::<serde_json::Value>()
+        .get("sessions")
+        .is_none());
+}
+
+/// Test: admins remove accounts in bulk, with a result per id in the order given.
+#[serial]
+#[tokio::test]
+async fn remove_accounts() {
+    use sms3_shared::account::handle::manage::{RemoveAccountResult, RemoveAccountsDescriptor};
+    use sms3_shared::account::Permission;
+
+    let mut client = harness::TestClient::new();
+    let mut admin = fixtures::verified_account("admin", &[Permission::ManageAccounts]);
+    let admin_token = fixtures::new_token(&mut admin);
+    let admin_id = admin.id();
+    let op = fixtures::verified_account("op", &[Permission::Op]);
+    let op_id = op.id();
+    let user = fixtures::verified_account("user", &[]);
+    let user_id = user.id();
+    let pending = fixtures::pending_account("pending");
+    let pending_id = pending.id();
+    for account in [admin, op, user, pending] {
+        crate::account::INSTANCE.push(account);
+    }
+
+    client.authorize(admin_id, admin_token);
+    let response = client
+        .post(
+            "/api/account/manage/remove",
+            &RemoveAccountsDescriptor {
+                accounts: vec![user_id, 1, op_id, pending_id],
+            },
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let results: Vec<RemoveAccountResult> =
+        serde_json::from_value(response.json::<serde_json::Value>()["results"].clone()).unwrap();
+
+    let RemoveAccountResult::Ok {
+        id,
+        metadata: Some(metadata),
+    } = &results[0]
+    else {
+        panic!("{:?}", results[0]);
+    };
+    assert_eq!((*id, &metadata.email), (user_id, &fixtures::email("user")));
+    assert!(matches!(results[1], RemoveAccountResult::Err { id: 1, .. }));
+    // Accounts with permissions the admin lacks are kept.
+    assert!(matches!(results[2], RemoveAccountResult::Err { id, .. } if id == op_id));
+    assert!(matches!(
+        results[3],
+        RemoveAccountResult::Ok { id, metadata: None } if id == pending_id
+    ));
+
+    assert!(crate::account::INSTANCE.get(user_id).is_none());
+    assert!(crate::account::INSTANCE.get(pending_id).is_none());
+    assert!(crate::account::INSTANCE.get(op_id).is_some());
+    assert!(crate::account::INSTANCE.get(admin_id).is_some());
+
+    // Only account managers remove accounts.
+    let mut user = fixtures::verified_account("user", &[]);
+    let user_token = fixtures::new_token(&mut user);
+    crate::account::INSTANCE.push(user);
+    client.authorize(user_id, user_token);
+    let response = client
+        .post(
+            "/api/account/manage/remove",
+            &RemoveAccountsDescriptor {
+                accounts: vec![op_id],
+            },
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert!(crate::account::INSTANCE.get(op_id).is_some());
+}
@@ -0,0 +1,114 @@
+use axum::http::{header, Request, StatusCode};
+use tower::util::ServiceExt;
+
+const ALLOWED: &str = "https://sms.pkuschool.edu.cn";
+
+fn app() -> axum::Router {
+    crate::router_with(&crate::config::Cors {
+        allowed_origins: vec![ALLOWED.to_string()],
+        allow_credentials: true,
+        ..Default::default()
+    })
+}
+
+async fn preflight(app: axum::Router, origin: &str) -> axum::response::Response {
+    app.oneshot(
+        Request::builder()
+            .uri("/api/account/login")
+            .method("OPTIONS")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type,token")
+            .body(hyper::Body::empty())
+            .unwrap(),
+    )
+    .await
+    .unwrap()
+}
+
+/// Test: CORS headers are only set for allowed origins, including on preflight requests.
+#[tokio::test]
+async fn allowed_origins() {
+    let response = preflight(app(), ALLOWED).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], ALLOWED);
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    assert!(headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+        .to_str()
+        .unwrap()
+        .contains("POST"));
+
+    let response = app()
+        .oneshot(
+            Request::builder()
+                .uri("/api/account/houses")
+                .method("GET")
+                .header(header::ORIGIN, ALLOWED)
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        ALLOWED
+    );
+
+    let response = app()
+        .oneshot(
+            Request::builder()
+                .uri("/api/account/houses")
+                .method("GET")
+                .header(header::ORIGIN, "https://evil.example.com")
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    let response = preflight(app(), "https://evil.example.com").await;
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    // No origin is allowed by default.
+    let response = preflight(crate::router(), ALLOWED).await;
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+}
+
+/// Test: `*` allows any origin, but not with credentials, and invalid
+/// origins and methods fail validation instead of being skipped.
+#[tokio::test]
+async fn any_origin() {
+    let cors = |origins: &[&str], allow_credentials| crate::config::Cors {
+        allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+        allow_credentials,
+        ..Default::default()
+    };
+
+    let any = cors(&["*"], false);
+    assert!(any.validate().is_ok());
+    let response = preflight(crate::router_with(&any), "https://evil.example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+
+    assert!(cors(&["*"], true).validate().is_err());
+    assert!(cors(&["*", ALLOWED], false).validate().is_err());
+    assert!(cors(&["https://sms.pkuschool.edu.cn\n"], false)
+        .validate()
+        .is_err());
+    assert!(crate::config::Cors {
+        allowed_methods: vec!["GET POST".to_string()],
+        ..Default::default()
+    }
+    .validate()
+    .is_err());
+    assert!(cors(&[ALLOWED], true).validate().is_ok());
+}
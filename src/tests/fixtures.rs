@@ -0,0 +1,139 @@
+//! Shared setup of accounts for tests.
+//!
+//! ```ignore
+//! let mut admin = fixtures::verified_account("admin", &[Permission::ManageAccounts]);
+//! let token = fixtures::new_token(&mut admin);
+//! crate::account::INSTANCE.push(admin);
+//! ```
+//!
+//! Use [`manager_with`] for an isolated manager instead of the global instance,
+//! which still needs [`super::reset_all`] and `#[serial]`.
+
+use crate::account::{
+    verify, Account, AccountManager, Permission, UserAttributes, UserVerifyVariant,
+};
+
+/// Password of accounts from [`verified_account`].
+pub const PASSWORD: &str = "password123456";
+
+/// Get the school email address of target user.
+pub fn email(user: &str) -> lettre::Address {
+    lettre::Address::new(user, "i.pkuschool.edu.cn").unwrap()
+}
+
+/// Create attributes of target user with [`PASSWORD`] and `permissions`,
+/// ex. to check validation without an account.
+pub fn attributes(user: &str, permissions: &[Permission]) -> UserAttributes {
+    let mut attributes = UserAttributes {
+        email: email(user),
+        name: "Jiening Yu".to_string(),
+        school_id: 2522320,
+        phone: 16601550826,
+        house: None,
+        organization: None,
+        permissions: vec![],
+        registration_time: chrono::Utc::now(),
+        password_sha: sha256::digest(PASSWORD),
+        token_expiration_time: 0,
+        mute_login_notification: false,
+        password_history: vec![],
+        admin_notes: vec![],
+        house_history: vec![],
+        scoped_permissions: vec![],
+        password_changed_at: None,
+        alternate_email: None,
+        must_change_password: false,
+        pending_deletion: None,
+        suspended: false,
+    };
+    attributes.set_permissions(permissions.to_vec());
+    attributes
+}
+
+/// Create a verified account of target user with [`attributes`],
+/// identified by [`crate::account::email_id`] of its email.
+pub fn verified_account(user: &str, permissions: &[Permission]) -> Account {
+    let attributes = attributes(user, permissions);
+    Account::Verified {
+        id: crate::account::email_id(&attributes.email),
+        attributes,
+        tokens: verify::Tokens::new(),
+        verify: UserVerifyVariant::None,
+    }
+}
+
+/// Like [`verified_account`], but identified by `id`, ex. to test lookups by
+/// ids not derived from emails.
+pub fn verified_account_with_id(id: u64, user: &str, permissions: &[Permission]) -> Account {
+    let mut account = verified_account(user, permissions);
+    if let Account::Verified { id: old, .. } = &mut account {
+        *old = id;
+    }
+    account
+}
+
+/// Change attributes of a verified account, ex. its house or password.
+pub fn with_attributes(mut account: Account, f: impl FnOnce(&mut UserAttributes)) -> Account {
+    match &mut account {
+        Account::Verified { attributes, .. } => f(attributes),
+        Account::Unverified(_) => panic!("only verified accounts have attributes"),
+    }
+    account
+}
+
+/// Create an unverified account of target user, without mailing the code.
+pub fn pending_account(user: &str) -> Account {
+    Account::Unverified(verify::Context::new(email(user)))
+}
+
+/// Log in target verified account with a token never expiring, returning the token.
+pub fn new_token(account: &mut Account) -> String {
+    match account {
+        Account::Verified { tokens, .. } => tokens.new_token(0).unwrap(),
+        Account::Unverified(_) => panic!("only verified accounts can log in"),
+    }
+}
+
+/// Create a manager without a store, holding `accounts`.
+pub fn manager_with(accounts: impl IntoIterator<Item = Account>) -> AccountManager {
+    let manager = AccountManager::new();
+    for account in accounts {
+        manager.insert(account);
+    }
+    manager
+}
+
+/// Test: fixtures create accounts in the described states.
+#[test]
+fn fixtures() {
+    let mut admin = verified_account("admin", &[Permission::Op, Permission::ManageAccounts]);
+    let token = new_token(&mut admin);
+    assert_eq!(admin.id(), crate::account::email_id(&email("admin")));
+    assert_eq!(
+        admin.permissions(),
+        [Permission::ManageAccounts, Permission::Op]
+    );
+    let Account::Verified {
+        attributes, tokens, ..
+    } = &admin
+    else {
+        panic!("not verified");
+    };
+    assert!(crate::account::verify_password(
+        &attributes.password_sha,
+        PASSWORD
+    ));
+    assert!(tokens.token_usable(&token));
+
+    let pending = pending_account("pending");
+    assert!(matches!(&pending, Account::Unverified(cxt) if !cxt.is_expired()));
+
+    let manager = manager_with([admin, pending]);
+    assert_eq!(manager.snapshot_ids().len(), 2);
+    assert_eq!(
+        manager.find_by_token(&token),
+        Some(manager.find_by_email(&email("admin")).unwrap())
+    );
+    assert!(manager.find_by_email(&email("pending")).is_some());
+    assert!(manager_with([]).snapshot_ids().is_empty());
+}
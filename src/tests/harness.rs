@@ -0,0 +1,221 @@
+//! End-to-end harness issuing requests through the HTTP router.
+//!
+//! ```ignore
+//! let mut client = harness::TestClient::new();
+//! let response = client.post("/api/account/login", &descriptor).await;
+//! assert_eq!(response.status, StatusCode::OK);
+//! client.authorize(result.account_id, result.access_token);
+//! let me: ViewAccountResult = client.post("/api/account/view", &()).await.json();
+//! ```
+//!
+//! Accounts live in the in-memory [`crate::account::INSTANCE`], mails are
+//! captured instead of sent and the clock is frozen, so the harness resets all
+//! static instances and tests using it need `#[serial]`.
+
+use axum::http::{self, HeaderMap, Request, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use tower::ServiceExt;
+
+use crate::account::verify::Mail;
+
+/// A client of a freshly reset router, optionally authorized as an account.
+pub struct TestClient {
+    router: axum::Router,
+    auth: Option<(u64, String)>,
+}
+
+impl TestClient {
+    /// Reset all static instances, freeze the clock at the current time and
+    /// build the router.
+    pub fn new() -> Self {
+        super::reset_all();
+        crate::clock::set(chrono::Utc::now());
+        Self {
+            router: crate::router(),
+            auth: None,
+        }
+    }
+
+    /// Authorize later requests as target account with the token.
+    pub fn authorize(&mut self, account_id: u64, token: impl Into<String>) {
+        self.auth = Some((account_id, token.into()));
+    }
+
+    /// Stop authorizing later requests.
+    pub fn deauthorize(&mut self) {
+        self.auth = None;
+    }
+
+    /// Move the frozen clock forward.
+    pub fn advance(&self, duration: chrono::Duration) {
+        crate::clock::advance(duration);
+    }
+
+    /// Take mails sent since the last call.
+    pub fn take_mails(&self) -> Vec<Mail> {
+        std::mem::take(&mut *crate::account::verify::SENT_MAILS.lock())
+    }
+
+    /// Get the code of the latest verification mail.
+    pub fn verification_code(&self) -> u32 {
+        crate::account::verify::VERIFICATION_CODE.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Issue a `GET` request.
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.send(self.request(uri, "GET").body(hyper::Body::empty()).unwrap())
+            .await
+    }
+
+    /// Issue a `POST` request with a JSON body.
+    pub async fn post(&self, uri: &str, body: &impl Serialize) -> TestResponse {
+        self.send(
+            self.request(uri, "POST")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(serde_json::to_vec(body).unwrap().into())
+                .unwrap(),
+        )
+        .await
+    }
+
+    /// Issue a prepared request as is.
+    pub async fn send(&self, request: Request<hyper::Body>) -> TestResponse {
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        TestResponse {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: hyper::body::to_bytes(response.into_body())
+                .await
+                .unwrap()
+                .to_vec(),
+        }
+    }
+
+    fn request(&self, uri: &str, method: &str) -> http::request::Builder {
+        let builder = Request::builder().uri(uri).method(method);
+        match &self.auth {
+            Some((account_id, token)) => builder
+                .header("Token", token)
+                .header("AccountId", *account_id),
+            None => builder,
+        }
+    }
+}
+
+/// A buffered response.
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// Deserialize the body as JSON.
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_slice(&self.body).unwrap_or_else(|err| {
+            panic!(
+                "{} isn't the expected JSON: {err}",
+                String::from_utf8_lossy(&self.body)
+            )
+        })
+    }
+
+    /// Get the message of an error response.
+    pub fn error(&self) -> String {
+        self.json::<serde_json::Value>()["error"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+}
+
+/// Test: an account registers, verifies, logs in and views itself through the harness.
+#[serial_test::serial]
+#[tokio::test]
+async fn smoke() {
+    use sms3_shared::account::handle::{
+        AccountCreateDescriptor, AccountLoginDescriptor, AccountLoginResult,
+        AccountVerifyDescriptor, AccountVerifyVariant, EmailAvailabilityResult, ViewAccountResult,
+    };
+    use sms3_shared::account::Permission;
+
+    let mut client = TestClient::new();
+    let email = super::fixtures::email("smoke");
+    let available = |response: TestResponse| {
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.headers[http::header::CONTENT_TYPE],
+            mime::APPLICATION_JSON.as_ref()
+        );
+        response.json::<EmailAvailabilityResult>().available
+    };
+    let uri = format!("/api/account/available?email={email}");
+    assert!(available(client.get(&uri).await));
+
+    let response = client
+        .post(
+            "/api/account/create",
+            &AccountCreateDescriptor {
+                email: email.to_string(),
+            },
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let mails = client.take_mails();
+    assert_eq!(mails.len(), 1);
+    assert_eq!(mails[0].to, email);
+
+    let response = client
+        .post(
+            "/api/account/verify",
+            &AccountVerifyDescriptor {
+                code: client.verification_code(),
+                variant: AccountVerifyVariant::Activate {
+                    email: email.clone(),
+                    name: "Jiening Yu".to_string(),
+                    id: 2522320,
+                    phone: 16601550826,
+                    house: None,
+                    organization: None,
+                    password: super::fixtures::PASSWORD.to_string(),
+                },
+            },
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(!available(client.get(&uri).await));
+    let registered = crate::clock::now();
+    client.advance(chrono::Duration::minutes(1));
+
+    // Not logged in yet.
+    let response = client.post("/api/account/view", &()).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert!(!response.error().is_empty());
+
+    let response = client
+        .post(
+            "/api/account/login",
+            &AccountLoginDescriptor {
+                email: email.clone(),
+                password: super::fixtures::PASSWORD.to_string(),
+            },
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let login: AccountLoginResult = response.json();
+    client.authorize(login.account_id, login.access_token);
+
+    let response = client.post("/api/account/view", &()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let me: ViewAccountResult = response.json();
+    assert_eq!(me.id, login.account_id);
+    assert_eq!(me.metadata.email, email);
+    assert_eq!(me.registration_time, registered);
+    assert!(!me.permissions.contains(&Permission::Op));
+
+    client.deauthorize();
+    assert_eq!(
+        client.post("/api/account/view", &()).await.status,
+        StatusCode::UNAUTHORIZED
+    );
+}
@@ -0,0 +1,1869 @@
+use super::*;
+
+use serial_test::serial;
+
+/// Test: structural writes can proceed while iterating a snapshot of ids.
+#[serial]
+#[test]
+fn snapshot_iteration() {
+    reset_all();
+
+    for i in 0..3 {
+        crate::account::INSTANCE.push(fixtures::verified_account_with_id(
+            i,
+            &format!("user{i}"),
+            &[],
+        ));
+    }
+
+    let ids = crate::account::INSTANCE.snapshot_ids();
+    assert_eq!(ids.len(), 3);
+
+    let mut visited = 0;
+    for (i, id) in ids.iter().enumerate() {
+        assert!(crate::account::INSTANCE
+            .with_account(*id, |account| account.id() == *id)
+            .unwrap());
+        visited += 1;
+
+        // A structural write from another thread must not wait for the iteration.
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            crate::account::INSTANCE.push(fixtures::verified_account_with_id(
+                100 + i as u64,
+                &format!("new{i}"),
+                &[],
+            ));
+            tx.send(()).unwrap();
+        });
+        rx.recv_timeout(std::time::Duration::from_secs(5))
+            .expect("structural write blocked by snapshot iteration");
+    }
+
+    assert_eq!(visited, 3);
+    assert_eq!(crate::account::INSTANCE.snapshot_ids().len(), 6);
+    assert!(crate::account::INSTANCE.with_account(42, |_| ()).is_none());
+}
+
+/// Test: separate loads of the same accounts iterate them in the same order, by id.
+#[test]
+fn stable_order() {
+    use crate::account::{store::FileStore, AccountManager};
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(dir.path());
+    for id in [42, 7, u64::MAX, 1000, 3] {
+        store
+            .save(&fixtures::verified_account_with_id(
+                id,
+                &format!("user{id}"),
+                &[],
+            ))
+            .unwrap();
+    }
+
+    let first = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    let second = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    assert_eq!(first.snapshot_ids(), [3, 7, 42, 1000, u64::MAX]);
+    assert_eq!(first.snapshot_ids(), second.snapshot_ids());
+
+    // Loading also sorts the inner accounts.
+    let inner: Vec<u64> = first.inner().read().iter().map(|a| a.read().id()).collect();
+    assert_eq!(inner, first.snapshot_ids());
+
+    // New accounts keep the order of ids.
+    first.insert(fixtures::verified_account_with_id(5, "user5", &[]));
+    assert_eq!(first.snapshot_ids(), [3, 5, 7, 42, 1000, u64::MAX]);
+}
+
+/// Test: loading preallocates room for the expected count of accounts,
+/// so inserting up to it doesn't reallocate.
+#[test]
+fn load_with_capacity() {
+    use crate::account::{store::FileStore, AccountManager};
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(dir.path());
+    for id in 1..=3 {
+        store
+            .save(&fixtures::verified_account_with_id(
+                id,
+                &format!("user{id}"),
+                &[],
+            ))
+            .unwrap();
+    }
+
+    let manager = AccountManager::load_with_capacity(FileStore::new(dir.path()), 100).unwrap();
+    let capacity = manager.capacity();
+    assert!(capacity >= 100);
+    for id in 4..=100 {
+        manager.insert(fixtures::verified_account_with_id(
+            id,
+            &format!("user{id}"),
+            &[],
+        ));
+    }
+    assert_eq!(manager.snapshot_ids().len(), 100);
+    assert_eq!(manager.capacity(), capacity);
+
+    // Without a hint there is room for the loaded accounts.
+    let manager = AccountManager::load_with_capacity(FileStore::new(dir.path()), 0).unwrap();
+    assert!(manager.capacity() >= 3);
+    assert_eq!(manager.snapshot_ids(), [1, 2, 3]);
+}
+
+/// Test: refreshing an expiring account concurrently with guards on it
+/// and its removal and registration neither deadlocks nor leaves the index stale.
+#[serial]
+#[test]
+fn refresh_concurrently() {
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let pending = fixtures::pending_account("pending");
+    let pending_id = pending.id();
+    let first = fixtures::verified_account("first", &[]);
+    let first_id = first.id();
+    let manager = fixtures::manager_with([
+        first,
+        pending,
+        fixtures::verified_account("second", &[]),
+        fixtures::verified_account("third", &[]),
+    ]);
+    crate::clock::advance(chrono::Duration::minutes(16));
+
+    std::thread::scope(|s| {
+        // Register the expired account again whenever it's removed.
+        s.spawn(|| {
+            for _ in 0..500 {
+                if manager.get(pending_id).is_none() {
+                    let mut pending = fixtures::pending_account("pending");
+                    if let crate::account::Account::Unverified(cxt) = &mut pending {
+                        cxt.expire_time = crate::clock::now();
+                    }
+                    manager.insert(pending);
+                }
+                std::thread::yield_now();
+            }
+        });
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..500 {
+                    manager.refresh(pending_id);
+                }
+            });
+        }
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..500 {
+                    if let Some(account) = manager.get(pending_id) {
+                        assert_eq!(account.id(), pending_id);
+                    }
+                    // Holding an expired account doesn't stall other accounts.
+                    if let Some(account) = manager.get_mut(pending_id) {
+                        std::thread::yield_now();
+                        assert_eq!(account.id(), pending_id);
+                    }
+                    assert_eq!(manager.get(first_id).unwrap().id(), first_id);
+                }
+            });
+        }
+    });
+
+    manager.refresh(pending_id);
+    assert!(manager.get(pending_id).is_none());
+    let ids = manager.snapshot_ids();
+    assert_eq!(ids.len(), 3);
+    for id in ids {
+        assert_eq!(manager.get(id).unwrap().id(), id);
+    }
+}
+
+/// Test: read guards from `AccountManager::get`, including concurrent ones.
+#[serial]
+#[test]
+fn get() {
+    reset_all();
+
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(1, "user1", &[]));
+
+    let first = crate::account::INSTANCE.get(1).unwrap();
+    assert_eq!(first.id(), 1);
+    assert!(crate::account::INSTANCE.get(2).is_none());
+
+    // Another reader from a different thread isn't blocked by `first`.
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let second = crate::account::INSTANCE.get(1).unwrap();
+        tx.send(second.email().to_string()).unwrap();
+    });
+    assert_eq!(
+        rx.recv_timeout(std::time::Duration::from_secs(5)).unwrap(),
+        "user1@i.pkuschool.edu.cn"
+    );
+    assert_eq!(first.email().user(), "user1");
+}
+
+/// Test: `AccountManager::get_mut` saves the account only if it was mutated.
+#[test]
+fn get_mut_auto_save() {
+    use crate::account::{store::FileStore, Account, AccountManager};
+    use std::ops::DerefMut;
+
+    let dir = tempfile::tempdir().unwrap();
+    FileStore::new(dir.path())
+        .save(&fixtures::verified_account_with_id(1, "user1", &[]))
+        .unwrap();
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    let path = manager.store().unwrap().file_path(1);
+
+    // Reading through a write guard doesn't save.
+    std::fs::remove_file(&path).unwrap();
+    {
+        let account = manager.get_mut(1).unwrap();
+        assert_eq!(account.id(), 1);
+    }
+    assert!(!path.exists());
+
+    {
+        let mut account = manager.get_mut(1).unwrap();
+        if let Account::Verified { attributes, .. } = account.deref_mut() {
+            attributes.name = "Tianyang He".to_string();
+        }
+    }
+
+    let saved: Account = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved.metadata().unwrap().name, "Tianyang He");
+    assert_eq!(
+        manager.get(1).unwrap().metadata().unwrap().name,
+        "Tianyang He"
+    );
+}
+
+/// Test: resetting a password revokes the old tokens in the store.
+#[serial]
+#[test]
+fn reset_password_revokes_tokens() {
+    use crate::account::{
+        store::FileStore, verify::Context, Account, AccountManager, AccountVerifyVariant,
+        UserVerifyVariant,
+    };
+
+    reset_all();
+    let dir = tempfile::tempdir().unwrap();
+    let mut account = fixtures::verified_account_with_id(1, "user1", &[]);
+    let token = fixtures::new_token(&mut account);
+    let cxt = Context::new(account.email().clone());
+    let code = cxt.code;
+    if let Account::Verified { verify, .. } = &mut account {
+        *verify = UserVerifyVariant::ForgetPassword(cxt);
+    }
+    FileStore::new(dir.path()).save(&account).unwrap();
+
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    manager
+        .get_mut(1)
+        .unwrap()
+        .verify(
+            code,
+            AccountVerifyVariant::ResetPassword("newpassword".to_string()),
+        )
+        .unwrap();
+
+    let accounts = FileStore::new(dir.path()).load_all().unwrap();
+    let Account::Verified { tokens, verify, .. } = &accounts[0] else {
+        panic!("not verified");
+    };
+    assert!(!tokens.token_usable(&token));
+    assert!(matches!(verify, UserVerifyVariant::None));
+}
+
+/// Test: a manager loaded from a custom directory stores files there.
+#[serial]
+#[test]
+fn data_dir() {
+    use crate::account::{store::FileStore, Account, AccountManager};
+    use std::ops::DerefMut;
+
+    let dir = tempfile::tempdir().unwrap();
+
+    std::env::set_var(crate::config::DATA_DIR_VAR, dir.path());
+    assert_eq!(crate::config::data_dir(), dir.path());
+    std::env::remove_var(crate::config::DATA_DIR_VAR);
+    assert_eq!(crate::config::data_dir(), std::path::Path::new("./data"));
+
+    // The directory is created while loading.
+    let accounts_dir = dir.path().join("accounts");
+    assert!(AccountManager::load(FileStore::new(&accounts_dir))
+        .unwrap()
+        .snapshot_ids()
+        .is_empty());
+    assert!(accounts_dir.is_dir());
+
+    FileStore::new(&accounts_dir)
+        .save(&fixtures::verified_account_with_id(7, "user7", &[]))
+        .unwrap();
+    let manager = AccountManager::load(FileStore::new(&accounts_dir)).unwrap();
+
+    if let Account::Verified { attributes, .. } = manager.get_mut(7).unwrap().deref_mut() {
+        attributes.name = "Tianyang He".to_string();
+    }
+
+    assert!(accounts_dir.join("7.toml").is_file());
+    assert!(!std::path::Path::new("./data/accounts/7.toml").exists());
+}
+
+/// Test: removing returns the metadata of the removed account, or `NotFound`.
+#[serial]
+#[test]
+fn remove() {
+    reset_all();
+
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(1, "user1", &[]));
+
+    let metadata = crate::account::INSTANCE.remove(1).unwrap().unwrap();
+    assert_eq!(metadata.email.user(), "user1");
+    assert!(crate::account::INSTANCE.get(1).is_none());
+
+    assert!(matches!(
+        crate::account::INSTANCE.remove(1),
+        Err(crate::account::ManagerError::NotFound(1))
+    ));
+}
+
+/// Test: lookups of remaining accounts never fail while others are removed.
+#[serial]
+#[test]
+fn lookup_during_remove() {
+    reset_all();
+
+    for i in 0..200 {
+        crate::account::INSTANCE.push(fixtures::verified_account_with_id(
+            i,
+            &format!("user{i}"),
+            &[],
+        ));
+    }
+
+    let remover = std::thread::spawn(|| {
+        for i in (0..200).step_by(2) {
+            crate::account::INSTANCE.remove(i).unwrap();
+        }
+    });
+
+    while !remover.is_finished() {
+        for i in (1..200).step_by(2) {
+            assert_eq!(
+                crate::account::INSTANCE
+                    .get(i)
+                    .unwrap_or_else(|| panic!("account {i} not found"))
+                    .id(),
+                i
+            );
+        }
+    }
+    remover.join().unwrap();
+
+    let mut ids = crate::account::INSTANCE.snapshot_ids();
+    ids.sort_unstable();
+    assert_eq!(ids, (1..200).step_by(2).collect::<Vec<_>>());
+    for id in ids {
+        assert_eq!(crate::account::INSTANCE.get(id).unwrap().id(), id);
+    }
+}
+
+/// Test: bulk grants save changed accounts and persist audit entries.
+#[test]
+fn grant_permission_bulk() {
+    use crate::account::{store::FileStore, AccountManager, ManagerError, Permission};
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(dir.path());
+    store
+        .save(&fixtures::verified_account_with_id(1, "user1", &[]))
+        .unwrap();
+    store
+        .save(&fixtures::verified_account_with_id(2, "user2", &[]))
+        .unwrap();
+
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    let stale_generation = manager.store().unwrap().next_generation();
+    let results = manager.grant_permission_bulk(&[1, 3, 2, 1], Permission::Post, 7);
+
+    assert!(results[0].is_ok());
+    assert!(matches!(results[1], Err(ManagerError::NotFound(3))));
+    assert!(results[2].is_ok());
+    assert!(results[3].is_ok());
+
+    // Granting twice changes nothing.
+    assert_eq!(manager.audit().entries().len(), 2);
+
+    // Saves are generations, so a write taken before the grant can't replace it.
+    let store = manager.store().unwrap();
+    store
+        .write_generation(
+            1,
+            stale_generation,
+            store
+                .serialize(&fixtures::verified_account_with_id(1, "user1", &[]))
+                .unwrap(),
+        )
+        .unwrap();
+
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    assert_eq!(manager.snapshot_ids().len(), 2);
+    for id in [1, 2] {
+        assert_eq!(manager.get(id).unwrap().permissions(), [Permission::Post]);
+        assert_eq!(manager.audit().concerning(id).len(), 1);
+    }
+    assert_eq!(manager.audit().concerning(7).len(), 2);
+}
+
+/// Test: permission changes are audited as the permissions added and removed.
+#[test]
+fn permission_diff() {
+    use crate::account::audit::{Action, PermissionDiff};
+    use crate::account::{store::FileStore, Account, AccountManager, Permission};
+
+    assert_eq!(
+        PermissionDiff::between(
+            &[Permission::Post, Permission::View],
+            &[Permission::View, Permission::Check, Permission::Approve],
+        ),
+        PermissionDiff {
+            added: vec![Permission::Approve, Permission::Check],
+            removed: vec![Permission::Post],
+        }
+    );
+    assert!(PermissionDiff::between(&[Permission::View], &[Permission::View]).is_empty());
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut account = fixtures::verified_account_with_id(1, "user1", &[]);
+    if let Account::Verified { attributes, .. } = &mut account {
+        attributes.set_permissions(vec![Permission::View, Permission::Post, Permission::Check]);
+    }
+    FileStore::new(dir.path()).save(&account).unwrap();
+
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    assert!(manager.grant_permission_bulk(&[1], Permission::ViewAccounts, 7)[0].is_ok());
+    let entries = manager.audit().concerning(1);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0].action,
+        Action::ChangePermissions(PermissionDiff {
+            added: vec![Permission::ViewAccounts],
+            removed: vec![],
+        })
+    );
+}
+
+/// Test: expire times beyond the horizon are clamped when created and loaded,
+/// while normal ones pass through.
+#[serial]
+#[test]
+fn expiry_horizon() {
+    use crate::account::verify::{Context, Device, Tokens};
+    use crate::account::{email_id, store::FileStore, Account, AccountManager, UserVerifyVariant};
+    use chrono::Duration;
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+    let now = crate::clock::now();
+    let horizon = now + Duration::days(crate::config::INSTANCE.account.max_expiry_days as i64);
+
+    let mut tokens = Tokens::new();
+    tokens
+        .new_token_with(u16::MAX, Device::default(), 0)
+        .unwrap();
+    tokens.new_token_with(7, Device::default(), 0).unwrap();
+    let mut expire_times: Vec<_> = tokens.hashes().map(|(_, e)| e.unwrap()).collect();
+    expire_times.sort();
+    assert_eq!(expire_times, [now + Duration::days(7), horizon]);
+
+    let context = |user: &str, expire_time| Context {
+        email: lettre::Address::new(user, "i.pkuschool.edu.cn").unwrap(),
+        code: 0,
+        expire_time,
+    };
+    let far = chrono::NaiveDate::from_ymd_opt(9999, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc();
+    let normal = now + Duration::minutes(15);
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(dir.path());
+    let mut verified = fixtures::verified_account_with_id(1, "user1", &[]);
+    if let Account::Verified { verify, .. } = &mut verified {
+        *verify = UserVerifyVariant::ForgetPassword(context("user1", far));
+    }
+    store.save(&verified).unwrap();
+    store
+        .save(&Account::Unverified(context("user2", far)))
+        .unwrap();
+    store
+        .save(&Account::Unverified(context("user3", normal)))
+        .unwrap();
+
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    let expire_time = |id| match &*manager.get(id).unwrap() {
+        Account::Unverified(cxt) => cxt.expire_time,
+        Account::Verified {
+            verify: UserVerifyVariant::ForgetPassword(cxt),
+            ..
+        } => cxt.expire_time,
+        Account::Verified { .. } => unreachable!(),
+    };
+    let id = |user| email_id(&lettre::Address::new(user, "i.pkuschool.edu.cn").unwrap());
+    assert_eq!(expire_time(1), horizon);
+    assert_eq!(expire_time(id("user2")), horizon);
+    assert_eq!(expire_time(id("user3")), normal);
+}
+
+/// Test: searching accounts by part of their names.
+#[serial]
+#[test]
+fn search_by_name() {
+    reset_all();
+
+    for (id, name) in [
+        (1, "Jiening Yu"),
+        (2, "Yuguo Ma"),
+        (3, "Tianyang He"),
+        (4, "yu"),
+        (5, "Anya Yudina"),
+    ] {
+        let mut account = fixtures::verified_account_with_id(id, &format!("user{id}"), &[]);
+        if let crate::account::Account::Verified { attributes, .. } = &mut account {
+            attributes.name = name.to_string();
+        }
+        crate::account::INSTANCE.push(account);
+    }
+    crate::account::INSTANCE.push(crate::account::Account::Unverified(
+        crate::account::verify::Context {
+            email: lettre::Address::new("yuunverified", "i.pkuschool.edu.cn").unwrap(),
+            code: 123456,
+            expire_time: chrono::Utc::now() + chrono::Duration::minutes(15),
+        },
+    ));
+
+    let names = |query: &str, limit: usize| -> Vec<String> {
+        crate::account::INSTANCE
+            .search_by_name(query, limit)
+            .into_iter()
+            .map(|metadata| metadata.name)
+            .collect()
+    };
+
+    // Prefix matches come first, case-insensitively.
+    assert_eq!(
+        names("YU", 10),
+        ["Yuguo Ma", "yu", "Anya Yudina", "Jiening Yu"]
+    );
+    assert_eq!(names("yu", 2), ["Yuguo Ma", "yu"]);
+    assert_eq!(names("ang", 10), ["Tianyang He"]);
+    assert!(names("nobody", 10).is_empty());
+    assert!(names("yu", 0).is_empty());
+}
+
+/// Test: filtering accounts by house and organization.
+#[serial]
+#[test]
+fn filter_by_house_and_organization() {
+    use sms3_shared::account::House;
+
+    reset_all();
+
+    for (id, house, organization) in [
+        (1, Some(House::from("ZhiZhi")), Some("SubIT")),
+        (2, Some(House::from("GeWu")), Some("SubIT")),
+        (3, Some(House::from("ZhiZhi")), None),
+        (4, None, Some("Student Union")),
+        (5, Some(House::from("MingDe")), Some("subit")),
+    ] {
+        let mut account = fixtures::verified_account_with_id(id, &format!("user{id}"), &[]);
+        if let crate::account::Account::Verified { attributes, .. } = &mut account {
+            attributes.name = format!("User {id}");
+            attributes.house = house;
+            attributes.organization = organization.map(str::to_string);
+        }
+        crate::account::INSTANCE.push(account);
+    }
+
+    let users = |metadata: Vec<sms3_shared::account::UserMetadata>| -> Vec<String> {
+        metadata
+            .into_iter()
+            .map(|m| m.email.user().to_string())
+            .collect()
+    };
+
+    assert_eq!(
+        users(crate::account::INSTANCE.by_house(House::from("ZhiZhi"))),
+        ["user1", "user3"]
+    );
+    assert_eq!(
+        users(crate::account::INSTANCE.by_house(House::from("GeWu"))),
+        ["user2"]
+    );
+    assert!(crate::account::INSTANCE
+        .by_house(House::from("XinMin"))
+        .is_empty());
+
+    assert_eq!(
+        users(crate::account::INSTANCE.by_organization("SubIT")),
+        ["user1", "user2"]
+    );
+    assert_eq!(
+        users(crate::account::INSTANCE.by_organization("Student Union")),
+        ["user4"]
+    );
+    assert!(crate::account::INSTANCE.by_organization("").is_empty());
+}
+
+/// Test: account files encrypted with a key can only be read with the same key.
+#[test]
+fn encrypted_store() {
+    use crate::account::store::FileStore;
+
+    let dir = tempfile::tempdir().unwrap();
+    let key = [7; 32];
+
+    FileStore::new(dir.path())
+        .with_key(&key)
+        .save(&fixtures::verified_account_with_id(1, "user1", &[]))
+        .unwrap();
+
+    let data = std::fs::read_to_string(dir.path().join("1.toml")).unwrap();
+    assert!(!data.contains("user1"));
+    assert!(!data.contains("Jiening Yu"));
+
+    let accounts = FileStore::new(dir.path())
+        .with_key(&key)
+        .load_all()
+        .unwrap();
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].id(), 1);
+    assert_eq!(accounts[0].email().user(), "user1");
+
+    let err = FileStore::new(dir.path())
+        .with_key(&[8; 32])
+        .load_all()
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(FileStore::new(dir.path()).load_all().is_err());
+
+    // Plaintext files are still readable with a key.
+    FileStore::new(dir.path())
+        .save(&fixtures::verified_account_with_id(2, "user2", &[]))
+        .unwrap();
+    std::fs::remove_file(dir.path().join("1.toml")).unwrap();
+    let accounts = FileStore::new(dir.path())
+        .with_key(&key)
+        .load_all()
+        .unwrap();
+    assert_eq!(accounts[0].email().user(), "user2");
+}
+
+/// Test: concurrent writers of the same account never leave an interleaved file.
+#[test]
+fn concurrent_writers() {
+    use crate::account::{store::FileStore, Account};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_path_buf();
+
+    let accounts: Vec<String> = ["A", "B"]
+        .iter()
+        .map(|c| {
+            let mut account = fixtures::verified_account_with_id(1, "user1", &[]);
+            if let Account::Verified { attributes, .. } = &mut account {
+                attributes.name = c.repeat(64 * 1024);
+            }
+            toml::to_string(&account).unwrap()
+        })
+        .collect();
+
+    let writers: Vec<_> = accounts
+        .iter()
+        .cloned()
+        .map(|data| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let store = FileStore::new(path);
+                for _ in 0..50 {
+                    store.write(1, &data).unwrap();
+                }
+            })
+        })
+        .collect();
+
+    // The first write may not have happened yet.
+    while !dir.path().join("1.toml").exists() {
+        std::thread::yield_now();
+    }
+
+    let store = FileStore::new(dir.path());
+    while writers.iter().any(|w| !w.is_finished()) {
+        let loaded = store.load_all().unwrap();
+        assert_eq!(loaded.len(), 1);
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let data = std::fs::read_to_string(dir.path().join("1.toml")).unwrap();
+    assert!(accounts.contains(&data));
+
+    // Bulk locks exclude single writes from other handles.
+    let lock = store.lock_all().unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let writer = {
+        let data = accounts[0].clone();
+        std::thread::spawn(move || {
+            FileStore::new(path).write(1, &data).unwrap();
+            tx.send(()).unwrap();
+        })
+    };
+    assert!(rx
+        .recv_timeout(std::time::Duration::from_millis(200))
+        .is_err());
+    lock.write(1, &accounts[1]).unwrap();
+    drop(lock);
+    rx.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+    writer.join().unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("1.toml")).unwrap(),
+        accounts[0]
+    );
+}
+
+/// Test: corrupted account files are quarantined while valid ones load.
+#[test]
+fn quarantine_corrupted() {
+    use crate::account::{store::FileStore, AccountManager};
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(dir.path());
+    store
+        .save(&fixtures::verified_account_with_id(1, "user1", &[]))
+        .unwrap();
+    std::fs::write(dir.path().join("2.toml"), "[Verified]\nid = \"2\"\nname = ").unwrap();
+
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    assert_eq!(manager.snapshot_ids(), [1]);
+
+    assert!(!dir.path().join("2.toml").exists());
+    let quarantined: Vec<String> = std::fs::read_dir(store.quarantine_path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(quarantined.len(), 1);
+    assert!(quarantined[0].starts_with("2.toml."));
+
+    // Quarantined files are left alone on the next load.
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    assert_eq!(manager.snapshot_ids(), [1]);
+    assert_eq!(
+        std::fs::read_dir(store.quarantine_path()).unwrap().count(),
+        1
+    );
+}
+
+/// Test: compressed stores round-trip accounts in smaller files and read
+/// directories mixing both formats.
+#[test]
+fn compressed_store() {
+    use crate::account::store::FileStore;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut account = fixtures::verified_account_with_id(1, "user1", &[]);
+    if let crate::account::Account::Verified { attributes, .. } = &mut account {
+        attributes.organization = Some("Student Union ".repeat(16));
+    }
+
+    FileStore::new(dir.path()).save(&account).unwrap();
+    let plain_size = std::fs::metadata(dir.path().join("1.toml")).unwrap().len();
+
+    let store = FileStore::new(dir.path()).compressed();
+    store
+        .save(&fixtures::verified_account_with_id(2, "user2", &[]))
+        .unwrap();
+    assert!(dir.path().join("2.toml.zst").is_file());
+
+    // Mixed directory during migration.
+    let mut emails: Vec<String> = store
+        .load_all()
+        .unwrap()
+        .iter()
+        .map(|a| a.email().to_string())
+        .collect();
+    emails.sort();
+    assert_eq!(
+        emails,
+        ["user1@i.pkuschool.edu.cn", "user2@i.pkuschool.edu.cn"]
+    );
+
+    // Rewriting migrates the file.
+    store.save(&account).unwrap();
+    assert!(!dir.path().join("1.toml").exists());
+    let compressed_size = std::fs::metadata(dir.path().join("1.toml.zst"))
+        .unwrap()
+        .len();
+    assert!(compressed_size < plain_size);
+
+    let accounts = store.load_all().unwrap();
+    assert_eq!(accounts.len(), 2);
+    assert!(accounts
+        .iter()
+        .any(|a| a.metadata().unwrap().organization == Some("Student Union ".repeat(16))));
+
+    // Compression combines with encryption.
+    let key = [7; 32];
+    let store = FileStore::new(dir.path()).compressed().with_key(&key);
+    store.save(&account).unwrap();
+    assert!(FileStore::new(dir.path()).compressed().load_all().is_err());
+    assert_eq!(store.load_all().unwrap().len(), 2);
+
+    store.remove(1).unwrap();
+    assert_eq!(store.load_all().unwrap().len(), 1);
+}
+
+/// Test: accounts round-trip through every file format, and directories
+/// mixing formats load during migration.
+#[serial]
+#[test]
+fn file_formats() {
+    use crate::account::store::{FileStore, Format};
+
+    let mut account = fixtures::verified_account_with_id(1, "user1", &[]);
+    if let crate::account::Account::Verified { tokens, .. } = &mut account {
+        let _ = tokens.new_token(5).unwrap();
+    }
+    let expected = toml::to_string(&account).unwrap();
+
+    for (format, file) in [
+        (Format::Toml, "1.toml"),
+        (Format::Json, "1.json"),
+        (Format::Bincode, "1.bin"),
+    ] {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStore::new(dir.path()).with_format(format);
+        store.save(&account).unwrap();
+        assert!(dir.path().join(file).is_file());
+
+        let accounts = store.load_all().unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(toml::to_string(&accounts[0]).unwrap(), expected);
+    }
+
+    // Mixed directory during migration.
+    let dir = tempfile::tempdir().unwrap();
+    FileStore::new(dir.path()).save(&account).unwrap();
+    FileStore::new(dir.path())
+        .with_format(Format::Json)
+        .save(&fixtures::verified_account_with_id(2, "user2", &[]))
+        .unwrap();
+
+    let store = FileStore::new(dir.path()).with_format(Format::Bincode);
+    assert_eq!(store.load_all().unwrap().len(), 2);
+
+    // Rewriting migrates the file.
+    store.save(&account).unwrap();
+    assert!(!dir.path().join("1.toml").exists());
+    assert!(dir.path().join("1.bin").is_file());
+    assert_eq!(store.load_all().unwrap().len(), 2);
+
+    store.remove(2).unwrap();
+    assert!(!dir.path().join("2.json").exists());
+    assert_eq!(store.load_all().unwrap().len(), 1);
+}
+
+/// Test: statistics aggregate a known population.
+#[serial]
+#[test]
+fn statistics() {
+    use sms3_shared::account::{House, Permission};
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let with = |id: u64, house: Option<House>, permissions: Vec<Permission>| {
+        let mut account = fixtures::verified_account_with_id(id, &format!("user{id}"), &[]);
+        if let crate::account::Account::Verified { attributes, .. } = &mut account {
+            attributes.house = house;
+            attributes.permissions = permissions;
+        }
+        account
+    };
+
+    let mut account = with(1, Some(House::from("ZhiZhi")), vec![Permission::View]);
+    if let crate::account::Account::Verified { tokens, verify, .. } = &mut account {
+        let _ = tokens.new_token(0).unwrap();
+        let _ = tokens.new_token(1).unwrap();
+        *verify = crate::account::UserVerifyVariant::ForgetPassword(
+            crate::account::verify::Context::new(
+                lettre::Address::new("user1", "i.pkuschool.edu.cn").unwrap(),
+            ),
+        );
+    }
+    crate::account::INSTANCE.push(account);
+
+    let mut account = with(
+        2,
+        Some(House::from("ZhiZhi")),
+        vec![Permission::View, Permission::Post],
+    );
+    if let crate::account::Account::Verified { tokens, .. } = &mut account {
+        let _ = tokens.new_token(0).unwrap();
+    }
+    crate::account::INSTANCE.push(account);
+
+    crate::account::INSTANCE.push(with(3, Some(House::from("GeWu")), vec![Permission::View]));
+    crate::account::INSTANCE.push(with(4, None, vec![]));
+    crate::account::INSTANCE.push(crate::account::Account::Unverified(
+        crate::account::verify::Context::new(
+            lettre::Address::new("pending", "i.pkuschool.edu.cn").unwrap(),
+        ),
+    ));
+
+    // One token of account 1 expires.
+    crate::clock::advance(chrono::Duration::days(2));
+
+    let stats = crate::account::INSTANCE.statistics();
+    assert_eq!(stats.total, 5);
+    assert_eq!(stats.verified, 4);
+    assert_eq!(stats.pending, 1);
+    // The verification of the pending account has expired too.
+    assert_eq!(stats.pending_unexpired, 0);
+    assert_eq!(
+        stats.by_house,
+        [(House::from("ZhiZhi"), 2), (House::from("GeWu"), 1)]
+            .into_iter()
+            .collect()
+    );
+    assert_eq!(stats.without_house, 1);
+    assert_eq!(
+        stats.by_permission,
+        [(Permission::View, 3), (Permission::Post, 1)]
+            .into_iter()
+            .collect()
+    );
+    assert_eq!(stats.active_tokens, 2);
+    // The password reset has expired too.
+    assert_eq!(stats.pending_password_resets, 0);
+
+    let mut account = with(5, None, vec![]);
+    if let crate::account::Account::Verified { verify, .. } = &mut account {
+        *verify = crate::account::UserVerifyVariant::ForgetPassword(
+            crate::account::verify::Context::new(
+                lettre::Address::new("user5", "i.pkuschool.edu.cn").unwrap(),
+            ),
+        );
+    }
+    crate::account::INSTANCE.push(account);
+    assert_eq!(
+        crate::account::INSTANCE
+            .statistics()
+            .pending_password_resets,
+        1
+    );
+}
+
+/// Test: integrity checks flag a corrupted index without touching it, and repairing rebuilds it.
+#[serial]
+#[test]
+fn verify_integrity() {
+    use std::hash::{Hash, Hasher};
+
+    reset_all();
+
+    let derived = |user: &str, school_id: u32| {
+        let email = lettre::Address::new(user, "i.pkuschool.edu.cn").unwrap();
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        email.hash(&mut hasher);
+        let mut account = fixtures::verified_account_with_id(hasher.finish(), user, &[]);
+        if let crate::account::Account::Verified { attributes, .. } = &mut account {
+            attributes.school_id = school_id;
+        }
+        account
+    };
+
+    let first = derived("user1", 1);
+    let second = derived("user2", 2);
+    let (first_id, second_id) = (first.id(), second.id());
+    crate::account::INSTANCE.push(first);
+    crate::account::INSTANCE.push(second);
+    assert!(crate::account::INSTANCE.verify_integrity().is_ok());
+
+    // Point the first id at the second account and leave an entry for a missing account.
+    crate::account::INSTANCE.index().insert(first_id, 1);
+    crate::account::INSTANCE.index().insert(42, 0);
+
+    let report = crate::account::INSTANCE.verify_integrity();
+    assert_eq!(report.index_mismatches, vec![first_id]);
+    assert_eq!(report.dangling_index, vec![42]);
+    assert!(report.duplicate_ids.is_empty());
+    assert!(report.id_mismatches.is_empty());
+    // Verifying does not mutate the index.
+    assert_eq!(*crate::account::INSTANCE.index().get(&first_id).unwrap(), 1);
+
+    // An account missing from the index and the token index.
+    let mut untracked = derived("user4", 4);
+    let token = fixtures::new_token(&mut untracked);
+    let untracked_id = untracked.id();
+    crate::account::INSTANCE
+        .inner()
+        .write()
+        .push(std::sync::Arc::new(parking_lot::RwLock::new(untracked)));
+    assert_eq!(crate::account::INSTANCE.find_by_token(&token), None);
+
+    crate::account::INSTANCE.repair();
+    assert!(crate::account::INSTANCE.verify_integrity().is_ok());
+    assert_eq!(
+        crate::account::INSTANCE.find_by_token(&token),
+        Some(untracked_id)
+    );
+    assert_eq!(
+        crate::account::INSTANCE.get(first_id).unwrap().id(),
+        first_id
+    );
+    assert_eq!(
+        crate::account::INSTANCE.get(second_id).unwrap().id(),
+        second_id
+    );
+
+    // An id not derived from its email, reusing the email and school id of another account.
+    let mut account = fixtures::verified_account_with_id(3, "user1", &[]);
+    if let crate::account::Account::Verified { attributes, .. } = &mut account {
+        attributes.school_id = 2;
+    }
+    crate::account::INSTANCE.push(account);
+
+    let report = crate::account::INSTANCE.verify_integrity();
+    assert_eq!(report.id_mismatches, vec![3]);
+    assert_eq!(
+        report.duplicate_emails,
+        vec![lettre::Address::new("user1", "i.pkuschool.edu.cn").unwrap()]
+    );
+    assert_eq!(report.duplicate_school_ids, vec![2]);
+    assert!(report.index_mismatches.is_empty());
+}
+
+/// Test: permissions stay sorted without duplicates however they are assigned.
+#[test]
+fn permission_normalization() {
+    use crate::account::{store::FileStore, Account, AccountManager, Permission};
+
+    let mut account = fixtures::verified_account_with_id(1, "user1", &[]);
+    let Account::Verified { attributes, .. } = &mut account else {
+        unreachable!()
+    };
+
+    assert!(attributes.grant_permission(Permission::View));
+    assert!(!attributes.grant_permission(Permission::View));
+    assert!(attributes.grant_permission(Permission::Approve));
+    assert_eq!(
+        attributes.permissions,
+        [Permission::Approve, Permission::View]
+    );
+
+    attributes.set_permissions(vec![
+        Permission::View,
+        Permission::Post,
+        Permission::View,
+        Permission::Check,
+    ]);
+    assert_eq!(
+        attributes.permissions,
+        [Permission::Check, Permission::Post, Permission::View]
+    );
+
+    assert!(attributes.revoke_permission(Permission::Post));
+    assert!(!attributes.revoke_permission(Permission::Post));
+    assert!(account.has_permission(Permission::View));
+    assert!(account.has_permission(Permission::Check));
+    assert!(!account.has_permission(Permission::Post));
+
+    // Duplicates in stored files are dropped on load.
+    if let Account::Verified { attributes, .. } = &mut account {
+        attributes.permissions = vec![Permission::View, Permission::Check, Permission::View];
+    }
+    let dir = tempfile::tempdir().unwrap();
+    FileStore::new(dir.path()).save(&account).unwrap();
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    assert_eq!(
+        manager.get(1).unwrap().permissions(),
+        [Permission::Check, Permission::View]
+    );
+}
+
+/// Test: accounts are equal and hashed by id only.
+#[test]
+fn account_identity() {
+    use std::collections::HashSet;
+
+    let account = fixtures::verified_account_with_id(1, "user1", &[]);
+    let mut renamed = fixtures::verified_account_with_id(1, "user2", &[]);
+    if let crate::account::Account::Verified { attributes, .. } = &mut renamed {
+        attributes.name = "Yuguo Ma".to_string();
+    }
+    let other = fixtures::verified_account_with_id(2, "user1", &[]);
+
+    assert_eq!(account, renamed);
+    assert_ne!(account, other);
+
+    let set: HashSet<_> = [account, renamed, other].into_iter().collect();
+    assert_eq!(set.len(), 2);
+
+    let unverified = crate::account::Account::Unverified(crate::account::verify::Context::new(
+        lettre::Address::new("pending", "i.pkuschool.edu.cn").unwrap(),
+    ));
+    assert_eq!(
+        unverified,
+        fixtures::verified_account_with_id(unverified.id(), "someone_else", &[])
+    );
+}
+
+/// Test: email-derived ids are stable, and random ids are unrelated to the email.
+#[serial]
+#[test]
+fn id_strategy() {
+    use crate::config::IdStrategy;
+
+    reset_all();
+    let assign_id = |strategy, email| crate::account::INSTANCE.assign_id(strategy, email);
+
+    let email = lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap();
+    let upper = lettre::Address::new("YuJiening2025", "i.pkuschool.edu.cn").unwrap();
+
+    // Fixed across runs and releases, as stored accounts rely on it.
+    assert_eq!(
+        assign_id(IdStrategy::EmailHash, &email),
+        11333005080735600197
+    );
+    assert_eq!(
+        assign_id(IdStrategy::EmailHash, &upper),
+        assign_id(IdStrategy::EmailHash, &email)
+    );
+
+    assert_ne!(
+        assign_id(IdStrategy::Random, &email),
+        assign_id(IdStrategy::Random, &email)
+    );
+
+    // Verifying with a random id moves the index entry of the unverified account.
+    crate::account::INSTANCE.push(crate::account::Account::Unverified(
+        crate::account::verify::Context::new(email.clone()),
+    ));
+    let old = crate::account::email_id(&email);
+    let new = assign_id(IdStrategy::Random, &email);
+    crate::account::INSTANCE.reassign(old, new);
+    assert!(crate::account::INSTANCE.index().get(&old).is_none());
+    assert_eq!(*crate::account::INSTANCE.index().get(&new).unwrap(), 0);
+}
+
+/// Test: only unexpired unverified accounts count as pending.
+#[serial]
+#[test]
+fn pending_count() {
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let unverified = |user: &str| {
+        crate::account::Account::Unverified(crate::account::verify::Context::new(
+            lettre::Address::new(user, "i.pkuschool.edu.cn").unwrap(),
+        ))
+    };
+
+    crate::account::INSTANCE.push(unverified("expired1"));
+    crate::account::INSTANCE.push(unverified("expired2"));
+    crate::clock::advance(chrono::Duration::days(1));
+
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(1, "user1", &[]));
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(2, "user2", &[]));
+    crate::account::INSTANCE.push(unverified("pending1"));
+    crate::account::INSTANCE.push(unverified("pending2"));
+    crate::account::INSTANCE.push(unverified("pending3"));
+
+    assert_eq!(crate::account::INSTANCE.pending_count(), 3);
+
+    let stats = crate::account::INSTANCE.statistics();
+    assert_eq!(stats.pending, 5);
+    assert_eq!(stats.pending_unexpired, 3);
+}
+
+/// Test: a refresh dry run changes nothing and matches the following sweep.
+#[serial]
+#[test]
+fn refresh_dry_run() {
+    use crate::account::{Account, UserVerifyVariant};
+    use sms3_shared::account::Permission;
+
+    reset_all();
+    crate::clock::set(chrono::Utc::now());
+
+    let mut account = fixtures::verified_account_with_id(1, "user1", &[]);
+    if let Account::Verified { tokens, verify, .. } = &mut account {
+        let _ = tokens.new_token(1).unwrap();
+        let _ = tokens.new_token(0).unwrap();
+        *verify = UserVerifyVariant::ForgetPassword(crate::account::verify::Context::new(
+            lettre::Address::new("user1", "i.pkuschool.edu.cn").unwrap(),
+        ));
+    }
+    crate::account::INSTANCE.push(account);
+
+    // Tokens of sensitive accounts are clamped before expired ones are pruned.
+    let mut account = fixtures::verified_account_with_id(2, "user2", &[]);
+    if let Account::Verified {
+        attributes, tokens, ..
+    } = &mut account
+    {
+        attributes.permissions = vec![Permission::ManageAccounts];
+        let _ = tokens.new_token(0).unwrap();
+    }
+    crate::account::INSTANCE.push(account);
+
+    let expired = lettre::Address::new("expired", "i.pkuschool.edu.cn").unwrap();
+    crate::account::INSTANCE.push(Account::Unverified(crate::account::verify::Context::new(
+        expired.clone(),
+    )));
+
+    crate::clock::advance(chrono::Duration::days(8));
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(3, "user3", &[]));
+    crate::account::INSTANCE.push(Account::Unverified(crate::account::verify::Context::new(
+        lettre::Address::new("pending", "i.pkuschool.edu.cn").unwrap(),
+    )));
+
+    let token_count = |id| {
+        crate::account::INSTANCE
+            .with_account(id, |account| match account {
+                Account::Verified { tokens, .. } => tokens.len(),
+                Account::Unverified(_) => 0,
+            })
+            .unwrap()
+    };
+    let has_reset = || {
+        crate::account::INSTANCE
+            .with_account(1, |account| {
+                matches!(
+                    account,
+                    Account::Verified {
+                        verify: UserVerifyVariant::ForgetPassword(_),
+                        ..
+                    }
+                )
+            })
+            .unwrap()
+    };
+
+    let plan = crate::account::INSTANCE.refresh_dry_run();
+    assert_eq!(
+        plan.removed_accounts,
+        vec![crate::account::email_id(&expired)]
+    );
+    assert_eq!(plan.pruned_tokens, [(1, 1), (2, 1)].into_iter().collect());
+    assert_eq!(plan.cleared_resets, vec![1]);
+
+    // Nothing changed yet.
+    assert_eq!(crate::account::INSTANCE.snapshot_ids().len(), 5);
+    assert_eq!((token_count(1), token_count(2)), (2, 1));
+    assert!(has_reset());
+
+    crate::account::INSTANCE.refresh_all();
+
+    let ids = crate::account::INSTANCE.snapshot_ids();
+    assert_eq!(ids.len(), 4);
+    assert!(!ids.contains(&crate::account::email_id(&expired)));
+    assert_eq!((token_count(1), token_count(2)), (1, 0));
+    assert!(!has_reset());
+    assert_eq!(
+        crate::account::INSTANCE.refresh_dry_run(),
+        Default::default()
+    );
+}
+
+/// Test: concurrent registrations of an email create a single pending
+/// account, and emails of verified accounts with other ids are rejected.
+#[serial]
+#[test]
+fn register() {
+    use crate::account::{email_id, Error, Registration, INSTANCE};
+
+    reset_all();
+    INSTANCE.push(fixtures::verified_account_with_id(7, "user1", &[]));
+    assert!(matches!(
+        INSTANCE.register(fixtures::email("User1")),
+        Err(Error::UserRegistered)
+    ));
+
+    let email = fixtures::email("user2");
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..4)
+            .map(|_| scope.spawn(|| INSTANCE.register(email.clone())))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(
+        results
+            .iter()
+            .filter(|result| matches!(result, Ok(Registration::Created)))
+            .count(),
+        1
+    );
+    assert_eq!(INSTANCE.snapshot_ids(), vec![7, email_id(&email)]);
+    let report = INSTANCE.verify_integrity();
+    assert!(report.index_mismatches.is_empty() && report.duplicate_emails.is_empty());
+}
+
+/// Test: an email re-registered while expired accounts are swept ends up
+/// with exactly one pending account.
+#[serial]
+#[test]
+fn reregister_during_refresh() {
+    reset_all();
+
+    let email = lettre::Address::new("myg", "i.pkuschool.edu.cn").unwrap();
+    let id = crate::account::email_id(&email);
+    crate::account::INSTANCE.register(email.clone()).unwrap();
+
+    for _ in 0..200 {
+        // Codes are valid for 15 minutes.
+        crate::clock::advance(chrono::Duration::minutes(16));
+        crate::account::verify::MAIL_LIMITER.reset();
+
+        let barrier = std::sync::Barrier::new(4);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                barrier.wait();
+                crate::account::INSTANCE.refresh_all();
+            });
+            scope.spawn(|| {
+                barrier.wait();
+                crate::account::INSTANCE.refresh(id);
+            });
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    barrier.wait();
+                    crate::account::INSTANCE.register(email.clone()).unwrap();
+                });
+            }
+        });
+
+        assert_eq!(crate::account::INSTANCE.snapshot_ids(), vec![id]);
+        assert_eq!(
+            crate::account::INSTANCE.with_account(id, |account| account.is_expired()),
+            Some(false)
+        );
+        assert!(crate::account::INSTANCE.verify_integrity().is_ok());
+    }
+}
+
+/// Test: a revoked token saved synchronously stays revoked after reloading,
+/// even if a save from before the revocation finishes later.
+#[test]
+fn revocation_persisted() {
+    use crate::account::{store::FileStore, Account};
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(dir.path());
+
+    let mut account = fixtures::verified_account_with_id(1, "user1", &[]);
+    let token = account
+        .login(fixtures::PASSWORD, Default::default())
+        .unwrap();
+
+    // A background save taking its generation before the revocation.
+    let stale_generation = store.next_generation();
+    let stale_data = store.serialize(&account).unwrap();
+
+    account.logout(&token).unwrap();
+    store
+        .write_generation(
+            1,
+            store.next_generation(),
+            store.serialize(&account).unwrap(),
+        )
+        .unwrap();
+    store
+        .write_generation(1, stale_generation, stale_data)
+        .unwrap();
+
+    let accounts = FileStore::new(dir.path()).load_all().unwrap();
+    let [Account::Verified { tokens, .. }] = accounts.as_slice() else {
+        panic!("account not reloaded");
+    };
+    assert!(!tokens.token_usable(&token));
+
+    // Stale writes can't recreate removed files either.
+    let stale_generation = store.next_generation();
+    store.remove_generation(1, store.next_generation()).unwrap();
+    store
+        .write_generation(1, stale_generation, store.serialize(&account).unwrap())
+        .unwrap();
+    assert!(FileStore::new(dir.path()).load_all().unwrap().is_empty());
+
+    // Plain saves and removals, also under the store lock, take generations too.
+    let stale_generation = store.next_generation();
+    store.save(&account).unwrap();
+    store.remove_generation(1, stale_generation).unwrap();
+    assert_eq!(FileStore::new(dir.path()).load_all().unwrap().len(), 1);
+
+    let stale_generation = store.next_generation();
+    store.lock_all().unwrap().remove(1).unwrap();
+    store
+        .write_generation(1, stale_generation, store.serialize(&account).unwrap())
+        .unwrap();
+    assert!(FileStore::new(dir.path()).load_all().unwrap().is_empty());
+}
+
+/// Test: merging moves attributes, audit entries and the file of the dropped account.
+#[test]
+fn merge() {
+    use crate::account::{
+        audit, store::FileStore, Account, AccountManager, ManagerError, Permission,
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(dir.path());
+
+    let mut keep = fixtures::verified_account_with_id(1, "user1", &[]);
+    let mut drop = fixtures::verified_account_with_id(2, "User1", &[]);
+    if let Account::Verified { attributes, .. } = &mut keep {
+        attributes.permissions = vec![Permission::View];
+    }
+    let mut token = String::new();
+    if let Account::Verified {
+        attributes, tokens, ..
+    } = &mut drop
+    {
+        attributes.permissions = vec![Permission::Post, Permission::View];
+        attributes.school_id = 2100000;
+        attributes.house = Some(sms3_shared::account::House::from("ZhiZhi"));
+        attributes.organization = Some("SubIT".to_string());
+        token = tokens.new_token(0).unwrap();
+    }
+    store.save(&keep).unwrap();
+    store.save(&drop).unwrap();
+
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    assert!(manager.grant_permission_bulk(&[2], Permission::ViewAccounts, 1)[0].is_ok());
+
+    manager.merge(1, 2).unwrap();
+
+    assert_eq!(manager.snapshot_ids(), vec![1]);
+    let report = manager.verify_integrity();
+    assert!(report.index_mismatches.is_empty() && report.dangling_index.is_empty());
+    assert_eq!(manager.find_by_token(&token), None);
+    manager
+        .with_account(1, |account| {
+            let Account::Verified { attributes, .. } = account else {
+                unreachable!()
+            };
+            assert_eq!(
+                attributes.permissions,
+                vec![Permission::Post, Permission::View, Permission::ViewAccounts]
+            );
+            // The kept account wins on fields both have.
+            assert_eq!(attributes.school_id, 2522320);
+            assert_eq!(
+                attributes.house,
+                Some(sms3_shared::account::House::from("ZhiZhi"))
+            );
+            assert_eq!(attributes.organization.as_deref(), Some("SubIT"));
+        })
+        .unwrap();
+
+    let entries = manager.audit().concerning(1);
+    assert!(entries.iter().any(|e| e.target == 1
+        && e.action
+            == audit::Action::ChangePermissions(audit::PermissionDiff {
+                added: vec![Permission::ViewAccounts],
+                removed: vec![],
+            })));
+    assert!(entries
+        .iter()
+        .any(|e| e.action == audit::Action::Merge { from: 2 }));
+
+    let stored = FileStore::new(dir.path()).load_all().unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].id(), 1);
+
+    assert!(matches!(
+        manager.merge(1, 3),
+        Err(ManagerError::NotFound(3))
+    ));
+    assert!(matches!(
+        manager.merge(3, 1),
+        Err(ManagerError::NotFound(3))
+    ));
+    assert!(matches!(
+        manager.merge(1, 1),
+        Err(ManagerError::Account(1, _))
+    ));
+}
+
+/// Test: merging keeps the house history of both accounts, ending with the
+/// house of the kept account.
+#[serial]
+#[test]
+fn merge_house_history() {
+    use crate::account::Account;
+    use chrono::Duration;
+    use sms3_shared::account::{House, HouseAssignment};
+
+    reset_all();
+    let now = chrono::Utc::now();
+    crate::clock::set(now);
+
+    let keep = fixtures::with_attributes(
+        fixtures::verified_account_with_id(1, "user1", &[]),
+        |attributes| {
+            attributes.house = Some(House::from("GeWu"));
+            attributes.registration_time = now - Duration::days(30);
+        },
+    );
+    let drop = fixtures::with_attributes(
+        fixtures::verified_account_with_id(2, "User1", &[]),
+        |attributes| {
+            attributes.registration_time = now - Duration::days(20);
+            attributes.set_house(Some(House::from("ZhiZhi")));
+        },
+    );
+    let manager = fixtures::manager_with([keep, drop]);
+    manager.merge(1, 2).unwrap();
+
+    let assignment = |house: &str, effective_from| HouseAssignment {
+        house: Some(House::from(house)),
+        effective_from,
+    };
+    manager
+        .with_account(1, |account| {
+            let Account::Verified { attributes, .. } = account else {
+                unreachable!()
+            };
+            assert_eq!(attributes.house, Some(House::from("GeWu")));
+            assert_eq!(
+                attributes.houses(),
+                [
+                    assignment("GeWu", now - Duration::days(30)),
+                    assignment("ZhiZhi", now),
+                    assignment("GeWu", now),
+                ]
+            );
+        })
+        .unwrap();
+}
+
+/// Test: member counts of houses and organizations follow account changes.
+#[serial]
+#[test]
+fn membership_counts() {
+    use sms3_shared::account::House;
+
+    reset_all();
+
+    for i in 0..3 {
+        crate::account::INSTANCE.push(fixtures::with_attributes(
+            fixtures::verified_account_with_id(i, &format!("user{i}"), &[]),
+            |attributes| attributes.house = Some(House::from("ZhiZhi")),
+        ));
+    }
+    let set = |id, house: &str, organization: Option<&str>| {
+        crate::account::INSTANCE.with_account_mut(id, |account| {
+            if let crate::account::Account::Verified { attributes, .. } = account {
+                attributes.house = Some(House::from(house));
+                attributes.organization = organization.map(str::to_string);
+            }
+        })
+    };
+    let houses = || crate::account::INSTANCE.house_counts();
+    let organizations = || crate::account::INSTANCE.organization_counts();
+
+    assert_eq!(houses(), [(House::from("ZhiZhi"), 3)].into_iter().collect());
+    assert!(organizations().is_empty());
+
+    set(0, "GeWu", Some("SubIT"));
+    assert_eq!(
+        houses(),
+        [(House::from("ZhiZhi"), 2), (House::from("GeWu"), 1)]
+            .into_iter()
+            .collect()
+    );
+    assert_eq!(
+        organizations(),
+        [("SubIT".to_string(), 1)].into_iter().collect()
+    );
+
+    // Changes through guards count once they are dropped.
+    {
+        let mut account = crate::account::INSTANCE.get_mut(1).unwrap();
+        if let crate::account::Account::Verified { attributes, .. } = &mut *account {
+            attributes.house = Some(House::from("GeWu"));
+        }
+    }
+    set(0, "GeWu", None);
+    assert_eq!(
+        houses(),
+        [(House::from("ZhiZhi"), 1), (House::from("GeWu"), 2)]
+            .into_iter()
+            .collect()
+    );
+    assert!(organizations().is_empty());
+
+    crate::account::INSTANCE.remove(2).unwrap();
+    assert_eq!(houses(), [(House::from("GeWu"), 2)].into_iter().collect());
+}
+
+/// Test: the token index follows logins, logouts, expiry and removal.
+#[serial]
+#[test]
+fn token_index() {
+    reset_all();
+
+    let mut never_expiring = fixtures::verified_account_with_id(1, "user1", &[]);
+    let kept = never_expiring
+        .login(fixtures::PASSWORD, Default::default())
+        .unwrap();
+    crate::account::INSTANCE.push(never_expiring);
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(2, "user2", &[]));
+    assert_eq!(crate::account::INSTANCE.find_by_token(&kept), Some(1));
+
+    let login = |id| {
+        crate::account::INSTANCE
+            .with_account_mut(id, |account| {
+                account.login(fixtures::PASSWORD, Default::default())
+            })
+            .unwrap()
+            .unwrap()
+    };
+    let first = login(2);
+    let second = login(2);
+    assert_eq!(crate::account::INSTANCE.find_by_token(&first), Some(2));
+    assert_eq!(crate::account::INSTANCE.find_by_token(&second), Some(2));
+    assert_eq!(crate::account::INSTANCE.find_by_token("unknown"), None);
+
+    crate::account::INSTANCE
+        .with_account_mut(2, |account| account.logout(&first))
+        .unwrap()
+        .unwrap();
+    assert_eq!(crate::account::INSTANCE.find_by_token(&first), None);
+    assert_eq!(crate::account::INSTANCE.find_by_token(&second), Some(2));
+
+    // Expired tokens are rejected before a refresh drops them.
+    crate::account::INSTANCE.with_account_mut(2, |account| {
+        if let crate::account::Account::Verified { attributes, .. } = account {
+            attributes.token_expiration_time = 1;
+        }
+    });
+    let expiring = login(2);
+    assert_eq!(crate::account::INSTANCE.find_by_token(&expiring), Some(2));
+    crate::clock::advance(chrono::Duration::days(2));
+    assert_eq!(crate::account::INSTANCE.find_by_token(&expiring), None);
+    crate::account::INSTANCE.refresh_all();
+    assert_eq!(crate::account::INSTANCE.find_by_token(&expiring), None);
+    assert_eq!(crate::account::INSTANCE.find_by_token(&second), Some(2));
+
+    crate::account::INSTANCE.remove(2).unwrap();
+    assert_eq!(crate::account::INSTANCE.find_by_token(&second), None);
+    assert_eq!(crate::account::INSTANCE.find_by_token(&kept), Some(1));
+
+    // The index is rebuilt when loading from a store.
+    let dir = tempfile::tempdir().unwrap();
+    let store = crate::account::store::FileStore::new(dir.path());
+    let mut account = fixtures::verified_account_with_id(3, "user3", &[]);
+    let token = account
+        .login(fixtures::PASSWORD, Default::default())
+        .unwrap();
+    store
+        .write_generation(
+            3,
+            store.next_generation(),
+            store.serialize(&account).unwrap(),
+        )
+        .unwrap();
+    let manager = crate::account::AccountManager::load(store).unwrap();
+    assert_eq!(manager.find_by_token(&token), Some(3));
+}
+
+/// Test: session tokens are validated through the index while the account is
+/// locked, still rejected while the password must be changed or suspended.
+#[serial]
+#[test]
+fn token_check_unlocked() {
+    use crate::account::{Account, Error};
+
+    reset_all();
+
+    let mut account = fixtures::verified_account_with_id(1, "user1", &[]);
+    let token = fixtures::new_token(&mut account);
+    crate::account::INSTANCE.push(account);
+    let cxt = crate::RequirePermissionContext {
+        token: token.clone(),
+        account_id: 1,
+    };
+
+    {
+        let accounts = crate::account::INSTANCE.inner().read();
+        let _locked = accounts[0].write();
+        assert!(crate::account::INSTANCE.check_token(1, &token).is_ok());
+        assert!(cxt.valid(&[]).is_ok());
+    }
+    assert!(matches!(
+        crate::account::INSTANCE.check_token(2, &token),
+        Err(Error::TokenIncorrect)
+    ));
+
+    let set = |f: fn(&mut crate::account::UserAttributes)| {
+        crate::account::INSTANCE.with_account_mut(1, |account| {
+            if let Account::Verified { attributes, .. } = account {
+                f(attributes)
+            }
+        })
+    };
+    set(|attributes| attributes.suspended = true);
+    assert!(matches!(
+        crate::account::INSTANCE.check_token(1, &token),
+        Err(Error::AccountSuspended)
+    ));
+    set(|attributes| {
+        attributes.suspended = false;
+        attributes.must_change_password = true;
+    });
+    assert!(matches!(
+        crate::account::INSTANCE.check_token(1, &token),
+        Err(Error::PasswordChangeRequired)
+    ));
+    set(|attributes| attributes.must_change_password = false);
+    assert!(crate::account::INSTANCE.check_token(1, &token).is_ok());
+}
+
+/// Test: once an awaitable save completes, the account file exists.
+#[tokio::test]
+async fn save_awaitable() {
+    use crate::account::{store::FileStore, AccountManager};
+
+    let dir = tempfile::tempdir().unwrap();
+    let manager: &'static AccountManager = Box::leak(Box::new(
+        AccountManager::load(FileStore::new(dir.path())).unwrap(),
+    ));
+
+    let account = fixtures::verified_account_with_id(1, "user1", &[]);
+    let saved = manager.save_awaitable(&account);
+    drop(account);
+    saved.await.unwrap();
+
+    let accounts = FileStore::new(dir.path()).load_all().unwrap();
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].id(), 1);
+}
+
+/// Test: accounts inserted or removed while paging with cursors are neither skipped nor repeated.
+#[serial]
+#[test]
+fn list_after() {
+    reset_all();
+
+    for id in [10, 20, 30, 40, 50] {
+        crate::account::INSTANCE.push(fixtures::verified_account_with_id(
+            id,
+            &format!("user{id}"),
+            &[],
+        ));
+    }
+    crate::account::INSTANCE.push(crate::account::Account::Unverified(
+        crate::account::verify::Context::new(
+            lettre::Address::new("pending", "i.pkuschool.edu.cn").unwrap(),
+        ),
+    ));
+    let names = |page: &[sms3_shared::account::UserMetadata]| {
+        page.iter()
+            .map(|metadata| metadata.email.user().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let (page, cursor) = crate::account::INSTANCE.list_after(None, 2);
+    assert_eq!(names(&page), ["user10", "user20"]);
+    assert_eq!(cursor, Some(20));
+
+    // Accounts before the cursor don't shift later pages, and those after it are listed once.
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(15, "user15", &[]));
+    crate::account::INSTANCE.push(fixtures::verified_account_with_id(35, "user35", &[]));
+    crate::account::INSTANCE.remove(10).unwrap();
+
+    let (page, cursor) = crate::account::INSTANCE.list_after(cursor, 2);
+    assert_eq!(names(&page), ["user30", "user35"]);
+    assert_eq!(cursor, Some(35));
+
+    let mut rest = Vec::new();
+    let mut cursor = cursor;
+    while let Some(after) = cursor {
+        let (page, next) = crate::account::INSTANCE.list_after(Some(after), 2);
+        rest.extend(names(&page));
+        cursor = next;
+    }
+    // The unverified account is never listed.
+    assert_eq!(rest, ["user40", "user50"]);
+
+    let (page, cursor) = crate::account::INSTANCE.list_after(None, 100);
+    assert_eq!(page.len(), 6);
+    assert_eq!(cursor, None);
+}
+
+/// Test: bulk removal deletes files of existing accounts only and keeps the index consistent.
+#[test]
+fn remove_many() {
+    use crate::account::{store::FileStore, AccountManager, ManagerError};
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(dir.path());
+    for i in 1..=5 {
+        store
+            .save(&fixtures::verified_account_with_id(
+                i,
+                &format!("user{i}"),
+                &[],
+            ))
+            .unwrap();
+    }
+    let file = |id: u64| dir.path().join(format!("{id}.toml"));
+    assert!(file(1).exists());
+
+    let manager = AccountManager::load(FileStore::new(dir.path())).unwrap();
+    manager.insert(crate::account::Account::Unverified(
+        crate::account::verify::Context::new(
+            lettre::Address::new("pending", "i.pkuschool.edu.cn").unwrap(),
+        ),
+    ));
+    let pending =
+        crate::account::email_id(&lettre::Address::new("pending", "i.pkuschool.edu.cn").unwrap());
+
+    let results = manager.remove_many(&[4, 9, 2, pending, 4]);
+    assert_eq!(
+        results[0].as_ref().unwrap().as_ref().unwrap().email.user(),
+        "user4"
+    );
+    assert!(matches!(results[1], Err(ManagerError::NotFound(9))));
+    assert_eq!(
+        results[2].as_ref().unwrap().as_ref().unwrap().email.user(),
+        "user2"
+    );
+    assert!(matches!(results[3], Ok(None)));
+    assert!(matches!(results[4], Err(ManagerError::NotFound(4))));
+
+    let report = manager.verify_integrity();
+    assert!(report.index_mismatches.is_empty());
+    assert!(report.dangling_index.is_empty());
+    let mut ids = manager.snapshot_ids();
+    ids.sort_unstable();
+    assert_eq!(ids, [1, 3, 5]);
+    for id in [1, 3, 5] {
+        assert_eq!(manager.get(id).unwrap().id(), id);
+        assert!(file(id).exists());
+    }
+    for id in [2, 4, 9] {
+        assert!(manager.get(id).is_none());
+        assert!(!file(id).exists());
+    }
+}
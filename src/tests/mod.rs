@@ -1,5 +1,10 @@
 mod account;
 mod account_manage;
+mod cors;
+mod fixtures;
+mod harness;
+mod manager;
+mod openapi;
 
 mod post;
 
@@ -8,4 +13,17 @@ fn reset_all() {
     crate::account::INSTANCE.reset();
     crate::post::INSTANCE.reset();
     crate::post::cache::INSTANCE.reset();
+    crate::account::verify::SENT_MAILS.lock().clear();
+    crate::account::verify::SENT_SMS.lock().clear();
+    crate::account::verify::MAIL_LIMITER.reset();
+    crate::account::verify::LOGIN_LIMITER.reset();
+    crate::account::verify::AVAILABILITY_LIMITER.reset();
+    crate::account::verify::FAIL_MAILS.store(false, std::sync::atomic::Ordering::Relaxed);
+    crate::account::oidc::PENDING.reset();
+    crate::maintenance::disable();
+    *crate::config::REGISTRATION_WINDOW.lock() = None;
+    *crate::config::OIDC.lock() = None;
+    *crate::config::CODE_DELIVERY.lock() = None;
+    *crate::config::TRUSTED_PROXIES.lock() = None;
+    crate::clock::reset();
 }
@@ -0,0 +1,68 @@
+use axum::http::{Request, StatusCode};
+use tower::util::ServiceExt;
+
+/// Test: the generated specification documents account endpoints and their schemas.
+#[tokio::test]
+async fn openapi_json() {
+    let response = crate::router()
+        .oneshot(
+            Request::builder()
+                .uri("/openapi.json")
+                .method("GET")
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let spec: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+
+    for path in [
+        "/api/account/create",
+        "/api/account/verify",
+        "/api/account/login",
+        "/api/account/logout",
+        "/api/account/signout",
+        "/api/account/view",
+        "/api/account/edit",
+        "/api/account/reset-password",
+    ] {
+        assert!(spec["paths"][path]["post"].is_object(), "{path} missing");
+    }
+
+    assert!(spec["paths"]["/api/account/available"]["get"].is_object());
+
+    let login = &spec["paths"]["/api/account/login"]["post"]["responses"];
+    assert!(login["200"].is_object());
+    assert!(login["403"].is_object());
+
+    let schemas = &spec["components"]["schemas"];
+    assert!(schemas["AccountLoginDescriptor"].is_object());
+    assert!(schemas["ErrorResponse"]["properties"]["error"].is_object());
+    assert!(spec["components"]["securitySchemes"]["token"].is_object());
+}
+
+/// Test: Swagger UI page points to the specification.
+#[tokio::test]
+async fn swagger_ui() {
+    let response = crate::router()
+        .oneshot(
+            Request::builder()
+                .uri("/swagger-ui")
+                .method("GET")
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(std::str::from_utf8(&body)
+        .unwrap()
+        .contains("/openapi.json"));
+}
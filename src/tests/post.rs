@@ -26,17 +26,27 @@ async fn cache_image() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Jiening Yu".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![crate::account::Permission::Post],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: {
             let mut t = crate::account::verify::Tokens::new();
-            token = t.new_token(account_id, 0);
+            token = t.new_token(0).unwrap();
             t
         },
         verify: crate::account::UserVerifyVariant::None,
@@ -93,17 +103,27 @@ async fn new() {
             email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
             name: "Jiening Yu".to_string(),
             school_id: 2522320,
-            house: Some(sms3_shared::account::House::ZhiZhi),
+            house: Some(sms3_shared::account::House::from("ZhiZhi")),
             phone: 16601550826,
             organization: None,
             permissions: vec![sms3_shared::account::Permission::Post],
             registration_time: chrono::Utc::now(),
             password_sha: digest(password.to_string()),
             token_expiration_time: 0,
+            mute_login_notification: false,
+            password_history: vec![],
+            admin_notes: vec![],
+            house_history: vec![],
+            scoped_permissions: vec![],
+            password_changed_at: None,
+            alternate_email: None,
+            must_change_password: false,
+            pending_deletion: None,
+            suspended: false,
         },
         tokens: {
             let mut t = crate::account::verify::Tokens::new();
-            token = t.new_token(account_id, 0);
+            token = t.new_token(0).unwrap();
             t
         },
         verify: crate::account::UserVerifyVariant::None,
@@ -0,0 +1,219 @@
+use sha256::digest;
+use sms3_backend::account::{
+    self, store::FileStore, verify, Account, Permission, UserAttributes, UserVerifyVariant,
+};
+use std::process::Command;
+
+const ACCOUNT_ID: u64 = 123456;
+
+/// Create a data directory containing a verified and an expired unverified account.
+fn seed() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("data/accounts")).unwrap();
+    // Account policies, ex. token expiration limits, are read from the config.
+    std::fs::write(
+        dir.path().join("data/config.toml"),
+        "[mail_smtp]\nserver = \"\"\nport = 465\nusername = \"\"\npassword = \"\"\naddress = \"admin@i.pkuschool.edu.cn\"\n",
+    )
+    .unwrap();
+    let store = FileStore::new(dir.path().join("data/accounts"));
+
+    store
+        .save(&Account::Verified {
+            id: ACCOUNT_ID,
+            attributes: UserAttributes {
+                email: lettre::Address::new("yujiening2025", "i.pkuschool.edu.cn").unwrap(),
+                name: "Jiening Yu".to_string(),
+                school_id: 2522320,
+                house: None,
+                phone: 16601550826,
+                organization: None,
+                permissions: vec![Permission::View],
+                registration_time: chrono::Utc::now(),
+                password_sha: digest("password123456"),
+                token_expiration_time: 0,
+                mute_login_notification: false,
+                password_history: vec![],
+                admin_notes: vec![],
+                house_history: vec![],
+                scoped_permissions: vec![],
+                password_changed_at: None,
+                alternate_email: None,
+                must_change_password: false,
+                pending_deletion: None,
+                suspended: false,
+            },
+            tokens: verify::Tokens::new(),
+            verify: UserVerifyVariant::None,
+        })
+        .unwrap();
+
+    store
+        .save(&Account::Unverified(verify::Context {
+            email: lettre::Address::new("myg", "i.pkuschool.edu.cn").unwrap(),
+            code: 123456,
+            expire_time: chrono::Utc::now() - chrono::Duration::minutes(1),
+        }))
+        .unwrap();
+
+    dir
+}
+
+fn admin(dir: &tempfile::TempDir, args: &[&str]) -> (bool, serde_json::Value) {
+    let output = Command::new(env!("CARGO_BIN_EXE_sms3-admin"))
+        .arg("--json")
+        .arg("--data-dir")
+        .arg(dir.path().join("data"))
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        serde_json::from_slice(&output.stdout).unwrap(),
+    )
+}
+
+fn load(dir: &tempfile::TempDir) -> Vec<Account> {
+    FileStore::new(dir.path().join("data/accounts"))
+        .load_all()
+        .unwrap()
+}
+
+fn stored_account(dir: &tempfile::TempDir) -> UserAttributes {
+    load(dir)
+        .into_iter()
+        .find_map(|account| match account {
+            Account::Verified { id, attributes, .. } if id == ACCOUNT_ID => Some(attributes),
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn list() {
+    let dir = seed();
+    let (ok, value) = admin(&dir, &["list"]);
+
+    assert!(ok);
+    let accounts = value.as_array().unwrap();
+    assert_eq!(accounts.len(), 2);
+    assert!(accounts
+        .iter()
+        .any(|a| a["id"] == ACCOUNT_ID && a["verified"] == true));
+}
+
+#[test]
+fn grant_and_revoke() {
+    let dir = seed();
+
+    let (ok, value) = admin(&dir, &["grant", &ACCOUNT_ID.to_string(), "ManageAccounts"]);
+    assert!(ok);
+    assert_eq!(
+        value["permissions"],
+        serde_json::json!(["ManageAccounts", "View"])
+    );
+    assert!(stored_account(&dir)
+        .permissions
+        .contains(&Permission::ManageAccounts));
+
+    let (ok, _) = admin(&dir, &["revoke", &ACCOUNT_ID.to_string(), "View"]);
+    assert!(ok);
+    assert_eq!(
+        stored_account(&dir).permissions,
+        vec![Permission::ManageAccounts]
+    );
+
+    let (ok, value) = admin(&dir, &["grant", "42", "View"]);
+    assert!(!ok);
+    assert!(value["error"].as_str().unwrap().contains("not found"));
+
+    // Both changes are audited as the permissions added and removed.
+    let audit = FileStore::new(dir.path().join("data/accounts")).audit_path();
+    let actions: Vec<serde_json::Value> = std::fs::read_to_string(audit)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["action"].clone())
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            serde_json::json!({ "ChangePermissions": { "added": ["ManageAccounts"], "removed": [] } }),
+            serde_json::json!({ "ChangePermissions": { "added": [], "removed": ["View"] } }),
+        ]
+    );
+}
+
+#[test]
+fn reset_password() {
+    let dir = seed();
+
+    let (ok, _) = admin(
+        &dir,
+        &["reset-password", &ACCOUNT_ID.to_string(), "newpassword"],
+    );
+    assert!(ok);
+    assert!(account::verify_password(
+        &stored_account(&dir).password_sha,
+        "newpassword"
+    ));
+}
+
+#[test]
+fn suspend_and_unban() {
+    let dir = seed();
+
+    let (ok, value) = admin(&dir, &["suspend", &ACCOUNT_ID.to_string()]);
+    assert!(ok);
+    assert_eq!(value["suspended"], true);
+    assert!(stored_account(&dir).suspended);
+
+    // Suspending again changes nothing and isn't audited.
+    let (ok, _) = admin(&dir, &["suspend", &ACCOUNT_ID.to_string()]);
+    assert!(ok);
+
+    let (ok, value) = admin(&dir, &["unban", &ACCOUNT_ID.to_string()]);
+    assert!(ok);
+    assert_eq!(value["suspended"], false);
+    assert!(!stored_account(&dir).suspended);
+
+    let (ok, value) = admin(&dir, &["suspend", "42"]);
+    assert!(!ok);
+    assert!(value["error"].as_str().unwrap().contains("not found"));
+
+    let unverified = account::email_id(&lettre::Address::new("myg", "i.pkuschool.edu.cn").unwrap());
+    let (ok, value) = admin(&dir, &["suspend", &unverified.to_string()]);
+    assert!(!ok);
+    assert!(value["error"]
+        .as_str()
+        .unwrap()
+        .contains("not been verified"));
+
+    let audit = FileStore::new(dir.path().join("data/accounts")).audit_path();
+    let actions: Vec<serde_json::Value> = std::fs::read_to_string(audit)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["action"].clone())
+        .collect();
+    assert_eq!(
+        actions,
+        [serde_json::json!("Suspend"), serde_json::json!("Unban")]
+    );
+}
+
+#[test]
+fn refresh() {
+    let dir = seed();
+
+    let (ok, value) = admin(&dir, &["refresh", "--dry-run"]);
+    assert!(ok);
+    assert_eq!(value["would_remove"].as_array().unwrap().len(), 1);
+    assert_eq!(load(&dir).len(), 2);
+
+    let (ok, value) = admin(&dir, &["refresh"]);
+    assert!(ok);
+    assert_eq!(value["removed"], 1);
+
+    let accounts = load(&dir);
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].id(), ACCOUNT_ID);
+}